@@ -0,0 +1,50 @@
+/**
+ * The build script records the commit and the date hotdog is built from, which `hotdog version`
+ * reports
+ */
+use chrono::{TimeZone, Utc};
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    /*
+     * Reproducible builds pin the build date with SOURCE_DATE_EPOCH
+     */
+    let built = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .unwrap_or_else(Utc::now);
+
+    println!("cargo:rustc-env=HOTDOG_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=HOTDOG_BUILT={}",
+        built.format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    /*
+     * A commit moves the branch which HEAD points at rather than HEAD itself, and the branch may
+     * only be in the packed refs after a `git gc`. Cargo always reruns a build script for a
+     * missing file, so only those which exist are watched
+     */
+    let head = std::fs::read_to_string(".git/HEAD").unwrap_or_default();
+    let mut watched = vec![".git/packed-refs".to_string()];
+    if let Some(branch) = head.trim().strip_prefix("ref: ") {
+        watched.push(format!(".git/{}", branch));
+    }
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
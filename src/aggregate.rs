@@ -0,0 +1,240 @@
+/**
+ * The aggregate module counts and sums the messages matched by aggregate actions over tumbling
+ * windows, sending a single rollup event for each key once its window ends
+ */
+use crate::kafka::{KafkaMessage, Produce};
+use crate::reload::Reloadable;
+use crate::status::{Statistic, Stats};
+use async_channel::Sender;
+use async_std::{sync::Arc, task};
+use chrono::prelude::*;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::time::Duration;
+
+/**
+ * A Rollup of the messages for a key within a single window
+ */
+#[derive(Debug, PartialEq)]
+pub struct Rollup {
+    topic: String,
+    key: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    count: u64,
+    sum: Option<f64>,
+}
+
+impl Rollup {
+    /**
+     * Return the JSON event for the rollup, which only has a `sum` if values were recorded
+     */
+    fn to_json(&self) -> serde_json::Value {
+        let mut event = serde_json::json!({
+            "key": self.key,
+            "count": self.count,
+            "start": self.start.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "end": self.end.to_rfc3339_opts(SecondsFormat::Millis, true),
+        });
+
+        if let Some(sum) = self.sum {
+            event["sum"] = serde_json::Value::from(sum);
+        }
+        event
+    }
+
+    fn into_message(self) -> KafkaMessage {
+        let event = self.to_json().to_string();
+        KafkaMessage::new(self.topic, event.into_bytes())
+    }
+}
+
+/**
+ * Aggregator carries the rollups of the current windows, and should be shared between all
+ * connections
+ */
+pub struct Aggregator {
+    rollups: DashMap<String, Rollup>,
+    /**
+     * Rollups whose window ended while their key was seen again, before they could be sent
+     */
+    finished: Mutex<Vec<Rollup>>,
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Aggregator {
+            rollups: DashMap::default(),
+            finished: Mutex::new(vec![]),
+        }
+    }
+
+    /**
+     * Record a message for the key, and its value if it has one, in the current window.
+     *
+     * Windows are aligned to the Unix epoch so that every instance of hotdog uses the same
+     * windows. The topic is only rendered when a rollup is started
+     */
+    pub fn record<F>(&self, id: &str, key: &str, window: Duration, value: Option<f64>, topic: F)
+    where
+        F: FnOnce() -> String,
+    {
+        self.record_at(id, key, window, value, topic, Utc::now())
+    }
+
+    fn record_at<F>(
+        &self,
+        id: &str,
+        key: &str,
+        window: Duration,
+        value: Option<f64>,
+        topic: F,
+        now: DateTime<Utc>,
+    ) where
+        F: FnOnce() -> String,
+    {
+        let width = (window.as_millis() as i64).max(1);
+        let millis = now.timestamp_millis();
+        let start = Utc.timestamp_millis(millis - millis.rem_euclid(width));
+        let end = start + chrono::Duration::milliseconds(width);
+
+        let mut topic = Some(topic);
+        let mut new_rollup = || Rollup {
+            topic: (topic.take().expect("The topic can only be rendered once"))(),
+            key: key.to_string(),
+            start,
+            end,
+            count: 0,
+            sum: None,
+        };
+
+        let mut rollup = self
+            .rollups
+            .entry(format!("{}-{}", id, key))
+            .or_insert_with(&mut new_rollup);
+
+        if rollup.end <= now {
+            let ended = std::mem::replace(&mut *rollup, new_rollup());
+            self.finished.lock().push(ended);
+        }
+
+        rollup.count += 1;
+        if let Some(value) = value {
+            rollup.sum = Some(rollup.sum.unwrap_or(0.0) + value);
+        }
+    }
+
+    /**
+     * Remove the rollups whose windows have ended
+     */
+    fn ended(&self, now: DateTime<Utc>) -> Vec<Rollup> {
+        let mut ended: Vec<Rollup> = self.finished.lock().drain(..).collect();
+
+        let expired: Vec<String> = self
+            .rollups
+            .iter()
+            .filter(|rollup| rollup.end <= now)
+            .map(|rollup| rollup.key().to_string())
+            .collect();
+
+        for key in expired.iter() {
+            if let Some((_, rollup)) = self.rollups.remove_if(key, |_, r| r.end <= now) {
+                ended.push(rollup);
+            }
+        }
+        ended
+    }
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Aggregator::new()
+    }
+}
+
+/**
+ * Periodically send the rollups of the windows which have ended along to Kafka
+ */
+pub async fn rollup_loop(
+    aggregator: Arc<Aggregator>,
+    reloadable: Arc<Reloadable>,
+    stats: Sender<Statistic>,
+) {
+    loop {
+        task::sleep(Duration::from_secs(1)).await;
+        let sender = reloadable.sinks().sender.clone();
+
+        for rollup in aggregator.ended(Utc::now()) {
+            sender.send(Produce::Message(rollup.into_message())).await;
+            stats.send((Stats::RollupSent, 1)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic() -> String {
+        "rollups".to_string()
+    }
+
+    #[test]
+    fn test_record_within_window() {
+        let aggregator = Aggregator::new();
+        let window = Duration::from_secs(60);
+        let now = Utc.ymd(2020, 4, 18).and_hms(22, 16, 9);
+
+        aggregator.record_at("rule", "web", window, Some(10.0), topic, now);
+        aggregator.record_at("rule", "web", window, Some(2.5), topic, now);
+        aggregator.record_at("rule", "db", window, None, topic, now);
+        assert!(aggregator.ended(now).is_empty());
+
+        let mut ended = aggregator.ended(now + chrono::Duration::seconds(60));
+        ended.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(2, ended.len());
+        assert_eq!(1, ended[0].count);
+        assert_eq!(None, ended[0].sum);
+        assert_eq!(2, ended[1].count);
+        assert_eq!(Some(12.5), ended[1].sum);
+        assert_eq!(Utc.ymd(2020, 4, 18).and_hms(22, 16, 0), ended[1].start);
+        assert_eq!(Utc.ymd(2020, 4, 18).and_hms(22, 17, 0), ended[1].end);
+    }
+
+    #[test]
+    fn test_record_after_window() {
+        let aggregator = Aggregator::new();
+        let window = Duration::from_secs(60);
+        let now = Utc.ymd(2020, 4, 18).and_hms(22, 16, 9);
+        let later = now + chrono::Duration::seconds(60);
+
+        aggregator.record_at("rule", "web", window, None, topic, now);
+        aggregator.record_at("rule", "web", window, None, topic, later);
+
+        let ended = aggregator.ended(later);
+        assert_eq!(1, ended.len());
+        assert_eq!(1, ended[0].count);
+    }
+
+    #[test]
+    fn test_rollup_to_json() {
+        let rollup = Rollup {
+            topic: topic(),
+            key: "web".to_string(),
+            start: Utc.ymd(2020, 4, 18).and_hms(22, 16, 0),
+            end: Utc.ymd(2020, 4, 18).and_hms(22, 17, 0),
+            count: 2,
+            sum: Some(12.5),
+        };
+        assert_eq!(
+            serde_json::json!({
+                "key": "web",
+                "count": 2,
+                "sum": 12.5,
+                "start": "2020-04-18T22:16:00.000Z",
+                "end": "2020-04-18T22:17:00.000Z",
+            }),
+            rollup.to_json()
+        );
+    }
+}
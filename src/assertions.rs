@@ -0,0 +1,241 @@
+/**
+ * The assertions module tests the rules against lines which are paired with what the rules are
+ * expected to make of them, the rules which match them and the messages they produce, so that
+ * changes to the rules can be gated in CI with `hotdog test --assert`
+ */
+use crate::connection::Connection;
+use crate::kafka::{KafkaMessage, Produce};
+use crate::reload::Reloadable;
+use crate::rules;
+use crate::settings::{self, Settings};
+use crate::status::Statistic;
+use async_channel::{Receiver, Sender};
+use async_std::sync::Arc;
+use log::*;
+
+/**
+ * A line along with what is expected of it, the expectations which are left out are not checked
+ */
+#[derive(Debug, Deserialize)]
+pub struct Case {
+    /**
+     * The name of the case in the results, which defaults to the line
+     */
+    #[serde(default)]
+    pub name: Option<String>,
+    pub line: String,
+    /**
+     * The rules which are expected to match the line, in the order they are evaluated, by their
+     * name or, for the rules without a name, by their condition as `hotdog test` prints it
+     */
+    #[serde(default)]
+    pub matches: Option<Vec<String>>,
+    /**
+     * The messages which the line is expected to produce, in the order they are produced
+     */
+    #[serde(default)]
+    pub messages: Option<Vec<Expected>>,
+}
+
+/**
+ * A message which a line is expected to produce
+ */
+#[derive(Debug, Deserialize)]
+pub struct Expected {
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub key: Option<String>,
+    /**
+     * The payload of the message, which is compared as JSON when it is JSON, so that the order
+     * of the keys and the whitespace do not matter
+     */
+    #[serde(default)]
+    pub payload: Option<String>,
+}
+
+/**
+ * Load the cases from the `tests` of the file, which is YAML unless its extension says otherwise
+ */
+pub fn load(file: &str) -> Result<Vec<Case>, String> {
+    let path = std::path::Path::new(file);
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the assertions {}: {}", file, e))?;
+    let format = settings::format_for(path).unwrap_or(config::FileFormat::Yaml);
+    let mut conf = config::Config::default();
+
+    conf.merge(config::File::from_str(&contents, format))
+        .map_err(|e| format!("Failed to load the assertions {}: {}", file, e))?;
+    conf.get("tests")
+        .map_err(|e| format!("{}: `tests`: {}", file, e))
+}
+
+/**
+ * Run the cases of the file against the rules, printing the result of every case, and return
+ * the number of cases which failed
+ */
+pub async fn run(
+    file: &str,
+    reloadable: Arc<Reloadable>,
+    sunk: Receiver<Produce>,
+    stats: Sender<Statistic>,
+) -> Result<usize, String> {
+    let cases = load(file)?;
+    let settings = reloadable.current();
+    let connection = Connection::detached(reloadable, stats).dry();
+    let mut failed = 0;
+
+    for case in cases.iter() {
+        debug!("Asserting the line: {}", case.line);
+        let produced = rules::produce(&connection, &case.line, &sunk)
+            .await
+            .map_err(|e| format!("Failed to read the line `{}`: {:?}", case.line, e))?;
+        let failures = failures(case, &settings, &produced);
+        let name = case.name.as_ref().unwrap_or(&case.line);
+
+        if failures.is_empty() {
+            println!("ok {}", name);
+        } else {
+            failed += 1;
+            println!("FAILED {}", name);
+            for failure in failures.iter() {
+                println!("\t - {}", failure);
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    Ok(failed)
+}
+
+/**
+ * Describe how the rules and the messages the line produced differ from what the case expects
+ */
+fn failures(case: &Case, settings: &Settings, produced: &[KafkaMessage]) -> Vec<String> {
+    let mut failures = vec![];
+
+    if let Some(expected) = &case.matches {
+        let matched: Vec<String> = rules::matching(settings, &case.line)
+            .into_iter()
+            .map(|rule| rule.name.clone().unwrap_or_else(|| rule.to_string()))
+            .collect();
+
+        if &matched != expected {
+            failures.push(format!(
+                "expected to match {:?}, but matched {:?}",
+                expected, matched
+            ));
+        }
+    }
+
+    if let Some(expected) = &case.messages {
+        if expected.len() != produced.len() {
+            failures.push(format!(
+                "expected {} messages, but {} were produced",
+                expected.len(),
+                produced.len()
+            ));
+        }
+
+        for (index, (expected, kmsg)) in expected.iter().zip(produced.iter()).enumerate() {
+            if let Some(topic) = &expected.topic {
+                if topic != kmsg.topic() {
+                    failures.push(format!(
+                        "expected message {} to be sent to `{}`, but it was sent to `{}`",
+                        index,
+                        topic,
+                        kmsg.topic()
+                    ));
+                }
+            }
+
+            if expected.key.is_some() && expected.key.as_deref() != kmsg.key() {
+                failures.push(format!(
+                    "expected message {} to have the key {:?}, but it had {:?}",
+                    index,
+                    expected.key,
+                    kmsg.key()
+                ));
+            }
+
+            if let Some(payload) = &expected.payload {
+                let actual = String::from_utf8_lossy(kmsg.payload());
+
+                if !same_payload(payload, &actual) {
+                    failures.push(format!(
+                        "expected message {} to be `{}`, but it was `{}`",
+                        index, payload, actual
+                    ));
+                }
+            }
+        }
+    }
+    failures
+}
+
+/**
+ * Compare the payloads as JSON when they are both JSON, otherwise as they are
+ */
+fn same_payload(expected: &str, actual: &str) -> bool {
+    match (
+        serde_json::from_str::<serde_json::Value>(expected),
+        serde_json::from_str::<serde_json::Value>(actual),
+    ) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => expected == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serve::Sinks;
+    use crate::settings::Overrides;
+
+    #[test]
+    fn test_same_payload() {
+        assert!(same_payload(r#"{"a":1,"b":2}"#, r#"{ "b": 2, "a": 1 }"#));
+        assert!(!same_payload(r#"{"a":1}"#, r#"{"a":2}"#));
+        assert!(same_payload("hello", "hello"));
+        assert!(!same_payload("hello", "hello\n"));
+    }
+
+    #[async_std::test]
+    async fn test_run() {
+        let file = "test/configs/minimal.yml";
+        let settings = Arc::new(settings::load(file));
+        let (sinks, sunk) = Sinks::null(&settings);
+        let reloadable = Arc::new(Reloadable::new(file, Overrides::default(), settings, sinks));
+        let (stats, _) = async_channel::bounded(1);
+
+        let failed = run("test/assertions/minimal.yml", reloadable, sunk, stats)
+            .await
+            .expect("Failed to run the assertions");
+        assert_eq!(0, failed);
+    }
+
+    #[test]
+    fn test_failures() {
+        let settings = settings::load("test/configs/minimal.yml");
+        let case = Case {
+            name: None,
+            line: "hello world".to_string(),
+            matches: Some(vec![]),
+            messages: Some(vec![Expected {
+                topic: Some("logs-world".to_string()),
+                key: Some("world".to_string()),
+                payload: Some("hello world".to_string()),
+            }]),
+        };
+        let produced = vec![KafkaMessage::new(
+            "logs-world".to_string(),
+            "hello there".to_string(),
+        )];
+
+        let failures = failures(&case, &settings, &produced);
+        assert_eq!(3, failures.len());
+        assert!(failures[0].starts_with("expected to match []"));
+        assert!(failures[1].contains("the key"));
+        assert!(failures[2].contains("`hello there`"));
+    }
+}
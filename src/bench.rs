@@ -0,0 +1,326 @@
+/**
+ * The bench module generates synthetic RFC5424 syslog traffic at a fixed rate, either against a
+ * running hotdog or through the rules of a configuration with the messages going nowhere, and
+ * reports the throughput and how long the lines took, so that tuning changes can be measured
+ * reproducibly
+ */
+use crate::connection::Connection;
+use crate::kafka::Produce;
+use crate::reload::Reloadable;
+use crate::serve::Sinks;
+use crate::settings::{self, Overrides};
+use crate::status;
+use async_channel::{bounded, Sender};
+use async_std::{net::TcpStream, prelude::*, sync::Arc, task};
+use chrono::prelude::*;
+use futures::TryStreamExt;
+use std::time::{Duration, Instant};
+
+pub struct Options {
+    /**
+     * The address of a running hotdog to send the lines to, otherwise the lines are sent through
+     * the rules of the configuration
+     */
+    pub target: Option<String>,
+    pub lines: u64,
+    /**
+     * The number of lines per second, zero sends the lines as fast as possible
+     */
+    pub rate: u64,
+}
+
+/**
+ * What the durations of a benchmark measure, which differs between a target and the rules
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Measured {
+    /**
+     * The time from reading a line until its message reaches the producer
+     */
+    Latency,
+    /**
+     * The time each write to the target takes, which says nothing about when, or whether, the
+     * target forwarded the line to Kafka
+     */
+    WriteTime,
+}
+
+/**
+ * The results of a benchmark, with the durations in microseconds sorted from fastest to slowest
+ */
+pub struct Report {
+    lines: u64,
+    elapsed: Duration,
+    durations_us: Vec<u64>,
+    measured: Measured,
+}
+
+impl Report {
+    fn new(lines: u64, elapsed: Duration, mut durations_us: Vec<u64>, measured: Measured) -> Self {
+        durations_us.sort_unstable();
+
+        Report {
+            lines,
+            elapsed,
+            durations_us,
+            measured,
+        }
+    }
+
+    fn lines_per_sec(&self) -> f64 {
+        self.lines as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Sent {} lines in {:.3}s ({:.0} lines/s)",
+            self.lines,
+            self.elapsed.as_secs_f64(),
+            self.lines_per_sec()
+        )?;
+        let measured = match self.measured {
+            Measured::Latency => "Latency of the messages",
+            Measured::WriteTime => "Time to write the lines",
+        };
+        write!(
+            f,
+            "{} ({}): p50={}us p90={}us p99={}us max={}us",
+            measured,
+            self.durations_us.len(),
+            percentile(&self.durations_us, 0.5),
+            percentile(&self.durations_us, 0.9),
+            percentile(&self.durations_us, 0.99),
+            self.durations_us.last().copied().unwrap_or(0)
+        )
+    }
+}
+
+/**
+ * Run the benchmark of the options, loading the configuration only when there is no target
+ */
+pub async fn bench(
+    settings_file: &str,
+    overrides: Overrides,
+    options: &Options,
+) -> Result<Report, String> {
+    match &options.target {
+        Some(target) => against_target(target, options).await,
+        None => against_rules(settings_file, overrides, options).await,
+    }
+}
+
+/**
+ * Write the lines to a running hotdog, measuring how long each write takes. The target does not
+ * acknowledge the lines, so this is not the latency until they reach Kafka
+ */
+async fn against_target(target: &str, options: &Options) -> Result<Report, String> {
+    let mut stream = TcpStream::connect(target)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", target, e))?;
+    let mut write_times = Vec::with_capacity(options.lines as usize);
+    let start = Instant::now();
+
+    for sequence in 0..options.lines {
+        pace(start, sequence, options.rate).await;
+
+        let line = line(sequence);
+        let writing = Instant::now();
+        stream
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write to {}: {}", target, e))?;
+        write_times.push(writing.elapsed().as_micros() as u64);
+    }
+    stream
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to flush to {}: {}", target, e))?;
+
+    Ok(Report::new(
+        options.lines,
+        start.elapsed(),
+        write_times,
+        Measured::WriteTime,
+    ))
+}
+
+/**
+ * Read the lines through the rules of the configuration as a single connection would, measuring
+ * how long it takes from reading a line until its message reaches the sinks, which discard it
+ */
+async fn against_rules(
+    settings_file: &str,
+    overrides: Overrides,
+    options: &Options,
+) -> Result<Report, String> {
+    let settings = Arc::new(settings::try_load_blocking(settings_file, &overrides).await?);
+    let (sinks, sunk) = Sinks::null(&settings);
+    let reloadable = Arc::new(Reloadable::new(settings_file, overrides, settings, sinks));
+
+    /*
+     * The statistics of the connection are not what is measured, so they are discarded
+     */
+    let (stats, discarded) = bounded::<status::Statistic>(1024);
+    task::spawn(async move { while discarded.recv().await.is_ok() {} });
+
+    let measured = task::spawn(async move {
+        let mut latencies = vec![];
+
+        while let Ok(produce) = sunk.recv().await {
+            let messages = match produce {
+                Produce::Message(kmsg) => vec![kmsg],
+                Produce::Transaction(kmsgs) => kmsgs,
+            };
+            latencies.extend(
+                messages
+                    .iter()
+                    .filter_map(|kmsg| kmsg.received())
+                    .map(|received| received.elapsed().as_micros() as u64),
+            );
+        }
+        latencies
+    });
+
+    let connection = Connection::detached(reloadable.clone(), stats);
+
+    let (lines, generated) = bounded::<Vec<u8>>(1024);
+    let start = Instant::now();
+    task::spawn(generate(options.lines, options.rate, lines));
+
+    let reader = Box::pin(generated)
+        .map(Ok::<_, std::io::Error>)
+        .into_async_read();
+    connection
+        .read_logs(reader)
+        .await
+        .map_err(|e| format!("Failed to read the lines: {:?}", e))?;
+    let elapsed = start.elapsed();
+
+    /*
+     * Dropping the last references to the sinks closes them, which ends the measurement once
+     * every message in them has been received
+     */
+    drop(connection);
+    drop(reloadable);
+    let latencies = measured.await;
+
+    Ok(Report::new(
+        options.lines,
+        elapsed,
+        latencies,
+        Measured::Latency,
+    ))
+}
+
+/**
+ * Send the lines to the sender at the rate, until every line is sent or the reader is gone
+ */
+async fn generate(lines: u64, rate: u64, sender: Sender<Vec<u8>>) {
+    let start = Instant::now();
+
+    for sequence in 0..lines {
+        pace(start, sequence, rate).await;
+
+        if sender.send(line(sequence).into_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/**
+ * Wait until the line of the sequence is due at the rate, lines are never due later when the
+ * rate is zero
+ */
+pub async fn pace(start: Instant, sequence: u64, rate: u64) {
+    if rate == 0 {
+        return;
+    }
+
+    let due = start + Duration::from_secs_f64(sequence as f64 / rate as f64);
+    let now = Instant::now();
+
+    if due > now {
+        task::sleep(due - now).await;
+    }
+}
+
+/**
+ * Return the RFC5424 line of the sequence, whose message matches the rules of the example
+ * configuration
+ */
+fn line(sequence: u64) -> String {
+    format!(
+        "<134>1 {} bench.example.com hotdog-bench {} - - hello bench {}\n",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        std::process::id(),
+        sequence
+    )
+}
+
+/**
+ * Return the value at the percentile of the sorted values, or zero when there are none
+ */
+fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+
+    let index = (sorted.len() as f64 * percentile).ceil() as usize;
+    sorted[index.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_line() {
+        let line = line(3);
+        let msg = parse::parse_line(line.trim_end()).expect("Failed to parse the line");
+
+        assert_eq!(Some("hotdog-bench".to_string()), msg.appname);
+        assert_eq!("hello bench 3", msg.msg);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<u64> = (1..=100).collect();
+
+        assert_eq!(50, percentile(&sorted, 0.5));
+        assert_eq!(99, percentile(&sorted, 0.99));
+        assert_eq!(100, percentile(&sorted, 1.0));
+        assert_eq!(1, percentile(&sorted, 0.0));
+        assert_eq!(0, percentile(&[], 0.5));
+    }
+
+    #[async_std::test]
+    async fn test_bench_rules() {
+        let options = Options {
+            target: None,
+            lines: 10,
+            rate: 0,
+        };
+        let report = bench("test/configs/minimal.yml", Overrides::default(), &options)
+            .await
+            .expect("Failed to run the benchmark");
+
+        assert_eq!(10, report.lines);
+        assert_eq!(10, report.durations_us.len());
+        assert_eq!(Measured::Latency, report.measured);
+    }
+
+    #[test]
+    fn test_report_write_time() {
+        let report = Report::new(2, Duration::from_secs(1), vec![20, 10], Measured::WriteTime);
+
+        assert_eq!(
+            "Sent 2 lines in 1.000s (2 lines/s)\n\
+             Time to write the lines (2): p50=10us p90=20us p99=20us max=20us",
+            report.to_string()
+        );
+    }
+}
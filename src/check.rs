@@ -0,0 +1,95 @@
+/**
+ * The check module validates a configuration without starting hotdog, so that changes can be
+ * verified, e.g. in CI, before they are rolled out
+ */
+use crate::kafka;
+use crate::settings::{self, Destination, Overrides, TlsType};
+use async_std::sync::Arc;
+
+/**
+ * Check the configuration file, returning every error which was found.
+ *
+ * Loading the settings compiles the regular expressions and templates of the rules and validates
+ * their actions, after which the JMESPath expressions, the Kafka configurations, the static topic
+ * names, and the TLS files of every listener are checked
+ */
+pub fn check(file: &str, overrides: &Overrides) -> Vec<String> {
+    let settings = match settings::parse(file, overrides) {
+        Ok(settings) => Arc::new(settings),
+        Err(errors) => return errors,
+    };
+    let mut errors = vec![];
+
+    if !crate::connection::verify(settings.clone()) {
+        errors.push("The JMESPath expressions of the rules are invalid".to_string());
+    }
+
+    if let Err(e) = kafka::validate_conf(&settings.global.kafka.conf) {
+        errors.push(format!("Invalid `global.kafka.conf`: {}", e));
+    }
+
+    for (name, destination) in settings.global.destinations.iter() {
+        match destination {
+            Destination::Kafka(conf) => {
+                if let Err(e) = kafka::validate_conf(&conf.conf) {
+                    errors.push(format!(
+                        "Invalid `global.destinations.{}.conf`: {}",
+                        name, e
+                    ));
+                }
+            }
+        }
+    }
+
+    for topic in settings.static_topics() {
+        if kafka::sanitize_topic(&topic, "").as_ref() != Some(&topic) {
+            errors.push(format!("`{}` is not a valid Kafka topic name", topic));
+        }
+    }
+
+    for listen in settings.listeners() {
+        if let TlsType::CertAndKey { cert, key, ca } = &listen.tls {
+            for path in [Some(cert), Some(key), ca.as_ref()].iter().flatten() {
+                if !path.is_file() {
+                    errors.push(format!("The TLS file {} does not exist", path.display()));
+                }
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert!(check(
+            "test/configs/single-rule-with-merge.yml",
+            &Overrides::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_check_invalid_settings() {
+        assert_eq!(
+            1,
+            check(
+                "test/configs/single-rule-with-invalid-template.yml",
+                &Overrides::default()
+            )
+            .len()
+        );
+    }
+
+    #[test]
+    fn test_check_invalid_kafka() {
+        let errors = check("test/configs/invalid-kafka.yml", &Overrides::default());
+
+        assert_eq!(2, errors.len());
+        assert!(errors[0].contains("global.kafka.conf"));
+        assert!(errors[1].contains("logs/errors"));
+    }
+}
@@ -1,20 +1,44 @@
+use crate::aggregate::Aggregator;
+use crate::csv;
+use crate::decode;
 use crate::errors;
-use crate::kafka::KafkaMessage;
+use crate::exec::Executor;
+use crate::geoip::GeoIp;
+use crate::kafka::{self, KafkaMessage, Produce};
+use crate::kv;
 use crate::merge;
 use crate::parse;
+use crate::plugin::{self, Plugins};
+use crate::profile::{self, Stage};
+use crate::ratelimit::RateLimiter;
+use crate::reload::Reloadable;
 use crate::rules;
+use crate::sampled;
+use crate::schema::{SchemaError, Schemas};
+use crate::script::{self, Outcome};
+use crate::serialize::Serializers;
 use crate::settings::*;
-use crate::status::{Statistic, Stats};
+use crate::status::{self, Statistic, Stats};
+use crate::suppress::{Summary, Suppressor};
+use crate::templates;
+use crate::timestamp;
+use crate::trace::{self, Span};
 /**
  * The connection module is responsible for handling everything pertaining to a single inbound TCP
  * connection.
  */
-use async_channel::Sender;
+use async_channel::{bounded, Receiver, Sender};
 use async_std::{io::BufReader, prelude::*, sync::Arc, task};
+use bytes::Bytes;
 use chrono::prelude::*;
 use handlebars::Handlebars;
 use log::*;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 /**
  * RuleState exists to help carry state into merge/replacement functions and exists only during the
@@ -24,6 +48,93 @@ struct RuleState<'a> {
     variables: &'a HashMap<String, String>,
     hb: &'a handlebars::Handlebars<'a>,
     stats: Sender<Statistic>,
+    /**
+     * The rule whose templates are rendered, for profiling the pipeline
+     */
+    rule: Option<&'a Rule>,
+}
+
+impl RuleState<'_> {
+    /**
+     * Render the template with the variables, recording how long the rendering took
+     */
+    fn render(&self, template_id: &str) -> Result<String, handlebars::RenderError> {
+        let start = Instant::now();
+        let profiling = profile::start();
+        let rendered = self.hb.render(template_id, self.variables);
+        profile::finish(profiling, Stage::Render, self.rule);
+        let _ = self
+            .stats
+            .try_send((Stats::TemplateRendered, status::elapsed_us(start)));
+        rendered
+    }
+}
+
+/**
+ * How often the lines and bytes counted for the peer of a connection are reported
+ */
+const PEER_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * PeerCounts counts the lines and bytes read from the peer of a connection, and only reports them
+ * for the per-peer metrics once a second, rather than sending a statistic for every line
+ */
+struct PeerCounts<'a> {
+    stats: &'a Sender<Statistic>,
+    peer: &'a str,
+    lines: i64,
+    bytes: i64,
+    reported: Instant,
+}
+
+impl<'a> PeerCounts<'a> {
+    fn new(stats: &'a Sender<Statistic>, peer: &'a str) -> Self {
+        PeerCounts {
+            stats,
+            peer,
+            lines: 0,
+            bytes: 0,
+            reported: Instant::now(),
+        }
+    }
+
+    fn received(&mut self, bytes: usize) {
+        self.bytes += bytes as i64;
+    }
+
+    fn line(&mut self) {
+        self.lines += 1;
+
+        if self.reported.elapsed() >= PEER_REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        self.reported = Instant::now();
+
+        if self.lines > 0 {
+            let peer = self.peer.to_string();
+            let _ = self
+                .stats
+                .try_send((Stats::PeerLines { peer }, std::mem::take(&mut self.lines)));
+        }
+        if self.bytes > 0 {
+            let peer = self.peer.to_string();
+            let _ = self
+                .stats
+                .try_send((Stats::PeerBytes { peer }, std::mem::take(&mut self.bytes)));
+        }
+    }
+}
+
+/**
+ * The counts which have not been reported yet are reported however the connection ends
+ */
+impl Drop for PeerCounts<'_> {
+    fn drop(&mut self) {
+        self.report();
+    }
 }
 
 /**
@@ -35,76 +146,501 @@ pub struct Connection {
     /**
      * A reference to the global Settings object for all configuration information
      */
-    settings: Arc<Settings>,
+    settings: Arc<Reloadable>,
+    stats: Sender<Statistic>,
     /**
-     * The sender-side of the channel to our Kafka connection, allowing the logs read in to be
-     * sent over to the Kafka handler
+     * The shared serializers for encoding output before it is forwarded
      */
-    sender: Sender<KafkaMessage>,
-    stats: Sender<Statistic>,
+    serializers: Arc<Serializers>,
+    /**
+     * The shared token buckets for the rate_limit actions
+     */
+    rate_limiter: Arc<RateLimiter>,
+    /**
+     * The shared GeoIP databases for the geoip actions
+     */
+    geoip: Arc<GeoIp>,
+    /**
+     * The shared WebAssembly modules for the plugin actions
+     */
+    plugins: Arc<Plugins>,
+    /**
+     * The shared slots of running commands for the exec actions
+     */
+    executor: Arc<Executor>,
+    /**
+     * The shared windows for the dedupe actions
+     */
+    suppressor: Arc<Suppressor>,
+    /**
+     * The shared rollups for the aggregate actions
+     */
+    aggregator: Arc<Aggregator>,
+    /**
+     * The shared compiled JSON Schemas for the validate actions
+     */
+    schemas: Arc<Schemas>,
+    /**
+     * The Lua interpreter for the script actions of this connection
+     */
+    scripts: script::Engine,
+    /**
+     * The address of the sender on the other end of this connection, if known
+     */
+    peer: Option<SocketAddr>,
+    /**
+     * The IP address of the sender without its port, which the per-peer metrics are reported for
+     * so that every connection of a sender is counted together
+     */
+    peer_host: String,
+    /**
+     * The ruleset of the listener which accepted this connection, only its rules are evaluated
+     */
+    ruleset: Option<String>,
+    /**
+     * The statistics of this connection, for the runtime statistics report
+     */
+    tracked: status::Tracked,
+    /**
+     * The bytes of the messages this connection has queued for its dispatchers which have not yet
+     * been handed to the sendloops
+     */
+    inflight: Arc<AtomicUsize>,
+    /**
+     * Whether the rules are only being tried out, in which case the exec actions log the commands
+     * they would run rather than running them, and the messages record the rule which produced
+     * them
+     */
+    dry: bool,
+    /**
+     * The channel which every message forwarded by this connection holds on to, so that it is
+     * closed once all of them have settled
+     */
+    settled: Option<Sender<()>>,
 }
 
 impl Connection {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        settings: Arc<Settings>,
-        sender: Sender<KafkaMessage>,
+        settings: Arc<Reloadable>,
         stats: Sender<Statistic>,
+        serializers: Arc<Serializers>,
+        rate_limiter: Arc<RateLimiter>,
+        geoip: Arc<GeoIp>,
+        plugins: Arc<Plugins>,
+        executor: Arc<Executor>,
+        suppressor: Arc<Suppressor>,
+        aggregator: Arc<Aggregator>,
+        schemas: Arc<Schemas>,
+        peer: Option<SocketAddr>,
+        ruleset: Option<String>,
+        tracked: status::Tracked,
     ) -> Self {
         Connection {
             settings,
-            sender,
             stats,
+            serializers,
+            rate_limiter,
+            geoip,
+            plugins,
+            executor,
+            suppressor,
+            aggregator,
+            schemas,
+            scripts: script::Engine::new(),
+            peer,
+            peer_host: peer
+                .map(|peer| peer.ip().to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            ruleset,
+            tracked,
+            inflight: Arc::new(AtomicUsize::new(0)),
+            dry: false,
+            settled: None,
+        }
+    }
+
+    /**
+     * Only try out the rules with this connection, see `dry`
+     */
+    pub fn dry(mut self) -> Self {
+        self.dry = true;
+        self
+    }
+
+    /**
+     * Have every message forwarded by this connection hold on to the sender, which is dropped
+     * along with the connection, so that its channel is closed once every message has settled
+     */
+    pub fn with_settled(mut self, settled: Sender<()>) -> Self {
+        self.settled = Some(settled);
+        self
+    }
+
+    /**
+     * Return the rule to record in the messages it produces, only while trying out the rules
+     */
+    fn rule_name(&self, rule: &Rule) -> Option<String> {
+        if self.dry {
+            Some(rule.to_string())
+        } else {
+            None
         }
     }
 
+    /**
+     * Create a connection without a peer or a listener, with shared state of its own, for reading
+     * lines through the rules outside of the listeners, such as when benchmarking or testing
+     */
+    pub fn detached(settings: Arc<Reloadable>, stats: Sender<Statistic>) -> Self {
+        Connection::new(
+            settings,
+            stats,
+            Arc::new(Serializers::new()),
+            Arc::new(RateLimiter::new()),
+            Arc::new(GeoIp::new()),
+            Arc::new(Plugins::new()),
+            Arc::new(Executor::new()),
+            Arc::new(Suppressor::new()),
+            Arc::new(Aggregator::new()),
+            Arc::new(Schemas::new()),
+            None,
+            None,
+            Arc::new(status::Connections::default()).open(None),
+        )
+    }
+
     /**
      * connection_loop is responsible for handling incoming syslog streams connections
      *
+     * The lines are read and parsed by this task, while the messages forwarded for them are sent
+     * to the sendloops by the dispatchers of the connection, so that a slow broker does not hold up
+     * reading from the socket until the `dispatch_queue` is full
      */
     pub async fn read_logs<R: async_std::io::Read + std::marker::Unpin>(
         &self,
-        reader: BufReader<R>,
+        reader: R,
     ) -> Result<(), errors::HotdogError> {
-        let mut lines = reader.lines();
+        let settings = self.settings.current();
+        let limits = &settings.global.limits;
+        let (dispatches, queued) = bounded(limits.dispatch_queue);
+        let dispatcher = Dispatcher {
+            settings: self.settings.clone(),
+            stats: self.stats.clone(),
+            inflight: self.inflight.clone(),
+            settled: self.settled.clone(),
+        };
+        /*
+         * Concurrent dispatchers could send the messages of later lines first
+         */
+        let dispatchers = if settings.global.kafka.preserve_order {
+            1
+        } else {
+            limits.dispatchers
+        };
+        let dispatchers: Vec<task::JoinHandle<()>> = (0..dispatchers)
+            .map(|_| task::spawn(dispatcher.clone().run(queued.clone())))
+            .collect();
 
-        let mut hb = Handlebars::new();
-        let mut jmespaths = JmesPathExpressions::new();
+        let read = self.read_lines(reader, dispatches).await;
 
-        if !precompile_templates(&mut hb, self.settings.clone()) {
-            error!("Failing to precompile templates is a fatal error, not going to parse logs since the configuration is broken");
-            // TODO fix the Err types
-            return Ok(());
+        /*
+         * The connection is only finished once the messages of every line it read have been sent
+         */
+        for dispatcher in dispatchers.into_iter() {
+            dispatcher.await;
         }
+        read
+    }
+
+    async fn read_lines<R: async_std::io::Read + std::marker::Unpin>(
+        &self,
+        reader: R,
+        dispatches: Sender<Dispatch>,
+    ) -> Result<(), errors::HotdogError> {
+        let mut jmespaths = JmesPathExpressions::new();
+
+        /*
+         * The settings are checked for every line, so that reloaded rules are used without the
+         * connection needing to be re-established
+         */
+        let mut settings = self.settings.current();
 
-        if !precompile_jmespath(&mut jmespaths, self.settings.clone()) {
+        if !precompile_jmespath(&mut jmespaths, settings.clone()) {
             error!("Failing to precompile jmespaths is a fata error, not parsing this connection's logs because the configuration is broken");
             // TODO fix the Err types
             return Ok(());
         }
+        let mut reader = BufReader::with_capacity(
+            settings.global.limits.buffer_bytes,
+            Counted { reader, bytes: 0 },
+        );
+        let mut peer_counts = PeerCounts::new(&self.stats, &self.peer_host);
 
-        while let Some(line) = lines.next().await {
-            let line = line?;
+        loop {
+            self.backpressure(&settings, &dispatches).await;
+            let max_line_bytes = settings.global.limits.max_line_bytes;
+
+            /*
+             * A shutdown only waits for the lines which have already been read, so the connection
+             * stops reading as soon as hotdog is shutting down, even while the sender is still
+             * sending
+             */
+            let read = match smol::future::or(
+                async {
+                    self.tracked.closing().await;
+                    None
+                },
+                async { Some(read_line(&mut reader, max_line_bytes).await) },
+            )
+            .await
+            {
+                Some(read) => read?,
+                None => {
+                    debug!(
+                        "Closing the connection from {:?} for the shutdown",
+                        self.peer
+                    );
+                    break;
+                }
+            };
+
+            let bytes = std::mem::take(&mut reader.get_mut().bytes);
+            if bytes > 0 {
+                self.tracked.received(bytes);
+                peer_counts.received(bytes);
+            }
+
+            let line = match read {
+                Line::Complete(line) => Ok(line),
+                Line::Invalid(bytes) => Err(bytes),
+                Line::TooLong(head)
+                    if settings.global.limits.long_lines == LongLinePolicy::Truncate =>
+                {
+                    self.stats.send((Stats::LineTruncated, 1)).await;
+                    truncate_line(
+                        head,
+                        settings.global.limits.max_line_bytes,
+                        &settings.global.limits.truncation_marker,
+                    )
+                    .map_err(std::string::FromUtf8Error::into_bytes)
+                }
+                Line::TooLong(_) => {
+                    warn!(
+                        peer = self.peer_addr(), error = "line_too_long";
+                        "Discarding a line from {:?} which is longer than {} bytes",
+                        self.peer, settings.global.limits.max_line_bytes
+                    );
+                    self.stats.send((Stats::LineTooLong, 1)).await;
+                    self.stats.send(status::dropped("too_long")).await;
+                    continue;
+                }
+                Line::Partial(_)
+                    if settings.global.limits.partial_lines == PartialLinePolicy::Drop =>
+                {
+                    warn!(
+                        peer = self.peer_addr(), error = "partial_line";
+                        "Discarding a line from {:?} which the connection did not end",
+                        self.peer
+                    );
+                    self.stats.send((Stats::LinePartial, 1)).await;
+                    self.stats.send(status::dropped("partial")).await;
+                    continue;
+                }
+                Line::Partial(bytes) => {
+                    self.stats.send((Stats::LinePartial, 1)).await;
+                    String::from_utf8(bytes).map_err(std::string::FromUtf8Error::into_bytes)
+                }
+                Line::Eof => break,
+            };
+            /*
+             * A line which is not valid UTF-8 is decoded with replacement characters, and the
+             * `invalid_utf8` policy decides whether it is parsed, handled by the parse error
+             * policy, or passed through as it was received, rather than closing the connection
+             * along with every line the sender sends after it
+             */
+            let mut invalid = None;
+            let line = match line {
+                Ok(line) => line,
+                Err(bytes) => {
+                    self.stats.send((Stats::LineInvalidUtf8, 1)).await;
+                    let decoded = String::from_utf8_lossy(&bytes).into_owned();
+                    invalid = Some(bytes);
+                    decoded
+                }
+            };
             debug!("log: {}", line);
+            self.tracked.line();
+            peer_counts.line();
+            let received = Instant::now();
+            /*
+             * Every line is a trace of its own, which the spans of its stages are part of
+             */
+            let span = self.root_span("line");
 
-            let parsed = parse::parse_line(line);
+            let current = self.settings.current();
+            if !Arc::ptr_eq(&current, &settings) {
+                let mut reloaded_jmespaths = JmesPathExpressions::new();
 
-            if let Err(e) = &parsed {
-                self.stats.send((Stats::LogParseError, 1)).await;
-                error!("failed to parse message: {:?}", e);
-                continue;
+                if precompile_jmespath(&mut reloaded_jmespaths, current.clone()) {
+                    debug!("Switching to the reloaded rules");
+                    jmespaths = reloaded_jmespaths;
+                } else {
+                    error!("Failed to precompile the reloaded rules, continuing with the previous rules");
+                }
+                /*
+                 * Reloaded rules which fail to precompile are only attempted once
+                 */
+                settings = current;
             }
             /*
-             * Now that we've logged the error, let's unpack and bubble the error anyways
-             *
+             * The templates of the rules were registered when the settings were loaded
+             */
+            let hb: &Handlebars = &settings.templates;
+
+            if settings.global.parse_errors.invalid_utf8 == InvalidUtf8Policy::Raw {
+                if let Some(bytes) = invalid.take() {
+                    let kmsg = KafkaMessage::new(settings.global.kafka.topic.clone(), bytes);
+                    if !self
+                        .dispatch(
+                            &dispatches,
+                            &settings,
+                            vec![(None, kmsg)],
+                            &span.trace(),
+                            received,
+                        )
+                        .await
+                    {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            }
+
+            /*
              * Note: msg needs to be mutable so we can fish the `msg` out within it during a
              * simd_json parse
              */
-            let mut msg = parsed.unwrap();
-            self.stats.send((Stats::LineReceived, 1)).await;
+            let parsing = Span::start("parse", &span.trace());
+            let parse_start = Instant::now();
+            let profiling = profile::start();
+            let parsed = match invalid {
+                Some(_)
+                    if settings.global.parse_errors.invalid_utf8 != InvalidUtf8Policy::Lossy =>
+                {
+                    Err(parse::SyslogErrors::InvalidEncoding)
+                }
+                _ => parse::parse_line(&line),
+            };
+            profile::finish(profiling, Stage::Parse, None);
+            self.stats
+                .send((Stats::LineParsed, status::elapsed_us(parse_start)))
+                .await;
+
+            if let Err(e) = &parsed {
+                parsing.fail(format!("{:?}", e));
+            }
+            drop(parsing);
+
+            let mut msg = match parsed {
+                Ok(msg) => msg,
+                Err(e) => {
+                    self.stats.send((Stats::LogParseError, 1)).await;
+                    sampled::LOG_PARSE.error(format_args!(
+                        "Failed to parse the message ({:?}): {}",
+                        e, line
+                    ));
+
+                    match settings.global.parse_errors.policy {
+                        ParseErrorPolicy::Drop => {
+                            self.stats.send(status::dropped("parse_error")).await;
+                            continue;
+                        }
+                        ParseErrorPolicy::Dlq => {
+                            /*
+                             * The settings are only valid with the dlq policy when the dead
+                             * letter topic is configured
+                             */
+                            if let Some(dlq) = &settings.global.kafka.dlq {
+                                let bytes = invalid.take().unwrap_or_else(|| line.into_bytes());
+                                let kmsg = KafkaMessage::new(dlq.to_string(), bytes);
+                                if !self
+                                    .dispatch(
+                                        &dispatches,
+                                        &settings,
+                                        vec![(None, kmsg)],
+                                        &span.trace(),
+                                        received,
+                                    )
+                                    .await
+                                {
+                                    return Ok(());
+                                }
+                            }
+                            continue;
+                        }
+                        ParseErrorPolicy::Raw => parse::SyslogMessage {
+                            msg: line,
+                            ..Default::default()
+                        },
+                        ParseErrorPolicy::Disconnect => {
+                            self.stats.send(status::dropped("parse_error")).await;
+                            warn!(
+                                peer = self.peer_addr(), error = "log_parse";
+                                "Closing the connection from {:?} after a line which could not be parsed",
+                                self.peer
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+            self.tracked.line_received();
+            /*
+             * The time the line was received at, when the timestamp of the message is too skewed
+             * from it and the timestamp is to be annotated rather than corrected
+             */
+            let now = Utc::now();
+            let mut received_at = None;
+            if let (Some(timestamps), Some(timestamp)) =
+                (&settings.global.timestamps, msg.timestamp)
+            {
+                if let Some(sanitized) = timestamp::sanitize(timestamp, now, timestamps) {
+                    self.stats.send((Stats::LineTimestampSkewed, 1)).await;
+                    msg.timestamp = Some(sanitized);
+                    if timestamps.skewed == SkewedTimestampPolicy::Annotate {
+                        received_at = Some(now);
+                    }
+                }
+            }
             let mut continue_rules = true;
+            /*
+             * Messages which have been forwarded by the rules for this line, along with the name
+             * of their destination if it is not the default Kafka, these are only sent along once
+             * all the rules have been processed
+             */
+            let mut pending: Vec<(Option<String>, KafkaMessage)> = vec![];
             debug!("parsed as: {}", msg.msg);
+            let candidates = settings
+                .prefilter
+                .as_ref()
+                .map(|prefilter| prefilter.candidates(&msg.msg));
+            /*
+             * Whether any rule other than the default rule has matched this line
+             */
+            let mut matched = false;
+            let evaluating = Span::start("rules", &span.trace());
+            /*
+             * The variables of the line are only built once, and are copied for the rules which
+             * match it along with their captures
+             */
+            let mut variables = self.variables(&settings, &msg);
+            if let Some(received_at) = &received_at {
+                variables.insert("received_at".to_string(), received_at.to_rfc3339());
+            }
+            let mut captures = HashMap::new();
 
-            for rule in self.settings.rules.iter() {
+            for (rule_index, rule) in settings.all_rules().enumerate() {
                 /*
                  * If we have been told to stop processing rules, then it's time to bail on this log
                  * message
@@ -113,44 +649,38 @@ impl Connection {
                     break;
                 }
 
-                // The output buffer that we will ultimately send along to the Kafka service
-                let mut output = String::new();
-                let mut rule_matches = false;
-                let mut hash = HashMap::new();
-                hash.insert("msg".to_string(), String::from(&msg.msg));
-                hash.insert("version".to_string(), env!["CARGO_PKG_VERSION"].to_string());
-                hash.insert("iso8601".to_string(), Utc::now().to_rfc3339());
-
-                match rule.field {
-                    Field::Msg => {
-                        rule_matches = rules::apply_rule(&rule, &msg.msg, &jmespaths, &mut hash);
-                    }
-                    Field::Appname => {
-                        if let Some(appname) = &msg.appname {
-                            rule_matches =
-                                rules::apply_rule(&rule, &appname, &jmespaths, &mut hash);
-                        }
-                    }
-                    Field::Hostname => {
-                        if let Some(hostname) = &msg.hostname {
-                            rule_matches =
-                                rules::apply_rule(&rule, &hostname, &jmespaths, &mut hash);
-                        }
-                    }
-                    Field::Severity => {
-                        if let Some(severity) = &msg.severity {
-                            rule_matches =
-                                rules::apply_rule(&rule, &severity, &jmespaths, &mut hash);
-                        }
-                    }
-                    Field::Facility => {
-                        if let Some(facility) = &msg.facility {
-                            rule_matches =
-                                rules::apply_rule(&rule, &facility, &jmespaths, &mut hash);
-                        }
+                /*
+                 * The default rule is always last, and only handles lines no other rule matched,
+                 * whichever ruleset the listener serves
+                 */
+                let is_default = rule_index == settings.rules.len();
+
+                if !is_default && rule.ruleset != self.ruleset {
+                    continue;
+                }
+
+                if let Some(candidates) = &candidates {
+                    if candidates.get(rule_index) == Some(&false) {
+                        continue;
                     }
                 }
 
+                captures.clear();
+                let rule_matches = if is_default {
+                    !matched
+                } else {
+                    let profiling = profile::start();
+                    let evaluated = rules::evaluate_captures(
+                        &rule.condition,
+                        &msg,
+                        &jmespaths,
+                        &variables,
+                        &mut captures,
+                    );
+                    profile::finish(profiling, Stage::Match, Some(rule));
+                    evaluated
+                };
+
                 /*
                  * This specific didn't match, so onto the next one
                  */
@@ -158,17 +688,59 @@ impl Connection {
                     continue;
                 }
 
-                let rule_state = RuleState {
-                    hb: &hb,
-                    variables: &hash,
-                    stats: self.stats.clone(),
+                if !is_default {
+                    matched = true;
+
+                    if settings.global.rules.mode == RulesMode::FirstMatch {
+                        continue_rules = false;
+                    }
+                }
+
+                // The output buffer that we will ultimately send along to the Kafka service
+                let mut output = String::new();
+                /*
+                 * The bytes of the output when it was last forwarded, which are shared by the
+                 * messages of every forward action until the output changes
+                 */
+                let mut forwarded: Option<Bytes> = None;
+                let mut hash = variables.clone();
+                hash.extend(captures.drain());
+
+                self.stats
+                    .send((
+                        Stats::RuleMatched {
+                            rule: rule.name.clone(),
+                        },
+                        1,
+                    ))
+                    .await;
+                /*
+                 * The span of the rule covers its actions, and ends with this iteration
+                 */
+                let rule_span = Span::start("rule", &evaluating.trace());
+                rule_span.set("rule", rule.to_string());
+
+                /*
+                 * The record key set by a SetKey action, which applies to the subsequent Forward
+                 * actions of this rule
+                 */
+                let mut record_key: Option<String> = None;
+
+                /*
+                 * The input is kept for debug samples since actions may modify the message
+                 */
+                let sample_input = if rule.debug_sampled() {
+                    Some(msg.msg.clone())
+                } else {
+                    None
                 };
+                let sampled_from = pending.len();
 
                 /*
                  * Process the actions one the rule has matched
                  */
                 for index in 0..rule.actions.len() {
-                    let action = &rule.actions[index];
+                    let action = &rule.actions[index].action;
                     /*
                      * @stjepang says this will fix slow future polling
                      *
@@ -179,8 +751,49 @@ impl Connection {
                      */
                     task::yield_now().await;
 
+                    /*
+                     * The limits are checked before every action, so that no more work is done
+                     * on a line which the previous actions have grown past them
+                     */
+                    /*
+                     * Only the variables the rule captured or set count towards the limits, not
+                     * those which every line has, such as `global.vars`
+                     */
+                    let captured = hash.len().saturating_sub(variables.len());
+                    if let Some(exceeded) = settings.global.limits.exceeded(&output, captured) {
+                        warn!(
+                            peer = self.peer_addr(), rule = rule.to_string(), error = "over_limit";
+                            "Stopping the processing of a line matched by the rule `{}`, {}",
+                            rule, exceeded
+                        );
+                        self.stats.send((Stats::LineOverLimit, 1)).await;
+                        /*
+                         * The messages which the earlier actions forwarded are still sent
+                         */
+                        if pending.is_empty() {
+                            self.stats.send(status::dropped("over_limit")).await;
+                        }
+                        continue_rules = false;
+                        break;
+                    }
+
+                    /*
+                     * Actions with a `when` condition are skipped unless it matches, conditions
+                     * on variables see the changes made by the previous actions
+                     */
+                    if let Some(when) = &rule.actions[index].when {
+                        if !rules::evaluate(when, &msg, &jmespaths, &mut hash) {
+                            continue;
+                        }
+                    }
+
                     match action {
-                        Action::Forward { topic } => {
+                        Action::Forward {
+                            topic,
+                            serializer,
+                            payload,
+                            to,
+                        } => {
                             /*
                              * If a custom output was never defined, just take the
                              * raw message and pass that along.
@@ -189,35 +802,126 @@ impl Connection {
                                 output = String::from(&msg.msg);
                             }
 
-                            if let Ok(actual_topic) = hb.render_template(&topic, &hash) {
+                            /*
+                             * A skewed timestamp which is kept is always accompanied by the time
+                             * the line was received at, unless a timestamp action already added
+                             * it in its own format
+                             */
+                            if let Some(received_at) = &received_at {
+                                if let Some(annotated) = annotate_received(&output, received_at) {
+                                    output = annotated;
+                                }
+                            }
+
+                            /*
+                             * The payload template, when present, overrides whatever output has
+                             * been generated by the previous actions
+                             */
+                            let body = if payload.is_some() {
+                                match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => Some(rendered),
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the payload template: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        self.stats.send(status::dropped("render_failed")).await;
+                                        break;
+                                    }
+                                }
+                            } else {
+                                None
+                            };
+
+                            let topic_id =
+                                templates::part_id_for(&templates::id_for(&rule, index), "topic");
+
+                            if let Ok(rendered) = self.render(hb, rule, &topic_id, &hash).await {
+                                let rendered =
+                                    settings.global.kafka.resolve_topic(&rendered).to_string();
+                                let actual_topic = match kafka::sanitize_topic(
+                                    &rendered,
+                                    &settings.global.kafka.topic_replacement,
+                                ) {
+                                    Some(sanitized) => {
+                                        if sanitized != rendered {
+                                            self.stats.send((Stats::TopicSanitized, 1)).await;
+                                        }
+                                        sanitized
+                                    }
+                                    None => {
+                                        warn!(
+                                            rule = rule.to_string(), topic = rendered.as_str();
+                                            "The rendered topic `{}` is not a valid topic name, using `{}` instead",
+                                            rendered, settings.global.kafka.topic
+                                        );
+                                        self.stats.send((Stats::TopicFallback, 1)).await;
+                                        settings.global.kafka.topic.clone()
+                                    }
+                                };
                                 debug!("Enqueueing for topic: `{}`", actual_topic);
-                                /*
-                                 * `output` is consumed by send_to_kafka, so the rest of the rules
-                                 * should be skipped.
-                                 */
-                                let kmsg = KafkaMessage::new(actual_topic, output);
-                                self.sender.send(kmsg).await;
-                                /*
-                                 * Ensure that we're allowing other tasks to execute when we pass
-                                 * things off to the channel
-                                 *
-                                 * See also https://github.com/stjepang/smol/issues/159
-                                 */
-                                task::yield_now().await;
+                                let payload = match serializer {
+                                    Some(serializer) => {
+                                        match self
+                                            .serializers
+                                            .serialize(
+                                                serializer,
+                                                body.as_deref().unwrap_or(&output),
+                                                &hash,
+                                            )
+                                            .await
+                                        {
+                                            Ok(payload) => Bytes::from(payload),
+                                            Err(e) => {
+                                                error!(
+                                                    rule = rule.to_string(),
+                                                    topic = actual_topic.as_str(),
+                                                    error = "serialization_failed";
+                                                    "Failed to serialize the output: {:?}", e
+                                                );
+                                                self.stats
+                                                    .send((Stats::SerializationFailed, 1))
+                                                    .await;
+                                                self.stats
+                                                    .send(status::dropped("serialization_failed"))
+                                                    .await;
+                                                break;
+                                            }
+                                        }
+                                    }
+                                    None => match body {
+                                        Some(body) => Bytes::from(body),
+                                        None => shared_output(&mut forwarded, &output),
+                                    },
+                                };
+                                pending.push((
+                                    to.clone(),
+                                    KafkaMessage::new(actual_topic, payload)
+                                        .with_key(record_key.clone())
+                                        .with_rule(self.rule_name(rule)),
+                                ));
                                 continue_rules = false;
                             } else {
-                                error!("Failed to process the configured topic: `{}`", topic);
+                                error!(
+                                    rule = rule.to_string(), error = "topic_parse_failed";
+                                    "Failed to process the configured topic: `{}`", topic
+                                );
                                 self.stats.send((Stats::TopicParseFailed, 1)).await;
+                                self.stats.send(status::dropped("render_failed")).await;
+                                break;
                             }
-                            break;
                         }
 
                         Action::Merge { json, json_str: _ } => {
                             debug!("merging JSON content: {}", json);
                             if let Ok(buffer) = perform_merge(
                                 &mut msg.msg,
-                                &template_id_for(&rule, index),
-                                &rule_state,
+                                &templates::id_for(&rule, index),
+                                &self.rule_state(hb, rule, &hash),
                             ) {
                                 output = buffer;
                             } else {
@@ -225,73 +929,1456 @@ impl Connection {
                             }
                         }
 
-                        Action::Replace { template } => {
-                            let template_id = template_id_for(&rule, index);
+                        Action::Replace { template } => {
+                            let template_id = templates::id_for(&rule, index);
+
+                            debug!(
+                                "replacing content with template: {} ({})",
+                                template, template_id
+                            );
+                            if let Ok(rendered) = self.render(hb, rule, &template_id, &hash).await {
+                                output = rendered;
+                            }
+                        }
+
+                        Action::AddFields {
+                            fields,
+                            message_field,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            output = perform_add_fields(
+                                buffer,
+                                fields,
+                                message_field,
+                                &templates::id_for(&rule, index),
+                                &self.rule_state(hb, rule, &hash),
+                            )
+                            .await;
+                        }
+
+                        Action::Geoip {
+                            database,
+                            ip,
+                            pointer,
+                            field,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            let json: Option<serde_json::Value> = serde_json::from_str(buffer).ok();
+
+                            let address = match (ip, pointer) {
+                                (Some(_), _) => self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                    .ok(),
+                                (None, Some(pointer)) => json
+                                    .as_ref()
+                                    .and_then(|json| json.pointer(pointer))
+                                    .and_then(|value| value.as_str())
+                                    .map(|value| value.to_string()),
+                                (None, None) => None,
+                            };
+
+                            if let Some(address) = address {
+                                match self.geoip.lookup(database, &address) {
+                                    Ok(location) => {
+                                        let fields = location.fields();
+
+                                        for (name, value) in fields.iter() {
+                                            hash.insert(
+                                                format!("{}_{}", field, name),
+                                                variable_for(value),
+                                            );
+                                        }
+
+                                        if let Some(serde_json::Value::Object(mut object)) = json {
+                                            let location = fields
+                                                .into_iter()
+                                                .map(|(name, value)| (name.to_string(), value))
+                                                .collect();
+                                            object.insert(
+                                                field.to_string(),
+                                                serde_json::Value::Object(location),
+                                            );
+                                            output = serde_json::Value::Object(object).to_string();
+                                        }
+                                    }
+                                    Err(e) => {
+                                        debug!("Failed to look up `{}`: {:?}", address, e);
+                                        self.stats.send((Stats::GeoIpLookupFailed, 1)).await;
+                                    }
+                                }
+                            }
+                        }
+
+                        Action::Kv {
+                            source,
+                            separator,
+                            delimiter,
+                            quotes,
+                            field,
+                            message_field,
+                        } => {
+                            let input = match source {
+                                Some(_) => match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the kv source: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                },
+                                None => msg.msg.clone(),
+                            };
+                            let pairs = kv::parse(&input, separator, delimiter, quotes);
+
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            output = perform_extract(
+                                buffer,
+                                pairs,
+                                field.as_deref(),
+                                message_field,
+                                &mut hash,
+                            );
+                        }
+
+                        Action::Enrich {
+                            table: _,
+                            key: _,
+                            field,
+                            message_field,
+                            entries,
+                        } => {
+                            let key = match self
+                                .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                .await
+                            {
+                                Ok(rendered) => rendered,
+                                Err(e) => {
+                                    sampled::TEMPLATE_RENDER.error(format_args!(
+                                        "Failed to render the enrich key: {}",
+                                        e
+                                    ));
+                                    self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                    break;
+                                }
+                            };
+
+                            match entries.get(&key) {
+                                Some(fields) => {
+                                    let pairs = fields
+                                        .iter()
+                                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                                        .collect();
+
+                                    let buffer = if output.is_empty() { &msg.msg } else { &output };
+                                    output = perform_extract(
+                                        buffer,
+                                        pairs,
+                                        field.as_deref(),
+                                        message_field,
+                                        &mut hash,
+                                    );
+                                }
+                                None => {
+                                    debug!("No entry in the lookup table for `{}`", key);
+                                    self.stats.send((Stats::LineNotEnriched, 1)).await;
+                                }
+                            }
+                        }
+
+                        Action::Csv {
+                            columns,
+                            source,
+                            delimiter,
+                            quote,
+                            field,
+                            message_field,
+                        } => {
+                            let input = match source {
+                                Some(_) => match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the csv source: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                },
+                                None => msg.msg.clone(),
+                            };
+                            /*
+                             * Values without a column, and columns without a value, are ignored
+                             */
+                            let pairs = columns
+                                .iter()
+                                .zip(csv::split(&input, delimiter, *quote))
+                                .filter(|(column, _)| column.as_str() != "_")
+                                .map(|(column, value)| (column.to_string(), value))
+                                .collect();
+
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            output = perform_extract(
+                                buffer,
+                                pairs,
+                                field.as_deref(),
+                                message_field,
+                                &mut hash,
+                            );
+                        }
+
+                        Action::Decode {
+                            encoding,
+                            variable,
+                            pointer,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+
+                            let result = match (variable, pointer) {
+                                (Some(variable), _) => match hash.get(variable) {
+                                    Some(value) => decode::decode(encoding, value).map(|decoded| {
+                                        hash.insert(variable.to_string(), decoded);
+                                    }),
+                                    None => Ok(()),
+                                },
+                                (None, Some(pointer)) => decode::decode_pointer(
+                                    encoding, buffer, pointer,
+                                )
+                                .map(|updated| {
+                                    if let Some(updated) = updated {
+                                        output = updated;
+                                    }
+                                }),
+                                (None, None) => decode::decode(encoding, buffer).map(|decoded| {
+                                    output = decoded;
+                                }),
+                            };
+
+                            if let Err(e) = result {
+                                debug!("Failed to decode the value: {:?}", e);
+                                self.stats.send((Stats::DecodeFailed, 1)).await;
+                            }
+                        }
+
+                        Action::Validate {
+                            schema,
+                            invalid_topic,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+
+                            let reasons = match self.schemas.validate(schema, buffer) {
+                                Ok(reasons) => reasons,
+                                Err(SchemaError::NotJson) => vec!["not JSON".to_string()],
+                                Err(e) => {
+                                    error!(
+                                        rule = rule.to_string(), error = "schema";
+                                        "Failed to validate the output: {:?}", e
+                                    );
+                                    self.stats.send((Stats::SchemaFailed, 1)).await;
+                                    continue;
+                                }
+                            };
+
+                            if !reasons.is_empty() {
+                                debug!("The output is invalid: {}", reasons.join(", "));
+                                /*
+                                 * Invalid documents are handled like a drop, except that they
+                                 * may be sent along to the invalid topic for inspection
+                                 */
+                                pending.clear();
+                                if let Some(topic) = invalid_topic {
+                                    let topic = settings.global.kafka.resolve_topic(topic);
+                                    pending.push((
+                                        None,
+                                        KafkaMessage::new(
+                                            topic.to_string(),
+                                            buffer.as_bytes().to_vec(),
+                                        )
+                                        .with_rule(self.rule_name(rule)),
+                                    ));
+                                }
+                                continue_rules = false;
+                                self.stats.send((Stats::LineInvalid, 1)).await;
+                                break;
+                            }
+                        }
+
+                        Action::Timestamp {
+                            source,
+                            formats,
+                            output: format,
+                            field,
+                        } => {
+                            let mut parsed = match source {
+                                Some(_) => self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                    .ok()
+                                    .and_then(|value| timestamp::parse(&value, formats)),
+                                None => msg.timestamp,
+                            };
+
+                            /*
+                             * The timestamp of the message was sanitized when it was parsed, one
+                             * from a source is sanitized here
+                             */
+                            if let (Some(timestamps), Some(_), Some(timestamp)) =
+                                (&settings.global.timestamps, source, parsed)
+                            {
+                                if let Some(sanitized) =
+                                    timestamp::sanitize(timestamp, now, timestamps)
+                                {
+                                    self.stats.send((Stats::LineTimestampSkewed, 1)).await;
+                                    parsed = Some(sanitized);
+                                    if timestamps.skewed == SkewedTimestampPolicy::Annotate {
+                                        received_at = Some(now);
+                                        hash.insert("received_at".to_string(), now.to_rfc3339());
+                                    }
+                                }
+                            }
+
+                            if let Some(parsed) = parsed {
+                                let value = timestamp::format(&parsed, format);
+                                hash.insert(field.to_string(), variable_for(&value));
+
+                                let buffer = if output.is_empty() { &msg.msg } else { &output };
+                                if let Some(updated) = insert_field(buffer, field, value) {
+                                    output = updated;
+                                }
+
+                                /*
+                                 * The skewed timestamp of the message is kept, so the time it
+                                 * was received at goes alongside it
+                                 */
+                                if let Some(received_at) = &received_at {
+                                    let value = timestamp::format(received_at, format);
+                                    if let Some(updated) =
+                                        insert_field(&output, "received_at", value)
+                                    {
+                                        output = updated;
+                                    }
+                                }
+                            } else {
+                                debug!("Failed to parse a timestamp for the `{}` field", field);
+                                self.stats.send((Stats::TimestampParseFailed, 1)).await;
+                            }
+                        }
+
+                        Action::RemapSeverity { map, source } => {
+                            let current = match source {
+                                Some(_) => self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                    .ok(),
+                                None => msg.severity.clone(),
+                            };
+                            /*
+                             * The keys of the map are lowercased when the configuration is loaded
+                             */
+                            let remapped =
+                                current.and_then(|current| map.get(&current.trim().to_lowercase()));
+
+                            if let Some(remapped) = remapped {
+                                debug!("Remapping the severity to `{}`", remapped);
+                                msg.severity = Some(remapped.to_string());
+                                hash.insert("severity".to_string(), remapped.to_string());
+                            }
+                        }
+
+                        Action::Script { script } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+
+                            match self.scripts.run(script, buffer, &mut hash) {
+                                Ok(Outcome::Replace(updated)) => {
+                                    output = updated;
+                                }
+                                Ok(Outcome::Unchanged) => {}
+                                Ok(Outcome::Drop) => {
+                                    pending.clear();
+                                    continue_rules = false;
+                                    self.stats.send((Stats::LineDropped, 1)).await;
+                                    self.stats.send(status::dropped("script_drop")).await;
+                                    break;
+                                }
+                                Err(e) => {
+                                    error!(
+                                        rule = rule.to_string(), error = "script_failed";
+                                        "Failed to run the script for {}: {:?}", rule, e
+                                    );
+                                    self.stats.send((Stats::ScriptFailed, 1)).await;
+                                }
+                            }
+                        }
+
+                        Action::Plugin { module } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+
+                            match self.plugins.transform(module, buffer) {
+                                Ok(plugin::Outcome::Replace(updated)) => {
+                                    output = updated;
+                                }
+                                Ok(plugin::Outcome::Drop) => {
+                                    pending.clear();
+                                    continue_rules = false;
+                                    self.stats.send((Stats::LineDropped, 1)).await;
+                                    self.stats.send(status::dropped("plugin_drop")).await;
+                                    break;
+                                }
+                                Err(e) => {
+                                    error!(
+                                        rule = rule.to_string(), error = "plugin_failed";
+                                        "Failed to run the plugin for {}: {:?}", rule, e
+                                    );
+                                    self.stats.send((Stats::PluginFailed, 1)).await;
+                                }
+                            }
+                        }
+
+                        Action::Metric {
+                            name: _,
+                            kind,
+                            value,
+                        } => {
+                            let template_id = templates::id_for(&rule, index);
+                            let name = match self
+                                .render(
+                                    hb,
+                                    rule,
+                                    &templates::part_id_for(&template_id, "name"),
+                                    &hash,
+                                )
+                                .await
+                            {
+                                Ok(name) => name,
+                                Err(e) => {
+                                    sampled::TEMPLATE_RENDER.error(format_args!(
+                                        "Failed to render the metric name: {}",
+                                        e
+                                    ));
+                                    self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                    continue;
+                                }
+                            };
+                            let raw = match value {
+                                Some(_) => self
+                                    .render(
+                                        hb,
+                                        rule,
+                                        &templates::part_id_for(&template_id, "value"),
+                                        &hash,
+                                    )
+                                    .await
+                                    .ok(),
+                                None => Some("1".to_string()),
+                            };
+
+                            match raw.and_then(|raw| metric_value(kind, &raw)) {
+                                Some(value) => {
+                                    let stat = match kind {
+                                        MetricKind::Counter => Stats::LogCounter { name },
+                                        MetricKind::Gauge => Stats::LogGauge { name },
+                                        MetricKind::Timer => Stats::LogTimer { name },
+                                    };
+                                    self.stats.send((stat, value)).await;
+                                }
+                                None => {
+                                    debug!("The value for the `{}` metric was not a number", name);
+                                    self.stats.send((Stats::MetricValueInvalid, 1)).await;
+                                }
+                            }
+                        }
+
+                        Action::Exec {
+                            command,
+                            concurrency,
+                            timeout_ms,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+
+                            if self.dry {
+                                info!(
+                                    "Not running {:?} for rule {} while trying out the rules",
+                                    command, rule
+                                );
+                            } else if self.settings.sinks().is_null() {
+                                debug!("Not running {:?} during a dry run", command);
+                            } else if self.executor.spawn(
+                                &templates::id_for(&rule, index),
+                                command,
+                                buffer.to_string(),
+                                *concurrency,
+                                Duration::from_millis(*timeout_ms),
+                                self.stats.clone(),
+                            ) {
+                                self.stats.send((Stats::ExecStarted, 1)).await;
+                            } else {
+                                debug!("Skipping {:?}, too many are already running", command);
+                                self.stats.send((Stats::ExecSkipped, 1)).await;
+                            }
+                        }
+
+                        Action::Remove { paths } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            if let Some(stripped) = perform_remove(buffer, paths, None) {
+                                output = stripped;
+                            } else {
+                                debug!("Not removing fields from a message which is not JSON");
+                            }
+                        }
+
+                        Action::Redact { paths, replacement } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            if let Some(redacted) = perform_remove(buffer, paths, Some(replacement))
+                            {
+                                output = redacted;
+                            } else {
+                                debug!("Not redacting fields from a message which is not JSON");
+                            }
+                        }
+
+                        Action::Mask {
+                            patterns,
+                            replacement,
+                            hash,
+                            salt,
+                            ..
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            let salt = if *hash { Some(salt.as_str()) } else { None };
+                            output = perform_mask(buffer, patterns, replacement, salt);
+                        }
+
+                        Action::SetKey { template: _ } => {
+                            match self
+                                .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                .await
+                            {
+                                Ok(rendered) => {
+                                    debug!("Setting the record key: `{}`", rendered);
+                                    record_key = Some(rendered);
+                                }
+                                Err(e) => {
+                                    sampled::TEMPLATE_RENDER.error(format_args!(
+                                        "Failed to render the record key: {}",
+                                        e
+                                    ));
+                                    self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                    break;
+                                }
+                            }
+                        }
+
+                        Action::Stop => {
+                            continue_rules = false;
+                        }
+
+                        Action::Tag { tags } => {
+                            for tag in tags.iter() {
+                                if !msg.tags.contains(tag) {
+                                    msg.tags.push(tag.to_string());
+                                }
+                            }
+                        }
+
+                        Action::Drop => {
+                            /*
+                             * Dropping discards anything which was going to be forwarded for
+                             * this line, and skips the remaining actions and rules
+                             */
+                            pending.clear();
+                            continue_rules = false;
+                            self.stats.send((Stats::LineDropped, 1)).await;
+                            self.stats.send(status::dropped("drop_action")).await;
+                            break;
+                        }
+
+                        Action::Sample { rate, one_in } => {
+                            let probability = rules::sample_probability(*rate, *one_in);
+
+                            if !rules::sampled(probability) {
+                                pending.clear();
+                                continue_rules = false;
+                                self.stats.send((Stats::LineSampledOut, 1)).await;
+                                self.stats.send(status::dropped("sampled_out")).await;
+                                break;
+                            }
+                        }
+
+                        Action::Dedupe {
+                            key,
+                            window_ms,
+                            topic: _,
+                            summary,
+                        } => {
+                            let buffer = if output.is_empty() { &msg.msg } else { &output };
+                            let key = if key.is_some() {
+                                match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the dedupe key: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                }
+                            } else {
+                                buffer.to_string()
+                            };
+                            /*
+                             * Each action has its own windows, even when the keys are the same
+                             */
+                            let window = format!("{}-{}", templates::id_for(&rule, index), key);
+                            let topic_id =
+                                templates::part_id_for(&templates::id_for(&rule, index), "topic");
+
+                            let admitted = self.suppressor.admit(
+                                &window,
+                                1,
+                                Duration::from_millis(*window_ms),
+                                || summary_for(&settings, hb, &topic_id, summary, &hash),
+                            );
+
+                            if !admitted {
+                                pending.clear();
+                                continue_rules = false;
+                                self.stats.send((Stats::LineDeduplicated, 1)).await;
+                                self.stats.send(status::dropped("deduplicated")).await;
+                                break;
+                            }
+                        }
+
+                        Action::Aggregate {
+                            key,
+                            value,
+                            window_ms,
+                            topic: _,
+                        } => {
+                            let key = if key.is_some() {
+                                match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the aggregate key: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                }
+                            } else {
+                                String::new()
+                            };
+
+                            let value = match value {
+                                Some(_) => {
+                                    let value_id = templates::part_id_for(
+                                        &templates::id_for(&rule, index),
+                                        "value",
+                                    );
+                                    let parsed = self
+                                        .render(hb, rule, &value_id, &hash)
+                                        .await
+                                        .ok()
+                                        .and_then(|v| v.trim().parse::<f64>().ok())
+                                        .filter(|v| v.is_finite());
+
+                                    if parsed.is_none() {
+                                        debug!("The value for the aggregate was not a number");
+                                        self.stats.send((Stats::AggregateValueInvalid, 1)).await;
+                                    }
+                                    parsed
+                                }
+                                None => None,
+                            };
+
+                            let topic_id =
+                                templates::part_id_for(&templates::id_for(&rule, index), "topic");
+
+                            self.aggregator.record(
+                                &templates::id_for(&rule, index),
+                                &key,
+                                Duration::from_millis(*window_ms),
+                                value,
+                                || summary_topic(&settings, hb, &topic_id, &hash),
+                            );
+                        }
+
+                        Action::Throttle {
+                            key,
+                            limit,
+                            interval_ms,
+                            topic: _,
+                            summary,
+                        } => {
+                            let key = if key.is_some() {
+                                match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the throttle key: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                }
+                            } else {
+                                String::new()
+                            };
+                            let window = format!("{}-{}", templates::id_for(&rule, index), key);
+                            let topic_id =
+                                templates::part_id_for(&templates::id_for(&rule, index), "topic");
+
+                            let admitted = self.suppressor.admit(
+                                &window,
+                                *limit,
+                                Duration::from_millis(*interval_ms),
+                                || summary_for(&settings, hb, &topic_id, summary, &hash),
+                            );
+
+                            if !admitted {
+                                pending.clear();
+                                continue_rules = false;
+                                self.stats.send((Stats::LineThrottled, 1)).await;
+                                self.stats.send(status::dropped("throttled")).await;
+                                break;
+                            }
+                        }
+
+                        Action::RateLimit {
+                            key,
+                            per_second,
+                            burst,
+                        } => {
+                            let key = if key.is_some() {
+                                match self
+                                    .render(hb, rule, &templates::id_for(&rule, index), &hash)
+                                    .await
+                                {
+                                    Ok(rendered) => rendered,
+                                    Err(e) => {
+                                        sampled::TEMPLATE_RENDER.error(format_args!(
+                                            "Failed to render the rate limit key: {}",
+                                            e
+                                        ));
+                                        self.stats.send((Stats::TemplateRenderFailed, 1)).await;
+                                        break;
+                                    }
+                                }
+                            } else {
+                                String::new()
+                            };
+                            /*
+                             * Each action has its own buckets, even when the keys are the same
+                             */
+                            let bucket = format!("{}-{}", templates::id_for(&rule, index), key);
+                            let burst = burst.unwrap_or(*per_second).max(1.0);
+
+                            if !self.rate_limiter.acquire(&bucket, *per_second, burst) {
+                                pending.clear();
+                                continue_rules = false;
+                                self.stats.send((Stats::LineRateLimited, 1)).await;
+                                self.stats.send(status::dropped("rate_limited")).await;
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                if let Some(input) = sample_input {
+                    let destinations: Vec<String> = pending
+                        .iter()
+                        .skip(sampled_from)
+                        .map(|(to, kmsg)| match to {
+                            Some(to) => format!("{}/{}", to, kmsg.topic()),
+                            None => kmsg.topic().to_string(),
+                        })
+                        .collect();
+                    info!(
+                        "Debug sample for {}: input: {:?}, variables: {:?}, output: {:?}, destinations: {:?}",
+                        rule, input, hash, output, destinations
+                    );
+                }
+
+                /*
+                 * The actions may have changed the message, which the following rules see
+                 */
+                if continue_rules {
+                    variables = self.variables(&settings, &msg);
+                }
+            }
+
+            drop(evaluating);
+
+            if !matched {
+                self.stats.send((Stats::LineUnmatched, 1)).await;
+
+                /*
+                 * Lines which no rule matched are only lost when the default rule did not
+                 * forward them either
+                 */
+                if pending.is_empty() {
+                    self.stats.send(status::dropped("unmatched")).await;
+                }
+            }
+
+            if !self
+                .dispatch(&dispatches, &settings, pending, &span.trace(), received)
+                .await
+            {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Create the RuleState for the merge and enrichment functions
+     */
+    fn rule_state<'a>(
+        &self,
+        hb: &'a Handlebars<'a>,
+        rule: &'a Rule,
+        variables: &'a HashMap<String, String>,
+    ) -> RuleState<'a> {
+        RuleState {
+            hb,
+            variables,
+            stats: self.stats.clone(),
+            rule: Some(rule),
+        }
+    }
+
+    /**
+     * Render the template of the rule, recording how long the rendering took
+     */
+    async fn render(
+        &self,
+        hb: &Handlebars<'_>,
+        rule: &Rule,
+        template_id: &str,
+        hash: &HashMap<String, String>,
+    ) -> Result<String, handlebars::RenderError> {
+        let start = Instant::now();
+        let profiling = profile::start();
+        let rendered = hb.render(template_id, hash);
+        profile::finish(profiling, Stage::Render, Some(rule));
+        self.stats
+            .send((Stats::TemplateRendered, status::elapsed_us(start)))
+            .await;
+        rendered
+    }
+
+    /**
+     * Return the variables of the line which every rule starts out with
+     */
+    fn variables(
+        &self,
+        settings: &Settings,
+        msg: &parse::SyslogMessage,
+    ) -> HashMap<String, String> {
+        /*
+         * The global variables come first, so that the variables of the message take
+         * precedence over them
+         */
+        let mut hash = settings.global.vars.clone();
+        hash.insert("msg".to_string(), String::from(&msg.msg));
+        hash.insert("version".to_string(), env!["CARGO_PKG_VERSION"].to_string());
+        hash.insert("iso8601".to_string(), Utc::now().to_rfc3339());
+
+        if let Some(hostname) = &msg.hostname {
+            hash.insert("hostname".to_string(), hostname.to_string());
+        }
+        if let Some(severity) = &msg.severity {
+            hash.insert("severity".to_string(), severity.to_string());
+        }
+        if let Some(facility) = &msg.facility {
+            hash.insert("facility".to_string(), facility.to_string());
+        }
+        if let Some(appname) = &msg.appname {
+            hash.insert("appname".to_string(), appname.to_string());
+        }
+        if let Some(procid) = &msg.procid {
+            hash.insert("procid".to_string(), procid.to_string());
+        }
+        if let Some(msgid) = &msg.msgid {
+            hash.insert("msgid".to_string(), msgid.to_string());
+        }
+        if let Some(timestamp) = &msg.timestamp {
+            hash.insert("timestamp".to_string(), timestamp.to_rfc3339());
+        }
+        if let Some(peer) = &self.peer {
+            hash.insert("peer_addr".to_string(), peer.to_string());
+            hash.insert("peer_ip".to_string(), peer.ip().to_string());
+        }
+        hash
+    }
+
+    /**
+     * Return the address of the sender, for the `peer` field of the logs
+     */
+    fn peer_addr(&self) -> String {
+        self.peer.map(|peer| peer.to_string()).unwrap_or_default()
+    }
+
+    /**
+     * Start a trace with a span which records the peer of this connection
+     */
+    fn root_span(&self, name: &'static str) -> Span {
+        let span = Span::root(name);
+
+        if let Some(peer) = &self.peer {
+            span.set("peer", peer.to_string());
+        }
+        span
+    }
+
+    /**
+     * Stop reading from the socket while the queue of the default Kafka producer or the dispatch
+     * queue of this connection is fuller than the `pause_ratio`, so that TCP flow control pushes
+     * back on the sender instead of the lines piling up in hotdog
+     */
+    async fn backpressure(&self, settings: &Settings, dispatches: &Sender<Dispatch>) {
+        let ratio = settings.global.limits.pause_ratio;
+        let paused =
+            || saturated(&self.settings.sinks().sender, ratio) || saturated(dispatches, ratio);
+
+        if !paused() {
+            return;
+        }
+
+        debug!(
+            "The queues are saturated, pausing the connection from {:?}",
+            self.peer
+        );
+        self.stats.send((Stats::Backpressured, 1)).await;
+
+        while paused() {
+            task::sleep(BACKPRESSURE_INTERVAL).await;
+        }
+
+        debug!("Resuming the connection from {:?}", self.peer);
+        self.stats.send((Stats::Backpressured, -1)).await;
+    }
+
+    /**
+     * Queue the messages forwarded while processing a single line for the dispatchers, waiting
+     * only when the `dispatch_queue` of the connection is full.
+     *
+     * Messages which would take the connection past `max_inflight_bytes` are shed according to the
+     * `inflight_policy`, returning false when the connection should be closed. The messages of a
+     * line are always admitted when nothing else is in flight, so that a line whose messages are
+     * larger than the limit by themselves is not shed forever
+     */
+    async fn dispatch(
+        &self,
+        dispatches: &Sender<Dispatch>,
+        settings: &Arc<Settings>,
+        pending: Vec<(Option<String>, KafkaMessage)>,
+        trace: &trace::Trace,
+        received: Instant,
+    ) -> bool {
+        if pending.is_empty() {
+            return true;
+        }
+
+        let limits = &settings.global.limits;
+        let bytes = pending.iter().map(|(_, kmsg)| kmsg.size()).sum();
+        let inflight = self.inflight.fetch_add(bytes, Ordering::Relaxed);
+
+        if inflight > 0 && inflight + bytes > limits.max_inflight_bytes {
+            self.inflight.fetch_sub(bytes, Ordering::Relaxed);
+            self.stats.send((Stats::LineShed, 1)).await;
+            self.stats
+                .send((
+                    Stats::Dropped {
+                        reason: "inflight_limit",
+                    },
+                    pending.len() as i64,
+                ))
+                .await;
+
+            if limits.inflight_policy == InflightPolicy::Disconnect {
+                warn!(
+                    peer = self.peer_addr(), error = "inflight_limit";
+                    "Closing the connection from {:?} which has more than {} bytes of messages in flight",
+                    self.peer, limits.max_inflight_bytes
+                );
+                return false;
+            }
+            sampled::INFLIGHT_LIMIT.error(format_args!(
+                "Dropping the messages of a line from {:?} which has more than {} bytes of messages in flight",
+                self.peer, limits.max_inflight_bytes
+            ));
+            return true;
+        }
+
+        let dispatch = Dispatch {
+            settings: settings.clone(),
+            pending,
+            trace: trace.clone(),
+            received,
+            bytes,
+        };
+
+        if let Err(e) = dispatches.send(dispatch).await {
+            error!(
+                "Failed to queue the messages of a line for the dispatchers: {}",
+                e
+            );
+        }
+        true
+    }
+}
+
+/**
+ * How often a paused connection checks whether the queues have drained
+ */
+const BACKPRESSURE_INTERVAL: Duration = Duration::from_millis(10);
+
+/**
+ * Whether the bounded queue is at least as full as the ratio of its capacity
+ */
+fn saturated<T>(queue: &Sender<T>, ratio: f64) -> bool {
+    match queue.capacity() {
+        Some(capacity) => queue.len() as f64 >= capacity as f64 * ratio,
+        None => false,
+    }
+}
+
+/**
+ * The messages which were forwarded while processing a single line, along with the settings they
+ * were processed with
+ */
+struct Dispatch {
+    settings: Arc<Settings>,
+    pending: Vec<(Option<String>, KafkaMessage)>,
+    trace: trace::Trace,
+    received: Instant,
+    /**
+     * The size of the payloads of the messages, which count towards the `max_inflight_bytes` of
+     * the connection until they have been sent
+     */
+    bytes: usize,
+}
+
+/**
+ * Dispatcher sends the messages which the lines of a connection were forwarded as over to the
+ * sendloops of their destinations
+ */
+#[derive(Clone)]
+struct Dispatcher {
+    settings: Arc<Reloadable>,
+    stats: Sender<Statistic>,
+    inflight: Arc<AtomicUsize>,
+    settled: Option<Sender<()>>,
+}
+
+impl Dispatcher {
+    /**
+     * Send the queued messages until the connection has stopped reading lines
+     */
+    async fn run(self, queued: Receiver<Dispatch>) {
+        while let Ok(dispatch) = queued.recv().await {
+            let bytes = dispatch.bytes;
+            let profiling = profile::start();
+            self.send(dispatch).await;
+            profile::finish(profiling, Stage::Send, None);
+            self.inflight.fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /**
+     * Send the messages forwarded while processing a single line over to the sendloops of their
+     * destinations.
+     *
+     * When the default Kafka producer is transactional, a line which has been forwarded to
+     * multiple of its topics is sent as a single transaction so consumers never see a partial
+     * fan-out.
+     */
+    async fn send(&self, dispatch: Dispatch) {
+        let Dispatch {
+            settings,
+            pending,
+            trace,
+            received,
+            ..
+        } = dispatch;
+        let preserve_order = settings.global.kafka.preserve_order;
+
+        /*
+         * The sinks are looked up for every line, so that the producers started for reloaded
+         * Kafka settings are used without the connection needing to be re-established
+         */
+        let sinks = self.settings.sinks();
+        let mut default = vec![];
 
-                            debug!(
-                                "replacing content with template: {} ({})",
-                                template, template_id
-                            );
-                            if let Ok(rendered) = hb.render(&template_id, &hash) {
-                                output = rendered;
-                            }
-                        }
+        for (to, kmsg) in pending.into_iter() {
+            let mut kmsg = kmsg.with_trace(trace.clone()).with_received(received);
+            if let Some(settled) = &self.settled {
+                kmsg = kmsg.with_settled(settled.clone());
+            }
 
-                        Action::Stop => {
-                            continue_rules = false;
+            match to {
+                None => default.push(kmsg),
+                Some(name) => {
+                    /*
+                     * The producer of the destination is started by the first message sent to it
+                     */
+                    let sender = match sinks.destinations.get(&name) {
+                        Some(sink) => sink.sender().await,
+                        None => Err(errors::HotdogError::DestinationMissing {
+                            destination: name.clone(),
+                        }),
+                    };
+
+                    match sender {
+                        Ok(sender) => {
+                            produce(&sender, Produce::Message(kmsg), preserve_order, &self.stats)
+                                .await;
+                        }
+                        Err(errors::HotdogError::DestinationUnavailable { .. }) => {
+                            sampled::DESTINATION_UNAVAILABLE.error(format_args!(
+                                "The destination `{}` is not connected to its brokers yet, dropping the message",
+                                name
+                            ));
+                            self.stats
+                                .send(status::dropped("destination_unavailable"))
+                                .await;
+                        }
+                        Err(_) => {
+                            /*
+                             * Destinations are started in the background once the Kafka settings
+                             * have been reloaded, so reloaded rules may name one which does not
+                             * exist yet
+                             */
+                            error!(
+                                topic = kmsg.topic(), error = "destination_missing";
+                                "The destination `{}` has not been started, dropping the message",
+                                name
+                            );
+                            self.stats.send((Stats::DestinationMissing, 1)).await;
+                            self.stats
+                                .send(status::dropped("destination_missing"))
+                                .await;
                         }
                     }
                 }
             }
         }
 
-        Ok(())
+        if default.len() > 1 && settings.global.kafka.transactional {
+            produce(
+                &sinks.sender,
+                Produce::Transaction(default),
+                preserve_order,
+                &self.stats,
+            )
+            .await;
+            return;
+        }
+
+        for kmsg in default.into_iter() {
+            produce(
+                &sinks.sender,
+                Produce::Message(kmsg),
+                preserve_order,
+                &self.stats,
+            )
+            .await;
+        }
+    }
+}
+
+/**
+ * Pass the messages over to a sendloop, and when the order of the messages is preserved wait until
+ * they have settled, so that the next message cannot overtake them.
+ *
+ * The sendloop is closed once its sinks have been retired by a reload, the messages of a line
+ * which was still being dispatched to the previous sinks are counted as dropped
+ */
+async fn produce(
+    sender: &Sender<Produce>,
+    produce: Produce,
+    preserve_order: bool,
+    stats: &Sender<Statistic>,
+) {
+    if !preserve_order {
+        if let Err(e) = sender.send(produce).await {
+            retired(e.into_inner(), stats).await;
+        }
+        /*
+         * Ensure that we're allowing other tasks to execute when we pass
+         * things off to the channel
+         *
+         * See also https://github.com/stjepang/smol/issues/159
+         */
+        task::yield_now().await;
+        return;
+    }
+
+    let (settled, waiting) = bounded::<()>(1);
+    let produce = match produce {
+        Produce::Message(kmsg) => Produce::Message(kmsg.with_settled(settled.clone())),
+        Produce::Transaction(batch) => Produce::Transaction(
+            batch
+                .into_iter()
+                .map(|kmsg| kmsg.with_settled(settled.clone()))
+                .collect(),
+        ),
+    };
+    drop(settled);
+    if let Err(e) = sender.send(produce).await {
+        retired(e.into_inner(), stats).await;
+        return;
+    }
+
+    /*
+     * Nothing is ever sent on the channel, it is closed once every message has been dropped
+     */
+    let _ = waiting.recv().await;
+}
+
+/**
+ * Count the messages which could not be passed over to the sendloop of retired sinks
+ */
+async fn retired(produce: Produce, stats: &Sender<Statistic>) {
+    let count = match &produce {
+        Produce::Message(_) => 1,
+        Produce::Transaction(batch) => batch.len(),
+    };
+
+    error!(
+        error = "retired";
+        "The producer was replaced by a reload, dropping {} messages", count
+    );
+    stats
+        .send((Stats::Dropped { reason: "retired" }, count as i64))
+        .await;
+}
+
+/**
+ * Render the topic for the summaries and rollups which are sent once a window ends, falling back
+ * to the default topic if it cannot be rendered
+ */
+fn summary_topic(
+    settings: &Settings,
+    hb: &Handlebars,
+    topic_id: &str,
+    hash: &HashMap<String, String>,
+) -> String {
+    match hb.render(topic_id, hash) {
+        Ok(rendered) => settings.global.kafka.resolve_topic(&rendered).to_string(),
+        Err(e) => {
+            error!(
+                "Failed to render the summary topic, using the default: {}",
+                e
+            );
+            settings.global.kafka.topic.clone()
+        }
+    }
+}
+
+/**
+ * Create the summary for a window of the dedupe and throttle actions, which is sent to the
+ * rendered topic once the window ends
+ */
+fn summary_for(
+    settings: &Settings,
+    hb: &Handlebars,
+    topic_id: &str,
+    summary: &str,
+    hash: &HashMap<String, String>,
+) -> Summary {
+    Summary {
+        topic: summary_topic(settings, hb, topic_id, hash),
+        template: summary.to_string(),
+        variables: hash.clone(),
+    }
+}
+
+/**
+ * Counted counts the bytes which are read from the connection, before they are buffered
+ */
+struct Counted<R> {
+    reader: R,
+    /**
+     * The number of bytes which have been read since they were last reported
+     */
+    bytes: usize,
+}
+
+impl<R: async_std::io::Read + std::marker::Unpin> async_std::io::Read for Counted<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(read)) = poll {
+            self.bytes += read;
+        }
+        poll
     }
 }
 
 /**
- * Generate a unique identifier for the given template
+ * A line read from a connection
  */
-fn template_id_for(rule: &Rule, index: usize) -> String {
-    format!("{}-{}", rule.uuid, index)
+#[derive(Debug, PartialEq)]
+enum Line {
+    Complete(String),
+    /**
+     * The line was longer than the limit, only the bytes up to the limit have been kept and the
+     * remainder has been discarded
+     */
+    TooLong(Vec<u8>),
+    /**
+     * The line was not valid UTF-8
+     */
+    Invalid(Vec<u8>),
+    /**
+     * The connection ended, or failed, before the line was ended
+     */
+    Partial(Vec<u8>),
+    Eof,
 }
 
 /**
- * precompile_templates will register templates for all the Merge and Replace actions from the
- * settings
+ * Read the next line, without its line ending, from the reader.
  *
- * Will usually return a true, unless some setting parse failure occurred which is a critical
- * failure for the daemon
+ * At most `max` bytes of a line are buffered, the remainder of a longer line is read and
+ * discarded until its end. The bytes of a line which the connection did not end are returned as a
+ * partial line, even when reading them failed, and any error is returned by the next read
  */
-fn precompile_templates(hb: &mut Handlebars, settings: Arc<Settings>) -> bool {
-    for rule in settings.rules.iter() {
-        for index in 0..rule.actions.len() {
-            match &rule.actions[index] {
-                Action::Merge { json: _, json_str } => {
-                    let template_id = template_id_for(rule, index);
-
-                    if let Some(template) = json_str {
-                        if let Err(e) = hb.register_template_string(&template_id, &template) {
-                            error!("Failed to register template! {}\n{}", e, template);
-                            return false;
-                        }
-                    } else {
-                        error!("Could not look up the json_str for a Merge action");
-                        return false;
-                    }
-                }
-                Action::Replace { template } => {
-                    let template_id = format!("{}-{}", rule.uuid, index);
-                    if let Err(e) = hb.register_template_string(&template_id, &template) {
-                        error!("Failed to register template! {}\n{}", e, template);
-                        return false;
-                    }
-                }
-                _ => {}
+async fn read_line<R: async_std::io::BufRead + async_std::io::Read + std::marker::Unpin>(
+    reader: &mut R,
+    max: usize,
+) -> std::io::Result<Line> {
+    let limit = max as u64 + 1;
+    let mut buffer = vec![];
+
+    match (&mut *reader)
+        .take(limit)
+        .read_until(b'\n', &mut buffer)
+        .await
+    {
+        Ok(0) => return Ok(Line::Eof),
+        Ok(_) => {}
+        Err(e) if buffer.is_empty() => return Err(e),
+        Err(e) => {
+            debug!("Failed to read the rest of a line: {:?}", e);
+            return Ok(Line::Partial(buffer));
+        }
+    }
+
+    if buffer.len() > max && buffer.last() != Some(&b'\n') {
+        buffer.truncate(max);
+        let mut discarded = vec![];
+
+        loop {
+            discarded.clear();
+            /*
+             * A failure while discarding the remainder ends the line, the error is returned by
+             * the next read
+             */
+            let read = (&mut *reader)
+                .take(limit)
+                .read_until(b'\n', &mut discarded)
+                .await
+                .unwrap_or(0);
+
+            if read == 0 || discarded.last() == Some(&b'\n') {
+                return Ok(Line::TooLong(buffer));
             }
         }
     }
-    true
+
+    if buffer.last() != Some(&b'\n') {
+        return Ok(Line::Partial(buffer));
+    }
+    buffer.pop();
+
+    if buffer.last() == Some(&b'\r') {
+        buffer.pop();
+    }
+
+    Ok(String::from_utf8(buffer)
+        .map(Line::Complete)
+        .unwrap_or_else(|e| Line::Invalid(e.into_bytes())))
+}
+
+/**
+ * Return the bytes of the output for a message, sharing the bytes of the previously forwarded
+ * output rather than copying the output again when it has not changed since
+ */
+fn shared_output(forwarded: &mut Option<Bytes>, output: &str) -> Bytes {
+    match forwarded {
+        Some(bytes) if bytes[..] == *output.as_bytes() => bytes.clone(),
+        _ => {
+            let bytes = Bytes::copy_from_slice(output.as_bytes());
+            *forwarded = Some(bytes.clone());
+            bytes
+        }
+    }
+}
+
+/**
+ * Truncate the start of a line which was too long so that it fits in `max` bytes along with the
+ * marker
+ */
+fn truncate_line(
+    mut head: Vec<u8>,
+    max: usize,
+    marker: &str,
+) -> Result<String, std::string::FromUtf8Error> {
+    head.truncate(max.saturating_sub(marker.len()));
+
+    /*
+     * Avoid cutting a multi-byte UTF-8 character in half
+     */
+    if let Err(e) = std::str::from_utf8(&head) {
+        if e.error_len().is_none() {
+            head.truncate(e.valid_up_to());
+        }
+    }
+    head.extend_from_slice(marker.as_bytes());
+
+    String::from_utf8(head)
+}
+
+/**
+ * Check that all the JMESPath expressions of the settings can be precompiled, which must be the
+ * case before reloaded settings are used. The templates are already registered when the settings
+ * are loaded
+ */
+pub fn verify(settings: Arc<Settings>) -> bool {
+    let mut jmespaths = JmesPathExpressions::new();
+
+    precompile_jmespath(&mut jmespaths, settings)
 }
 
 /**
@@ -299,8 +2386,16 @@ fn precompile_templates(hb: &mut Handlebars, settings: Arc<Settings>) -> bool {
  * configuration file and shove thoe in the map given to it
  */
 fn precompile_jmespath(map: &mut JmesPathExpressions, settings: Arc<Settings>) -> bool {
-    for rule in settings.rules.iter() {
-        if let Some(expression) = &rule.jmespath {
+    for rule in settings.all_rules() {
+        let conditions = rule
+            .actions
+            .iter()
+            .filter_map(|action| action.when.as_ref());
+
+        for expression in std::iter::once(&rule.condition)
+            .chain(conditions)
+            .flat_map(|condition| condition.jmespaths())
+        {
             if !map.contains_key(expression) {
                 if let Ok(compiled) = jmespath::compile(&expression) {
                     map.insert(expression.to_string(), compiled);
@@ -323,16 +2418,19 @@ fn perform_merge(
     state: &RuleState,
 ) -> Result<String, String> {
     if let Ok(mut msg_json) = crate::json::from_str(&mut buffer) {
-        if let Ok(mut rendered) = state.hb.render(template_id, &state.variables) {
-            let to_merge: serde_json::Value = crate::json::from_str(&mut rendered)
-                .expect("Failed to deserialize our rendered to_merge_str");
+        if let Ok(mut rendered) = state.render(template_id) {
+            let to_merge: serde_json::Value =
+                crate::json::from_str(&mut rendered).unwrap_or(serde_json::Value::Null);
 
             /*
              * If the administrator configured the merge incorrectly, just pass the buffer along un-merged
              */
             if !to_merge.is_object() {
-                error!("Merge requested was not a JSON object: {}", to_merge);
-                state.stats.send((Stats::MergeTargetNotJsonError, 1));
+                sampled::MERGE_TARGET_NOT_JSON.error(format_args!(
+                    "Merge requested was not a JSON object: {}",
+                    rendered
+                ));
+                let _ = state.stats.try_send((Stats::MergeTargetNotJsonError, 1));
                 return Ok(buffer.to_string());
             }
 
@@ -344,16 +2442,222 @@ fn perform_merge(
         }
         Err("Failed to merge and serialize".to_string())
     } else {
-        error!("Failed to parse as JSON, stopping actions: {}", buffer);
-        state.stats.send((Stats::MergeInvalidJsonError, 1));
+        sampled::MERGE_INVALID_JSON.error(format_args!(
+            "Failed to parse as JSON, stopping actions: {}",
+            buffer
+        ));
+        let _ = state.stats.try_send((Stats::MergeInvalidJsonError, 1));
         Err("Not JSON".to_string())
     }
 }
 
+/**
+ * perform_add_fields will add the rendered fields to the buffer if it is a JSON object, otherwise
+ * the buffer is wrapped in a new JSON object under the message_field
+ */
+async fn perform_add_fields(
+    buffer: &str,
+    fields: &HashMap<String, String>,
+    message_field: &str,
+    template_id: &str,
+    state: &RuleState<'_>,
+) -> String {
+    let mut object = object_for(buffer, message_field);
+
+    for field in fields.keys() {
+        let field_id = templates::part_id_for(template_id, field);
+
+        match state.render(&field_id) {
+            Ok(rendered) => {
+                object.insert(field.to_string(), serde_json::Value::String(rendered));
+            }
+            Err(e) => {
+                sampled::TEMPLATE_RENDER.error(format_args!(
+                    "Failed to render the `{}` field: {}",
+                    field, e
+                ));
+                state.stats.send((Stats::TemplateRenderFailed, 1)).await;
+            }
+        }
+    }
+
+    serde_json::Value::Object(object).to_string()
+}
+
+/**
+ * perform_extract will add the extracted pairs to the variables and to the JSON output, nested
+ * under the field when one is given
+ */
+fn perform_extract(
+    buffer: &str,
+    pairs: Vec<(String, String)>,
+    field: Option<&str>,
+    message_field: &str,
+    variables: &mut HashMap<String, String>,
+) -> String {
+    let mut object = object_for(buffer, message_field);
+    let mut extracted = serde_json::Map::new();
+
+    for (key, value) in pairs.into_iter() {
+        variables.insert(key.clone(), value.clone());
+        extracted.insert(key, serde_json::Value::String(value));
+    }
+
+    match field {
+        Some(field) => {
+            object.insert(field.to_string(), serde_json::Value::Object(extracted));
+        }
+        None => object.extend(extracted),
+    }
+    serde_json::Value::Object(object).to_string()
+}
+
+/**
+ * Return the buffer as a JSON object, wrapping buffers which are not JSON objects into an object
+ * with the buffer under the `message_field`
+ */
+fn object_for(buffer: &str, message_field: &str) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::from_str::<serde_json::Value>(buffer) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => {
+            let mut object = serde_json::Map::new();
+            object.insert(
+                message_field.to_string(),
+                serde_json::Value::String(buffer.to_string()),
+            );
+            object
+        }
+    }
+}
+
+/**
+ * Convert a JSON value into the string used for template variables, which is the value itself for
+ * strings and the JSON representation for everything else
+ */
+fn variable_for(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/**
+ * Convert the rendered value of a metric action into the value reported for its kind. Timers are
+ * configured in milliseconds but reported in microseconds, and counters cannot be negative
+ */
+fn metric_value(kind: &MetricKind, raw: &str) -> Option<i64> {
+    let value = raw.trim().parse::<f64>().ok().filter(|v| v.is_finite())?;
+
+    match kind {
+        MetricKind::Counter if value < 0.0 => None,
+        MetricKind::Timer if value < 0.0 => None,
+        MetricKind::Timer => Some((value * 1_000.0).round() as i64),
+        _ => Some(value.round() as i64),
+    }
+}
+
+/**
+ * Insert the field into the buffer if it is a JSON object, returning the updated buffer
+ */
+fn insert_field(buffer: &str, field: &str, value: serde_json::Value) -> Option<String> {
+    match serde_json::from_str::<serde_json::Value>(buffer) {
+        Ok(serde_json::Value::Object(mut object)) => {
+            object.insert(field.to_string(), value);
+            Some(serde_json::Value::Object(object).to_string())
+        }
+        _ => None,
+    }
+}
+
+/**
+ * Insert the time the line was received at into the buffer if it is a JSON object which does not
+ * have it yet, returning the updated buffer
+ */
+fn annotate_received(buffer: &str, received_at: &DateTime<Utc>) -> Option<String> {
+    match serde_json::from_str::<serde_json::Value>(buffer) {
+        Ok(serde_json::Value::Object(mut object)) if !object.contains_key("received_at") => {
+            object.insert(
+                "received_at".to_string(),
+                timestamp::format(received_at, &TimestampFormat::default()),
+            );
+            Some(serde_json::Value::Object(object).to_string())
+        }
+        _ => None,
+    }
+}
+
+/**
+ * perform_remove will remove the fields at the given JSON pointer paths from the buffer, or
+ * replace their values when a replacement is given.
+ *
+ * Returns None if the buffer is not JSON
+ */
+fn perform_remove(buffer: &str, paths: &[String], replacement: Option<&str>) -> Option<String> {
+    let mut value: serde_json::Value = serde_json::from_str(buffer).ok()?;
+
+    for path in paths.iter() {
+        match replacement {
+            Some(replacement) => {
+                if let Some(field) = value.pointer_mut(path) {
+                    *field = serde_json::Value::String(replacement.to_string());
+                }
+            }
+            None => {
+                if let Some((parent, last)) = path.rsplit_once('/') {
+                    let last = last.replace("~1", "/").replace("~0", "~");
+
+                    match value.pointer_mut(parent) {
+                        Some(serde_json::Value::Object(object)) => {
+                            object.remove(&last);
+                        }
+                        Some(serde_json::Value::Array(array)) => {
+                            if let Ok(index) = last.parse::<usize>() {
+                                if index < array.len() {
+                                    array.remove(index);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    Some(value.to_string())
+}
+
+/**
+ * perform_mask will replace everything in the buffer matching the patterns with the replacement,
+ * or with the hex encoded SHA-256 hash of the salt and the match when a salt is given
+ */
+fn perform_mask(
+    buffer: &str,
+    patterns: &[regex::Regex],
+    replacement: &str,
+    salt: Option<&str>,
+) -> String {
+    let mut masked = buffer.to_string();
+
+    for pattern in patterns.iter() {
+        masked = pattern
+            .replace_all(&masked, |captures: &regex::Captures| match salt {
+                Some(salt) => {
+                    use sha2::Digest;
+                    let mut hasher = sha2::Sha256::new();
+                    hasher.update(salt.as_bytes());
+                    hasher.update(captures[0].as_bytes());
+                    format!("{:x}", hasher.finalize())
+                }
+                None => replacement.to_string(),
+            })
+            .into_owned();
+    }
+    masked
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use async_channel::bounded;
 
     /**
      * Generating a test RuleState for consistent states in test
@@ -367,9 +2671,263 @@ mod tests {
             hb: &hb,
             variables: &hash,
             stats: unused_sender,
+            rule: None,
+        }
+    }
+
+    #[async_std::test]
+    async fn read_line_limits() {
+        let input = "hello\r\nthis line is too long\nworld";
+        let mut reader = BufReader::new(async_std::io::Cursor::new(input.as_bytes()));
+
+        assert_eq!(
+            Line::Complete("hello".to_string()),
+            read_line(&mut reader, 8).await.unwrap()
+        );
+        assert_eq!(
+            Line::TooLong(b"this lin".to_vec()),
+            read_line(&mut reader, 8).await.unwrap()
+        );
+        assert_eq!(
+            Line::Partial(b"world".to_vec()),
+            read_line(&mut reader, 8).await.unwrap()
+        );
+        assert_eq!(Line::Eof, read_line(&mut reader, 8).await.unwrap());
+    }
+
+    /**
+     * A reader which fails after its input, like a connection which is reset mid-line
+     */
+    struct Reset<'a>(&'a [u8]);
+
+    impl async_std::io::Read for Reset<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.0.is_empty() {
+                return Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()));
+            }
+            let read = std::cmp::min(buf.len(), self.0.len());
+            buf[..read].copy_from_slice(&self.0[..read]);
+            self.0 = &self.0[read..];
+            Poll::Ready(Ok(read))
         }
     }
 
+    #[async_std::test]
+    async fn read_line_reset_mid_line() {
+        let mut reader = BufReader::new(Reset(b"hello\nwor"));
+
+        assert_eq!(
+            Line::Complete("hello".to_string()),
+            read_line(&mut reader, 64).await.unwrap()
+        );
+        /*
+         * The bytes which were read before the failure are kept, the failure ends the next read
+         */
+        assert_eq!(
+            Line::Partial(b"wor".to_vec()),
+            read_line(&mut reader, 64).await.unwrap()
+        );
+        assert!(read_line(&mut reader, 64).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn read_line_invalid_utf8() {
+        let input = b"hello\n\xff\xfe oops\nworld\n";
+        let mut reader = BufReader::new(async_std::io::Cursor::new(&input[..]));
+
+        assert_eq!(
+            Line::Complete("hello".to_string()),
+            read_line(&mut reader, 64).await.unwrap()
+        );
+        assert_eq!(
+            Line::Invalid(b"\xff\xfe oops".to_vec()),
+            read_line(&mut reader, 64).await.unwrap()
+        );
+        /*
+         * The lines after the invalid line are still read
+         */
+        assert_eq!(
+            Line::Complete("world".to_string()),
+            read_line(&mut reader, 64).await.unwrap()
+        );
+    }
+
+    #[test]
+    fn shared_output_until_changed() {
+        let mut forwarded = None;
+        let first = shared_output(&mut forwarded, "hello");
+        let second = shared_output(&mut forwarded, "hello");
+        assert_eq!(first.as_ptr(), second.as_ptr());
+
+        let changed = shared_output(&mut forwarded, "hello world");
+        assert_eq!(&b"hello world"[..], &changed[..]);
+        assert_ne!(first.as_ptr(), changed.as_ptr());
+    }
+
+    #[test]
+    fn truncate_long_line() {
+        assert_eq!(
+            "this ...",
+            truncate_line(b"this lin".to_vec(), 8, "...").unwrap()
+        );
+        /*
+         * The limit falls in the middle of the two bytes of the é
+         */
+        assert_eq!(
+            "caf...",
+            truncate_line("café au lait".as_bytes().to_vec(), 7, "...").unwrap()
+        );
+    }
+
+    #[test]
+    fn saturated_queue() {
+        let (queue, _unused) = bounded(4);
+
+        assert!(!saturated(&queue, 0.5));
+        queue.try_send(()).unwrap();
+        assert!(!saturated(&queue, 0.5));
+        queue.try_send(()).unwrap();
+        assert!(saturated(&queue, 0.5));
+        assert!(!saturated(&queue, 1.0));
+    }
+
+    #[async_std::test]
+    async fn read_line_counts_bytes() {
+        let input = "hello\r\nworld\n";
+        let mut reader = BufReader::new(Counted {
+            reader: async_std::io::Cursor::new(input.as_bytes()),
+            bytes: 0,
+        });
+
+        read_line(&mut reader, 8).await.unwrap();
+        read_line(&mut reader, 8).await.unwrap();
+        assert_eq!(input.len(), reader.get_ref().bytes);
+    }
+
+    #[async_std::test]
+    async fn add_fields_wraps_plain_text() {
+        let mut hb = Handlebars::new();
+        hb.register_template_string(&templates::part_id_for("test", "host"), "{{hostname}}")
+            .unwrap();
+        let mut hash = HashMap::<String, String>::new();
+        hash.insert("hostname".to_string(), "coconut".to_string());
+        let state = rule_state(&hb, &hash);
+
+        let mut fields = HashMap::new();
+        fields.insert("host".to_string(), "{{hostname}}".to_string());
+
+        let output = perform_add_fields("hello world", &fields, "message", "test", &state).await;
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!("hello world", value["message"]);
+        assert_eq!("coconut", value["host"]);
+    }
+
+    #[async_std::test]
+    async fn add_fields_to_json_object() {
+        let mut hb = Handlebars::new();
+        hb.register_template_string(&templates::part_id_for("test", "env"), "prod")
+            .unwrap();
+        let hash = HashMap::<String, String>::new();
+        let state = rule_state(&hb, &hash);
+
+        let mut fields = HashMap::new();
+        fields.insert("env".to_string(), "prod".to_string());
+
+        let output = perform_add_fields(r#"{"hello":1}"#, &fields, "message", "test", &state).await;
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(1, value["hello"]);
+        assert_eq!("prod", value["env"]);
+        assert!(value.get("message").is_none());
+    }
+
+    #[test]
+    fn insert_field_into_object() {
+        assert_eq!(
+            Some(r#"{"a":1,"ts":2}"#.to_string()),
+            insert_field(r#"{"a":1}"#, "ts", serde_json::Value::from(2))
+        );
+        assert_eq!(
+            None,
+            insert_field("hello", "ts", serde_json::Value::from(2))
+        );
+    }
+
+    #[test]
+    fn annotate_received_once() {
+        let received_at = Utc.ymd(2020, 4, 18).and_hms(12, 0, 0);
+        assert_eq!(
+            Some(r#"{"a":1,"received_at":"2020-04-18T12:00:00.000Z"}"#.to_string()),
+            annotate_received(r#"{"a":1}"#, &received_at)
+        );
+        assert_eq!(
+            None,
+            annotate_received(r#"{"received_at":1}"#, &received_at)
+        );
+        assert_eq!(None, annotate_received("hello", &received_at));
+    }
+
+    #[test]
+    fn remove_fields() {
+        let paths = vec!["/user/password".to_string(), "/tags/0".to_string()];
+        let output = perform_remove(
+            r#"{"user":{"name":"tyler","password":"hunter2"},"tags":["a","b"]}"#,
+            &paths,
+            None,
+        );
+        assert_eq!(
+            Some(r#"{"tags":["b"],"user":{"name":"tyler"}}"#.to_string()),
+            output
+        );
+    }
+
+    #[test]
+    fn redact_fields() {
+        let paths = vec!["/user/password".to_string(), "/missing".to_string()];
+        let output = perform_remove(
+            r#"{"user":{"password":"hunter2"}}"#,
+            &paths,
+            Some("[REDACTED]"),
+        );
+        assert_eq!(
+            Some(r#"{"user":{"password":"[REDACTED]"}}"#.to_string()),
+            output
+        );
+    }
+
+    #[test]
+    fn remove_from_non_json() {
+        assert_eq!(None, perform_remove("hello", &["/a".to_string()], None));
+    }
+
+    #[test]
+    fn mask_with_replacement() {
+        let patterns = vec![regex::Regex::new(r"[\w.+-]+@[\w-]+\.[\w.]+").unwrap()];
+        let output = perform_mask(
+            "login from tyler@example.com ok",
+            &patterns,
+            "[MASKED]",
+            None,
+        );
+        assert_eq!("login from [MASKED] ok", output);
+    }
+
+    #[test]
+    fn mask_with_hash() {
+        let patterns = vec![regex::Regex::new(r"\d{4}-\d{4}").unwrap()];
+        let first = perform_mask("card 1234-5678", &patterns, "[MASKED]", Some("salt"));
+        let second = perform_mask("card 1234-5678", &patterns, "[MASKED]", Some("salt"));
+        let other = perform_mask("card 1234-5678", &patterns, "[MASKED]", Some("pepper"));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(!first.contains("1234"));
+        assert_eq!("card ".len() + 64, first.len());
+    }
+
     #[test]
     fn merge_with_empty() {
         let mut hb = Handlebars::new();
@@ -456,27 +3014,12 @@ mod tests {
     }
 
     #[test]
-    fn test_precompile_templates_merge() {
-        let mut hb = Handlebars::new();
-        let settings = Arc::new(load("test/configs/single-rule-with-merge.yml"));
-        // Assuming that we're going to register the template with this id
-        let template_id = format!("{}-{}", settings.rules[0].uuid, 0);
-
-        let result = precompile_templates(&mut hb, settings.clone());
-        assert!(result);
-        assert!(hb.has_template(&template_id));
-    }
-
-    #[test]
-    fn test_precompile_templates_replace() {
-        let mut hb = Handlebars::new();
-        let settings = Arc::new(load("test/configs/single-rule-with-replace.yml"));
-        // Assuming that we're going to register the template with this id
-        let template_id = format!("{}-{}", settings.rules[0].uuid, 0);
-
-        let result = precompile_templates(&mut hb, settings.clone());
-        assert!(result);
-        assert!(hb.has_template(&template_id));
+    fn test_metric_value() {
+        assert_eq!(Some(1), metric_value(&MetricKind::Counter, "1"));
+        assert_eq!(None, metric_value(&MetricKind::Counter, "-1"));
+        assert_eq!(Some(-3), metric_value(&MetricKind::Gauge, " -3 "));
+        assert_eq!(Some(12_500), metric_value(&MetricKind::Timer, "12.5"));
+        assert_eq!(None, metric_value(&MetricKind::Gauge, "fast"));
     }
 
     #[test]
@@ -485,7 +3028,7 @@ mod tests {
         let mut map = JmesPathExpressions::new();
         let result = precompile_jmespath(&mut map, settings.clone());
         assert!(result);
-        let expected = settings.rules[0].jmespath.as_ref().unwrap();
+        let expected = settings.rules[0].condition.jmespath.as_ref().unwrap();
         assert!(map.contains_key(expected));
     }
 
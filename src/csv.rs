@@ -0,0 +1,95 @@
+/**
+ * The csv module splits delimited messages, such as comma separated values or fixed-format logs,
+ * into their columns
+ */
+
+/**
+ * Split the input into its columns on the delimiter.
+ *
+ * Columns which start with the `quote` character may contain the delimiter, and a doubled quote
+ * inside of a quoted column is a literal quote, as in RFC 4180
+ */
+pub fn split(input: &str, delimiter: &str, quote: Option<char>) -> Vec<String> {
+    let mut columns = vec![];
+    let mut rest = input;
+
+    loop {
+        let mut column = String::new();
+
+        if let Some(quote) = quote {
+            if rest.starts_with(quote) {
+                let (value, remaining) = quoted(&rest[quote.len_utf8()..], quote);
+                column = value;
+                rest = remaining;
+            }
+        }
+
+        let end = if delimiter.is_empty() {
+            None
+        } else {
+            rest.find(delimiter)
+        };
+        let end = end.unwrap_or(rest.len());
+
+        column.push_str(&rest[..end]);
+        columns.push(column);
+
+        if end == rest.len() {
+            break;
+        }
+        rest = &rest[end + delimiter.len()..];
+    }
+    columns
+}
+
+/**
+ * Read the quoted column up until the closing quote, returning the unescaped value and whatever
+ * follows the closing quote. An unterminated column runs to the end of the input
+ */
+fn quoted(input: &str, quote: char) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        if c == quote {
+            if let Some((_, next)) = chars.peek() {
+                if *next == quote {
+                    chars.next();
+                    value.push(quote);
+                    continue;
+                }
+            }
+            return (value, &input[index + c.len_utf8()..]);
+        }
+        value.push(c);
+    }
+    (value, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(vec!["a", "", "c", ""], split("a,,c,", ",", Some('"')));
+    }
+
+    #[test]
+    fn test_split_quoted() {
+        assert_eq!(
+            vec!["GET /", "said \"hi\", then left", "200"],
+            split(r#""GET /","said ""hi"", then left",200"#, ",", Some('"'))
+        );
+    }
+
+    #[test]
+    fn test_split_without_quote() {
+        assert_eq!(vec!["\"a", "b\""], split("\"a|b\"", "|", None));
+    }
+
+    #[test]
+    fn test_split_multiple_character_delimiter() {
+        assert_eq!(vec!["a", "b", "c"], split("a :: b :: c", " :: ", None));
+    }
+}
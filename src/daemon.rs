@@ -0,0 +1,203 @@
+/**
+ * The daemon module detaches hotdog from the terminal which started it and manages its pidfile,
+ * for deployments which are managed by classic init scripts rather than systemd or containers
+ */
+use crate::handover;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+/**
+ * The daemonized hotdog, which still has to tell the process which started it that it started
+ */
+pub struct Detached {
+    started: File,
+}
+
+impl Detached {
+    /**
+     * Let the process which started hotdog exit successfully, and stop writing to the terminal
+     */
+    pub fn ready(mut self) {
+        let _ = self.started.write_all(b"1");
+        detach_terminal();
+    }
+}
+
+/**
+ * Fork hotdog into the background, returning in the daemon while the process which started it
+ * exits once the daemon is ready, or with an error if the daemon exits before it is ready.
+ *
+ * Forking only copies the calling thread, so this has to happen before any other thread is
+ * started, such as those of the executor
+ */
+pub fn detach() -> std::io::Result<Detached> {
+    let (mut waiting, started) = pipe()?;
+
+    if fork()? != 0 {
+        drop(started);
+        /*
+         * A daemon which fails to start has already printed why before it exits, closing the
+         * pipe without writing to it
+         */
+        let mut ready = [0u8; 1];
+        let code = match waiting.read(&mut ready) {
+            Ok(1) => 0,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+    drop(waiting);
+
+    /*
+     * A new session leaves the controlling terminal behind, and forking once more keeps the
+     * daemon from ever acquiring another one
+     */
+    if unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if fork()? != 0 {
+        std::process::exit(0);
+    }
+
+    Ok(Detached { started })
+}
+
+/**
+ * Write the process id to the pidfile, unless another hotdog holds the lock on it. The returned
+ * file holds the lock for as long as it is open, so it has to be kept until hotdog exits.
+ *
+ * A hotdog which has been handed the listeners replaces the pidfile of the hotdog it is taking
+ * over from, which still holds the lock on the file it replaced
+ */
+pub fn write_pidfile(path: &str) -> std::io::Result<File> {
+    let pid = format!("{}\n", std::process::id());
+
+    if std::env::var_os(handover::ENV_LISTEN_FDS).is_some() {
+        let replacing = format!("{}.{}", path, std::process::id());
+        std::fs::write(&replacing, &pid)?;
+        std::fs::rename(&replacing, path)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EWOULDBLOCK) {
+            return Err(e);
+        }
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            match read_pid(path) {
+                Some(running) => format!("hotdog is already running as {}", running),
+                None => "hotdog is already running".to_string(),
+            },
+        ));
+    }
+    file.set_len(0)?;
+    file.write_all(pid.as_bytes())?;
+    Ok(file)
+}
+
+/**
+ * Remove the pidfile, unless it has already been replaced by the hotdog which took over
+ */
+pub fn remove_pidfile(path: &str) {
+    if read_pid(path) == Some(std::process::id()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn read_pid(path: &str) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn fork() -> std::io::Result<libc::pid_t> {
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
+
+/**
+ * Return the reading and the writing ends of a new pipe
+ */
+fn pipe() -> std::io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/**
+ * Point stdin at /dev/null, along with stdout and stderr when they are the terminal, so that the
+ * output of a daemon whose init script redirected it to a file is kept
+ */
+fn detach_terminal() {
+    let null = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+    {
+        Ok(null) => null,
+        Err(_) => return,
+    };
+
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO].iter() {
+        if *fd == libc::STDIN_FILENO || unsafe { libc::isatty(*fd) } == 1 {
+            unsafe {
+                libc::dup2(null.as_raw_fd(), *fd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile() {
+        let path = std::env::temp_dir().join(format!("hotdog-{}.pid", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let locked = write_pidfile(path).expect("Failed to write the pidfile");
+        assert_eq!(Some(std::process::id()), read_pid(path));
+        drop(locked);
+
+        /*
+         * A pidfile which is not locked was left behind by a hotdog which is no longer running
+         */
+        std::fs::write(path, "1\n").unwrap();
+        let locked = write_pidfile(path).expect("Failed to write the pidfile again");
+        assert_eq!(Some(std::process::id()), read_pid(path));
+
+        remove_pidfile(path);
+        assert_eq!(None, read_pid(path));
+        drop(locked);
+    }
+
+    #[test]
+    fn test_pidfile_of_another_hotdog() {
+        let path = std::env::temp_dir().join(format!("hotdog-{}.pid", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        /*
+         * Every open of the pidfile takes the lock on its own, so the first one stands in for
+         * another hotdog
+         */
+        let locked = write_pidfile(path).expect("Failed to write the pidfile");
+
+        let e = write_pidfile(path).expect_err("Wrote the pidfile of another hotdog");
+        assert_eq!(std::io::ErrorKind::AlreadyExists, e.kind());
+        assert_eq!(Some(std::process::id()), read_pid(path));
+
+        drop(locked);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+/**
+ * The decode module unwraps values which have been encoded, such as base64 payloads or percent
+ * encoded query strings, so that the rules can work with what they actually contain
+ */
+use crate::settings::Encoding;
+
+/**
+ * Enum of the errors which can occur while decoding a value
+ */
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /**
+     * The value was not validly encoded
+     */
+    Invalid(String),
+    /**
+     * The decoded value was not valid UTF-8
+     */
+    NotUtf8,
+}
+
+/**
+ * Decode the value with the given encoding
+ */
+pub fn decode(encoding: &Encoding, value: &str) -> Result<String, DecodeError> {
+    match encoding {
+        Encoding::Base64 => {
+            let value = value.trim();
+            /*
+             * Both the standard and the URL-safe alphabets are common, so fall back to the latter
+             */
+            let bytes = base64::decode(value)
+                .or_else(|_| base64::decode_config(value, base64::URL_SAFE))
+                .map_err(|e| DecodeError::Invalid(e.to_string()))?;
+            String::from_utf8(bytes).map_err(|_| DecodeError::NotUtf8)
+        }
+        Encoding::Url => {
+            /*
+             * Spaces are encoded as `+` in form encoded values, while a literal `+` is always
+             * percent encoded
+             */
+            let value = value.replace('+', " ");
+            percent_encoding::percent_decode_str(&value)
+                .decode_utf8()
+                .map(|decoded| decoded.to_string())
+                .map_err(|_| DecodeError::NotUtf8)
+        }
+    }
+}
+
+/**
+ * Decode the string at the JSON pointer in the buffer, returning the updated buffer.
+ *
+ * Returns None if the buffer is not JSON, or has no string at the pointer
+ */
+pub fn decode_pointer(
+    encoding: &Encoding,
+    buffer: &str,
+    pointer: &str,
+) -> Result<Option<String>, DecodeError> {
+    let mut json: serde_json::Value = match serde_json::from_str(buffer) {
+        Ok(json) => json,
+        Err(_) => return Ok(None),
+    };
+
+    match json.pointer_mut(pointer) {
+        Some(serde_json::Value::String(value)) => {
+            *value = decode(encoding, value)?;
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(json.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(
+            Ok("hello world".to_string()),
+            decode(&Encoding::Base64, "aGVsbG8gd29ybGQ=\n")
+        );
+        assert_eq!(Ok("~~~".to_string()), decode(&Encoding::Base64, "fn5+"));
+        assert_eq!(Ok("~~~".to_string()), decode(&Encoding::Base64, "fn5-"));
+        assert!(matches!(
+            decode(&Encoding::Base64, "not base64!"),
+            Err(DecodeError::Invalid(_))
+        ));
+        assert_eq!(Err(DecodeError::NotUtf8), decode(&Encoding::Base64, "/w=="));
+    }
+
+    #[test]
+    fn test_decode_url() {
+        assert_eq!(
+            Ok("a b+c/d".to_string()),
+            decode(&Encoding::Url, "a+b%2Bc%2Fd")
+        );
+        assert_eq!(Err(DecodeError::NotUtf8), decode(&Encoding::Url, "%FF"));
+    }
+
+    #[test]
+    fn test_decode_pointer() {
+        let decoded = decode_pointer(
+            &Encoding::Base64,
+            r#"{"payload":{"data":"aGk="}}"#,
+            "/payload/data",
+        );
+        assert_eq!(
+            Ok(Some(r#"{"payload":{"data":"hi"}}"#.to_string())),
+            decoded
+        );
+        assert_eq!(
+            Ok(None),
+            decode_pointer(&Encoding::Base64, r#"{"data":1}"#, "/data")
+        );
+        assert_eq!(Ok(None), decode_pointer(&Encoding::Base64, "hi", "/data"));
+    }
+}
@@ -4,8 +4,25 @@
 
 #[derive(Debug)]
 pub enum HotdogError {
-    IOError { err: std::io::Error },
+    IOError {
+        err: std::io::Error,
+    },
     KafkaConnectError,
+    MissingTopicsError {
+        topics: Vec<String>,
+    },
+    /**
+     * The destination is not in the settings the sinks were started for
+     */
+    DestinationMissing {
+        destination: String,
+    },
+    /**
+     * The destination is still connecting to its brokers, or its sinks have been replaced
+     */
+    DestinationUnavailable {
+        destination: String,
+    },
 }
 
 impl std::convert::From<std::io::Error> for HotdogError {
@@ -0,0 +1,122 @@
+/**
+ * The events module publishes hotdog's own lifecycle events, such as starting up or reloading its
+ * settings, as JSON messages to the `events` topic of the Kafka settings, so that the health of
+ * every hotdog can be followed from Kafka itself
+ */
+use crate::kafka::{KafkaMessage, Produce};
+use async_channel::Sender;
+use chrono::prelude::*;
+use log::*;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /**
+     * hotdog has started, with the SHA-256 hash of its configuration file
+     */
+    Started { config_hash: Option<String> },
+    /**
+     * The settings have been reloaded, with the SHA-256 hash of the reloaded configuration file
+     */
+    Reloaded { config_hash: Option<String> },
+    /**
+     * The settings could not be reloaded, and the current settings were kept
+     */
+    ReloadFailed { error: String },
+    /**
+     * A listener could not listen on its address or accept a connection
+     */
+    ListenerFailed { address: String, error: String },
+    /**
+     * A message could not be delivered to its topic
+     */
+    DeliveryFailed { topic: String, error: String },
+}
+
+/**
+ * The JSON message of an event, which identifies the hotdog that published it
+ */
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    timestamp: String,
+    host: String,
+    version: &'static str,
+}
+
+impl Event {
+    fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&Envelope {
+            event: self,
+            timestamp: Utc::now().to_rfc3339(),
+            host: host(),
+            version: env!("CARGO_PKG_VERSION"),
+        })
+    }
+}
+
+/**
+ * Publish the event to the topic through the sender of a Kafka sendloop.
+ *
+ * Events are best effort, they are never waited on so that a full sendloop cannot hold up what
+ * hotdog is doing
+ */
+pub fn publish(sender: &Sender<Produce>, topic: &str, event: Event) {
+    match event.to_json() {
+        Ok(payload) => {
+            let kmsg = KafkaMessage::new(topic.to_string(), payload);
+
+            if let Err(e) = sender.try_send(Produce::Message(kmsg)) {
+                debug!("Failed to publish the event {:?}: {}", event, e);
+            }
+        }
+        Err(e) => error!("Failed to serialize the event {:?}: {}", event, e),
+    }
+}
+
+/**
+ * Return the name of the host which hotdog is running on
+ */
+pub fn host() -> String {
+    hostname::get()
+        .map(|host| host.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::ListenerFailed {
+            address: "0.0.0.0:514".to_string(),
+            error: "Address already in use".to_string(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_slice(&event.to_json().expect("Failed to serialize the event"))
+                .expect("Failed to parse the event");
+
+        assert_eq!("listener_failed", json["event"]);
+        assert_eq!("0.0.0.0:514", json["address"]);
+        assert_eq!(env!("CARGO_PKG_VERSION"), json["version"]);
+        assert!(json["timestamp"].is_string());
+        assert!(json["host"].is_string());
+    }
+
+    #[async_std::test]
+    async fn test_publish() {
+        let (sender, receiver) = async_channel::bounded(1);
+        publish(
+            &sender,
+            "hotdog-events",
+            Event::Started { config_hash: None },
+        );
+
+        match receiver.recv().await {
+            Ok(Produce::Message(kmsg)) => assert_eq!("hotdog-events", kmsg.topic()),
+            other => panic!("Expected the event to be published: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+/**
+ * The exec module runs the external commands of exec actions, limiting how many of them can be
+ * running at once and how long each of them can take
+ */
+use crate::status::{Statistic, Stats};
+use async_channel::{bounded, Receiver, Sender};
+use async_std::{future, prelude::*, task};
+use dashmap::DashMap;
+use log::*;
+use smol::process::{Command, Stdio};
+use std::time::Duration;
+
+/**
+ * Enum of the errors which can occur while running a command
+ */
+#[derive(Debug)]
+pub enum ExecError {
+    /**
+     * The command could not be started, or its input could not be written
+     */
+    Io(std::io::Error),
+    /**
+     * The command exited unsuccessfully
+     */
+    Failed(std::process::ExitStatus),
+    /**
+     * The command did not exit within the timeout, and was killed
+     */
+    Timeout,
+}
+
+impl From<std::io::Error> for ExecError {
+    fn from(e: std::io::Error) -> Self {
+        ExecError::Io(e)
+    }
+}
+
+/**
+ * Executor carries the slots of running commands for each exec action, and should be shared
+ * between all connections
+ */
+pub struct Executor {
+    /**
+     * A bounded channel for each action acts as a semaphore, with a message in the channel for
+     * each running command
+     */
+    slots: DashMap<String, (Sender<()>, Receiver<()>)>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            slots: DashMap::default(),
+        }
+    }
+
+    /**
+     * Spawn the command in the background with the input written to its stdin, unless there are
+     * already `concurrency` commands running for the given key.
+     *
+     * Returns false if the command was skipped
+     */
+    pub fn spawn(
+        &self,
+        key: &str,
+        command: &[String],
+        input: String,
+        concurrency: usize,
+        timeout: Duration,
+        stats: Sender<Statistic>,
+    ) -> bool {
+        let (acquire, release) = self
+            .slots
+            .entry(key.to_string())
+            .or_insert_with(|| bounded(concurrency.max(1)))
+            .clone();
+
+        if acquire.try_send(()).is_err() {
+            return false;
+        }
+
+        let command = command.to_vec();
+        task::spawn(async move {
+            if let Err(e) = run(&command, &input, timeout).await {
+                error!("Failed to execute {:?}: {:?}", command, e);
+                stats.send((Stats::ExecFailed, 1)).await;
+            }
+            let _ = release.recv().await;
+        });
+        true
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new()
+    }
+}
+
+/**
+ * Run the command to completion with the input written to its stdin
+ */
+async fn run(command: &[String], input: &str, timeout: Duration) -> Result<(), ExecError> {
+    let (program, args) = match command.split_first() {
+        Some(split) => split,
+        None => {
+            return Err(ExecError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The command is empty",
+            )))
+        }
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    /*
+     * Writing the input is included in the timeout, since a command which never reads its stdin
+     * would otherwise block the write forever
+     */
+    let completed = future::timeout(timeout, async {
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+        child.status().await
+    })
+    .await;
+
+    match completed {
+        Ok(status) => {
+            let status = status?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(ExecError::Failed(status))
+            }
+        }
+        Err(_) => {
+            let _ = child.kill();
+            Err(ExecError::Timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[async_std::test]
+    async fn test_run_success() {
+        let result = run(&command(&["cat"]), "hello", Duration::from_secs(5)).await;
+        assert!(result.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_run_failed() {
+        let result = run(&command(&["false"]), "", Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(ExecError::Failed(_))));
+    }
+
+    #[async_std::test]
+    async fn test_run_timeout() {
+        let result = run(&command(&["sleep", "5"]), "", Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(ExecError::Timeout)));
+    }
+
+    #[async_std::test]
+    async fn test_spawn_concurrency() {
+        let executor = Executor::new();
+        let (stats, _receiver) = async_channel::unbounded();
+        let sleep = command(&["sleep", "1"]);
+        let timeout = Duration::from_secs(5);
+
+        assert!(executor.spawn("rule", &sleep, String::new(), 1, timeout, stats.clone()));
+        assert!(!executor.spawn("rule", &sleep, String::new(), 1, timeout, stats.clone()));
+        assert!(executor.spawn("other", &sleep, String::new(), 1, timeout, stats));
+    }
+}
@@ -0,0 +1,164 @@
+/**
+ * The geoip module looks up IP addresses in MaxMind GeoIP2/GeoLite2 databases in order to enrich
+ * messages with the country, city, and ASN of the address
+ */
+#[cfg(feature = "geoip")]
+use async_std::sync::Arc;
+#[cfg(feature = "geoip")]
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/**
+ * Enum of the errors which can occur while looking up an address
+ */
+#[derive(Debug)]
+pub enum GeoIpError {
+    /**
+     * The value to look up was not an IP address
+     */
+    InvalidAddress(String),
+    /**
+     * GeoIP lookups were not compiled into this build of hotdog
+     */
+    Unsupported(String),
+    /**
+     * The database could not be opened, or the lookup failed
+     */
+    Database(String),
+}
+
+/**
+ * The Location of an IP address, only the fields which are available in the database are set
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct Location {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+}
+
+impl Location {
+    /**
+     * Return the fields of the location which are set, as JSON values
+     */
+    pub fn fields(&self) -> Vec<(&'static str, serde_json::Value)> {
+        let mut fields = vec![];
+
+        if let Some(country) = &self.country {
+            fields.push(("country", serde_json::Value::from(country.as_str())));
+        }
+        if let Some(city) = &self.city {
+            fields.push(("city", serde_json::Value::from(city.as_str())));
+        }
+        if let Some(asn) = self.asn {
+            fields.push(("asn", serde_json::Value::from(asn)));
+        }
+        if let Some(asn_org) = &self.asn_org {
+            fields.push(("asn_org", serde_json::Value::from(asn_org.as_str())));
+        }
+        fields
+    }
+}
+
+/**
+ * GeoIp carries the cache of opened databases, and should be shared between all connections
+ */
+#[derive(Default)]
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    readers: DashMap<std::path::PathBuf, Arc<maxminddb::Reader<Vec<u8>>>>,
+}
+
+impl GeoIp {
+    pub fn new() -> Self {
+        GeoIp::default()
+    }
+
+    /**
+     * Look up the address in the database at the given path
+     */
+    pub fn lookup(&self, database: &Path, address: &str) -> Result<Location, GeoIpError> {
+        let ip: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| GeoIpError::InvalidAddress(address.to_string()))?;
+        self.lookup_ip(database, ip)
+    }
+
+    #[cfg(feature = "geoip")]
+    fn lookup_ip(&self, database: &Path, ip: IpAddr) -> Result<Location, GeoIpError> {
+        use maxminddb::geoip2;
+
+        let reader = self.reader(database)?;
+        let mut location = Location::default();
+
+        if reader.metadata.database_type.contains("ASN") {
+            let asn: geoip2::Asn = reader
+                .lookup(ip)
+                .map_err(|e| GeoIpError::Database(e.to_string()))?;
+            location.asn = asn.autonomous_system_number;
+            location.asn_org = asn.autonomous_system_organization.map(|o| o.to_string());
+        } else {
+            let city: geoip2::City = reader
+                .lookup(ip)
+                .map_err(|e| GeoIpError::Database(e.to_string()))?;
+            location.country = city.country.and_then(|c| c.iso_code).map(|c| c.to_string());
+            location.city = city
+                .city
+                .and_then(|c| c.names)
+                .and_then(|names| names.get("en").map(|n| n.to_string()));
+        }
+        Ok(location)
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn lookup_ip(&self, _database: &Path, _ip: IpAddr) -> Result<Location, GeoIpError> {
+        Err(GeoIpError::Unsupported(
+            "hotdog was not built with the `geoip` feature".to_string(),
+        ))
+    }
+
+    /**
+     * Return the reader for the database, opening it on first use
+     */
+    #[cfg(feature = "geoip")]
+    fn reader(&self, database: &Path) -> Result<Arc<maxminddb::Reader<Vec<u8>>>, GeoIpError> {
+        if let Some(reader) = self.readers.get(database) {
+            return Ok(reader.value().clone());
+        }
+
+        let reader = Arc::new(
+            maxminddb::Reader::open_readfile(database)
+                .map_err(|e| GeoIpError::Database(e.to_string()))?,
+        );
+        self.readers.insert(database.to_path_buf(), reader.clone());
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_invalid_address() {
+        let geoip = GeoIp::new();
+        let result = geoip.lookup(Path::new("GeoLite2-City.mmdb"), "not-an-ip");
+        assert!(matches!(result, Err(GeoIpError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn test_location_fields() {
+        let location = Location {
+            country: Some("US".to_string()),
+            asn: Some(15169),
+            ..Default::default()
+        };
+        let fields = location.fields();
+        assert_eq!(2, fields.len());
+        assert_eq!(("country", serde_json::Value::from("US")), fields[0]);
+        assert_eq!(("asn", serde_json::Value::from(15169)), fields[1]);
+    }
+}
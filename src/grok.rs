@@ -0,0 +1,265 @@
+/**
+ * The grok module expands grok patterns, e.g. `%{IP:client} %{WORD:method}`, into regular
+ * expressions which can be compiled by the regex crate.
+ *
+ * The built-in pattern library follows the Logstash patterns, adjusted where necessary since the
+ * regex crate does not support look-around or atomic groups.
+ */
+use std::collections::HashMap;
+
+/**
+ * Maximum depth of nested pattern references, which guards against patterns which refer to
+ * themselves
+ */
+const MAX_DEPTH: usize = 32;
+
+/**
+ * The standard pattern library
+ */
+const PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    (
+        "EMAILLOCALPART",
+        r"[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+)*",
+    ),
+    ("EMAILADDRESS", r"%{EMAILLOCALPART}@%{HOSTNAME}"),
+    ("INT", r"(?:[+-]?(?:[0-9]+))"),
+    ("BASE10NUM", r"(?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))"),
+    ("NUMBER", r"(?:%{BASE10NUM})"),
+    ("BASE16NUM", r"(?:[+-]?(?:0x)?(?:[0-9A-Fa-f]+))"),
+    ("POSINT", r"\b(?:[1-9][0-9]*)\b"),
+    ("NONNEGINT", r"\b(?:[0-9]+)\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    (
+        "QUOTEDSTRING",
+        r#"(?:"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|`(?:[^`\\]|\\.)*`)"#,
+    ),
+    ("QS", r"%{QUOTEDSTRING}"),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    ("MAC", r"(?:%{CISCOMAC}|%{WINDOWSMAC}|%{COMMONMAC})"),
+    ("CISCOMAC", r"(?:(?:[A-Fa-f0-9]{4}\.){2}[A-Fa-f0-9]{4})"),
+    ("WINDOWSMAC", r"(?:(?:[A-Fa-f0-9]{2}-){5}[A-Fa-f0-9]{2})"),
+    ("COMMONMAC", r"(?:(?:[A-Fa-f0-9]{2}:){5}[A-Fa-f0-9]{2})"),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)",
+    ),
+    (
+        "IPV6",
+        r"(?:(?:[0-9A-Fa-f]{1,4}:){1,7}:?(?:[0-9A-Fa-f]{1,4})?(?::[0-9A-Fa-f]{1,4})*|::(?:[0-9A-Fa-f]{1,4}:?)*)",
+    ),
+    ("IP", r"(?:%{IPV6}|%{IPV4})"),
+    (
+        "HOSTNAME",
+        r"\b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?",
+    ),
+    ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("PATH", r"(?:%{UNIXPATH}|%{WINPATH})"),
+    ("UNIXPATH", r"(?:/[\w_%!$@:.,+~-]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]+:|\\)(?:\\[^\\?*]*)+"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+\-.]*"),
+    ("URIHOST", r"%{IPORHOST}(?::%{POSINT})?"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+    ("MONTHDAY", r"(?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])"),
+    (
+        "DAY",
+        r"(?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+    ("MINUTE", r"(?:[0-5][0-9])"),
+    ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    ("PROG", r"[\x21-\x5a\x5c\x5e-\x7e]+"),
+    ("SYSLOGPROG", r"%{PROG:program}(?:\[%{POSINT:pid}\])?"),
+    (
+        "LOGLEVEL",
+        r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)",
+    ),
+    (
+        "COMMONAPACHELOG",
+        r#"%{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)"#,
+    ),
+    (
+        "COMBINEDAPACHELOG",
+        r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}",
+    ),
+];
+
+/**
+ * Expand the grok pattern into a regular expression, looking up pattern names first in the
+ * user-defined patterns and then in the standard library.
+ *
+ * `%{NAME}` expands to a non-capturing group, while `%{NAME:variable}` expands to a named group
+ * which is exposed as a variable like any other named group. Logstash's type conversions, e.g.
+ * `%{NUMBER:bytes:int}`, are accepted but ignored since all variables are strings.
+ */
+pub fn expand(pattern: &str, custom: &HashMap<String, String>) -> Result<String, String> {
+    expand_at_depth(pattern, custom, 0)
+}
+
+fn expand_at_depth(
+    pattern: &str,
+    custom: &HashMap<String, String>,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!(
+            "The grok pattern `{}` is nested too deeply, is it recursive?",
+            pattern
+        ));
+    }
+
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut remaining = pattern;
+
+    while let Some(start) = remaining.find("%{") {
+        expanded.push_str(&remaining[..start]);
+        let reference = &remaining[start + 2..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| format!("Unterminated grok reference in `{}`", pattern))?;
+
+        let mut parts = reference[..end].splitn(3, ':');
+        let name = parts.next().unwrap_or("");
+        let variable = parts.next();
+
+        let definition = lookup(name, custom)
+            .ok_or_else(|| format!("Unknown grok pattern `{}` in `{}`", name, pattern))?;
+        let inner = expand_at_depth(definition, custom, depth + 1)?;
+
+        match variable {
+            Some(variable) if !variable.is_empty() => {
+                expanded.push_str(&format!("(?P<{}>{})", group_name(variable), inner));
+            }
+            _ => {
+                expanded.push_str(&format!("(?:{})", inner));
+            }
+        }
+        remaining = &reference[end + 1..];
+    }
+    expanded.push_str(remaining);
+    Ok(expanded)
+}
+
+fn lookup<'a>(name: &str, custom: &'a HashMap<String, String>) -> Option<&'a str> {
+    /*
+     * The keys of maps in the configuration are lowercased when loaded, so user-defined patterns
+     * may not have the same case as their references
+     */
+    if let Some(definition) = custom
+        .get(name)
+        .or_else(|| custom.get(&name.to_lowercase()))
+    {
+        return Some(definition.as_str());
+    }
+    PATTERNS
+        .iter()
+        .find(|(pattern, _)| *pattern == name)
+        .map(|(_, definition)| *definition)
+}
+
+/**
+ * Logstash allows semantic names such as `[http][method]` which are not valid group names for the
+ * regex crate, so anything other than word characters is replaced with an underscore
+ */
+fn group_name(variable: &str) -> String {
+    variable
+        .trim_matches(|c| c == '[' || c == ']')
+        .replace("][", "_")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_named() {
+        let expanded = expand("%{WORD:method} %{NOTSPACE}", &HashMap::new()).unwrap();
+        assert_eq!(r"(?P<method>\b\w+\b) (?:\S+)", expanded);
+    }
+
+    #[test]
+    fn test_expand_unknown_pattern() {
+        assert!(expand("%{NOPE:x}", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_expand_recursive_pattern() {
+        let mut custom = HashMap::new();
+        custom.insert("LOOP".to_string(), "%{LOOP}".to_string());
+        assert!(expand("%{LOOP}", &custom).is_err());
+    }
+
+    #[test]
+    fn test_expand_custom_overrides() {
+        let mut custom = HashMap::new();
+        custom.insert("WORD".to_string(), "[a-z]+".to_string());
+        assert_eq!("(?:[a-z]+)", expand("%{WORD}", &custom).unwrap());
+    }
+
+    #[test]
+    fn test_group_name() {
+        assert_eq!("http_method", group_name("[http][method]"));
+        assert_eq!("client_ip", group_name("client.ip"));
+    }
+
+    #[test]
+    fn test_standard_library_compiles() {
+        for (name, _) in PATTERNS.iter() {
+            let expanded = expand(&format!("%{{{}}}", name), &HashMap::new()).unwrap();
+            assert!(
+                regex::Regex::new(&expanded).is_ok(),
+                "Failed to compile {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_combined_apache_log() {
+        let expanded = expand("^%{COMBINEDAPACHELOG}$", &HashMap::new()).unwrap();
+        let regex = regex::Regex::new(&expanded).unwrap();
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08""#;
+        let captures = regex.captures(line).expect("Failed to match the line");
+        assert_eq!("127.0.0.1", &captures["clientip"]);
+        assert_eq!("GET", &captures["verb"]);
+        assert_eq!("200", &captures["response"]);
+    }
+}
@@ -0,0 +1,286 @@
+/**
+ * The handover module restarts hotdog without closing its listeners, so that an upgrade does not
+ * refuse any connections. On SIGUSR2 a new hotdog is started with the same arguments, inheriting
+ * the file descriptors of the listeners. Once the new hotdog has bound them, this hotdog stops
+ * accepting connections and shuts down once its connections have finished
+ */
+use async_channel::Receiver;
+use log::*;
+use signal_hook::consts::signal::SIGTERM;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+ * The environment variable which passes the listeners to the new hotdog, as `address=fd` pairs
+ * separated by commas
+ */
+pub const ENV_LISTEN_FDS: &str = "HOTDOG_LISTEN_FDS";
+/**
+ * The environment variable which passes the pipe to the new hotdog, which it writes to once every
+ * listener has been bound
+ */
+const ENV_READY_FD: &str = "HOTDOG_READY_FD";
+/**
+ * How long the new hotdog has to bind its listeners, before it is stopped and this hotdog keeps
+ * serving
+ */
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/**
+ * The file descriptors of the bound listeners, by their address
+ */
+static LISTENERS: Mutex<Vec<(String, RawFd)>> = Mutex::new(Vec::new());
+/**
+ * The file descriptors of the listeners handed over by the previous hotdog which have not been
+ * taken yet, or None until they have been read from the environment
+ */
+static INHERITED: Mutex<Option<Vec<(String, RawFd)>>> = Mutex::new(None);
+static HANDED_OVER: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Record the file descriptor of a listener which has been bound to the address, so that it can
+ * be handed over
+ */
+pub fn register(addr: &str, fd: RawFd) {
+    if let Ok(mut listeners) = LISTENERS.lock() {
+        listeners.retain(|(bound, _)| bound != addr);
+        listeners.push((addr.to_string(), fd));
+    }
+}
+
+/**
+ * Forget the listener of the address, once it has been closed
+ */
+pub fn unregister(addr: &str) {
+    if let Ok(mut listeners) = LISTENERS.lock() {
+        listeners.retain(|(bound, _)| bound != addr);
+    }
+}
+
+/**
+ * Take the listener for the address which was handed over by the previous hotdog, if there is one
+ */
+pub fn inherited(addr: &str) -> Option<std::net::TcpListener> {
+    let mut inherited = INHERITED.lock().ok()?;
+    let fds = inherited.get_or_insert_with(|| {
+        std::env::var(ENV_LISTEN_FDS)
+            .map(|fds| parse_fds(&fds))
+            .unwrap_or_default()
+    });
+    let index = fds.iter().position(|(bound, _)| bound == addr)?;
+    let (_, fd) = fds.remove(index);
+
+    /*
+     * The file descriptor was a listener of the previous hotdog, and is removed from the inherited
+     * ones so that it is only ever owned once
+     */
+    Some(unsafe { std::net::TcpListener::from_raw_fd(fd) })
+}
+
+/**
+ * Return whether the listeners have been handed over to a new hotdog, in which case the
+ * connections of this hotdog are given the time to close by themselves
+ */
+pub fn handed_over() -> bool {
+    HANDED_OVER.load(Ordering::Relaxed)
+}
+
+/**
+ * Tell the previous hotdog that every listener has been bound, after which it shuts down. This
+ * does nothing unless hotdog was started by a handover
+ */
+pub fn ready() {
+    let fd = match std::env::var(ENV_READY_FD)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+    {
+        Some(fd) => fd,
+        None => return,
+    };
+    std::env::remove_var(ENV_READY_FD);
+
+    /*
+     * The pipe was created by the previous hotdog for this hotdog alone, which owns it from now on
+     */
+    let mut notify = unsafe { File::from_raw_fd(fd) };
+    if let Err(e) = notify.write_all(b"1") {
+        error!(
+            "Failed to tell the previous hotdog that the listeners are bound: {:?}",
+            e
+        );
+    }
+}
+
+/**
+ * Restart hotdog every time it receives SIGUSR2, until a new hotdog has bound the listeners, at
+ * which point this hotdog shuts down as if it had received SIGTERM
+ */
+pub async fn restart_loop(restarts: Receiver<()>) {
+    while restarts.recv().await.is_ok() {
+        match restart().await {
+            Ok(pid) => {
+                info!("The new hotdog {} has taken over the listeners", pid);
+                HANDED_OVER.store(true, Ordering::Relaxed);
+
+                if let Err(e) = signal_hook::low_level::raise(SIGTERM) {
+                    error!(
+                        "Failed to shut down after handing over the listeners: {:?}",
+                        e
+                    );
+                }
+                return;
+            }
+            Err(e) => error!("Failed to restart hotdog, continuing to serve: {:?}", e),
+        }
+    }
+}
+
+/**
+ * Start a new hotdog with the same arguments, which inherits the file descriptors of the
+ * listeners, and wait until it has bound them, returning its process id. A new hotdog which does
+ * not bind its listeners in time is stopped again
+ */
+async fn restart() -> std::io::Result<u32> {
+    let listeners = LISTENERS
+        .lock()
+        .map(|listeners| listeners.clone())
+        .unwrap_or_default();
+    let fds: Vec<String> = listeners
+        .iter()
+        .map(|(addr, fd)| format!("{}={}", addr, fd))
+        .collect();
+    let (ready, notify) = pipe()?;
+    let inherited: Vec<RawFd> = listeners
+        .iter()
+        .map(|(_, fd)| *fd)
+        .chain(std::iter::once(notify.as_raw_fd()))
+        .collect();
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(ENV_LISTEN_FDS, fds.join(","))
+        .env(ENV_READY_FD, notify.as_raw_fd().to_string());
+    /*
+     * Every file descriptor is close-on-exec, which is only cleared for the new hotdog between
+     * forking and executing it, so that no command started at the same time inherits the listeners
+     */
+    unsafe {
+        command.pre_exec(move || {
+            for fd in inherited.iter() {
+                close_on_exec(*fd, false)?;
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+
+    /*
+     * Only the new hotdog may hold the writing end of the pipe, so that reading it ends once the
+     * new hotdog has exited
+     */
+    drop(notify);
+    let pid = child.id();
+
+    async_std::task::spawn_blocking(move || {
+        let started = wait_ready(ready, READY_TIMEOUT);
+
+        if started.is_err() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        started.map(|_| pid)
+    })
+    .await
+}
+
+/**
+ * Wait until the new hotdog writes to the pipe, failing when it exits or times out first
+ */
+fn wait_ready(mut ready: File, timeout: Duration) -> std::io::Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd: ready.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let polled = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+
+    if polled < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if polled == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("the new hotdog did not bind its listeners in {:?}", timeout),
+        ));
+    }
+
+    let mut byte = [0; 1];
+    match ready.read(&mut byte)? {
+        1 => Ok(()),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "the new hotdog exited before binding its listeners",
+        )),
+    }
+}
+
+/**
+ * Create a pipe whose ends are both close-on-exec
+ */
+fn pipe() -> std::io::Result<(File, File)> {
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (ready, notify) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    close_on_exec(ready.as_raw_fd(), true)?;
+    close_on_exec(notify.as_raw_fd(), true)?;
+    Ok((ready, notify))
+}
+
+fn close_on_exec(fd: RawFd, enabled: bool) -> std::io::Result<()> {
+    let flags = if enabled { libc::FD_CLOEXEC } else { 0 };
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/**
+ * Parse the `address=fd` pairs of the listeners handed over by the previous hotdog, ignoring any
+ * which are malformed
+ */
+fn parse_fds(fds: &str) -> Vec<(String, RawFd)> {
+    fds.split(',')
+        .filter_map(|pair| {
+            let (addr, fd) = pair.rsplit_once('=')?;
+            Some((addr.to_string(), fd.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fds() {
+        assert_eq!(
+            vec![
+                ("0.0.0.0:514".to_string(), 3),
+                ("127.0.0.1:6514".to_string(), 7)
+            ],
+            parse_fds("0.0.0.0:514=3,bogus,127.0.0.1:6514=7,[::]:514=x")
+        );
+        assert!(parse_fds("").is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+/**
+ * The heartbeat module sends a small JSON message to the heartbeat topic every interval, so that
+ * the consumers of the topics can tell a hotdog which has stopped forwarding apart from a source
+ * which has stopped sending logs
+ */
+use crate::events;
+use crate::kafka::{KafkaMessage, Produce};
+use crate::reload::Reloadable;
+use crate::settings::Heartbeat;
+use crate::status::{StatsHandler, Totals};
+use async_std::sync::Arc;
+use chrono::prelude::*;
+use log::*;
+use std::time::Duration;
+
+/**
+ * The JSON message of a heartbeat
+ */
+#[derive(Debug, Serialize)]
+struct Beat<'a> {
+    instance: &'a str,
+    /**
+     * The number of the heartbeat since hotdog started, so that a missed heartbeat is noticeable
+     */
+    sequence: u64,
+    timestamp: String,
+    version: &'static str,
+    uptime_secs: u64,
+    #[serde(flatten)]
+    totals: Totals,
+    dropped: i64,
+    connections: usize,
+}
+
+/**
+ * Send a heartbeat to the topic of the settings every interval, for as long as hotdog runs
+ */
+pub async fn heartbeat_loop(
+    settings: Heartbeat,
+    reloadable: Arc<Reloadable>,
+    stats: Arc<StatsHandler>,
+) {
+    let instance = settings.instance.clone().unwrap_or_else(events::host);
+    let interval = Duration::from_millis(settings.interval_ms);
+    let connections = stats.connections();
+
+    for sequence in 0.. {
+        let beat = Beat {
+            instance: &instance,
+            sequence,
+            timestamp: Utc::now().to_rfc3339(),
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: stats.uptime().as_secs(),
+            totals: stats.totals(),
+            dropped: stats.dropped(),
+            connections: connections.count(),
+        };
+
+        match serde_json::to_vec(&beat) {
+            Ok(payload) => {
+                let kmsg = KafkaMessage::new(settings.topic.clone(), payload);
+
+                if let Err(e) = reloadable.sinks().sender.send(Produce::Message(kmsg)).await {
+                    error!("Failed to send the heartbeat {}: {}", sequence, e);
+                }
+            }
+            Err(e) => error!("Failed to serialize the heartbeat {:?}: {}", beat, e),
+        }
+
+        async_std::task::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beat_json() {
+        let beat = Beat {
+            instance: "edge-1",
+            sequence: 3,
+            timestamp: Utc::now().to_rfc3339(),
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: 30,
+            totals: Totals {
+                lines: 120,
+                matches: 100,
+                forwarded: 99,
+                errors: 1,
+            },
+            dropped: 20,
+            connections: 2,
+        };
+        let json = serde_json::to_value(&beat).expect("Failed to serialize the heartbeat");
+
+        assert_eq!("edge-1", json["instance"]);
+        assert_eq!(3, json["sequence"]);
+        assert_eq!(120, json["lines"]);
+        assert_eq!(99, json["forwarded"]);
+        assert_eq!(20, json["dropped"]);
+        assert_eq!(2, json["connections"]);
+    }
+}
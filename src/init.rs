@@ -0,0 +1,177 @@
+/**
+ * The init module generates a commented example configuration, which new users can start from
+ * rather than writing their first configuration from scratch
+ */
+use std::io::Write;
+
+/**
+ * The optional sections to include in the example configuration
+ */
+#[derive(Debug, Default)]
+pub struct Options {
+    /**
+     * Listen for syslog over TLS rather than in plaintext
+     */
+    pub tls: bool,
+    /**
+     * Enable the HTTP status server
+     */
+    pub status: bool,
+}
+
+const HEADER: &str = r#"# hotdog configuration, generated by `hotdog init`
+#
+# Every section is described in detail in the README:
+#   https://github.com/rtyler/hotdog
+---
+global:
+  listen:
+    # The address and port on which hotdog listens for syslog messages
+    address: '0.0.0.0'
+"#;
+
+const PLAINTEXT: &str = r#"    port: 1514
+"#;
+
+const TLS: &str = r#"    port: 6514
+    # The PEM-encoded certificate and key to serve syslog over TLS with, the
+    # `ca` is optional and enables the verification of client certificates
+    tls:
+      cert: './cert.pem'
+      key: './cert-key.pem'
+      #ca: './ca.pem'
+"#;
+
+const STATUS: &str = r#"
+  # Serves hotdog's statistics as JSON on /stats
+  status:
+    address: '127.0.0.1'
+    port: 8585
+"#;
+
+const FOOTER: &str = r#"
+  kafka:
+    # Any librdkafka configuration, such as the SASL or SSL settings, can be
+    # added to the conf
+    conf:
+      bootstrap.servers: 'localhost:9092'
+    # The topic for messages which are forwarded without a topic
+    topic: 'hotdog'
+
+  # hotdog reports its metrics to statsd
+  metrics:
+    statsd: 'localhost:8125'
+
+# The rules are evaluated in order against every message, until one of them
+# stops the processing
+rules:
+  # Match JSON messages which have a meta.topic value, e.g.
+  #   {"meta":{"topic" : "foo"}}
+  # and forward them to that topic
+  - jmespath: 'meta.topic'
+    field: msg
+    actions:
+      - type: merge
+        json:
+          meta:
+            hotdog:
+              version: '{{version}}'
+              timestamp: '{{iso8601}}'
+
+      - type: forward
+        topic: '{{value}}'
+
+      - type: stop
+
+  # Catch-all, send everything else to the "logs-unknown" topic
+  - regex: '.*'
+    field: msg
+    actions:
+      - type: forward
+        topic: 'logs-unknown'
+
+      - type: stop
+"#;
+
+/**
+ * Return the example configuration with the given options
+ */
+pub fn example(options: &Options) -> String {
+    let mut example = HEADER.to_string();
+
+    if options.tls {
+        example.push_str(TLS);
+    } else {
+        example.push_str(PLAINTEXT);
+    }
+
+    if options.status {
+        example.push_str(STATUS);
+    }
+    example.push_str(FOOTER);
+    example
+}
+
+/**
+ * Write the example configuration to the file, which must not exist yet unless it should be
+ * overwritten
+ */
+pub fn init(file: &str, options: &Options, overwrite: bool) -> std::io::Result<()> {
+    let mut open = std::fs::OpenOptions::new();
+    open.write(true);
+
+    if overwrite {
+        open.create(true).truncate(true);
+    } else {
+        open.create_new(true);
+    }
+
+    open.open(file)?.write_all(example(options).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{self, Overrides, TlsType};
+
+    fn parse(options: &Options, name: &str) -> settings::Settings {
+        let file = std::env::temp_dir().join(format!("hotdog-init-{}.yml", name));
+        let file = file
+            .to_str()
+            .expect("Failed to use the temporary directory");
+
+        init(file, options, true).expect("Failed to write the example");
+        let settings = settings::parse(file, &Overrides::default());
+        let _ = std::fs::remove_file(file);
+
+        settings.expect("Failed to parse the example")
+    }
+
+    #[test]
+    fn test_example() {
+        let settings = parse(&Options::default(), "default");
+
+        assert_eq!(1514, settings.global.listen.port);
+        assert_eq!(TlsType::None, settings.global.listen.tls);
+        assert!(settings.global.status.is_none());
+        assert_eq!(2, settings.rules.len());
+    }
+
+    #[test]
+    fn test_example_with_options() {
+        let options = Options {
+            tls: true,
+            status: true,
+        };
+        let settings = parse(&options, "options");
+
+        assert_eq!(6514, settings.global.listen.port);
+        assert_ne!(TlsType::None, settings.global.listen.tls);
+        assert!(settings.global.status.is_some());
+    }
+
+    #[test]
+    fn test_init_existing() {
+        assert!(init("hotdog.yml", &Options::default(), false).is_err());
+    }
+}
@@ -1,33 +1,352 @@
-use crate::status::{Statistic, Stats};
+use crate::events::{self, Event};
+use crate::sampled;
+use crate::settings::{OversizePolicy, SendTimeoutPolicy};
+use crate::spool::Spool;
+use crate::status::{self, Statistic, Stats};
+use crate::trace::{Span, Trace};
 use async_channel::{bounded, Receiver, Sender};
 /**
  * The Kafka module contains all the tooling/code necessary for connecting hotdog to Kafka for
  * sending log lines along as Kafka messages
  */
 use async_std::task;
+use bytes::Bytes;
 use log::*;
-use rdkafka::client::DefaultClientContext;
+use rdkafka::client::ClientContext;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{BaseConsumer, Consumer};
 use rdkafka::error::{KafkaError, RDKafkaErrorCode};
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::producer::{BaseProducer, FutureProducer, FutureRecord, Producer};
+use rdkafka::statistics::Statistics;
 use rdkafka::util::Timeout;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 /**
- * KafkaMessage just carries a message and its destination topic between tasks
+ * KafkaMessage just carries a message, its destination topic, and optionally its record key
+ * between tasks
  */
-#[derive(Debug)]
 pub struct KafkaMessage {
     topic: String,
-    msg: String,
+    /**
+     * The payload, which is reference counted so that a message fanned out to several topics or
+     * split into several messages shares its bytes rather than copying them
+     */
+    msg: Bytes,
+    key: Option<String>,
+    /**
+     * The trace of the line the message was built from, which its delivery is recorded in
+     */
+    trace: Trace,
+    /**
+     * When the line the message was built from was read, for the end-to-end latency
+     */
+    received: Option<Instant>,
+    /**
+     * Closed once the message and every message split from it have been delivered, failed or
+     * dropped, for a connection which waits for its messages to settle to preserve their order
+     * and for a replay which waits for all of its messages
+     */
+    settled: Vec<Sender<()>>,
+    /**
+     * The rule which produced the message, which is only recorded when the rules are tested
+     */
+    rule: Option<String>,
 }
 
 impl KafkaMessage {
-    pub fn new(topic: String, msg: String) -> KafkaMessage {
-        KafkaMessage { topic, msg }
+    pub fn new<M: Into<Bytes>>(topic: String, msg: M) -> KafkaMessage {
+        KafkaMessage {
+            topic,
+            msg: msg.into(),
+            key: None,
+            trace: Trace::default(),
+            received: None,
+            settled: vec![],
+            rule: None,
+        }
+    }
+
+    /**
+     * Return the topic the message is destined for
+     */
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /**
+     * Return the record key of the message, if it has one
+     */
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /**
+     * Return the payload of the message
+     */
+    pub fn payload(&self) -> &[u8] {
+        &self.msg
+    }
+
+    /**
+     * Set the record key used by Kafka for partitioning the message
+     */
+    pub fn with_key(mut self, key: Option<String>) -> KafkaMessage {
+        self.key = key;
+        self
+    }
+
+    /**
+     * Return the rule which produced the message, if it was recorded
+     */
+    pub fn rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+
+    /**
+     * Record the rule which produced the message
+     */
+    pub fn with_rule(mut self, rule: Option<String>) -> KafkaMessage {
+        self.rule = rule;
+        self
+    }
+
+    /**
+     * Set the trace which the delivery of the message is recorded in
+     */
+    pub fn with_trace(mut self, trace: Trace) -> KafkaMessage {
+        self.trace = trace;
+        self
+    }
+
+    /**
+     * Return the size of the payload of the message in bytes
+     */
+    pub fn size(&self) -> usize {
+        self.msg.len()
+    }
+
+    /**
+     * Return when the line the message was built from was read, if it was built from a line
+     */
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    /**
+     * Set when the line the message was built from was read
+     */
+    pub fn with_received(mut self, received: Instant) -> KafkaMessage {
+        self.received = Some(received);
+        self
+    }
+
+    /**
+     * Add a channel which is closed once the message has settled, by dropping the sender along
+     * with the message
+     */
+    pub fn with_settled(mut self, settled: Sender<()>) -> KafkaMessage {
+        self.settled.push(settled);
+        self
+    }
+
+    /**
+     * Record the time from reading the line to Kafka acknowledging the message
+     */
+    async fn acknowledged(&self, stats: &Sender<Statistic>) {
+        if let Some(received) = self.received {
+            stats
+                .send((Stats::LineLatency, status::elapsed_us(received)))
+                .await;
+        }
+    }
+
+    /**
+     * Create the record to send to Kafka for this message to the given topic
+     */
+    fn record<'a>(&'a self, topic: &'a str) -> FutureRecord<'a, String, [u8]> {
+        let record = FutureRecord::to(topic).payload(&self.msg[..]);
+
+        match &self.key {
+            Some(key) => record.key(key),
+            None => record,
+        }
+    }
+}
+
+impl std::fmt::Debug for KafkaMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("KafkaMessage")
+            .field("topic", &self.topic)
+            .field("key", &self.key)
+            .field("msg", &String::from_utf8_lossy(&self.msg))
+            .finish()
+    }
+}
+
+/**
+ * Produce is what is actually passed into the Kafka sendloop, either a single message or a batch
+ * of messages which must be written atomically in a single Kafka transaction
+ */
+#[derive(Debug)]
+pub enum Produce {
+    Message(KafkaMessage),
+    Transaction(Vec<KafkaMessage>),
+}
+
+/**
+ * StatsContext receives the statistics periodically emitted by librdkafka, when
+ * `statistics.interval.ms` is configured, and records them alongside hotdog's own metrics
+ */
+pub struct StatsContext {
+    stats: Sender<Statistic>,
+}
+
+impl ClientContext for StatsContext {
+    fn stats(&self, statistics: Statistics) {
+        /*
+         * This callback is invoked on a librdkafka thread, so try_send must be used rather than
+         * awaiting the channel
+         */
+        let _ = self
+            .stats
+            .try_send((Stats::KafkaClientQueueDepth, statistics.msg_cnt as i64));
+        let _ = self
+            .stats
+            .try_send((Stats::KafkaClientTxBytes, statistics.tx_bytes as i64));
+
+        for broker in statistics.brokers.values() {
+            /*
+             * The bootstrap brokers are reported with a node id of -1 in addition to their real
+             * node id, so skip those
+             */
+            if broker.nodeid < 0 {
+                continue;
+            }
+
+            if let Some(rtt) = &broker.rtt {
+                let _ = self.stats.try_send((
+                    Stats::KafkaBrokerRtt {
+                        broker: broker.nodeid.to_string(),
+                    },
+                    rtt.avg as i64,
+                ));
+            }
+        }
+    }
+}
+
+/**
+ * DeliveryPolicy describes how hotdog should behave when a message cannot be delivered to Kafka
+ */
+#[derive(Clone, Debug)]
+pub struct DeliveryPolicy {
+    /**
+     * The number of times a message will be retried after a retriable error
+     */
+    pub retries: u32,
+    /**
+     * The base amount of time to wait between retries, multiplied by the attempt number
+     */
+    pub backoff: Duration,
+    /**
+     * The topic to route messages to which could not be delivered
+     */
+    pub dlq: Option<String>,
+    /**
+     * The topic to publish an event to for every message which could not be delivered
+     */
+    pub events: Option<String>,
+    /**
+     * How long to wait for room in the producer's queue before failing a send
+     */
+    pub queue_timeout: Duration,
+    /**
+     * Whether the producer should be initialized for transactions
+     */
+    pub transactional: bool,
+    /**
+     * The largest message, in bytes, which will be sent to Kafka without applying the oversize
+     * policy
+     */
+    pub max_message_bytes: usize,
+    /**
+     * What to do with messages which are larger than max_message_bytes
+     */
+    pub oversize: OversizePolicy,
+    /**
+     * The marker appended to messages which have been truncated
+     */
+    pub truncation_marker: String,
+    /**
+     * The maximum number of messages which may be awaiting delivery at once, once reached the
+     * sendloop stops pulling messages from its channel which in turn exerts backpressure on the
+     * connections reading logs
+     */
+    pub max_in_flight: usize,
+    /**
+     * How long the messages of a topic are collected before they are delivered together
+     */
+    pub collect: Duration,
+    /**
+     * The number of messages of a topic which are delivered together at most
+     */
+    pub collect_messages: usize,
+    /**
+     * The spool which messages are written to when Kafka cannot be reached, rather than failing
+     */
+    pub spool: Option<Arc<Spool>>,
+    /**
+     * How long a message may wait for its delivery report before the send timeout policy is
+     * applied to it
+     */
+    pub send_timeout: Option<Duration>,
+    pub send_timeout_policy: SendTimeoutPolicy,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> DeliveryPolicy {
+        DeliveryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            dlq: None,
+            events: None,
+            queue_timeout: Duration::from_secs(60),
+            transactional: false,
+            max_in_flight: 10_000,
+            max_message_bytes: 1_000_000,
+            oversize: OversizePolicy::Drop,
+            truncation_marker: String::from("...[truncated]"),
+            collect: Duration::from_millis(0),
+            collect_messages: 500,
+            spool: None,
+            send_timeout: None,
+            send_timeout_policy: SendTimeoutPolicy::Retry,
+        }
+    }
+}
+
+impl From<&crate::settings::Kafka> for DeliveryPolicy {
+    fn from(conf: &crate::settings::Kafka) -> DeliveryPolicy {
+        DeliveryPolicy {
+            retries: conf.retries,
+            backoff: Duration::from_millis(conf.retry_backoff_ms),
+            dlq: conf.dlq.clone(),
+            events: conf.events.clone(),
+            queue_timeout: Duration::from_millis(conf.producer.queue_timeout_ms),
+            transactional: conf.transactional,
+            max_in_flight: conf.max_in_flight,
+            max_message_bytes: conf.max_message_bytes,
+            oversize: conf.oversize,
+            truncation_marker: conf.truncation_marker.clone(),
+            collect: Duration::from_millis(conf.batch.collect_ms),
+            collect_messages: conf.batch.collect_messages,
+            spool: None,
+            send_timeout: conf.send_timeout_ms.map(Duration::from_millis),
+            send_timeout_policy: conf.send_timeout_policy,
+        }
     }
 }
 
@@ -40,17 +359,24 @@ pub struct Kafka {
      * that I can think to create an effective two-phase construction of this struct between
      * ::new() and the .connect() function
      */
-    producer: Option<FutureProducer<DefaultClientContext>>,
+    producer: Option<FutureProducer<StatsContext>>,
+    policy: DeliveryPolicy,
+    /**
+     * The topics which existed on the cluster when hotdog connected
+     */
+    topics: Vec<String>,
     stats: Sender<Statistic>,
-    rx: Receiver<KafkaMessage>,
-    tx: Sender<KafkaMessage>,
+    rx: Receiver<Produce>,
+    tx: Sender<Produce>,
 }
 
 impl Kafka {
-    pub fn new(message_max: usize, stats: Sender<Statistic>) -> Kafka {
+    pub fn new(message_max: usize, policy: DeliveryPolicy, stats: Sender<Statistic>) -> Kafka {
         let (tx, rx) = bounded(message_max);
         Kafka {
             producer: None,
+            policy,
+            topics: vec![],
             stats,
             tx,
             rx,
@@ -84,9 +410,20 @@ impl Kafka {
             rd_conf.set("bootstrap.servers", &broker);
         }
 
-        let consumer: BaseConsumer = rd_conf
-            .create()
-            .expect("Creation of Kafka consumer (for metadata) failed");
+        /*
+         * Creating the clients only fails for a configuration which librdkafka rejects, which is
+         * retried like any other failure to connect rather than crashing hotdog
+         */
+        let consumer: BaseConsumer = match rd_conf.create() {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                error!(
+                    "Failed to create the Kafka consumer for the metadata: {}",
+                    e
+                );
+                return false;
+            }
+        };
 
         let timeout = match timeout_ms {
             Some(ms) => ms,
@@ -99,11 +436,41 @@ impl Kafka {
             debug!("  Metadata broker name: {}", metadata.orig_broker_name());
             debug!("  Metadata broker id: {}\n", metadata.orig_broker_id());
 
-            self.producer = Some(
-                rd_conf
-                    .create()
-                    .expect("Failed to create the Kafka producer!"),
-            );
+            self.topics = metadata
+                .topics()
+                .iter()
+                .map(|t| t.name().to_string())
+                .collect();
+
+            if self.policy.transactional && rd_conf.get("transactional.id").is_none() {
+                let id = format!("hotdog-{}", uuid::Uuid::new_v4());
+                warn!(
+                    "No `transactional.id` was configured, using a generated one: {}",
+                    id
+                );
+                rd_conf.set("transactional.id", &id);
+            }
+
+            let context = StatsContext {
+                stats: self.stats.clone(),
+            };
+            let producer: FutureProducer<StatsContext> = match rd_conf.create_with_context(context)
+            {
+                Ok(producer) => producer,
+                Err(e) => {
+                    error!("Failed to create the Kafka producer: {}", e);
+                    return false;
+                }
+            };
+
+            if self.policy.transactional {
+                if let Err(e) = producer.init_transactions(timeout) {
+                    error!("Failed to initialize the producer for transactions: {}", e);
+                    return false;
+                }
+            }
+
+            self.producer = Some(producer);
 
             return true;
         }
@@ -113,100 +480,796 @@ impl Kafka {
         false
     }
 
+    /**
+     * Return the topics from the given list which did not exist on the cluster at the time that
+     * hotdog connected
+     */
+    pub fn missing_topics(&self, expected: &[String]) -> Vec<String> {
+        expected
+            .iter()
+            .filter(|t| !self.topics.contains(t))
+            .cloned()
+            .collect()
+    }
+
+    /**
+     * Return a handle to the underlying producer, which can be used to flush it independently of
+     * the sendloop
+     */
+    pub fn get_producer(&self) -> Option<FutureProducer<StatsContext>> {
+        self.producer.clone()
+    }
+
     /**
      * get_sender() will return a cloned reference to the sender suitable for tasks or threads to
      * consume and take ownership of
      */
-    pub fn get_sender(&self) -> Sender<KafkaMessage> {
+    pub fn get_sender(&self) -> Sender<Produce> {
         self.tx.clone()
     }
 
     /**
-     * sendloop should be called in a thread/task and will never return
+     * sendloop should be called in a thread/task and will only return once the channel has been
+     * closed, which happens when the Kafka settings are reloaded
      */
-    pub async fn sendloop(&self) -> ! {
+    pub async fn sendloop(&self) {
         if self.producer.is_none() {
             panic!("Cannot enter the sendloop() without a valid producer");
         }
 
         let producer = self.producer.as_ref().unwrap();
+        /*
+         * The permits channel acts as a semaphore: a permit is sent before each delivery is
+         * spawned and received once it completes, so a full channel means we are at the limit
+         */
+        let (permit_tx, permit_rx) = bounded::<()>(self.policy.max_in_flight.max(1));
+
+        let mut collected = Collected::new(self.policy.collect, self.policy.collect_messages);
 
         loop {
-            if let Ok(kmsg) = self.rx.recv().await {
-                debug!("Sending to Kafka: {:?}", kmsg);
-                /* Note, setting the `K` (key) type on FutureRecord to a string
-                 * even though we're explicitly not sending a key
-                 */
-                let stats = self.stats.clone();
+            /*
+             * While messages are being collected, the sendloop only waits until the oldest of
+             * them are due to be delivered
+             */
+            let received = match collected.next_due(Instant::now()) {
+                Some(due) => match async_std::future::timeout(due, self.rx.recv()).await {
+                    Ok(received) => Some(received),
+                    Err(_) => None,
+                },
+                None => Some(self.rx.recv().await),
+            };
+
+            let produce = match received {
+                Some(Ok(produce)) => produce,
+                Some(Err(_)) => break,
+                None => {
+                    for batch in collected.due(Instant::now()).into_iter() {
+                        self.send_batch(producer, batch, &permit_tx, &permit_rx)
+                            .await;
+                    }
+                    continue;
+                }
+            };
+
+            let kmsg = match produce {
+                Produce::Message(kmsg) => kmsg,
+                Produce::Transaction(batch) => {
+                    /*
+                     * The messages collected so far are delivered first, so that they are not
+                     * overtaken by the transaction
+                     */
+                    for collected in collected.drain().into_iter() {
+                        self.send_batch(producer, collected, &permit_tx, &permit_rx)
+                            .await;
+                    }
+                    /*
+                     * Transactions are processed inline, since a producer can only have a
+                     * single open transaction at a time
+                     */
+                    let limited = self.limit_batch(batch).await;
+                    if !limited.is_empty() {
+                        self.transact(producer, limited).await;
+                    }
+                    continue;
+                }
+            };
+
+            for kmsg in self.limit(kmsg).await.into_iter() {
+                if let Some(batch) = collected.push(kmsg, Instant::now()) {
+                    self.send_batch(producer, batch, &permit_tx, &permit_rx)
+                        .await;
+                }
+            }
+        }
+
+        for batch in collected.drain().into_iter() {
+            self.send_batch(producer, batch, &permit_tx, &permit_rx)
+                .await;
+        }
+    }
+
+    /**
+     * Apply the oversize policy to a message which is larger than `max_message_bytes`, returning
+     * the messages (if any) which should be sent in its place
+     */
+    async fn limit(&self, kmsg: KafkaMessage) -> Vec<KafkaMessage> {
+        if kmsg.msg.len() <= self.policy.max_message_bytes {
+            return vec![kmsg];
+        }
+
+        warn!(
+            topic = kmsg.topic.as_str(), error = "oversize";
+            "Message for {} is {} bytes which exceeds the maximum of {}, applying the {:?} policy",
+            kmsg.topic,
+            kmsg.msg.len(),
+            self.policy.max_message_bytes,
+            self.policy.oversize
+        );
+        self.stats.send((Stats::KafkaMsgOversize, 1)).await;
+
+        let messages = oversize(kmsg, &self.policy);
+        if messages.is_empty() {
+            self.stats.send(status::dropped("oversize")).await;
+        }
+        messages
+    }
+
+    /**
+     * Apply the oversize policy to every message of a transaction, which would otherwise be
+     * aborted by the brokers for the sake of a single message which is too large
+     */
+    async fn limit_batch(&self, batch: Vec<KafkaMessage>) -> Vec<KafkaMessage> {
+        let mut limited = vec![];
+        for kmsg in batch.into_iter() {
+            limited.extend(self.limit(kmsg).await);
+        }
+        limited
+    }
+
+    /**
+     * Deliver a batch of collected messages. A transactional producer cannot send anything outside
+     * of a transaction, so there every batch is sent as a transaction of its own
+     */
+    async fn send_batch(
+        &self,
+        producer: &FutureProducer<StatsContext>,
+        batch: Vec<KafkaMessage>,
+        permit_tx: &Sender<()>,
+        permit_rx: &Receiver<()>,
+    ) {
+        if self.policy.transactional {
+            self.transact(producer, batch).await;
+        } else {
+            /*
+             * The permits of a whole batch are taken before it is delivered, so a batch larger than
+             * the messages which may be in flight would wait for its own permits forever
+             */
+            let mut batch = batch;
+            let max_in_flight = self.policy.max_in_flight.max(1);
+
+            while batch.len() > max_in_flight {
+                let rest = batch.split_off(max_in_flight);
+                self.spawn_delivery(producer, batch, permit_tx, permit_rx)
+                    .await;
+                batch = rest;
+            }
+            self.spawn_delivery(producer, batch, permit_tx, permit_rx)
+                .await;
+        }
+    }
+
+    /**
+     * Deliver the batch of messages in a task of its own, waiting for a permit for every message
+     * of the batch first
+     */
+    async fn spawn_delivery(
+        &self,
+        producer: &FutureProducer<StatsContext>,
+        batch: Vec<KafkaMessage>,
+        permit_tx: &Sender<()>,
+        permit_rx: &Receiver<()>,
+    ) {
+        debug!("Sending to Kafka: {:?}", batch);
+        let stats = self.stats.clone();
+        let producer = producer.clone();
+        let policy = self.policy.clone();
+        let permit_rx = permit_rx.clone();
+        let sender = self.tx.clone();
+        let count = batch.len();
+
+        /*
+         * Needed in order to prevent concurrent writers from totally
+         * killing parallel performance
+         */
+        task::yield_now().await;
+
+        for _ in 0..count {
+            if permit_tx.is_full() {
+                debug!("Maximum number of in-flight messages reached, applying backpressure");
+            }
+            permit_tx.send(()).await;
+        }
+        stats.send((Stats::KafkaQueueDepth, count as i64)).await;
+
+        task::spawn(async move {
+            let start_time = Instant::now();
+            let deliveries = batch.into_iter().map(|kmsg| {
+                deliver(
+                    producer.clone(),
+                    kmsg,
+                    policy.clone(),
+                    stats.clone(),
+                    sender.clone(),
+                    start_time,
+                )
+            });
+            futures::future::join_all(deliveries).await;
+
+            for _ in 0..count {
+                permit_rx.recv().await;
+            }
+            stats.send((Stats::KafkaQueueDepth, -(count as i64))).await;
+        });
+    }
+
+    /**
+     * Send the batch of messages inside of a single Kafka transaction, aborting the transaction
+     * if any of the messages cannot be delivered
+     */
+    async fn transact(&self, producer: &FutureProducer<StatsContext>, batch: Vec<KafkaMessage>) {
+        debug!("Sending a transaction to Kafka: {:?}", batch);
+        let timeout = Timeout::After(self.policy.queue_timeout);
+
+        if let Err(e) = producer.begin_transaction() {
+            error!("Failed to begin a Kafka transaction: {}", e);
+            self.stats.send((Stats::KafkaTransactionAborted, 1)).await;
+            for _ in batch.iter() {
+                self.stats.send(status::dropped("delivery_failed")).await;
+            }
+            return;
+        }
+
+        for kmsg in batch.iter() {
+            self.stats
+                .send((
+                    Stats::KafkaMsgProduced {
+                        topic: kmsg.topic.clone(),
+                    },
+                    1,
+                ))
+                .await;
+        }
 
-                let start_time = Instant::now();
-                let producer = producer.clone();
+        let deliveries = batch.iter().map(|kmsg| {
+            let record = kmsg.record(&kmsg.topic);
+            producer.send(record, timeout)
+        });
 
+        let results = futures::future::join_all(deliveries).await;
+
+        if results.iter().all(|r| r.is_ok()) {
+            /*
+             * Committing and aborting block until the brokers have answered, so they are kept off
+             * of the executor
+             */
+            let committing = producer.clone();
+            let committed =
+                task::spawn_blocking(move || committing.commit_transaction(timeout)).await;
+
+            match committed {
+                Ok(_) => {
+                    for kmsg in batch.into_iter() {
+                        self.stats
+                            .send((
+                                Stats::KafkaMsgSubmitted {
+                                    topic: kmsg.topic.clone(),
+                                },
+                                1,
+                            ))
+                            .await;
+                        kmsg.acknowledged(&self.stats).await;
+                        self.stats
+                            .send((Stats::KafkaMsgDelivered { topic: kmsg.topic }, 1))
+                            .await;
+                    }
+                    self.stats.send((Stats::KafkaTransactionCommitted, 1)).await;
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to commit the Kafka transaction: {}", e);
+                }
+            }
+        } else {
+            error!("Failed to send every message in the transaction, aborting");
+        }
+
+        let aborting = producer.clone();
+        if let Err(e) = task::spawn_blocking(move || aborting.abort_transaction(timeout)).await {
+            error!("Failed to abort the Kafka transaction: {}", e);
+        }
+        for kmsg in batch.into_iter() {
+            self.stats
+                .send((Stats::KafkaMsgFailed { topic: kmsg.topic }, 1))
+                .await;
+            self.stats.send(status::dropped("delivery_failed")).await;
+        }
+        self.stats.send((Stats::KafkaTransactionAborted, 1)).await;
+    }
+}
+
+/**
+ * Flush any messages which are still waiting in the sendloop's channel or the producer's queue,
+ * waiting no longer than the given timeout
+ */
+pub async fn flush(
+    producer: FutureProducer<StatsContext>,
+    pending: &Sender<Produce>,
+    timeout: Duration,
+) {
+    let deadline = Instant::now() + timeout;
+
+    while !pending.is_empty() && Instant::now() < deadline {
+        task::sleep(Duration::from_millis(100)).await;
+    }
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    info!("Flushing {} messages to Kafka", producer.in_flight_count());
+    /*
+     * Flushing blocks the calling thread, so keep it off of the executor
+     */
+    task::spawn_blocking(move || {
+        producer.flush(Timeout::After(remaining));
+    })
+    .await;
+}
+
+/**
+ * Deliver a single message to Kafka, retrying with a backoff when librdkafka reports an error
+ * which is likely to be transient.
+ *
+ * Messages which fail permanently, or which exhaust their retries, are routed to the dead letter
+ * topic when one has been configured.
+ */
+async fn deliver(
+    producer: FutureProducer<StatsContext>,
+    kmsg: KafkaMessage,
+    policy: DeliveryPolicy,
+    stats: Sender<Statistic>,
+    sender: Sender<Produce>,
+    start_time: Instant,
+) {
+    let mut attempt = 0;
+    let span = Span::start("kafka.send", &kmsg.trace);
+    span.set("topic", kmsg.topic.clone());
+
+    stats
+        .send((
+            Stats::KafkaMsgProduced {
+                topic: kmsg.topic.clone(),
+            },
+            1,
+        ))
+        .await;
+
+    /*
+     * Whether the last attempt timed out waiting for its delivery report
+     */
+    let timed_out = loop {
+        let record = kmsg.record(&kmsg.topic);
+        let timeout = Timeout::After(policy.queue_timeout);
+        let sending = producer.send(record, timeout);
+
+        /*
+         * A delivery which timed out may still be delivered by librdkafka, so retrying or
+         * spooling it may deliver it twice
+         */
+        let sent = match policy.send_timeout {
+            Some(send_timeout) => async_std::future::timeout(send_timeout, sending).await.ok(),
+            None => Some(sending.await),
+        };
+
+        match sent {
+            Some(Ok(_)) => {
+                stats
+                    .send((
+                        Stats::KafkaMsgSubmitted {
+                            topic: kmsg.topic.clone(),
+                        },
+                        1,
+                    ))
+                    .await;
+                stats
+                    .send((
+                        Stats::KafkaMsgDelivered {
+                            topic: kmsg.topic.clone(),
+                        },
+                        1,
+                    ))
+                    .await;
+                kmsg.acknowledged(&stats).await;
                 /*
-                 * Needed in order to prevent concurrent writers from totally
-                 * killing parallel performance
+                 * dipstick only supports u64 timers anyways, but as_micros() can
+                 * give a u128 (!).
                  */
-                task::yield_now().await;
+                if let Ok(elapsed) = start_time.elapsed().as_micros().try_into() {
+                    stats
+                        .send((Stats::KafkaMsgSent { topic: kmsg.topic }, elapsed))
+                        .await;
+                } else {
+                    error!("Could not collect message time because the duration couldn't fit in an i64, yikes");
+                }
+                return;
+            }
+            failed => {
+                let timed_out = failed.is_none();
+                /*
+                 * err_type will be one of RdKafkaError types defined:
+                 * https://docs.rs/rdkafka/0.23.1/rdkafka/error/enum.RDKafkaError.html
+                 */
+                let (errcode, retriable, err) = match failed {
+                    Some(Err((KafkaError::MessageProduction(err_type), _))) => (
+                        metric_name_for(err_type),
+                        is_retriable(err_type),
+                        KafkaError::MessageProduction(err_type).to_string(),
+                    ),
+                    Some(Err((err, _))) => (String::from("generic"), false, err.to_string()),
+                    _ => {
+                        stats.send((Stats::KafkaMsgTimedOut, 1)).await;
+                        (
+                            String::from("send_timeout"),
+                            policy.send_timeout_policy != SendTimeoutPolicy::Drop,
+                            format!("no delivery report within {:?}", policy.send_timeout),
+                        )
+                    }
+                };
 
-                task::spawn(async move {
-                    let record = FutureRecord::<String, String>::to(&kmsg.topic).payload(&kmsg.msg);
-                    let timeout = Timeout::After(Duration::from_secs(60));
-                    /*
-                     * Intentionally setting the timeout_ms to -1 here so this blocks forever if the
-                     * outbound librdkafka queue is full. This will block up the crossbeam channel
-                     * properly and cause messages to begin to be dropped, rather than buffering
-                     * "forever" inside of hotdog
-                     */
-                    match producer.send(record, timeout).await {
-                        Ok(_) => {
-                            stats
-                                .send((Stats::KafkaMsgSubmitted { topic: kmsg.topic }, 1))
-                                .await;
-                            /*
-                             * dipstick only supports u64 timers anyways, but as_micros() can
-                             * give a u128 (!).
-                             */
-                            if let Ok(elapsed) = start_time.elapsed().as_micros().try_into() {
-                                stats.send((Stats::KafkaMsgSent, elapsed)).await;
-                            } else {
-                                error!("Could not collect message time because the duration couldn't fit in an i64, yikes");
+                stats
+                    .send((
+                        Stats::KafkaMsgErrored {
+                            errcode: errcode.clone(),
+                        },
+                        1,
+                    ))
+                    .await;
+
+                let spool_now = timed_out && policy.send_timeout_policy == SendTimeoutPolicy::Spool;
+
+                if retriable && attempt < policy.retries && !spool_now {
+                    attempt += 1;
+                    warn!(
+                        topic = kmsg.topic.as_str(), error = errcode.as_str();
+                        "Failed to send message to Kafka due to a retriable error ({}), retry {} of {}",
+                        errcode, attempt, policy.retries
+                    );
+                    stats.send((Stats::KafkaMsgRetried, 1)).await;
+                    span.set("retries", attempt.to_string());
+                    task::sleep(policy.backoff * attempt).await;
+                    continue;
+                }
+
+                /*
+                 * Messages which could not be delivered because Kafka could not be reached are
+                 * spooled until it can be, if there is room for them
+                 */
+                if retriable {
+                    if let Some(spool) = &policy.spool {
+                        match spool.append(&kmsg).await {
+                            Ok(true) => {
+                                debug!(
+                                    "Spooled a message for {} until Kafka can be reached",
+                                    kmsg.topic
+                                );
+                                stats.send((Stats::KafkaMsgSpooled, 1)).await;
+                                return;
                             }
-                        }
-                        Err((err, _)) => {
-                            match err {
-                                /*
-                                 * err_type will be one of RdKafkaError types defined:
-                                 * https://docs.rs/rdkafka/0.23.1/rdkafka/error/enum.RDKafkaError.html
-                                 */
-                                KafkaError::MessageProduction(err_type) => {
-                                    error!("Failed to send message to Kafka due to: {}", err_type);
-                                    stats
-                                        .send((
-                                            Stats::KafkaMsgErrored {
-                                                errcode: metric_name_for(err_type),
-                                            },
-                                            1,
-                                        ))
-                                        .await;
-                                }
-                                _ => {
-                                    error!("Failed to send message to Kafka!");
-                                    stats
-                                        .send((
-                                            Stats::KafkaMsgErrored {
-                                                errcode: String::from("generic"),
-                                            },
-                                            1,
-                                        ))
-                                        .await;
-                                }
+                            Ok(false) => {
+                                sampled::SPOOL_FULL.error(format_args!(
+                                    "The spool is full, failing the message for {}",
+                                    kmsg.topic
+                                ));
+                            }
+                            Err(e) => {
+                                error!("Failed to spool the message for {}: {}", kmsg.topic, e)
                             }
                         }
                     }
-                });
+                }
+
+                error!(
+                    topic = kmsg.topic.as_str(), error = errcode.as_str();
+                    "Failed to send message to Kafka due to a {} error ({}): {}",
+                    if retriable { "retriable" } else { "permanent" },
+                    errcode,
+                    err
+                );
+
+                /*
+                 * The failures of the events themselves are not published, they would only fail
+                 * again
+                 */
+                if let Some(topic) = policy.events.as_ref().filter(|t| *t != &kmsg.topic) {
+                    events::publish(
+                        &sender,
+                        topic,
+                        Event::DeliveryFailed {
+                            topic: kmsg.topic.clone(),
+                            error: errcode.clone(),
+                        },
+                    );
+                }
+                span.fail(errcode);
+                break timed_out;
+            }
+        }
+    };
+
+    stats
+        .send((
+            Stats::KafkaMsgFailed {
+                topic: kmsg.topic.clone(),
+            },
+            1,
+        ))
+        .await;
+
+    if timed_out && policy.send_timeout_policy == SendTimeoutPolicy::Drop {
+        stats.send((Stats::KafkaMsgDropped, 1)).await;
+        stats.send(status::dropped("send_timeout")).await;
+        return;
+    }
+
+    if let Some(dlq) = &policy.dlq {
+        if dlq != &kmsg.topic {
+            let record = kmsg.record(dlq);
+            let timeout = Timeout::After(policy.queue_timeout);
+            let sending = producer.send(record, timeout);
+
+            /*
+             * The dead letter topic is held to the same send timeout, so that a message whose
+             * brokers are not responding does not wait for its delivery report indefinitely
+             */
+            let sent = match policy.send_timeout {
+                Some(send_timeout) => async_std::future::timeout(send_timeout, sending)
+                    .await
+                    .map_or(false, |sent| sent.is_ok()),
+                None => sending.await.is_ok(),
+            };
+
+            if sent {
+                stats.send((Stats::KafkaMsgDeadLettered, 1)).await;
+                return;
             }
+            error!(
+                topic = kmsg.topic.as_str(), error = "dead_letter_failed";
+                "Failed to send message to the dead letter topic: {}", dlq
+            );
         }
     }
+    stats.send((Stats::KafkaMsgDropped, 1)).await;
+    stats.send(status::dropped("delivery_failed")).await;
+}
+
+/**
+ * Check that the librdkafka configuration is valid, such as having no unknown properties, without
+ * connecting to the brokers
+ */
+pub fn validate_conf(rdkafka_conf: &HashMap<String, String>) -> Result<(), String> {
+    let mut rd_conf = ClientConfig::new();
+
+    for (key, value) in rdkafka_conf.iter() {
+        rd_conf.set(key, value);
+    }
+
+    rd_conf
+        .create::<BaseProducer>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/**
+ * Kafka only allows ASCII alphanumerics, `.`, `_`, and `-` in topic names, up to 249 characters.
+ *
+ * Any invalid characters in the given topic are replaced with the replacement string. None is
+ * returned when the result still cannot be used as a topic name, e.g. when it is empty.
+ */
+pub fn sanitize_topic(topic: &str, replacement: &str) -> Option<String> {
+    let mut sanitized = String::with_capacity(topic.len());
+
+    for c in topic.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' {
+            sanitized.push(c);
+        } else {
+            sanitized.push_str(replacement);
+        }
+    }
+
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." || sanitized.len() > 249 {
+        return None;
+    }
+    Some(sanitized)
+}
+
+/**
+ * Collected holds the messages of every topic which the sendloop is collecting, until they are
+ * delivered together once the oldest of them has waited for the collect time or there are enough
+ * of them
+ */
+struct Collected {
+    collect: Duration,
+    max_messages: usize,
+    /**
+     * The messages of every topic, along with when the first of them arrived
+     */
+    topics: HashMap<String, (Instant, Vec<KafkaMessage>)>,
+}
+
+impl Collected {
+    fn new(collect: Duration, max_messages: usize) -> Self {
+        Collected {
+            collect,
+            max_messages,
+            topics: HashMap::new(),
+        }
+    }
+
+    /**
+     * Add the message to the messages of its topic, returning them once they should be delivered.
+     *
+     * A message whose connection waits for it to settle is delivered right away along with the
+     * messages collected before it, since the connection sends nothing else until it has
+     */
+    fn push(&mut self, kmsg: KafkaMessage, now: Instant) -> Option<Vec<KafkaMessage>> {
+        if self.collect.as_millis() == 0 || self.max_messages <= 1 {
+            return Some(vec![kmsg]);
+        }
+
+        if !kmsg.settled.is_empty() {
+            let mut messages = self
+                .topics
+                .remove(&kmsg.topic)
+                .map(|(_, messages)| messages)
+                .unwrap_or_default();
+            messages.push(kmsg);
+            return Some(messages);
+        }
+
+        let (_, messages) = self
+            .topics
+            .entry(kmsg.topic.clone())
+            .or_insert_with(|| (now, vec![]));
+        messages.push(kmsg);
+
+        if messages.len() >= self.max_messages {
+            let topic = messages[0].topic.clone();
+            return self.topics.remove(&topic).map(|(_, messages)| messages);
+        }
+        None
+    }
+
+    /**
+     * Return how long until the oldest messages are due to be delivered, if any are collected
+     */
+    fn next_due(&self, now: Instant) -> Option<Duration> {
+        self.topics
+            .values()
+            .map(|(first, _)| (*first + self.collect).saturating_duration_since(now))
+            .min()
+    }
+
+    /**
+     * Remove the messages of every topic which have been collected for long enough
+     */
+    fn due(&mut self, now: Instant) -> Vec<Vec<KafkaMessage>> {
+        let due: Vec<String> = self
+            .topics
+            .iter()
+            .filter(|(_, (first, _))| now >= *first + self.collect)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        due.iter()
+            .filter_map(|topic| self.topics.remove(topic))
+            .map(|(_, messages)| messages)
+            .collect()
+    }
+
+    /**
+     * Remove the messages of every topic, regardless of how long they have been collected
+     */
+    fn drain(&mut self) -> Vec<Vec<KafkaMessage>> {
+        self.topics
+            .drain()
+            .map(|(_, (_, messages))| messages)
+            .collect()
+    }
+}
+
+/**
+ * Apply the oversize policy to a message which is too large to be sent to Kafka, returning the
+ * messages (if any) which should be sent in its place
+ */
+fn oversize(kmsg: KafkaMessage, policy: &DeliveryPolicy) -> Vec<KafkaMessage> {
+    let max = policy.max_message_bytes.max(1);
+
+    match policy.oversize {
+        OversizePolicy::Truncate => vec![truncate(kmsg, max, &policy.truncation_marker)],
+        OversizePolicy::Split => (0..kmsg.msg.len())
+            .step_by(max)
+            .map(|start| KafkaMessage {
+                topic: kmsg.topic.clone(),
+                msg: kmsg.msg.slice(start..(start + max).min(kmsg.msg.len())),
+                key: kmsg.key.clone(),
+                trace: kmsg.trace.clone(),
+                received: kmsg.received,
+                settled: kmsg.settled.clone(),
+                rule: kmsg.rule.clone(),
+            })
+            .collect(),
+        /*
+         * The brokers would reject the message on the dead letter topic just the same, so it is
+         * truncated there
+         */
+        OversizePolicy::Dlq => match &policy.dlq {
+            Some(dlq) => vec![KafkaMessage {
+                topic: dlq.to_string(),
+                ..truncate(kmsg, max, &policy.truncation_marker)
+            }],
+            None => {
+                warn!("Oversize message cannot be sent to the dead letter topic since none is configured");
+                vec![]
+            }
+        },
+        OversizePolicy::Drop => vec![],
+    }
+}
+
+/**
+ * Truncate the payload of the message to at most `max` bytes, including the marker appended to
+ * it, which is itself cut short when it is longer than `max`
+ */
+fn truncate(mut kmsg: KafkaMessage, max: usize, marker: &str) -> KafkaMessage {
+    let marker = marker.as_bytes();
+    let marker = &marker[..char_boundary(marker, max)];
+    let cut = char_boundary(&kmsg.msg, max - marker.len());
+
+    kmsg.msg = [&kmsg.msg[..cut], marker].concat().into();
+    kmsg
+}
+
+/**
+ * Return the largest index of the bytes up to `at` which does not cut a multi-byte UTF-8
+ * character in half
+ */
+fn char_boundary(bytes: &[u8], mut at: usize) -> usize {
+    if at >= bytes.len() {
+        return bytes.len();
+    }
+    while at > 0 && (bytes[at] & 0xC0) == 0x80 {
+        at -= 1;
+    }
+    at
+}
+
+/**
+ * Determine whether the given error is likely to be transient, meaning that the delivery of the
+ * message should be retried.
+ */
+fn is_retriable(err: RDKafkaErrorCode) -> bool {
+    matches!(
+        err,
+        RDKafkaErrorCode::QueueFull
+            | RDKafkaErrorCode::MessageTimedOut
+            | RDKafkaErrorCode::OperationTimedOut
+            | RDKafkaErrorCode::RequestTimedOut
+            | RDKafkaErrorCode::BrokerTransportFailure
+            | RDKafkaErrorCode::AllBrokersDown
+            | RDKafkaErrorCode::BrokerNotAvailable
+            | RDKafkaErrorCode::LeaderNotAvailable
+            | RDKafkaErrorCode::NotLeaderForPartition
+            | RDKafkaErrorCode::NetworkException
+            | RDKafkaErrorCode::NotEnoughReplicas
+            | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+    )
 }
 
 /**
@@ -236,10 +1299,20 @@ mod tests {
         );
         let (unused_sender, _) = bounded(1);
 
-        let mut k = Kafka::new(1, unused_sender);
+        let mut k = Kafka::new(1, DeliveryPolicy::default(), unused_sender);
         assert_eq!(false, k.connect(&conf, Some(Duration::from_secs(1))));
     }
 
+    #[test]
+    fn test_missing_topics() {
+        let (unused_sender, _) = bounded(1);
+        let mut k = Kafka::new(1, DeliveryPolicy::default(), unused_sender);
+        k.topics = vec!["logs".to_string()];
+
+        let missing = k.missing_topics(&["logs".to_string(), "other".to_string()]);
+        assert_eq!(vec!["other".to_string()], missing);
+    }
+
     /**
      * Tests for converting RDKafkaError strings into statsd suitable metric strings
      */
@@ -261,4 +1334,198 @@ mod tests {
     fn test_metric_name_3() {
         assert_eq!("readonly", metric_name_for(RDKafkaErrorCode::ReadOnly));
     }
+
+    fn oversize_policy(oversize: OversizePolicy) -> DeliveryPolicy {
+        DeliveryPolicy {
+            max_message_bytes: 20,
+            oversize,
+            dlq: Some("dlq".to_string()),
+            ..DeliveryPolicy::default()
+        }
+    }
+
+    fn large_message() -> KafkaMessage {
+        KafkaMessage::new("logs".to_string(), vec![b'a'; 50])
+    }
+
+    #[test]
+    fn test_oversize_truncate() {
+        let messages = oversize(large_message(), &oversize_policy(OversizePolicy::Truncate));
+        assert_eq!(1, messages.len());
+        assert_eq!(20, messages[0].msg.len());
+        assert!(messages[0].msg.ends_with(b"...[truncated]"));
+    }
+
+    #[test]
+    fn test_oversize_split() {
+        let messages = oversize(large_message(), &oversize_policy(OversizePolicy::Split));
+        assert_eq!(3, messages.len());
+        assert_eq!("logs", messages[2].topic);
+        assert_eq!(10, messages[2].msg.len());
+    }
+
+    #[test]
+    fn test_oversize_split_keeps_key() {
+        let kmsg = large_message().with_key(Some("trace-1".to_string()));
+        let messages = oversize(kmsg, &oversize_policy(OversizePolicy::Split));
+        assert!(messages
+            .iter()
+            .all(|m| m.key == Some("trace-1".to_string())));
+    }
+
+    #[async_std::test]
+    async fn test_split_settled_once_dropped() {
+        let (settled, waiting) = bounded::<()>(1);
+        let kmsg = large_message().with_settled(settled);
+        let mut messages = oversize(kmsg, &oversize_policy(OversizePolicy::Split));
+
+        messages.truncate(1);
+        assert!(!waiting.is_closed());
+        drop(messages);
+        assert!(waiting.recv().await.is_err());
+    }
+
+    #[test]
+    fn test_oversize_truncate_long_marker() {
+        let policy = DeliveryPolicy {
+            truncation_marker: "...[this message was truncated]".to_string(),
+            ..oversize_policy(OversizePolicy::Truncate)
+        };
+        let messages = oversize(large_message(), &policy);
+        assert_eq!(1, messages.len());
+        assert_eq!(b"...[this message was", &messages[0].msg[..]);
+    }
+
+    #[test]
+    fn test_oversize_truncate_multibyte() {
+        let kmsg = KafkaMessage::new("logs".to_string(), "ü".repeat(20));
+        let messages = oversize(kmsg, &oversize_policy(OversizePolicy::Truncate));
+        assert!(std::str::from_utf8(&messages[0].msg).is_ok());
+        assert!(messages[0].msg.len() <= 20);
+    }
+
+    #[test]
+    fn test_oversize_dlq() {
+        let messages = oversize(large_message(), &oversize_policy(OversizePolicy::Dlq));
+        assert_eq!(1, messages.len());
+        assert_eq!("dlq", messages[0].topic);
+        assert_eq!(20, messages[0].msg.len());
+        assert!(messages[0].msg.ends_with(b"...[truncated]"));
+    }
+
+    #[async_std::test]
+    async fn test_limit_transaction() {
+        let (stats, counted) = async_channel::unbounded();
+        let k = Kafka::new(1, oversize_policy(OversizePolicy::Split), stats);
+        let batch = vec![
+            KafkaMessage::new("logs".to_string(), b"small".to_vec()),
+            large_message(),
+        ];
+
+        let limited = k.limit_batch(batch).await;
+        assert_eq!(4, limited.len());
+        assert!(limited.iter().all(|kmsg| kmsg.msg.len() <= 20));
+        assert_eq!(Ok((Stats::KafkaMsgOversize, 1)), counted.try_recv());
+    }
+
+    #[test]
+    fn test_oversize_drop() {
+        let messages = oversize(large_message(), &oversize_policy(OversizePolicy::Drop));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_topic_valid() {
+        assert_eq!(
+            Some("logs.app-1_x".to_string()),
+            sanitize_topic("logs.app-1_x", "_")
+        );
+    }
+
+    #[test]
+    fn test_sanitize_topic_invalid_chars() {
+        assert_eq!(
+            Some("logs_my_app".to_string()),
+            sanitize_topic("logs/my app", "_")
+        );
+    }
+
+    #[test]
+    fn test_sanitize_topic_empty() {
+        assert_eq!(None, sanitize_topic("", "_"));
+        assert_eq!(None, sanitize_topic("..", "_"));
+    }
+
+    #[test]
+    fn test_is_retriable() {
+        assert!(is_retriable(RDKafkaErrorCode::QueueFull));
+        assert!(is_retriable(RDKafkaErrorCode::MessageTimedOut));
+    }
+
+    #[test]
+    fn test_is_not_retriable() {
+        assert!(!is_retriable(RDKafkaErrorCode::MessageSizeTooLarge));
+        assert!(!is_retriable(RDKafkaErrorCode::TopicAuthorizationFailed));
+    }
+
+    fn message(topic: &str) -> KafkaMessage {
+        KafkaMessage::new(topic.to_string(), b"hello".to_vec())
+    }
+
+    #[test]
+    fn test_collected_disabled() {
+        let mut collected = Collected::new(Duration::from_millis(0), 500);
+        let now = Instant::now();
+
+        assert_eq!(1, collected.push(message("logs"), now).unwrap().len());
+        assert_eq!(None, collected.next_due(now));
+    }
+
+    #[test]
+    fn test_collected_full() {
+        let mut collected = Collected::new(Duration::from_millis(50), 2);
+        let now = Instant::now();
+
+        assert!(collected.push(message("logs"), now).is_none());
+        assert!(collected.push(message("audit"), now).is_none());
+        assert_eq!(2, collected.push(message("logs"), now).unwrap().len());
+        assert_eq!(1, collected.drain().len());
+    }
+
+    #[test]
+    fn test_collected_settled() {
+        let mut collected = Collected::new(Duration::from_millis(50), 500);
+        let now = Instant::now();
+        let (settled, _waiting) = bounded::<()>(1);
+
+        assert!(collected.push(message("logs"), now).is_none());
+        assert!(collected.push(message("audit"), now).is_none());
+
+        let batch = collected
+            .push(message("logs").with_settled(settled), now)
+            .expect("Failed to deliver the settled message right away");
+        assert_eq!(2, batch.len());
+        assert!(!batch[1].settled.is_empty());
+        assert_eq!(1, collected.drain().len());
+    }
+
+    #[test]
+    fn test_collected_due() {
+        let mut collected = Collected::new(Duration::from_millis(50), 500);
+        let now = Instant::now();
+
+        collected.push(message("logs"), now);
+        collected.push(message("audit"), now + Duration::from_millis(20));
+
+        assert_eq!(Some(Duration::from_millis(50)), collected.next_due(now));
+        assert!(collected.due(now).is_empty());
+
+        let due = collected.due(now + Duration::from_millis(60));
+        assert_eq!(1, due.len());
+        assert_eq!("logs", due[0][0].topic());
+        assert_eq!(
+            Some(Duration::from_millis(10)),
+            collected.next_due(now + Duration::from_millis(60))
+        );
+    }
 }
@@ -0,0 +1,145 @@
+/**
+ * The kv module extracts `key=value` pairs from messages, such as the ones commonly emitted by
+ * firewalls and audit logs
+ */
+
+/**
+ * Parse the key-value pairs out of the input, in the order they appear.
+ *
+ * Pairs are split on the `separator`, and keys from values on the `delimiter`. Values may be
+ * wrapped in any of the `quotes` characters in order to contain the separator, with a backslash
+ * escaping the quote inside of the value. Tokens without a delimiter or with an empty key are
+ * skipped
+ */
+pub fn parse(input: &str, separator: &str, delimiter: &str, quotes: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        if !separator.is_empty() && rest.starts_with(separator) {
+            rest = &rest[separator.len()..];
+            continue;
+        }
+
+        let key_end = find(rest, delimiter);
+        let token_end = find(rest, separator);
+
+        let key_end = match (key_end, token_end) {
+            (Some(key_end), Some(token_end)) if key_end < token_end => key_end,
+            (Some(key_end), None) => key_end,
+            (_, token_end) => {
+                rest = &rest[token_end.unwrap_or(rest.len())..];
+                continue;
+            }
+        };
+
+        let key = rest[..key_end].trim();
+        rest = &rest[key_end + delimiter.len()..];
+
+        let value = match rest.chars().next() {
+            Some(quote) if quotes.contains(quote) => {
+                let (value, remaining) = quoted(&rest[quote.len_utf8()..], quote);
+                rest = remaining;
+                value
+            }
+            _ => {
+                let end = find(rest, separator).unwrap_or(rest.len());
+                let value = rest[..end].to_string();
+                rest = &rest[end..];
+                value
+            }
+        };
+
+        if !key.is_empty() {
+            pairs.push((key.to_string(), value));
+        }
+    }
+    pairs
+}
+
+/**
+ * Find the pattern in the input, an empty pattern is never found
+ */
+fn find(input: &str, pattern: &str) -> Option<usize> {
+    if pattern.is_empty() {
+        None
+    } else {
+        input.find(pattern)
+    }
+}
+
+/**
+ * Read the quoted value up until the closing quote, returning the unescaped value and whatever
+ * follows the closing quote. An unterminated value runs to the end of the input
+ */
+fn quoted(input: &str, quote: char) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        if c == quote {
+            return (value, &input[index + c.len_utf8()..]);
+        }
+
+        if c == '\\' {
+            if let Some((_, escaped)) = chars.clone().next() {
+                if escaped == quote || escaped == '\\' {
+                    chars.next();
+                    value.push(escaped);
+                    continue;
+                }
+            }
+        }
+        value.push(c);
+    }
+    (value, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(input: &[(&str, &str)]) -> Vec<(String, String)> {
+        input
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            pairs(&[("src", "10.0.0.1"), ("dst", "10.0.0.2"), ("action", "")]),
+            parse("src=10.0.0.1  dst=10.0.0.2 action=", " ", "=", "\"'")
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted() {
+        assert_eq!(
+            pairs(&[("msg", "hello \"world\""), ("user", "tyler croy")]),
+            parse(
+                r#"msg="hello \"world\"" user='tyler croy'"#,
+                " ",
+                "=",
+                "\"'"
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_tokens_without_delimiter() {
+        assert_eq!(
+            pairs(&[("proto", "tcp")]),
+            parse("DROP IN proto=tcp =ignored", " ", "=", "\"")
+        );
+    }
+
+    #[test]
+    fn test_parse_custom_separators() {
+        assert_eq!(
+            pairs(&[("a", "1"), ("b", "2 3")]),
+            parse("a: 1, b: 2 3", ", ", ": ", "")
+        );
+    }
+}
@@ -0,0 +1,342 @@
+/**
+ * The logging module configures hotdog's own logging from the `global.log` settings
+ */
+use crate::settings::{Log, LogFormat};
+use log::{kv, LevelFilter};
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::sync::{Mutex, RwLock};
+
+/**
+ * The levels in the order in which `-v` raises them
+ */
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/**
+ * The logger which is installed the first time `init` is called, and whose configuration is
+ * replaced by every later call
+ */
+static LOGGER: Reconfigurable = Reconfigurable {
+    logger: RwLock::new(None),
+};
+
+/**
+ * Reconfigurable forwards the records to the logger of the current configuration, since a logger
+ * can only be installed once, but hotdog logs before its settings have been loaded
+ */
+struct Reconfigurable {
+    logger: RwLock<Option<Configured>>,
+}
+
+/**
+ * Configured is the logger of the current configuration, which writes to stderr unless a file to
+ * log to has been configured
+ */
+struct Configured {
+    logger: env_logger::Logger,
+    format: LogFormat,
+    file: Option<Mutex<LineWriter<File>>>,
+}
+
+impl log::Log for Reconfigurable {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match self.logger.read() {
+            Ok(logger) => logger
+                .as_ref()
+                .map_or(false, |c| c.logger.enabled(metadata)),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Ok(logger) = self.logger.read() {
+            match logger.as_ref() {
+                Some(Configured {
+                    logger,
+                    format,
+                    file: Some(file),
+                }) => {
+                    if logger.matches(record) {
+                        if let Ok(mut file) = file.lock() {
+                            let _ = writeln!(file, "{}", line_for(record, *format));
+                        }
+                    }
+                }
+                Some(configured) => configured.logger.log(record),
+                None => {}
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(logger) = self.logger.read() {
+            if let Some(configured) = logger.as_ref() {
+                configured.logger.flush();
+
+                if let Some(Ok(mut file)) = configured.file.as_ref().map(|f| f.lock()) {
+                    let _ = file.flush();
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Initialize the logger with the level and format of the settings, or reconfigure it once the
+ * settings have been loaded.
+ *
+ * The `RUST_LOG` environment variable takes precedence over the configured level, and the `-v`
+ * and `-q` arguments raise or silence hotdog's own logs from there, so that the verbosity can be
+ * changed for a single run without changing the configuration
+ */
+pub fn init(log: &Log, verbosity: i64) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    let configured = std::env::var("RUST_LOG").ok().or_else(|| log.level.clone());
+    let filters = filters_for(configured.as_deref(), verbosity);
+
+    if let Some(filters) = filters {
+        builder.parse_filters(&filters);
+    }
+
+    if log.format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_for(record)));
+    }
+
+    let logger = builder.build();
+    let level = logger.filter();
+
+    /*
+     * Without a file to log to, e.g. because its directory does not exist, hotdog rather logs to
+     * stderr than not at all
+     */
+    let file = log.file.as_ref().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(Mutex::new(LineWriter::new(file))),
+            Err(e) => {
+                eprintln!("Failed to open the log file {:?}: {}", path, e);
+                None
+            }
+        }
+    });
+
+    match LOGGER.logger.write() {
+        Ok(mut current) => {
+            let installed = current.is_some();
+            *current = Some(Configured {
+                logger,
+                format: log.format,
+                file,
+            });
+
+            if !installed {
+                if let Err(e) = log::set_logger(&LOGGER) {
+                    eprintln!("Failed to initialize the logger: {}", e);
+                }
+            }
+            log::set_max_level(level);
+        }
+        Err(e) => eprintln!("Failed to configure the logger: {}", e),
+    }
+}
+
+/**
+ * Return the verbosity of the number of `-v` and `-q` arguments, where `-q` logs nothing at all
+ * however many `-v` arguments there are alongside it
+ */
+pub fn verbosity(verbose: u64, quiet: u64) -> i64 {
+    if quiet > 0 {
+        -1
+    } else {
+        verbose as i64
+    }
+}
+
+/**
+ * Return the filters for the verbosity, which is either the number of `-v` arguments or negative
+ * for `-q`, on top of the configured filters. Every `-v` raises the level of hotdog's own
+ * logs by one from the level the configured filters give them, while the filters of its
+ * dependencies are kept as they were configured
+ */
+fn filters_for(configured: Option<&str>, verbosity: i64) -> Option<String> {
+    if verbosity < 0 {
+        return Some("off".to_string());
+    }
+    if verbosity == 0 {
+        return configured.map(String::from);
+    }
+
+    /*
+     * Without any filters only errors are logged, otherwise hotdog logs at the level of its own
+     * filter or of the default one, if there is either
+     */
+    let (directives, regex) = match configured {
+        Some(configured) => match configured.split_once('/') {
+            Some((directives, regex)) => (directives, Some(regex)),
+            None => (configured, None),
+        },
+        None => ("error", None),
+    };
+    let mut own = None;
+    let mut default = None;
+    let mut kept = vec![];
+
+    for directive in directives.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some(("hotdog", level)) => {
+                own = level.parse::<LevelFilter>().ok().or(own);
+                continue;
+            }
+            None => default = directive.parse::<LevelFilter>().ok().or(default),
+            _ => {}
+        }
+        if !directive.is_empty() {
+            kept.push(directive.to_string());
+        }
+    }
+    let level = own.or(default).unwrap_or(LevelFilter::Off);
+
+    let current = LEVELS.iter().position(|l| *l == level).unwrap_or(0);
+    let raised = LEVELS[(current + verbosity as usize).min(LEVELS.len() - 1)];
+    kept.push(format!("hotdog={}", raised.to_string().to_lowercase()));
+
+    let mut filters = kept.join(",");
+    if let Some(regex) = regex {
+        filters = format!("{}/{}", filters, regex);
+    }
+    Some(filters)
+}
+
+/**
+ * Return the JSON object for the record, with the key-values of the record, such as the `peer`,
+ * `rule`, `topic`, or `error`, as fields alongside the `timestamp`, `level`, `target`, and
+ * `message`
+ */
+fn json_for(record: &log::Record) -> Value {
+    let mut fields = Fields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+
+    let mut json = fields.0;
+    json.insert(
+        "timestamp".to_string(),
+        Value::from(chrono::Utc::now().to_rfc3339()),
+    );
+    json.insert("level".to_string(), Value::from(record.level().to_string()));
+    json.insert("target".to_string(), Value::from(record.target()));
+    json.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+    Value::Object(json)
+}
+
+/**
+ * Return the line to write to the log file for the record, in the configured format
+ */
+fn line_for(record: &log::Record, format: LogFormat) -> String {
+    match format {
+        LogFormat::Json => json_for(record).to_string(),
+        LogFormat::Text => format!(
+            "{} {:<5} {} > {}",
+            chrono::Utc::now().to_rfc3339(),
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+    }
+}
+
+/**
+ * Fields collects the key-values of a record as strings
+ */
+struct Fields(Map<String, Value>);
+
+impl<'kvs> kv::Visitor<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0
+            .insert(key.to_string(), Value::from(value.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_for() {
+        assert_eq!(Some("off".to_string()), filters_for(Some("info"), -1));
+        /*
+         * `-q` silences the logs even alongside `-v`
+         */
+        assert_eq!(
+            Some("off".to_string()),
+            filters_for(Some("info"), verbosity(1, 1))
+        );
+        assert_eq!(
+            Some("info,hotdog=trace".to_string()),
+            filters_for(Some("info"), verbosity(2, 0))
+        );
+        assert_eq!(None, filters_for(None, 0));
+        assert_eq!(Some("info".to_string()), filters_for(Some("info"), 0));
+        assert_eq!(Some("error,hotdog=warn".to_string()), filters_for(None, 1));
+        assert_eq!(Some("error,hotdog=trace".to_string()), filters_for(None, 5));
+        /*
+         * The level is raised from the configured one, and the dependencies keep theirs
+         */
+        assert_eq!(
+            Some("info,hotdog=debug".to_string()),
+            filters_for(Some("info"), 1)
+        );
+        assert_eq!(
+            Some("warn,rdkafka=debug,hotdog=trace".to_string()),
+            filters_for(Some("warn,hotdog=debug,rdkafka=debug"), 2)
+        );
+        assert_eq!(
+            Some("rdkafka=warn,hotdog=error/kafka".to_string()),
+            filters_for(Some("rdkafka=warn/kafka"), 1)
+        );
+    }
+
+    #[test]
+    fn test_json_for() {
+        let fields = vec![("peer", "127.0.0.1:514"), ("error", "log_parse")];
+        let json = json_for(
+            &log::Record::builder()
+                .args(format_args!("Failed to parse the message"))
+                .level(log::Level::Error)
+                .target("hotdog::connection")
+                .key_values(&fields)
+                .build(),
+        );
+
+        assert_eq!("Failed to parse the message", json["message"]);
+        assert_eq!("ERROR", json["level"]);
+        assert_eq!("hotdog::connection", json["target"]);
+        assert_eq!("127.0.0.1:514", json["peer"]);
+        assert_eq!("log_parse", json["error"]);
+        assert!(json["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_line_for() {
+        let record = log::Record::builder()
+            .args(format_args!("Listening on 127.0.0.1:514"))
+            .level(log::Level::Info)
+            .target("hotdog")
+            .build();
+
+        assert!(line_for(&record, LogFormat::Text)
+            .ends_with(" INFO  hotdog > Listening on 127.0.0.1:514"));
+        let json: Value = serde_json::from_str(&line_for(&record, LogFormat::Json))
+            .expect("Failed to parse the JSON line");
+        assert_eq!("Listening on 127.0.0.1:514", json["message"]);
+    }
+}
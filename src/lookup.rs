@@ -0,0 +1,123 @@
+/**
+ * The lookup module loads the tables used by enrich actions, which map a value such as a hostname
+ * to the fields which should be added for it
+ */
+use std::collections::HashMap;
+use std::path::Path;
+
+/**
+ * A lookup Table maps each key to its fields
+ */
+pub type Table = HashMap<String, HashMap<String, String>>;
+
+/**
+ * Load the lookup table from either a JSON or a CSV file, depending on its extension.
+ *
+ * JSON tables are an object whose keys map to objects of fields. CSV tables have a header row,
+ * and the first column of each row is its key, while the other columns are its fields
+ */
+pub fn load(path: &Path) -> Result<Table, String> {
+    let extension = path.extension().and_then(|e| e.to_str());
+
+    if extension != Some("json") && extension != Some("csv") {
+        return Err(format!(
+            "The lookup table {:?} must be either a `.json` or a `.csv` file",
+            path
+        ));
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the lookup table {:?}: {}", path, e))?;
+
+    if extension == Some("json") {
+        from_json(&contents)
+            .map_err(|e| format!("Failed to load the lookup table {:?}: {}", path, e))
+    } else {
+        Ok(from_csv(&contents))
+    }
+}
+
+fn from_json(contents: &str) -> Result<Table, String> {
+    let value: serde_json::Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| "The table is not a JSON object".to_string())?;
+
+    let mut table = Table::new();
+
+    for (key, fields) in object.iter() {
+        let fields = fields
+            .as_object()
+            .ok_or_else(|| format!("The entry for `{}` is not a JSON object", key))?;
+
+        let fields = fields
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(s) => s.to_string(),
+                    other => other.to_string(),
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        table.insert(key.to_string(), fields);
+    }
+    Ok(table)
+}
+
+fn from_csv(contents: &str) -> Table {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let mut table = Table::new();
+
+    let header = match lines.next() {
+        Some(header) => crate::csv::split(header, ",", Some('"')),
+        None => return table,
+    };
+
+    for line in lines {
+        let mut columns = crate::csv::split(line, ",", Some('"')).into_iter();
+
+        if let Some(key) = columns.next() {
+            let fields = header
+                .iter()
+                .skip(1)
+                .zip(columns)
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            table.insert(key, fields);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_csv() {
+        let table = load(Path::new("test/lookups/hosts.csv")).expect("Failed to load the table");
+        assert_eq!(2, table.len());
+        assert_eq!(
+            Some(&"us-west-2".to_string()),
+            table["coconut"].get("datacenter")
+        );
+        assert_eq!(Some(&"web, api".to_string()), table["coconut"].get("team"));
+    }
+
+    #[test]
+    fn test_load_json() {
+        let table = load(Path::new("test/lookups/hosts.json")).expect("Failed to load the table");
+        assert_eq!(2, table.len());
+        assert_eq!(
+            Some(&"us-west-2".to_string()),
+            table["coconut"].get("datacenter")
+        );
+        assert_eq!(Some(&"3".to_string()), table["coconut"].get("rack"));
+    }
+
+    #[test]
+    fn test_load_unknown_extension() {
+        assert!(load(Path::new("test/lookups/hosts.txt")).is_err());
+    }
+}
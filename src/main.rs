@@ -12,7 +12,6 @@ extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
-extern crate serde_regex;
 #[cfg(feature = "simd")]
 extern crate simd_json;
 extern crate strum;
@@ -22,36 +21,76 @@ extern crate syslog_rfc5424;
 extern crate strum_macros;
 
 use async_std::{sync::Arc, task};
-use clap::{App, Arg};
-use dipstick::{Input, Prefixed, Statsd};
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
 use log::*;
 
+mod aggregate;
+mod assertions;
+mod bench;
+mod check;
 mod connection;
+mod csv;
+#[cfg(unix)]
+mod daemon;
+mod decode;
 mod errors;
+mod events;
+mod exec;
+mod geoip;
+mod grok;
+#[cfg(unix)]
+mod handover;
+mod heartbeat;
+mod init;
 mod json;
 mod kafka;
+mod kv;
+mod logging;
+mod lookup;
 mod merge;
 mod parse;
+mod plugin;
+mod profile;
+mod prometheus;
+mod ratelimit;
+mod reload;
+mod replay;
 mod rules;
+mod runtime;
+mod sampled;
+mod schema;
+mod script;
+mod secrets;
+mod serialize;
 mod serve;
 mod serve_plain;
 mod serve_tls;
+#[cfg(windows)]
+mod service;
 mod settings;
+mod signals;
+mod spool;
 mod status;
+mod supervise;
+mod suppress;
+mod tagged;
+mod templates;
+mod timestamp;
+mod trace;
+mod version;
 
 use serve::*;
 use settings::*;
 
-#[async_std::main]
-async fn main() -> Result<(), errors::HotdogError> {
-    pretty_env_logger::init();
-
-    info!("Starting hotdog version {}", env!["CARGO_PKG_VERSION"]);
-
-    let matches = App::new("Hotdog")
+/**
+ * The command line of hotdog, which the completions are generated from as well
+ */
+fn app() -> App<'static, 'static> {
+    App::new("Hotdog")
         .version(env!("CARGO_PKG_VERSION"))
         .author("R Tyler Croy <rtyler+hotdog@brokenco.de")
         .about("Forward syslog over to Kafka with ease")
+        .setting(AppSettings::VersionlessSubcommands)
         .arg(
             Arg::with_name("config")
                 .short("c")
@@ -59,29 +98,592 @@ async fn main() -> Result<(), errors::HotdogError> {
                 .value_name("FILE")
                 .help("Sets a custom config file")
                 .default_value("hotdog.yml")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .global(true)
+                .help("Logs more of what hotdog does, repeat for even more, e.g. -vv"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .multiple(true)
+                .conflicts_with("verbose")
+                .global(true)
+                .help("Logs nothing at all"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .short("p")
+                .long("profile")
+                .value_name("PROFILE")
+                .help("Overlays the named profile of the configuration's profiles")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDRESS")
+                .help("Overrides the address to listen on")
+                .global(true)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .help("Overrides the port to listen on")
+                .validator(|port| {
+                    port.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| format!("`{}` is not a valid port", port))
+                })
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("brokers")
+                .long("brokers")
+                .value_name("BROKERS")
+                .help("Overrides the Kafka brokers to connect to, e.g. `kafka:9092`")
+                .global(true)
+                .takes_value(true),
+        )
+        /*
+         * The options which preceded the subcommands are still accepted, but no longer shown
+         */
         .arg(
             Arg::with_name("test")
                 .short("t")
                 .long("test")
                 .value_name("TEST_FILE")
-                .help("Test a log file against the configured rules")
+                .help("Deprecated, use `hotdog test TEST_FILE` instead")
+                .hidden(true)
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("profile-pipeline")
+                .long("profile-pipeline")
+                .help("Deprecated, use `hotdog serve --profile-pipeline` instead")
+                .hidden(true),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Listens for syslog and forwards it to Kafka, which is the default")
+                .arg(
+                    Arg::with_name("profile-pipeline")
+                        .long("profile-pipeline")
+                        .help("Logs the time spent parsing, matching, rendering and sending"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Logs and counts what would be sent instead of sending it to Kafka"),
+                )
+                .arg(
+                    Arg::with_name("daemon")
+                        .long("daemon")
+                        .help("Detaches from the terminal and runs in the background"),
+                )
+                .arg(
+                    Arg::with_name("pidfile")
+                        .long("pidfile")
+                        .value_name("FILE")
+                        .help("Writes the process id to the file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("service")
+                        .long("service")
+                        .help("Runs under the Windows service control manager")
+                        .conflicts_with_all(&["daemon", "pidfile"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Tests a log file against the configured rules")
+                .arg(
+                    Arg::with_name("TEST_FILE")
+                        .help("The log file to test, one message per line")
+                        .required_unless("assert")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("assert")
+                        .long("assert")
+                        .value_name("FILE")
+                        .help("Fails unless the lines of the file do what they are expected to")
+                        .conflicts_with("TEST_FILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks the configuration for errors without starting hotdog"),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Writes a commented example configuration to the config file")
+                .arg(
+                    Arg::with_name("with-tls")
+                        .long("with-tls")
+                        .help("Listens for syslog over TLS in the example"),
+                )
+                .arg(
+                    Arg::with_name("with-status")
+                        .long("with-status")
+                        .help("Enables the status server in the example"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrites the config file if it already exists"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Prints the merged configuration, with the secrets masked")
+                .arg(
+                    Arg::with_name("resolved")
+                        .long("resolved")
+                        .help("Prints the settings hotdog will run with, including the defaults"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Generates syslog traffic and reports the throughput and the latency")
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .value_name("ADDRESS")
+                        .help("Sends the traffic to a running hotdog instead of through the rules")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("lines")
+                        .long("lines")
+                        .value_name("LINES")
+                        .help("The number of lines to send")
+                        .default_value("100000")
+                        .validator(count)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("LINES_PER_SEC")
+                        .help("The number of lines to send per second, 0 sends them all at once")
+                        .default_value("0")
+                        .validator(count)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Replays a log file through the rules and produces its messages to Kafka")
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("The log file to replay, one message per line")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("LINES_PER_SEC")
+                        .help(
+                            "The number of lines to replay per second, 0 replays them all at once",
+                        )
+                        .default_value("0")
+                        .validator(count)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Prints the completions of hotdog for the shell")
+                .arg(
+                    Arg::with_name("SHELL")
+                        .help("The shell to complete hotdog in")
+                        .possible_values(&Shell::variants())
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("version")
+                .about("Prints the version of hotdog, along with how it was built"),
+        )
+}
+
+fn main() -> Result<(), errors::HotdogError> {
+    let app_matches = app().get_matches();
+    /*
+     * The global arguments may be given before or after the subcommand, those of the subcommand
+     * have both
+     */
+    let matches = app_matches.subcommand().1.unwrap_or(&app_matches);
 
     let settings_file = matches.value_of("config").unwrap_or("hotdog.yml");
-    let settings = Arc::new(settings::load(settings_file));
-    let metrics = Arc::new(
-        Statsd::send_to(&settings.global.metrics.statsd)
-            .expect("Failed to create Statsd recorder")
-            .named("hotdog")
-            .metrics(),
+    let verbosity = logging::verbosity(
+        matches.occurrences_of("verbose"),
+        matches.occurrences_of("quiet"),
     );
+    let overrides = Overrides {
+        listen: matches.value_of("listen").map(String::from),
+        port: matches.value_of("port").and_then(|port| port.parse().ok()),
+        brokers: matches.value_of("brokers").map(String::from),
+        profile: matches.value_of("profile").map(String::from),
+    };
 
-    let stats = Arc::new(status::StatsHandler::new(metrics.clone()));
+    /*
+     * Loading the settings may already log, such as when resolving secrets, so there is a logger
+     * before there are settings. Serving and testing the rules reconfigure it once they are loaded
+     */
+    logging::init(&settings::Log::default(), verbosity);
+
+    match app_matches.subcommand() {
+        ("init", Some(init)) => {
+            let options = init::Options {
+                tls: init.is_present("with-tls"),
+                status: init.is_present("with-status"),
+            };
+
+            if let Err(e) = init::init(settings_file, &options, init.is_present("force")) {
+                eprintln!("Failed to write {}: {}", settings_file, e);
+                std::process::exit(1);
+            }
+            println!("Wrote an example configuration to {}", settings_file);
+            Ok(())
+        }
+        ("check", _) => {
+            let errors = check::check(settings_file, &overrides);
+
+            for error in errors.iter() {
+                eprintln!("{}: {}", settings_file, error);
+            }
+
+            if !errors.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{} is valid", settings_file);
+            Ok(())
+        }
+        ("config", Some(config)) => {
+            match settings::resolved(settings_file, &overrides, config.is_present("resolved")) {
+                Ok(resolved) => {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&resolved).unwrap_or_default()
+                    );
+                    Ok(())
+                }
+                Err(errors) => {
+                    for error in errors.iter() {
+                        eprintln!("{}: {}", settings_file, error);
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("bench", Some(bench)) => {
+            let options = bench::Options {
+                target: bench.value_of("target").map(String::from),
+                lines: bench
+                    .value_of("lines")
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0),
+                rate: bench
+                    .value_of("rate")
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0),
+            };
+
+            match task::block_on(bench::bench(settings_file, overrides, &options)) {
+                Ok(report) => {
+                    println!("{}", report);
+                    Ok(())
+                }
+                Err(e) => {
+                    eprintln!("Failed to run the benchmark: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("replay", Some(replay)) => {
+            let options = replay::Options {
+                file: replay.value_of("FILE").unwrap_or_default().to_string(),
+                rate: replay
+                    .value_of("rate")
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0),
+            };
+
+            match task::block_on(replay::replay(settings_file, overrides, &options)) {
+                Ok(report) => {
+                    println!("{}", report);
+                    Ok(())
+                }
+                Err(e) => {
+                    eprintln!("Failed to replay {}: {}", options.file, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("completions", Some(completions)) => {
+            if let Some(shell) = completions
+                .value_of("SHELL")
+                .and_then(|shell| shell.parse::<Shell>().ok())
+            {
+                app().gen_completions_to("hotdog", shell, &mut std::io::stdout());
+            }
+            Ok(())
+        }
+        ("version", _) => {
+            println!("{}", version::describe());
+            Ok(())
+        }
+        ("test", Some(test)) => {
+            let testing = match test.value_of("assert") {
+                Some(assertions) => rules::Test::Assertions(assertions),
+                None => rules::Test::Lines(test.value_of("TEST_FILE").unwrap_or_default()),
+            };
+            start(
+                settings_file,
+                overrides,
+                verbosity,
+                &Serve::default(),
+                Some(testing),
+            )
+        }
+        ("serve", Some(serve)) => {
+            let serve = Serve {
+                profile_pipeline: serve.is_present("profile-pipeline"),
+                dry_run: serve.is_present("dry-run"),
+                daemon: serve.is_present("daemon"),
+                pidfile: serve.value_of("pidfile"),
+                service: serve.is_present("service"),
+            };
+
+            /*
+             * The service control manager runs the service on a thread of its own, which takes
+             * everything it needs to start hotdog along
+             */
+            #[cfg(windows)]
+            if serve.service {
+                let settings_file = settings_file.to_string();
+                let serve = Serve {
+                    profile_pipeline: serve.profile_pipeline,
+                    dry_run: serve.dry_run,
+                    ..Serve::default()
+                };
+                return service::run(move || {
+                    start(&settings_file, overrides, verbosity, &serve, None)
+                });
+            }
+            start(settings_file, overrides, verbosity, &serve, None)
+        }
+        /*
+         * Without a subcommand hotdog serves, as it always has
+         */
+        _ => {
+            if let Some(test_file) = matches.value_of("test") {
+                warn!(
+                    "`--test` is deprecated, use `hotdog test {}` instead",
+                    test_file
+                );
+                return start(
+                    settings_file,
+                    overrides,
+                    verbosity,
+                    &Serve::default(),
+                    Some(rules::Test::Lines(test_file)),
+                );
+            }
+            if matches.is_present("profile-pipeline") {
+                warn!("`--profile-pipeline` is deprecated, use `hotdog serve --profile-pipeline` instead");
+            }
+            let serve = Serve {
+                profile_pipeline: matches.is_present("profile-pipeline"),
+                ..Serve::default()
+            };
+            start(settings_file, overrides, verbosity, &serve, None)
+        }
+    }
+}
+
+/**
+ * The arguments of the `serve` subcommand
+ */
+#[derive(Default)]
+struct Serve<'a> {
+    profile_pipeline: bool,
+    dry_run: bool,
+    daemon: bool,
+    pidfile: Option<&'a str>,
+    service: bool,
+}
+
+/**
+ * Load the settings and run hotdog with them, either serving or testing the rules against the
+ * test file
+ */
+fn start(
+    settings_file: &str,
+    overrides: Overrides,
+    verbosity: i64,
+    serve: &Serve,
+    test: Option<rules::Test<'_>>,
+) -> Result<(), errors::HotdogError> {
+    #[cfg(not(unix))]
+    if serve.daemon || serve.pidfile.is_some() {
+        eprintln!("--daemon and --pidfile are only supported on Unix, use --service on Windows");
+        std::process::exit(1);
+    }
+    #[cfg(not(windows))]
+    if serve.service {
+        eprintln!("--service is only supported on Windows, use --daemon elsewhere");
+        std::process::exit(1);
+    }
+
+    /*
+     * Forking only copies the calling thread, so hotdog is daemonized before anything else
+     */
+    #[cfg(unix)]
+    let detached = if serve.daemon {
+        match daemon::detach() {
+            Ok(detached) => Some(detached),
+            Err(e) => {
+                eprintln!("Failed to run in the background: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    /*
+     * The executor starts along with the first task, which resolving the secrets of the settings
+     * may already run, so it is configured, and hotdog is pinned to its CPUs for the threads it
+     * starts to inherit, before the settings are loaded
+     */
+    let configured = settings::runtime(settings_file, &overrides);
+    runtime::pin_cpus(&configured);
+    runtime::configure(&configured);
+
+    let settings = match settings::try_load(settings_file, &overrides) {
+        Ok(settings) => Arc::new(settings),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&settings.global.log, verbosity);
+    info!(
+        "Starting hotdog version {} ({})",
+        version::VERSION,
+        version::COMMIT
+    );
+
+    /*
+     * The pidfile stays locked until hotdog exits
+     */
+    #[cfg(unix)]
+    let _pidfile = match serve.pidfile {
+        Some(pidfile) => match daemon::write_pidfile(pidfile) {
+            Ok(locked) => Some(locked),
+            Err(e) => {
+                eprintln!("Failed to write the pidfile {}: {}", pidfile, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    /*
+     * The process which started a daemon only exits, and a Windows service is only reported as
+     * running, once its listeners have been bound
+     */
+    let ready = move || {
+        #[cfg(unix)]
+        if let Some(detached) = detached {
+            detached.ready();
+        }
+        #[cfg(windows)]
+        service::ready();
+    };
+
+    if serve.profile_pipeline {
+        profile::enable();
+        task::spawn(profile::report_loop());
+    }
+    let ran = task::block_on(run(
+        settings,
+        settings_file,
+        overrides,
+        serve.dry_run,
+        test,
+        ready,
+    ));
+
+    #[cfg(unix)]
+    if let Some(pidfile) = serve.pidfile {
+        daemon::remove_pidfile(pidfile);
+    }
+    ran
+}
+
+/**
+ * Validate that the argument is a number which can be counted to
+ */
+fn count(n: String) -> Result<(), String> {
+    n.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("`{}` is not a valid number", n))
+}
+
+/**
+ * Run hotdog with the loaded settings, until its listeners have stopped. `ready` is called once
+ * every listener has been bound
+ */
+async fn run<F: FnOnce()>(
+    settings: Arc<Settings>,
+    settings_file: &str,
+    overrides: Overrides,
+    dry_run: bool,
+    test: Option<rules::Test<'_>>,
+    ready: F,
+) -> Result<(), errors::HotdogError> {
+    if let Some(tracing) = &settings.global.tracing {
+        trace::init(tracing);
+    }
+
+    /*
+     * hotdog can do its job without its metrics, so an unreachable metrics backend is only logged
+     */
+    let metrics = status::metrics_for(&settings.global.metrics).unwrap_or_else(|e| {
+        error!(
+            "Failed to create the metrics scope for {}, no metrics will be sent: {:?}",
+            settings.global.metrics.destination(),
+            e
+        );
+        status::Metrics::none()
+    });
+
+    let stats = Arc::new(status::StatsHandler::new(
+        metrics,
+        settings.global.metrics.max_topics,
+        settings.global.metrics.max_peers,
+        settings.global.metrics.max_metrics,
+    ));
     let stats_sender = stats.tx.clone();
+    stats.set_config_hash(settings.hash.clone());
 
     if let Some(st) = &settings.global.status {
         task::spawn(status::status_server(
@@ -90,39 +692,132 @@ async fn main() -> Result<(), errors::HotdogError> {
         ));
     }
 
+    let runloop = stats.clone();
     task::spawn(async move {
-        stats.runloop().await;
+        runloop.runloop().await;
     });
+    task::spawn(status::summary_loop(stats.clone()));
+    task::spawn(status::counter_loop(stats.clone()));
+
+    if let Some(test) = test {
+        let (sinks, sunk) = Sinks::null(&settings);
+        let reloadable = Arc::new(reload::Reloadable::new(
+            settings_file,
+            overrides,
+            settings,
+            sinks,
+        ));
+
+        return match test {
+            rules::Test::Lines(file) => {
+                rules::test_rules(file, reloadable, sunk, stats_sender).await
+            }
+            rules::Test::Assertions(file) => {
+                match assertions::run(file, reloadable, sunk, stats_sender).await {
+                    Ok(0) => Ok(()),
+                    Ok(_) => std::process::exit(1),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        };
+    }
 
-    if let Some(test_file) = matches.value_of("test") {
-        return rules::test_rules(&test_file, settings).await;
+    /*
+     * A dry run goes through everything but Kafka, so a new configuration can be tried out on live
+     * traffic
+     */
+    let sinks = if dry_run {
+        warn!("Running dry, nothing will be sent to Kafka");
+        let (sinks, sunk) = Sinks::null(&settings);
+        task::spawn(serve::dry_run(sunk, stats_sender.clone()));
+        sinks
+    } else {
+        Sinks::start(&settings, stats_sender.clone()).await?
+    };
+    let reloadable = Arc::new(reload::Reloadable::new(
+        settings_file,
+        overrides,
+        settings.clone(),
+        sinks,
+    ));
+    reloadable.publish(events::Event::Started {
+        config_hash: settings.hash.clone(),
+    });
+    if let Some(heartbeat) = &settings.global.heartbeat {
+        task::spawn(heartbeat::heartbeat_loop(
+            heartbeat.clone(),
+            reloadable.clone(),
+            stats.clone(),
+        ));
     }
+    let connections = stats.connections();
+    task::spawn(status::dump_loop(
+        stats.clone(),
+        reloadable.clone(),
+        signals::dump(),
+    ));
+    #[cfg(unix)]
+    task::spawn(handover::restart_loop(signals::restart()));
+    task::spawn(reload::reload_loop(
+        reloadable.clone(),
+        signals::reload(),
+        stats.clone(),
+    ));
 
-    let addr = format!(
-        "{}:{}",
-        settings.global.listen.address, settings.global.listen.port
+    let listeners = settings.listeners().count();
+    let (bound, binding) = async_channel::bounded(listeners);
+    let state = ServerState::new(
+        settings.clone(),
+        reloadable,
+        stats_sender.clone(),
+        connections,
+        bound,
     );
-    info!("Listening on: {}", addr);
 
-    let state = ServerState {
-        settings: settings.clone(),
-        stats: stats_sender,
-    };
+    /*
+     * Every listener gets a task of its own which is restarted if it fails, hotdog waits on the
+     * task serving `global.listen`. hotdog only starts once every listener has been bound
+     */
+    let shutdown = signals::shutdown();
+    for listener in 1..listeners {
+        let state = state.for_listener(listener);
 
-    match &settings.global.listen.tls {
-        TlsType::CertAndKey {
-            cert: _,
-            key: _,
-            ca: _,
-        } => {
-            info!("Serving in TLS mode");
-            let mut server = crate::serve_tls::TlsServer::new(&state);
-            server.accept_loop(&addr, state).await
-        }
-        _ => {
-            info!("Serving in plaintext mode");
-            let mut server = crate::serve_plain::PlaintextServer {};
-            server.accept_loop(&addr, state).await
+        task::spawn(supervise::supervise(
+            "listener",
+            stats_sender.clone(),
+            shutdown.clone(),
+            move || serve::serve(state.clone()),
+        ));
+    }
+    let served = task::spawn(supervise::supervise(
+        "listener",
+        stats_sender.clone(),
+        shutdown,
+        move || serve::serve(state.clone()),
+    ));
+
+    for _ in 0..listeners {
+        if let Ok(Err(e)) = binding.recv().await {
+            error!("{}", e);
+            return Err(std::io::Error::new(std::io::ErrorKind::AddrNotAvailable, e).into());
         }
     }
+    drop(binding);
+    #[cfg(unix)]
+    handover::ready();
+    ready();
+
+    let served = served.await;
+    stats.report_counters().await;
+    trace::shutdown();
+    served.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "The listener of `global.listen` kept failing",
+        )
+        .into()
+    })
 }
@@ -1,4 +1,5 @@
-use log::*;
+use chrono::prelude::*;
+use std::collections::HashMap;
 
 /**
  * Enum of syslog parse related errors
@@ -6,38 +7,77 @@ use log::*;
 #[derive(Debug)]
 pub enum SyslogErrors {
     UnknownFormat,
+    /**
+     * The line was not valid UTF-8
+     */
+    InvalidEncoding,
 }
 
 /**
  * SyslogMessage is just a wrapper struct to allow us to deserialize RFC 5424 and RFC 3164 syslog
  * messages into some format that can be passed throughout hotdog
  */
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SyslogMessage {
     pub msg: String,
     pub severity: Option<String>,
     pub facility: Option<String>,
     pub hostname: Option<String>,
     pub appname: Option<String>,
+    pub procid: Option<String>,
+    pub msgid: Option<String>,
+    /**
+     * Map of the SD-IDs of structured data elements to their parameters
+     */
+    pub structured_data: HashMap<String, HashMap<String, String>>,
+    /**
+     * The timestamp of the syslog message, if available
+     */
+    pub timestamp: Option<DateTime<Utc>>,
+    /**
+     * Tags attached to the message by the `tag` actions of the rules which have matched it
+     */
+    pub tags: Vec<String>,
 }
 
 /**
  * Attempt to parse a given line either as RFC 5424 or RFC 3164
  */
-pub fn parse_line(line: String) -> std::result::Result<SyslogMessage, SyslogErrors> {
-    match syslog_rfc5424::parse_message(&line) {
+pub fn parse_line(line: &str) -> std::result::Result<SyslogMessage, SyslogErrors> {
+    match syslog_rfc5424::parse_message(line) {
         Ok(msg) => {
+            let nanos = msg.timestamp_nanos.unwrap_or(0) as u32;
             let wrapped = SyslogMessage {
                 msg: msg.msg,
                 severity: Some(msg.severity.as_str().to_string()),
                 facility: Some(msg.facility.as_str().to_string()),
                 hostname: msg.hostname,
                 appname: msg.appname,
+                procid: msg.procid.map(|p| match p {
+                    syslog_rfc5424::message::ProcId::PID(pid) => pid.to_string(),
+                    syslog_rfc5424::message::ProcId::Name(name) => name,
+                }),
+                msgid: msg.msgid,
+                timestamp: msg
+                    .timestamp
+                    .and_then(|secs| Utc.timestamp_opt(secs, nanos).single()),
+                structured_data: msg
+                    .sd
+                    .iter()
+                    .map(|(id, params)| {
+                        let params = params
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.to_string()))
+                            .collect();
+                        (id.to_string(), params)
+                    })
+                    .collect(),
+                tags: vec![],
             };
             Ok(wrapped)
         }
         Err(_) => {
-            let parsed = syslog_loose::parse_message(&line);
+            let parsed = syslog_loose::parse_message(line);
 
             /*
              * Since syslog_loose doesn't give a Result, the only way to tell if themessage wasn't
@@ -56,10 +96,28 @@ pub fn parse_line(line: String) -> std::result::Result<SyslogMessage, SyslogErro
                         .hostname
                         .map_or_else(|| None, |h| Some(h.to_string())),
                     appname: parsed.appname.map_or_else(|| None, |a| Some(a.to_string())),
+                    procid: parsed.procid.map(|p| match p {
+                        syslog_loose::ProcId::PID(pid) => pid.to_string(),
+                        syslog_loose::ProcId::Name(name) => name.to_string(),
+                    }),
+                    msgid: parsed.msgid.map(|m| m.to_string()),
+                    timestamp: parsed.timestamp.map(|t| t.with_timezone(&Utc)),
+                    structured_data: parsed
+                        .structured_data
+                        .iter()
+                        .map(|element| {
+                            let params = element
+                                .params
+                                .iter()
+                                .map(|(k, v)| (k.to_string(), v.to_string()))
+                                .collect();
+                            (element.id.to_string(), params)
+                        })
+                        .collect(),
+                    tags: vec![],
                 };
                 return Ok(wrapped);
             }
-            warn!("Message received we cannot parse: {}", line);
             Err(SyslogErrors::UnknownFormat)
         }
     }
@@ -72,7 +130,7 @@ mod tests {
     #[test]
     fn test_parsing_invalid() {
         let buffer = "blah".to_string();
-        let parsed = parse_line(buffer);
+        let parsed = parse_line(&buffer);
         if let Ok(msg) = &parsed {
             println!("msg: {}", msg.msg);
         }
@@ -82,13 +140,37 @@ mod tests {
     #[test]
     fn test_5424() {
         let buffer = r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut tyler - - [timeQuality tzKnown="1" isSynced="1" syncAccuracy="505061"] hi"#.to_string();
-        let parsed = parse_line(buffer);
+        let parsed = parse_line(&buffer);
         assert!(parsed.is_ok());
         if let Ok(msg) = parsed {
             assert_eq!("hi", msg.msg);
             assert_eq!(Some("coconut".to_string()), msg.hostname);
             assert_eq!(Some("user".to_string()), msg.facility);
             assert_eq!(Some("notice".to_string()), msg.severity);
+            assert_eq!(None, msg.procid);
+            assert_eq!(None, msg.msgid);
+            assert_eq!(
+                Some(&"505061".to_string()),
+                msg.structured_data["timeQuality"].get("syncAccuracy")
+            );
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn test_5424_with_procid_and_msgid() {
+        let buffer =
+            r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut tyler 1234 ID47 - hi"#.to_string();
+        let parsed = parse_line(&buffer);
+        assert!(parsed.is_ok());
+        if let Ok(msg) = parsed {
+            assert_eq!(Some("1234".to_string()), msg.procid);
+            assert_eq!(Some("ID47".to_string()), msg.msgid);
+            assert_eq!(
+                Some(Utc.ymd(2020, 4, 18).and_hms_micro(22, 16, 9, 956153)),
+                msg.timestamp
+            );
         } else {
             assert!(false);
         }
@@ -97,7 +179,7 @@ mod tests {
     #[test]
     fn test_3164() {
         let buffer = r#"<190>May 13 21:45:18 coconut hotdog: hi"#.to_string();
-        let parsed = parse_line(buffer);
+        let parsed = parse_line(&buffer);
         assert!(parsed.is_ok());
         if let Ok(msg) = parsed {
             assert_eq!("hi", msg.msg);
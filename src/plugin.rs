@@ -0,0 +1,234 @@
+/**
+ * The plugin module runs WebAssembly modules for the plugin actions, allowing custom
+ * transformations to be shipped without changing hotdog itself.
+ *
+ * Plugin modules must export:
+ *
+ *  - `memory`: the linear memory of the module
+ *  - `alloc(len: i32) -> i32`: allocate `len` bytes for the input, returning the offset
+ *  - `transform(ptr: i32, len: i32) -> i64`: transform the input at the offset, returning the
+ *    offset of the output in the upper 32 bits and its length in the lower 32 bits, or a negative
+ *    number to drop the message
+ */
+#[cfg(feature = "wasm")]
+use dashmap::DashMap;
+use std::path::Path;
+
+/**
+ * The fuel each call into a module is given, roughly one per WebAssembly instruction, so that a
+ * plugin which loops forever traps rather than stalling the connection running it
+ */
+#[cfg(feature = "wasm")]
+const FUEL: u64 = 10_000_000;
+
+/**
+ * Enum of the errors which can occur while running a plugin
+ */
+#[derive(Debug)]
+pub enum PluginError {
+    /**
+     * WebAssembly plugins were not compiled into this build of hotdog
+     */
+    Unsupported(String),
+    /**
+     * The module could not be loaded, does not implement the interface, or trapped
+     */
+    Module(String),
+}
+
+/**
+ * The Outcome of transforming a message with a plugin
+ */
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /**
+     * The transformed message, which replaces the output
+     */
+    Replace(String),
+    /**
+     * The plugin asked for the message to be dropped
+     */
+    Drop,
+}
+
+/**
+ * Plugins carries the cache of compiled modules, and should be shared between all connections
+ */
+pub struct Plugins {
+    #[cfg(feature = "wasm")]
+    engine: wasmtime::Engine,
+    #[cfg(feature = "wasm")]
+    modules: DashMap<std::path::PathBuf, wasmtime::Module>,
+}
+
+impl Plugins {
+    #[cfg(feature = "wasm")]
+    pub fn new() -> Self {
+        Plugins {
+            engine: engine(),
+            modules: DashMap::new(),
+        }
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn new() -> Self {
+        Plugins {}
+    }
+
+    /**
+     * Transform the input with the module at the given path, compiling it on first use
+     */
+    #[cfg(feature = "wasm")]
+    pub fn transform(&self, module: &Path, input: &str) -> Result<Outcome, PluginError> {
+        let compiled = match self.modules.get(module) {
+            Some(compiled) => compiled.value().clone(),
+            None => {
+                let compiled = wasmtime::Module::from_file(&self.engine, module)
+                    .map_err(|e| PluginError::Module(e.to_string()))?;
+                self.modules.insert(module.to_path_buf(), compiled.clone());
+                compiled
+            }
+        };
+        run_module(&self.engine, &compiled, input).map_err(|e| PluginError::Module(e.to_string()))
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn transform(&self, _module: &Path, _input: &str) -> Result<Outcome, PluginError> {
+        Err(PluginError::Unsupported(
+            "hotdog was not built with the `wasm` feature".to_string(),
+        ))
+    }
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        Plugins::new()
+    }
+}
+
+/**
+ * Create the engine for running plugins, which meters the fuel of every call
+ */
+#[cfg(feature = "wasm")]
+fn engine() -> wasmtime::Engine {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+
+    wasmtime::Engine::new(&config).unwrap_or_else(|e| {
+        log::error!(
+            "Failed to create an engine which meters the fuel of plugins: {}",
+            e
+        );
+        wasmtime::Engine::default()
+    })
+}
+
+/**
+ * Instantiate the module and run its transform function over the input
+ */
+#[cfg(feature = "wasm")]
+fn run_module(
+    engine: &wasmtime::Engine,
+    module: &wasmtime::Module,
+    input: &str,
+) -> anyhow::Result<Outcome> {
+    let mut store = wasmtime::Store::new(engine, ());
+    store.add_fuel(FUEL)?;
+    let instance = wasmtime::Linker::new(engine).instantiate(&mut store, module)?;
+
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| anyhow::anyhow!("The module does not export its `memory`"))?;
+    let alloc = instance.get_typed_func::<i32, i32, _>(&mut store, "alloc")?;
+    let transform = instance.get_typed_func::<(i32, i32), i64, _>(&mut store, "transform")?;
+
+    let len = input.len() as i32;
+    let ptr = alloc.call(&mut store, len)?;
+    memory.write(&mut store, ptr as usize, input.as_bytes())?;
+
+    let result = transform.call(&mut store, (ptr, len))?;
+
+    if result < 0 {
+        return Ok(Outcome::Drop);
+    }
+
+    let offset = (result >> 32) as usize;
+    let mut output = vec![0; (result & 0xffff_ffff) as usize];
+    memory.read(&store, offset, &mut output)?;
+    Ok(Outcome::Replace(String::from_utf8(output)?))
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use super::*;
+
+    /**
+     * A module which returns its input as-is, unless it starts with `drop`
+     */
+    const IDENTITY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32)
+            i32.const 0)
+          (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+            (if (i32.eq (i32.load (local.get $ptr)) (i32.const 0x706f7264))
+              (then (return (i64.const -1))))
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    #[test]
+    fn test_run_module() {
+        let engine = engine();
+        let module = wasmtime::Module::new(&engine, IDENTITY).expect("Failed to compile");
+
+        assert_eq!(
+            Outcome::Replace("hello".to_string()),
+            run_module(&engine, &module, "hello").unwrap()
+        );
+        assert_eq!(
+            Outcome::Drop,
+            run_module(&engine, &module, "drop me").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_run_module_out_of_fuel() {
+        let engine = engine();
+        let module = wasmtime::Module::new(
+            &engine,
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "alloc") (param i32) (result i32)
+                i32.const 0)
+              (func (export "transform") (param i32) (param i32) (result i64)
+                (loop $forever (br $forever))
+                (i64.const -1)))
+        "#,
+        )
+        .expect("Failed to compile");
+
+        assert!(run_module(&engine, &module, "hello").is_err());
+    }
+
+    #[test]
+    fn test_transform_missing_module() {
+        let plugins = Plugins::new();
+        let result = plugins.transform(Path::new("nonexistent.wasm"), "hello");
+        assert!(matches!(result, Err(PluginError::Module(_))));
+    }
+}
+
+#[cfg(all(test, not(feature = "wasm")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_unsupported() {
+        let plugins = Plugins::new();
+        let result = plugins.transform(Path::new("plugin.wasm"), "hello");
+        assert!(matches!(result, Err(PluginError::Unsupported(_))));
+    }
+}
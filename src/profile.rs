@@ -0,0 +1,194 @@
+/**
+ * The profile module records the time which the lines spend in each stage of the pipeline when
+ * hotdog runs with `serve --profile-pipeline`, and periodically logs where the time went, so
+ * that the rule whose regex or template is responsible for a CPU spike can be found without an
+ * external profiler
+ */
+use crate::settings::Rule;
+use log::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+ * The interval between the reports of the time spent in the stages
+ */
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/**
+ * The number of the slowest stages which every report lists
+ */
+const REPORT_STAGES: usize = 20;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/**
+ * The time spent in each stage, by rule, since the last report
+ */
+static TIMINGS: Mutex<BTreeMap<(Stage, String), Timing>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /**
+     * Parsing the line into a syslog message
+     */
+    Parse,
+    /**
+     * Evaluating the condition of a rule against the message
+     */
+    Match,
+    /**
+     * Rendering the templates of the actions of a rule
+     */
+    Render,
+    /**
+     * Handing the messages of a line over to the sendloops
+     */
+    Send,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Stage::Parse => "parse",
+            Stage::Match => "match",
+            Stage::Render => "render",
+            Stage::Send => "send",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Timing {
+    count: u64,
+    elapsed: Duration,
+}
+
+/**
+ * Start recording the stages, until then the stages are not even timed
+ */
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/**
+ * Return the time a stage starts at, when the stages are being profiled
+ */
+pub fn start() -> Option<Instant> {
+    if ENABLED.load(Ordering::Relaxed) {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/**
+ * Record the time spent in the stage since it started, for the rule if the stage belongs to one
+ */
+pub fn finish(started: Option<Instant>, stage: Stage, rule: Option<&Rule>) {
+    if let Some(started) = started {
+        let rule = rule.map(|rule| rule.to_string()).unwrap_or_default();
+        record(stage, rule, started.elapsed());
+    }
+}
+
+fn record(stage: Stage, rule: String, elapsed: Duration) {
+    if let Ok(mut timings) = TIMINGS.lock() {
+        let timing = timings.entry((stage, rule)).or_default();
+        timing.count += 1;
+        timing.elapsed += elapsed;
+    }
+}
+
+/**
+ * Log the time spent in the stages every interval, for as long as hotdog runs
+ */
+pub async fn report_loop() {
+    info!(
+        "Profiling the pipeline, the time spent in its stages is logged every {}s",
+        REPORT_INTERVAL.as_secs()
+    );
+
+    loop {
+        async_std::task::sleep(REPORT_INTERVAL).await;
+
+        let timings = match TIMINGS.lock() {
+            Ok(mut timings) => std::mem::take(&mut *timings),
+            Err(_) => continue,
+        };
+
+        if !timings.is_empty() {
+            info!(
+                "Time spent in the pipeline in the last {}s:\n{}",
+                REPORT_INTERVAL.as_secs(),
+                report(&timings).join("\n")
+            );
+        }
+    }
+}
+
+/**
+ * Describe the stages which took the most time, slowest first
+ */
+fn report(timings: &BTreeMap<(Stage, String), Timing>) -> Vec<String> {
+    let total: Duration = timings.values().map(|timing| timing.elapsed).sum();
+    let mut slowest: Vec<_> = timings.iter().collect();
+    slowest.sort_by(|(_, a), (_, b)| b.elapsed.cmp(&a.elapsed));
+
+    slowest
+        .into_iter()
+        .take(REPORT_STAGES)
+        .map(|((stage, rule), timing)| {
+            let share =
+                100.0 * timing.elapsed.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON);
+            let mean_us = timing.elapsed.as_micros() / u128::from(timing.count.max(1));
+            let mut line = format!(
+                "  {:<6} {:>10.3}ms {:>5.1}% {:>8} times {:>6}us each",
+                stage,
+                timing.elapsed.as_secs_f64() * 1000.0,
+                share,
+                timing.count,
+                mean_us
+            );
+
+            if !rule.is_empty() {
+                line.push_str(&format!(" rule {}", rule));
+            }
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut timings = BTreeMap::new();
+        timings.insert(
+            (Stage::Parse, String::new()),
+            Timing {
+                count: 4,
+                elapsed: Duration::from_millis(1),
+            },
+        );
+        timings.insert(
+            (Stage::Match, "slow /(a+)+$/".to_string()),
+            Timing {
+                count: 2,
+                elapsed: Duration::from_millis(3),
+            },
+        );
+
+        let report = report(&timings);
+        assert_eq!(2, report.len());
+        assert!(report[0].starts_with("  match"));
+        assert!(report[0].contains("75.0%"));
+        assert!(report[0].contains("1500us each"));
+        assert!(report[0].ends_with(" rule slow /(a+)+$/"));
+        assert!(report[1].starts_with("  parse"));
+        assert!(report[1].ends_with("250us each"));
+    }
+}
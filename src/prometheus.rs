@@ -0,0 +1,311 @@
+/**
+ * The prometheus module keeps the metrics in a registry which can be rendered in the Prometheus
+ * text exposition format, for the `/metrics` endpoint of the status server
+ */
+use dashmap::DashMap;
+use std::collections::BTreeMap;
+
+/**
+ * The Prometheus type of a metric
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    /**
+     * Timers are exposed as histograms, so that their quantiles can be calculated
+     */
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/**
+ * A single label of a series, e.g. the topic of a Kafka metric
+ */
+pub type Label = Option<(&'static str, String)>;
+
+/**
+ * The upper bounds of the buckets of the histograms of latencies, in microseconds
+ */
+pub const BUCKETS: &[i64] = &[
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 5_000_000,
+];
+
+/**
+ * The upper bounds of the buckets of the histograms of the steps of the pipeline which take
+ * microseconds, such as parsing a line or rendering a template
+ */
+pub const FAST_BUCKETS: &[i64] = &[
+    1, 2, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000,
+];
+
+/**
+ * The upper bounds of the buckets of the histograms of long lived things, such as connections,
+ * from a second up to a day
+ */
+pub const LONG_BUCKETS: &[i64] = &[
+    1_000_000,
+    10_000_000,
+    60_000_000,
+    300_000_000,
+    900_000_000,
+    3_600_000_000,
+    21_600_000_000,
+    86_400_000_000,
+];
+
+#[derive(Debug)]
+struct Series {
+    kind: Kind,
+    value: i64,
+    count: i64,
+    /**
+     * The upper bounds of the buckets of a histogram
+     */
+    bounds: &'static [i64],
+    /**
+     * The number of observations of a histogram which are within each of the bounds
+     */
+    buckets: Vec<i64>,
+}
+
+impl Series {
+    fn new(kind: Kind, value: i64) -> Self {
+        Series {
+            kind,
+            value,
+            count: 0,
+            bounds: &[],
+            buckets: vec![],
+        }
+    }
+
+    fn histogram(bounds: &'static [i64]) -> Self {
+        Series {
+            kind: Kind::Histogram,
+            value: 0,
+            count: 0,
+            bounds,
+            buckets: vec![0; bounds.len()],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    series: DashMap<(String, Label), Series>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /**
+     * Add the value to the series, for counters and for gauges which are tracked as changes
+     */
+    pub fn add(&self, kind: Kind, name: String, label: Label, value: i64) {
+        self.series
+            .entry((name, label))
+            .or_insert_with(|| Series::new(kind, 0))
+            .value += value;
+    }
+
+    /**
+     * Set the series to the absolute value of a gauge
+     */
+    pub fn set(&self, name: String, label: Label, value: i64) {
+        self.series
+            .insert((name, label), Series::new(Kind::Gauge, value));
+    }
+
+    /**
+     * Record an observation of a timer, in microseconds, into the histogram with the given
+     * bounds of its buckets
+     */
+    pub fn observe(&self, name: String, label: Label, value: i64, bounds: &'static [i64]) {
+        let mut series = self
+            .series
+            .entry((name, label))
+            .or_insert_with(|| Series::histogram(bounds));
+        let series = &mut *series;
+        series.value += value;
+        series.count += 1;
+
+        for (bucket, bound) in series.buckets.iter_mut().zip(series.bounds.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+    }
+
+    /**
+     * Render every series in the Prometheus text exposition format
+     */
+    pub fn render(&self) -> String {
+        /*
+         * The lines of each series are kept together, and the series are sorted by their labels
+         * so that the output is stable
+         */
+        let mut families: BTreeMap<String, (Kind, BTreeMap<String, Vec<String>>)> = BTreeMap::new();
+
+        for entry in self.series.iter() {
+            let (name, label) = entry.key();
+            let series = entry.value();
+            let labels = render_labels(label, None);
+            let mut lines = vec![];
+
+            if series.kind == Kind::Histogram {
+                for (bucket, bound) in series.buckets.iter().zip(series.bounds.iter()) {
+                    lines.push(format!(
+                        "{}_bucket{} {}",
+                        name,
+                        render_labels(label, Some(&bound.to_string())),
+                        bucket
+                    ));
+                }
+                lines.push(format!(
+                    "{}_bucket{} {}",
+                    name,
+                    render_labels(label, Some("+Inf")),
+                    series.count
+                ));
+                lines.push(format!("{}_sum{} {}", name, labels, series.value));
+                lines.push(format!("{}_count{} {}", name, labels, series.count));
+            } else {
+                lines.push(format!("{}{} {}", name, labels, series.value));
+            }
+
+            families
+                .entry(name.to_string())
+                .or_insert_with(|| (series.kind, BTreeMap::new()))
+                .1
+                .insert(labels, lines);
+        }
+
+        let mut output = String::new();
+
+        for (name, (kind, series)) in families.into_iter() {
+            output.push_str(&format!("# TYPE {} {}\n", name, kind.as_str()));
+
+            for line in series.values().flatten() {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
+
+/**
+ * Turn a dotted metric name, e.g. `kafka.delivered`, into a valid Prometheus metric name
+ */
+pub fn metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/**
+ * Render the label of a series, along with the upper bound of a histogram's bucket
+ */
+fn render_labels(label: &Label, le: Option<&str>) -> String {
+    let mut labels = vec![];
+
+    if let Some((key, value)) = label {
+        labels.push(format!("{}=\"{}\"", key, escape(value)));
+    }
+    if let Some(le) = le {
+        labels.push(format!("le=\"{}\"", le));
+    }
+
+    if labels.is_empty() {
+        return "".to_string();
+    }
+    format!("{{{}}}", labels.join(","))
+}
+
+/**
+ * Escape a label value for the text exposition format
+ */
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::new();
+        registry.add(Kind::Counter, "hotdog_lines_total".to_string(), None, 2);
+        registry.add(Kind::Counter, "hotdog_lines_total".to_string(), None, 1);
+        registry.set(
+            "hotdog_kafka_client_broker_rtt".to_string(),
+            Some(("broker", "kafka:9092".to_string())),
+            15,
+        );
+        registry.observe(
+            "hotdog_kafka_producer_sent_us".to_string(),
+            Some(("topic", "logs".to_string())),
+            100,
+            BUCKETS,
+        );
+        registry.observe(
+            "hotdog_kafka_producer_sent_us".to_string(),
+            Some(("topic", "logs".to_string())),
+            300,
+            BUCKETS,
+        );
+
+        assert_eq!(
+            "# TYPE hotdog_kafka_client_broker_rtt gauge\n\
+             hotdog_kafka_client_broker_rtt{broker=\"kafka:9092\"} 15\n\
+             # TYPE hotdog_kafka_producer_sent_us histogram\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"100\"} 1\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"250\"} 1\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"500\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"1000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"2500\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"5000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"10000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"25000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"50000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"100000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"250000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"500000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"1000000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"5000000\"} 2\n\
+             hotdog_kafka_producer_sent_us_bucket{topic=\"logs\",le=\"+Inf\"} 2\n\
+             hotdog_kafka_producer_sent_us_sum{topic=\"logs\"} 400\n\
+             hotdog_kafka_producer_sent_us_count{topic=\"logs\"} 2\n\
+             # TYPE hotdog_lines_total counter\n\
+             hotdog_lines_total 3\n",
+            registry.render()
+        );
+    }
+
+    #[test]
+    fn test_metric_name() {
+        assert_eq!("kafka_delivered", metric_name("kafka.delivered"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a \\\"b\\\"", escape("a \"b\""));
+    }
+}
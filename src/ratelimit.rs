@@ -0,0 +1,190 @@
+/**
+ * The ratelimit module contains the token buckets used by the rate_limit action, which are shared
+ * between all the connections
+ */
+use crate::sampled;
+use dashmap::DashMap;
+use log::*;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+ * How often the buckets which have refilled are evicted
+ */
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/**
+ * The number of buckets which are kept at most, the keys beyond it share the bucket of OTHER_KEY
+ */
+const MAX_BUCKETS: usize = 100_000;
+const OTHER_KEY: &str = "__other__";
+/**
+ * The longest a bucket is kept without being used, even when it has not refilled by then
+ */
+const MAX_IDLE_SECS: f64 = 86_400.0;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /**
+     * When the bucket will have refilled, after which it is no different from a new bucket
+     */
+    full: Instant,
+}
+
+pub struct RateLimiter {
+    buckets: DashMap<String, Bucket>,
+    max_buckets: usize,
+    swept: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::with_max_buckets(MAX_BUCKETS)
+    }
+
+    fn with_max_buckets(max_buckets: usize) -> Self {
+        RateLimiter {
+            buckets: DashMap::default(),
+            max_buckets,
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+
+    /**
+     * Attempt to take a token from the bucket for the given key, which refills at `per_second`
+     * tokens per second up to `burst` tokens.
+     *
+     * Returns false if the bucket is empty and the event should be limited
+     */
+    pub fn acquire(&self, key: &str, per_second: f64, burst: f64) -> bool {
+        self.acquire_at(key, per_second, burst, Instant::now())
+    }
+
+    fn acquire_at(&self, key: &str, per_second: f64, burst: f64, now: Instant) -> bool {
+        self.sweep(now);
+
+        /*
+         * The keys are rendered from the messages, so their number is capped to bound the memory
+         */
+        let key = if self.buckets.len() >= self.max_buckets && !self.buckets.contains_key(key) {
+            sampled::RATE_LIMIT_KEYS.error(format_args!(
+                "More than {} rate limit keys are in use, `{}` shares the bucket of `{}`",
+                self.max_buckets, key, OTHER_KEY
+            ));
+            OTHER_KEY
+        } else {
+            key
+        };
+
+        let mut bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: burst,
+                updated: now,
+                full: now,
+            });
+
+        let elapsed = now
+            .checked_duration_since(bucket.updated)
+            .unwrap_or_default()
+            .as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.updated = now;
+
+        let acquired = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        };
+
+        let refill = if per_second > 0.0 {
+            ((burst - bucket.tokens) / per_second).max(0.0)
+        } else {
+            f64::INFINITY
+        };
+        bucket.full = now + Duration::from_secs_f64(refill.min(MAX_IDLE_SECS));
+        acquired
+    }
+
+    /**
+     * Evict the buckets which have refilled, at most once every SWEEP_INTERVAL
+     */
+    fn sweep(&self, now: Instant) {
+        {
+            let mut swept = self.swept.lock();
+            if now.saturating_duration_since(*swept) < SWEEP_INTERVAL {
+                return;
+            }
+            *swept = now;
+        }
+
+        let before = self.buckets.len();
+        self.buckets.retain(|_, bucket| bucket.full > now);
+        debug!(
+            "Evicted {} idle rate limit buckets",
+            before.saturating_sub(self.buckets.len())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_acquire_until_empty() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at("host", 1.0, 2.0, now));
+        assert!(limiter.acquire_at("host", 1.0, 2.0, now));
+        assert!(!limiter.acquire_at("host", 1.0, 2.0, now));
+    }
+
+    #[test]
+    fn test_acquire_refills() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at("host", 10.0, 1.0, now));
+        assert!(!limiter.acquire_at("host", 10.0, 1.0, now));
+        assert!(limiter.acquire_at("host", 10.0, 1.0, now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_sweep_evicts_refilled() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at("idle", 1.0, 1.0, now));
+        assert!(limiter.acquire_at("busy", 0.001, 1.0, now));
+        limiter.sweep(now + SWEEP_INTERVAL);
+
+        assert!(!limiter.buckets.contains_key("idle"));
+        assert!(limiter.buckets.contains_key("busy"));
+    }
+
+    #[test]
+    fn test_acquire_beyond_max_buckets() {
+        let limiter = RateLimiter::with_max_buckets(1);
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at("first", 1.0, 1.0, now));
+        assert!(limiter.acquire_at("second", 1.0, 1.0, now));
+        assert!(!limiter.acquire_at("third", 1.0, 1.0, now));
+        assert!(limiter.buckets.contains_key(OTHER_KEY));
+    }
+
+    #[test]
+    fn test_acquire_per_key() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        assert!(limiter.acquire_at("chatty", 1.0, 1.0, now));
+        assert!(!limiter.acquire_at("chatty", 1.0, 1.0, now));
+        assert!(limiter.acquire_at("quiet", 1.0, 1.0, now));
+    }
+}
@@ -0,0 +1,328 @@
+/**
+ * The reload module allows the rules to be reloaded from the configuration while hotdog is
+ * running, without dropping the active connections
+ */
+use crate::events::{self, Event};
+use crate::serve::Sinks;
+use crate::settings::{self, Overrides, Settings};
+use crate::status::{self, Statistic, Stats, StatsHandler};
+use async_channel::{bounded, Receiver, Sender};
+use async_std::{sync::Arc, task};
+use log::*;
+use parking_lot::{Mutex, RwLock};
+
+/**
+ * Reloadable carries the current Settings, which connections should check for every line so that
+ * reloaded rules are picked up without reconnecting, along with the Sinks for the current Kafka
+ * settings
+ */
+pub struct Reloadable {
+    /**
+     * The configuration file which the settings are reloaded from
+     */
+    file: String,
+    /**
+     * The overrides from the arguments, which are applied to the reloaded settings as well
+     */
+    overrides: Overrides,
+    current: RwLock<Arc<Settings>>,
+    sinks: RwLock<Arc<Sinks>>,
+    /**
+     * Notifies every accept loop that the listen settings have changed
+     */
+    rebinds: Mutex<Vec<Sender<()>>>,
+    /**
+     * Held while new sinks are started and swapped in, so that the producers of reloads which
+     * follow each other quickly cannot be swapped in out of order
+     */
+    restarting: async_std::sync::Mutex<()>,
+}
+
+impl Reloadable {
+    pub fn new(file: &str, overrides: Overrides, settings: Arc<Settings>, sinks: Sinks) -> Self {
+        Reloadable {
+            file: file.to_string(),
+            overrides,
+            current: RwLock::new(settings),
+            sinks: RwLock::new(Arc::new(sinks)),
+            rebinds: Mutex::new(vec![]),
+            restarting: async_std::sync::Mutex::new(()),
+        }
+    }
+
+    /**
+     * Return the current settings
+     */
+    pub fn current(&self) -> Arc<Settings> {
+        self.current.read().clone()
+    }
+
+    /**
+     * Return the current sinks
+     */
+    pub fn sinks(&self) -> Arc<Sinks> {
+        self.sinks.read().clone()
+    }
+
+    /**
+     * Publish the event to the events topic of the current settings, if there is one
+     */
+    pub fn publish(&self, event: Event) {
+        if let Some(topic) = &self.current().global.kafka.events {
+            events::publish(&self.sinks().sender, topic, event);
+        }
+    }
+
+    /**
+     * Return a Receiver which is notified every time the listen settings have been reloaded, each
+     * accept loop needs a Receiver of its own
+     */
+    pub fn rebinds(&self) -> Receiver<()> {
+        let (tx, rx) = bounded(1);
+        self.rebinds.lock().push(tx);
+        rx
+    }
+
+    /**
+     * Notify every accept loop that the listen settings have been reloaded
+     */
+    fn rebind(&self) {
+        for rebind in self.rebinds.lock().iter() {
+            let _ = rebind.try_send(());
+        }
+    }
+
+    /**
+     * Load the settings from the configuration file again, and swap them in if they are valid,
+     * returning the previous settings.
+     *
+     * Invalid settings are rejected, leaving the current settings in place
+     */
+    pub async fn reload(&self) -> Result<Arc<Settings>, String> {
+        let settings = Arc::new(settings::try_load_blocking(&self.file, &self.overrides).await?);
+
+        if !crate::connection::verify(settings.clone()) {
+            return Err("The JMESPath expressions of the rules are invalid".to_string());
+        }
+
+        Ok(std::mem::replace(&mut *self.current.write(), settings))
+    }
+
+    /**
+     * Return whether the Kafka settings are still those of the current settings, rather than
+     * having been changed again by a later reload
+     */
+    fn is_current_kafka(&self, settings: &Settings) -> bool {
+        let current = self.current();
+        current.global.kafka == settings.global.kafka
+            && current.global.destinations == settings.global.destinations
+    }
+
+    /**
+     * Swap in the new sinks, returning the previous sinks which should be retired
+     */
+    fn swap_sinks(&self, sinks: Sinks) -> Arc<Sinks> {
+        std::mem::replace(&mut *self.sinks.write(), Arc::new(sinks))
+    }
+}
+
+/**
+ * Reload the settings every time the receiver is notified, until it is closed.
+ *
+ * Besides the rules, changes to the Kafka settings start new producers in the background which
+ * are swapped in once they have connected, changes to the metrics settings switch to a new
+ * metrics scope, and changes to the listen settings rebind the listeners
+ */
+pub async fn reload_loop(
+    reloadable: Arc<Reloadable>,
+    notifications: Receiver<()>,
+    stats: Arc<StatsHandler>,
+) {
+    while notifications.recv().await.is_ok() {
+        info!("Reloading the settings from {}", reloadable.file);
+
+        match reloadable.reload().await {
+            Ok(previous) => {
+                let current = reloadable.current();
+                info!("Reloaded {} rules", current.rules.len());
+                stats.tx.send((Stats::ConfigReloaded, 1)).await;
+                stats.set_config_hash(current.hash.clone());
+                reloadable.publish(Event::Reloaded {
+                    config_hash: current.hash.clone(),
+                });
+
+                reconfigure(&reloadable, &previous, &current, &stats);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to reload the settings, keeping the current settings: {}",
+                    e
+                );
+                stats.tx.send((Stats::ConfigReloadFailed, 1)).await;
+                reloadable.publish(Event::ReloadFailed { error: e });
+            }
+        }
+    }
+}
+
+/**
+ * Apply the changes between the previous and the current settings which go beyond the rules
+ */
+fn reconfigure(
+    reloadable: &Arc<Reloadable>,
+    previous: &Settings,
+    current: &Arc<Settings>,
+    stats: &Arc<StatsHandler>,
+) {
+    if previous.global.kafka != current.global.kafka
+        || previous.global.destinations != current.global.destinations
+    {
+        if reloadable.sinks().is_null() {
+            warn!("The Kafka settings have changed, but a dry run never starts producers");
+        } else {
+            info!("The Kafka settings have changed, starting new producers");
+            task::spawn(restart_sinks(
+                reloadable.clone(),
+                current.clone(),
+                stats.tx.clone(),
+            ));
+        }
+    }
+
+    if previous.global.metrics != current.global.metrics {
+        match status::metrics_for(&current.global.metrics) {
+            Ok(metrics) => {
+                info!(
+                    "Sending metrics to {}",
+                    current.global.metrics.destination()
+                );
+                stats.set_metrics(metrics);
+                stats.set_max_topics(current.global.metrics.max_topics);
+                stats.set_max_peers(current.global.metrics.max_peers);
+                stats.set_max_metrics(current.global.metrics.max_metrics);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to create the metrics scope for {}, keeping the current metrics settings: {:?}",
+                    current.global.metrics.destination(), e
+                );
+            }
+        }
+    }
+
+    if previous.global.listeners.len() != current.global.listeners.len() {
+        warn!("Adding or removing `global.listeners` requires hotdog to be restarted");
+    }
+
+    if previous.listeners().ne(current.listeners()) {
+        reloadable.rebind();
+    }
+
+    if previous.global.status != current.global.status {
+        warn!("Changes to `global.status` require hotdog to be restarted");
+    }
+
+    if previous.global.log != current.global.log {
+        warn!("Changes to `global.log` require hotdog to be restarted");
+    }
+
+    if previous.global.runtime != current.global.runtime {
+        warn!("Changes to `global.runtime` require hotdog to be restarted");
+    }
+
+    if previous.global.tracing != current.global.tracing {
+        warn!("Changes to `global.tracing` require hotdog to be restarted");
+    }
+
+    if previous.global.heartbeat != current.global.heartbeat {
+        warn!("Changes to `global.heartbeat` require hotdog to be restarted");
+    }
+}
+
+/**
+ * Start the sinks for the reloaded Kafka settings, and swap them in once they have connected,
+ * retiring the previous sinks.
+ *
+ * Only one restart runs at a time, and the sinks of Kafka settings which a later reload has
+ * already replaced are never swapped in, since that reload restarts the sinks itself
+ */
+async fn restart_sinks(
+    reloadable: Arc<Reloadable>,
+    settings: Arc<Settings>,
+    stats: Sender<Statistic>,
+) {
+    let _restarting = reloadable.restarting.lock().await;
+
+    if !reloadable.is_current_kafka(&settings) {
+        debug!("The Kafka settings have been reloaded again, skipping the outdated producers");
+        return;
+    }
+
+    match Sinks::start(&settings, stats).await {
+        Ok(sinks) => {
+            if !reloadable.is_current_kafka(&settings) {
+                info!("The Kafka settings were reloaded again while starting the producers, retiring them");
+                sinks.retire().await;
+                return;
+            }
+
+            let previous = reloadable.swap_sinks(sinks);
+            info!("Switched to the producers for the reloaded Kafka settings");
+            previous.retire().await;
+        }
+        Err(e) => {
+            error!(
+                "Failed to start the producers for the reloaded Kafka settings, keeping the current producers: {:?}",
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_reload() {
+        let file = "test/configs/single-rule-with-merge.yml";
+        let reloadable = Reloadable::new(
+            file,
+            Overrides::default(),
+            Arc::new(settings::load(file)),
+            Sinks::unconnected(),
+        );
+        let before = reloadable.current();
+        let previous = reloadable
+            .reload()
+            .await
+            .expect("Failed to reload the settings");
+
+        assert!(Arc::ptr_eq(&before, &previous));
+        assert!(!Arc::ptr_eq(&before, &reloadable.current()));
+    }
+
+    #[async_std::test]
+    async fn test_reload_invalid() {
+        let file = "test/configs/single-rule-with-merge.yml";
+        let invalid = [
+            "test/configs/single-rule-with-invalid-remap-severity.yml",
+            "test/configs/single-rule-with-invalid-jmespath.yml",
+        ];
+
+        for invalid in invalid.iter() {
+            let reloadable = Reloadable {
+                file: invalid.to_string(),
+                overrides: Overrides::default(),
+                current: RwLock::new(Arc::new(settings::load(file))),
+                sinks: RwLock::new(Arc::new(Sinks::unconnected())),
+                rebinds: Mutex::new(vec![]),
+                restarting: async_std::sync::Mutex::new(()),
+            };
+            let before = reloadable.current();
+
+            assert!(reloadable.reload().await.is_err());
+            assert!(Arc::ptr_eq(&before, &reloadable.current()));
+        }
+    }
+}
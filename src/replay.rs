@@ -0,0 +1,213 @@
+/**
+ * The replay module reads an existing log file through the rules of the configuration, as a
+ * connection would read it, and produces the messages to Kafka at a fixed rate, for backfilling
+ * and for trying out new topics with historical data
+ */
+use crate::bench;
+use crate::connection::Connection;
+use crate::reload::Reloadable;
+use crate::serve::Sinks;
+use crate::settings::{self, Overrides, ParseErrorPolicy};
+use crate::status::{self, Stats};
+use async_channel::{bounded, Sender};
+use async_std::{fs::File, io::BufReader, prelude::*, sync::Arc, task};
+use futures::{FutureExt, TryStreamExt};
+use std::time::{Duration, Instant};
+
+pub struct Options {
+    pub file: String,
+    /**
+     * The number of lines per second, zero replays the lines as fast as Kafka takes them
+     */
+    pub rate: u64,
+}
+
+/**
+ * The results of a replay
+ */
+#[derive(Default)]
+pub struct Report {
+    lines: u64,
+    elapsed: Duration,
+    /**
+     * The number of messages which Kafka acknowledged
+     */
+    delivered: i64,
+    /**
+     * The number of messages which could not be delivered, whether or not they were sent to the
+     * dead letter topic afterwards
+     */
+    failed: i64,
+}
+
+impl Report {
+    /**
+     * Count the deliveries reported by the statistic
+     */
+    fn count(&mut self, stat: &status::Statistic) {
+        match stat {
+            (Stats::KafkaMsgDelivered { .. }, count) => self.delivered += count,
+            (Stats::KafkaMsgFailed { .. }, count) => self.failed += count,
+            _ => {}
+        }
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Replayed {} lines in {:.3}s ({:.0} lines/s), {} messages were delivered and {} failed",
+            self.lines,
+            self.elapsed.as_secs_f64(),
+            self.lines as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON),
+            self.delivered,
+            self.failed
+        )
+    }
+}
+
+/**
+ * Replay the lines of the file through the rules to Kafka, returning once every message has been
+ * delivered or has failed
+ */
+pub async fn replay(
+    settings_file: &str,
+    overrides: Overrides,
+    options: &Options,
+) -> Result<Report, String> {
+    let mut settings = settings::try_load_blocking(settings_file, &overrides).await?;
+    /*
+     * Log files are rarely all syslog, like `hotdog test` the lines which are not are handled as
+     * if the entire line was the `msg`
+     */
+    settings.global.parse_errors.policy = ParseErrorPolicy::Raw;
+    let settings = Arc::new(settings);
+    let file = File::open(&options.file)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", options.file, e))?;
+
+    /*
+     * The statistics of a short-lived replay are only counted for its report
+     */
+    let (stats, counted) = bounded::<status::Statistic>(1024);
+    let mut report = Report::default();
+
+    let sinks = Sinks::start(&settings, stats.clone())
+        .await
+        .map_err(|e| format!("Failed to connect to Kafka: {:?}", e))?;
+    let reloadable = Arc::new(Reloadable::new(settings_file, overrides, settings, sinks));
+    let (settled, waiting) = bounded::<()>(1);
+    let connection = Connection::detached(reloadable.clone(), stats).with_settled(settled);
+
+    let (lines, read) = bounded::<Vec<u8>>(1024);
+    let start = Instant::now();
+    let reading = task::spawn(read_lines(file, options.rate, lines));
+    let reader = Box::pin(read)
+        .map(Ok::<_, std::io::Error>)
+        .into_async_read();
+
+    let replaying = async move {
+        connection
+            .read_logs(reader)
+            .await
+            .map_err(|e| format!("Failed to replay the lines: {:?}", e))?;
+        drop(connection);
+        /*
+         * Nothing is ever sent on the channel, it is closed once every message has settled
+         */
+        let _ = waiting.recv().await;
+        Ok::<(), String>(())
+    };
+    let mut replaying = Box::pin(replaying.fuse());
+
+    loop {
+        futures::select! {
+            replayed = replaying => {
+                replayed?;
+                break;
+            }
+            stat = counted.recv().fuse() => {
+                if let Ok(stat) = stat {
+                    report.count(&stat);
+                }
+            }
+        }
+    }
+    /*
+     * Every delivery was counted before its message settled, so its statistics are waiting
+     */
+    for stat in std::iter::from_fn(|| counted.try_recv().ok()) {
+        report.count(&stat);
+    }
+    task::spawn(async move { while counted.recv().await.is_ok() {} });
+
+    report.lines = reading
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", options.file, e))?;
+    report.elapsed = start.elapsed();
+    reloadable.sinks().flush().await;
+
+    Ok(report)
+}
+
+/**
+ * Send the lines of the file to the sender at the rate, returning the number of lines read. The
+ * lines are sent as they are, so that lines which are not valid UTF-8 are handled as they would
+ * be when received
+ */
+async fn read_lines(file: File, rate: u64, sender: Sender<Vec<u8>>) -> std::io::Result<u64> {
+    let mut reader = BufReader::new(file);
+    let start = Instant::now();
+    let mut sequence = 0;
+
+    loop {
+        let mut line = vec![];
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+
+        bench::pace(start, sequence, rate).await;
+        sequence += 1;
+
+        if sender.send(line).await.is_err() {
+            break;
+        }
+    }
+    Ok(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_read_lines() {
+        let file = File::open("example.log")
+            .await
+            .expect("Failed to open the example log");
+        let (sender, receiver) = bounded(16);
+
+        let read = read_lines(file, 0, sender)
+            .await
+            .expect("Failed to read the lines");
+        assert_eq!(4, read);
+        assert_eq!(b"hello there\n".to_vec(), receiver.recv().await.unwrap());
+    }
+
+    #[test]
+    fn test_report_count() {
+        let mut report = Report::default();
+        let topic = || "logs".to_string();
+
+        report.count(&(Stats::KafkaMsgDelivered { topic: topic() }, 2));
+        report.count(&(Stats::KafkaMsgFailed { topic: topic() }, 1));
+        report.count(&(Stats::LineReceived, 3));
+
+        assert_eq!(2, report.delivered);
+        assert_eq!(1, report.failed);
+        assert!(report
+            .to_string()
+            .ends_with("2 messages were delivered and 1 failed"));
+    }
+}
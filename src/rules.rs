@@ -1,17 +1,48 @@
+use crate::connection::Connection;
 use crate::errors;
+use crate::kafka::{KafkaMessage, Produce};
+use crate::parse::{self, SyslogMessage};
+use crate::reload::Reloadable;
 use crate::settings::*;
+use crate::status::Statistic;
 /**
  * Rules processing module
  *
  */
+use async_channel::{Receiver, Sender};
 use async_std::{fs::File, io::BufReader, prelude::*, sync::Arc};
+use futures::FutureExt;
 use log::*;
+use std::borrow::Cow;
 use std::collections::HashMap;
 
+/**
+ * What the rules are tested with by `hotdog test`
+ */
+pub enum Test<'a> {
+    /**
+     * A log file, whose lines are printed along with what the rules made of them
+     */
+    Lines(&'a str),
+    /**
+     * A file of lines paired with what the rules are expected to make of them
+     */
+    Assertions(&'a str),
+}
+
+/**
+ * Test the lines of the file against the rules, printing the rules which match each line along
+ * with the messages which its actions produce. The messages end up in the receiver of the null
+ * sinks of the settings rather than in Kafka
+ */
 pub async fn test_rules(
     file_name: &str,
-    settings: Arc<Settings>,
+    reloadable: Arc<Reloadable>,
+    sunk: Receiver<Produce>,
+    stats: Sender<Statistic>,
 ) -> Result<(), errors::HotdogError> {
+    let settings = reloadable.current();
+    let connection = Connection::detached(reloadable, stats).dry();
     let file = File::open(file_name)
         .await
         .expect("Failed to open the file");
@@ -23,22 +54,7 @@ pub async fn test_rules(
         let line = line?;
         debug!("Testing the line: {}", line);
         number += 1;
-        let mut matches: Vec<&Rule> = vec![];
-        let mut unused = HashMap::<String, String>::new();
-        let also_unused = HashMap::<String, jmespath::Expression>::new();
-
-        for rule in settings.rules.iter() {
-            match rule.field {
-                Field::Msg => {
-                    if apply_rule(&rule, &line, &also_unused, &mut unused) {
-                        matches.push(rule);
-                    }
-                }
-                _ => {
-                    error!("The test mode will only work on `field: msg` rules");
-                }
-            }
-        }
+        let matches = matching(&settings, &line);
 
         if !matches.is_empty() {
             println!("Line {} matches on:", number);
@@ -46,18 +62,432 @@ pub async fn test_rules(
                 println!("\t - {}", m);
             }
         }
+
+        let produced = produce(&connection, &line, &sunk).await?;
+
+        if !produced.is_empty() {
+            println!("Line {} produces:", number);
+            for kmsg in produced.iter() {
+                println!("\t - {}", describe(kmsg));
+            }
+        }
     }
 
     Ok(())
 }
+
+/**
+ * Return the rules whose conditions match the line
+ */
+pub fn matching<'a>(settings: &'a Settings, line: &str) -> Vec<&'a Rule> {
+    let mut variables = settings.global.vars.clone();
+    let also_unused = HashMap::<String, jmespath::Expression>::new();
+    /*
+     * Lines which are not syslog formatted are tested as if the entire line was the `msg`
+     */
+    let msg = parse::parse_line(line).unwrap_or_else(|_| SyslogMessage {
+        msg: line.to_string(),
+        ..Default::default()
+    });
+
+    settings
+        .rules
+        .iter()
+        .filter(|rule| evaluate(&rule.condition, &msg, &also_unused, &mut variables))
+        .collect()
+}
+
 /**
- * Attempt to apply the given rule to the given field value, inserting the
+ * Read the line through the connection on its own, so that the messages it produced can be told
+ * apart from those of the other lines. The sinks are drained while the line is read, since its
+ * actions may produce more messages than the sinks can buffer
+ */
+pub async fn produce(
+    connection: &Connection,
+    line: &str,
+    sunk: &Receiver<Produce>,
+) -> Result<Vec<KafkaMessage>, errors::HotdogError> {
+    let line = format!("{}\n", line);
+    let mut reading = Box::pin(connection.read_logs(line.as_bytes()).fuse());
+    let mut produced = vec![];
+
+    loop {
+        futures::select! {
+            read = reading => {
+                read?;
+                break;
+            }
+            produce = sunk.recv().fuse() => match produce {
+                Ok(produce) => produced.push(produce),
+                Err(_) => {
+                    (&mut reading).await?;
+                    break;
+                }
+            },
+        }
+    }
+    /*
+     * The dispatchers of the line have finished by now, so whatever else it produced is waiting
+     */
+    produced.extend(std::iter::from_fn(|| sunk.try_recv().ok()));
+
+    Ok(produced
+        .into_iter()
+        .flat_map(|produce| match produce {
+            Produce::Message(kmsg) => vec![kmsg],
+            Produce::Transaction(kmsgs) => kmsgs,
+        })
+        .collect())
+}
+
+/**
+ * Describe the message with its topic, its key if it has one, and the rule which produced it if
+ * that was recorded, for the output of the tests
+ */
+fn describe(kmsg: &KafkaMessage) -> String {
+    let payload = String::from_utf8_lossy(kmsg.payload());
+    let described = match kmsg.key() {
+        Some(key) => format!("{} (key {}): {}", kmsg.topic(), key, payload),
+        None => format!("{}: {}", kmsg.topic(), payload),
+    };
+
+    match kmsg.rule() {
+        Some(rule) => format!("[{}] {}", rule, described),
+        None => described,
+    }
+}
+/**
+ * The Prefilter runs the regular expressions of all the rules which match on the `msg` field as a
+ * single RegexSet, which is considerably cheaper than running each one in turn, in order to rule
+ * out the rules which cannot possibly match before their captures are evaluated
+ */
+#[derive(Debug)]
+pub struct Prefilter {
+    set: regex::RegexSet,
+    /**
+     * The index of the rule for each pattern in the set
+     */
+    rules: Vec<usize>,
+    count: usize,
+}
+
+impl Prefilter {
+    /**
+     * Build the prefilter for the given rules, returning None if no rules can be prefiltered
+     */
+    pub fn new(rules: &[Rule]) -> Option<Self> {
+        let mut patterns = vec![];
+        let mut indexes = vec![];
+
+        for (index, rule) in rules.iter().enumerate() {
+            /*
+             * Negated rules match when their regular expression does not, so they cannot be ruled
+             * out by the set
+             */
+            if rule.condition.negate {
+                continue;
+            }
+
+            if let (Some(Field::Msg), Some(regex)) = (&rule.condition.field, &rule.condition.regex)
+            {
+                patterns.push(regex.as_str());
+                indexes.push(index);
+            }
+        }
+
+        if patterns.is_empty() {
+            return None;
+        }
+
+        match regex::RegexSet::new(patterns) {
+            Ok(set) => Some(Prefilter {
+                set,
+                rules: indexes,
+                count: rules.len(),
+            }),
+            Err(e) => {
+                warn!(
+                    "Unable to build the rule prefilter, rules will be evaluated one at a time: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /**
+     * Return whether each rule, by index, could match the given message
+     */
+    pub fn candidates(&self, msg: &str) -> Vec<bool> {
+        let mut candidates = vec![true; self.count];
+
+        for index in self.rules.iter() {
+            candidates[*index] = false;
+        }
+        for matched in self.set.matches(msg).iter() {
+            candidates[self.rules[matched]] = true;
+        }
+        candidates
+    }
+}
+
+/**
+ * Compute the probability of a message being kept by a `sample` action, preferring the `rate`
+ * when both are set
+ */
+pub fn sample_probability(rate: Option<f64>, one_in: Option<u64>) -> f64 {
+    match (rate, one_in) {
+        (Some(rate), _) => rate,
+        (None, Some(0)) => 0.0,
+        (None, Some(n)) => 1.0 / n as f64,
+        (None, None) => 1.0,
+    }
+}
+
+/**
+ * Randomly decide whether the message should be kept, given the probability of it being kept
+ */
+pub fn sampled(probability: f64) -> bool {
+    probability >= 1.0 || fastrand::f64() < probability
+}
+
+/**
+ * Return the value of the rule's field from the parsed syslog message, if the message has one
+ */
+pub fn field_value<'a>(field: &Field, msg: &'a SyslogMessage) -> Option<&'a str> {
+    match field {
+        Field::Msg => Some(&msg.msg),
+        Field::Appname => msg.appname.as_deref(),
+        Field::Hostname => msg.hostname.as_deref(),
+        Field::Severity => msg.severity.as_deref(),
+        Field::Facility => msg.facility.as_deref(),
+        Field::Procid => msg.procid.as_deref(),
+        Field::Msgid => msg.msgid.as_deref(),
+        Field::StructuredData { id, param } => msg
+            .structured_data
+            .get(id)
+            .and_then(|params| params.get(param))
+            .map(String::as_str),
+        /*
+         * Variables are not part of the message, see `evaluate`
+         */
+        Field::Variable(_) => None,
+    }
+}
+
+/**
+ * Convert the value of a severity or facility field into its numeric syslog code, allowing
+ * comparisons between them. Lower severity codes are more severe, e.g. `emerg` is 0.
+ *
+ * Numbers are passed through as-is, which allows comparisons on the numeric values of any field
+ */
+pub fn numeric_value(field: &Field, value: &str) -> Option<f64> {
+    if let Ok(number) = value.trim().parse::<f64>() {
+        return Some(number);
+    }
+
+    let value = value.to_lowercase();
+
+    match field {
+        Field::Severity => match value.as_str() {
+            "emerg" | "emergency" | "panic" => Some(0.0),
+            "alert" => Some(1.0),
+            "crit" | "critical" => Some(2.0),
+            "err" | "error" => Some(3.0),
+            "warning" | "warn" => Some(4.0),
+            "notice" => Some(5.0),
+            "info" | "informational" => Some(6.0),
+            "debug" => Some(7.0),
+            _ => None,
+        },
+        Field::Facility => match value.as_str() {
+            "kern" => Some(0.0),
+            "user" => Some(1.0),
+            "mail" => Some(2.0),
+            "daemon" => Some(3.0),
+            "auth" => Some(4.0),
+            "syslog" => Some(5.0),
+            "lpr" => Some(6.0),
+            "news" => Some(7.0),
+            "uucp" => Some(8.0),
+            "cron" => Some(9.0),
+            "authpriv" => Some(10.0),
+            "ftp" => Some(11.0),
+            "ntp" => Some(12.0),
+            "audit" => Some(13.0),
+            "alert" => Some(14.0),
+            "clockd" => Some(15.0),
+            "local0" => Some(16.0),
+            "local1" => Some(17.0),
+            "local2" => Some(18.0),
+            "local3" => Some(19.0),
+            "local4" => Some(20.0),
+            "local5" => Some(21.0),
+            "local6" => Some(22.0),
+            "local7" => Some(23.0),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/**
+ * Evaluate the condition, and any of its sub-conditions, against the message, inserting the
  * necessary variables into the hash along the way.
  *
- * If the rule matches, then this will return true
+ * If the condition matches, then this will return true
+ */
+pub fn evaluate(
+    condition: &Condition,
+    msg: &SyslogMessage,
+    jmespaths: &crate::connection::JmesPathExpressions,
+    hash: &mut HashMap<String, String>,
+) -> bool {
+    evaluate_captures(condition, msg, jmespaths, &HashMap::new(), hash)
+}
+
+/**
+ * Evaluate the condition like `evaluate`, looking up variables in the captures and then in the
+ * variables of the line, which are only borrowed. Only the captures are inserted into, so that
+ * the variables of a line need not be copied for the rules which do not match it
  */
-pub fn apply_rule(
-    rule: &Rule,
+pub fn evaluate_captures(
+    condition: &Condition,
+    msg: &SyslogMessage,
+    jmespaths: &crate::connection::JmesPathExpressions,
+    variables: &HashMap<String, String>,
+    captures: &mut HashMap<String, String>,
+) -> bool {
+    let mut evaluated = false;
+
+    if let Some(tag) = &condition.tagged {
+        evaluated = true;
+
+        if !msg.tags.contains(tag) {
+            return false;
+        }
+    }
+
+    if let Some(field) = &condition.field {
+        evaluated = true;
+
+        let matched = match lookup(field, msg, variables, captures) {
+            Some(value) => apply_matcher(condition, field, &value, jmespaths, captures),
+            None => false,
+        };
+
+        if matched == condition.negate {
+            return false;
+        }
+    }
+
+    if !condition.fields.is_empty() {
+        evaluated = true;
+        let mut matched = false;
+
+        /*
+         * Every field is matched, rather than stopping at the first, so that the captures from
+         * all of them end up in the hash
+         */
+        for field in condition.fields.iter() {
+            if let Some(value) = lookup(field, msg, variables, captures) {
+                if apply_matcher(condition, field, &value, jmespaths, captures) {
+                    matched = true;
+                }
+            }
+        }
+
+        if matched == condition.negate {
+            return false;
+        }
+    }
+
+    if !condition.all.is_empty() {
+        evaluated = true;
+
+        if !condition
+            .all
+            .iter()
+            .all(|c| evaluate_captures(c, msg, jmespaths, variables, captures))
+        {
+            return false;
+        }
+    }
+
+    if !condition.any.is_empty() {
+        evaluated = true;
+
+        if !condition
+            .any
+            .iter()
+            .any(|c| evaluate_captures(c, msg, jmespaths, variables, captures))
+        {
+            return false;
+        }
+    }
+
+    if !condition.none.is_empty() {
+        evaluated = true;
+
+        if condition
+            .none
+            .iter()
+            .any(|c| evaluate_captures(c, msg, jmespaths, variables, captures))
+        {
+            return false;
+        }
+    }
+
+    evaluated
+}
+
+/**
+ * Return the value of the field from the message, or from the captures and the variables for
+ * variables.
+ *
+ * Variables are cloned out of the hash since matching may insert into it
+ */
+fn lookup<'a>(
+    field: &Field,
+    msg: &'a SyslogMessage,
+    variables: &HashMap<String, String>,
+    captures: &HashMap<String, String>,
+) -> Option<Cow<'a, str>> {
+    match field {
+        Field::Variable(name) => captures
+            .get(name)
+            .or_else(|| variables.get(name))
+            .cloned()
+            .map(Cow::Owned),
+        _ => field_value(field, msg).map(Cow::Borrowed),
+    }
+}
+
+/**
+ * Attempt to apply the given condition's matcher to the given field value, inserting the
+ * necessary variables into the hash along the way.
+ *
+ * If the matcher matches, then this will return true
+ */
+pub fn apply_condition(
+    rule: &Condition,
+    value: &str,
+    jmespaths: &crate::connection::JmesPathExpressions,
+    hash: &mut HashMap<String, String>,
+) -> bool {
+    match &rule.field {
+        Some(field) => apply_matcher(rule, field, value, jmespaths, hash),
+        None => apply_matcher(rule, &Field::Msg, value, jmespaths, hash),
+    }
+}
+
+/**
+ * Apply the condition's matcher to the value of the given field, which may be one of several
+ * fields the condition matches on
+ */
+fn apply_matcher(
+    rule: &Condition,
+    field: &Field,
     value: &str,
     jmespaths: &crate::connection::JmesPathExpressions,
     hash: &mut HashMap<String, String>,
@@ -72,17 +502,55 @@ pub fn apply_rule(
         if let Ok(data) = jmespath::Variable::from_json(value) {
             // Search the data with the compiled expression
             if let Ok(result) = expr.search(data) {
-                if !result.is_null() {
-                    rule_matches = true;
+                /*
+                 * Boolean results allow for expressions such as `kubernetes.namespace == 'prod'`
+                 */
+                if !result.is_null() && result.as_boolean() != Some(false) {
                     debug!("jmespath rule matched, value: {}", result);
-                    if let Some(value) = result.as_string() {
-                        hash.insert("value".to_string(), value.to_string());
-                    } else {
-                        warn!("Unable to parse out the string value for {}, the `value` variable substitution will not be available,", result);
+                    let found = match result.as_string() {
+                        Some(value) => value.to_string(),
+                        None => result.to_string(),
+                    };
+                    rule_matches = equals(rule, &found);
+
+                    if rule_matches {
+                        if result.as_string().is_none() {
+                            warn!("The result {} is not a string, the `value` variable substitution will be its JSON representation", result);
+                        }
+                        hash.insert("value".to_string(), found);
                     }
                 }
             }
         }
+    } else if let Some(pointer) = &rule.pointer {
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(value) {
+            let found = match data.pointer(pointer) {
+                None | Some(serde_json::Value::Null) => None,
+                Some(serde_json::Value::String(s)) => Some(s.to_string()),
+                Some(other) => Some(other.to_string()),
+            };
+
+            if let Some(found) = found {
+                debug!("pointer rule matched, value: {}", found);
+                rule_matches = equals(rule, &found);
+
+                if rule_matches {
+                    hash.insert("value".to_string(), found);
+                }
+            }
+        }
+    } else if let Some(compare) = &rule.compare {
+        let lhs = numeric_value(field, value);
+        let rhs = numeric_value(field, &compare.value);
+
+        if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+            rule_matches = compare.compare(lhs, rhs);
+        } else if rhs.is_none() {
+            warn!(
+                "The comparison value `{}` is not valid for {:?}",
+                compare.value, field
+            );
+        }
     } else if let Some(regex) = &rule.regex {
         if let Some(captures) = regex.captures(value) {
             rule_matches = true;
@@ -95,6 +563,497 @@ pub fn apply_rule(
                 }
             }
         }
+    } else if let Some(contains) = &rule.contains {
+        rule_matches = value.contains(contains.as_str());
+    } else if let Some(prefix) = &rule.starts_with {
+        rule_matches = value.starts_with(prefix.as_str());
+    } else if let Some(suffix) = &rule.ends_with {
+        rule_matches = value.ends_with(suffix.as_str());
+    } else if let Some(expected) = &rule.equals {
+        rule_matches = value == expected.as_str();
     }
     rule_matches
 }
+
+/**
+ * Check the value found by a `jmespath` or `pointer` matcher against the rule's `equals`, if it
+ * has one
+ */
+fn equals(rule: &Condition, found: &str) -> bool {
+    match &rule.equals {
+        Some(expected) => expected == found,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> SyslogMessage {
+        parse::parse_line(r#"<190>May 13 21:45:18 coconut hotdog: hi"#)
+            .expect("Failed to parse the test message")
+    }
+
+    #[test]
+    fn test_describe_rule() {
+        let kmsg = KafkaMessage::new("logs".to_string(), b"hi".to_vec())
+            .with_rule(Some("audit".to_string()));
+        assert_eq!("[audit] logs: hi", describe(&kmsg));
+    }
+
+    #[async_std::test]
+    async fn test_produce() {
+        let file = "test/configs/single-rule-with-hostname-field.yml";
+        let settings = Arc::new(load(file));
+        let (sinks, sunk) = crate::serve::Sinks::null(&settings);
+        let reloadable = Arc::new(Reloadable::new(file, Overrides::default(), settings, sinks));
+        let (stats, _) = async_channel::bounded(1);
+        let connection = Connection::detached(reloadable, stats);
+
+        let produced = produce(
+            &connection,
+            "<190>May 13 21:45:18 coconut hotdog: hi",
+            &sunk,
+        )
+        .await
+        .expect("Failed to read the line");
+
+        assert!(!produced.is_empty());
+        assert_eq!("test", produced[0].topic());
+        assert!(describe(&produced[0]).starts_with("test: This messages was received"));
+    }
+
+    #[cfg(feature = "lua")]
+    #[async_std::test]
+    async fn test_produce_script_drop() {
+        let file = "test/configs/single-rule-with-script-drop.yml";
+        let settings = Arc::new(load(file));
+        let (sinks, sunk) = crate::serve::Sinks::null(&settings);
+        let reloadable = Arc::new(Reloadable::new(file, Overrides::default(), settings, sinks));
+        let (stats, counted) = async_channel::unbounded();
+        let connection = Connection::detached(reloadable, stats);
+
+        let produced = produce(
+            &connection,
+            "<190>May 13 21:45:18 coconut hotdog: hi",
+            &sunk,
+        )
+        .await
+        .expect("Failed to read the line");
+
+        assert!(produced.is_empty());
+        let counted: Vec<_> = std::iter::from_fn(|| counted.try_recv().ok()).collect();
+        assert!(
+            counted.contains(&crate::status::dropped("script_drop")),
+            "The dropped message should be counted"
+        );
+    }
+
+    #[test]
+    fn test_field_value_hostname() {
+        assert_eq!(Some("coconut"), field_value(&Field::Hostname, &message()));
+    }
+
+    #[test]
+    fn test_field_value_appname() {
+        assert_eq!(Some("hotdog"), field_value(&Field::Appname, &message()));
+    }
+
+    #[test]
+    fn test_apply_rule_on_appname() {
+        let settings = load("test/configs/single-rule-with-appname-field.yml");
+        let msg = message();
+        let mut hash = HashMap::new();
+        let jmespaths = HashMap::new();
+
+        assert!(evaluate(
+            &settings.rules[0].condition,
+            &msg,
+            &jmespaths,
+            &mut hash
+        ));
+        /*
+         * Named captures are variables whichever field the regex matched on
+         */
+        assert_eq!(Some(&"hotdog".to_string()), hash.get("app"));
+    }
+
+    #[test]
+    fn test_evaluate_captures_from_any_field() {
+        let msg = message();
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+        let condition = Condition {
+            field: Some(Field::Hostname),
+            regex: Some(regex::Regex::new(r"^(?P<tree>[a-z]+)nut$").unwrap()),
+            ..Default::default()
+        };
+
+        assert!(evaluate(&condition, &msg, &jmespaths, &mut hash));
+        assert_eq!(Some(&"coco".to_string()), hash.get("tree"));
+    }
+
+    #[test]
+    fn test_field_value_structured_data() {
+        let msg = parse::parse_line(r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut tyler - - [exampleSDID@32473 eventSource="Application"] hi"#)
+            .expect("Failed to parse the test message");
+        let field = Field::StructuredData {
+            id: "exampleSDID@32473".to_string(),
+            param: "eventSource".to_string(),
+        };
+        assert_eq!(Some("Application"), field_value(&field, &msg));
+    }
+
+    #[test]
+    fn test_numeric_value_severity() {
+        assert_eq!(Some(3.0), numeric_value(&Field::Severity, "err"));
+        assert_eq!(Some(4.0), numeric_value(&Field::Severity, "Warning"));
+        assert_eq!(None, numeric_value(&Field::Severity, "local7"));
+    }
+
+    #[test]
+    fn test_evaluate_variable_comparison() {
+        let settings = load("test/configs/single-rule-with-conditional-actions.yml");
+        let when = settings.rules[0].actions[0].when.as_ref().unwrap();
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+
+        assert!(!evaluate(when, &message(), &jmespaths, &mut hash));
+        hash.insert("status".to_string(), "404".to_string());
+        assert!(!evaluate(when, &message(), &jmespaths, &mut hash));
+        hash.insert("status".to_string(), "503".to_string());
+        assert!(evaluate(when, &message(), &jmespaths, &mut hash));
+    }
+
+    #[test]
+    fn test_evaluate_captures_borrows_variables() {
+        let settings = load("test/configs/single-rule-with-conditional-actions.yml");
+        let when = settings.rules[0].actions[0].when.as_ref().unwrap();
+        let jmespaths = HashMap::new();
+        let mut variables = HashMap::new();
+        variables.insert("status".to_string(), "503".to_string());
+        let mut captures = HashMap::new();
+
+        assert!(evaluate_captures(
+            when,
+            &message(),
+            &jmespaths,
+            &variables,
+            &mut captures
+        ));
+        assert!(captures.is_empty());
+
+        captures.insert("status".to_string(), "404".to_string());
+        assert!(!evaluate_captures(
+            when,
+            &message(),
+            &jmespaths,
+            &variables,
+            &mut captures
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_multiple_fields() {
+        let settings = load("test/configs/single-rule-with-multiple-fields.yml");
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+
+        assert!(evaluate(
+            &settings.rules[0].condition,
+            &message(),
+            &jmespaths,
+            &mut hash
+        ));
+        assert_eq!(Some(&"hi".to_string()), hash.get("greeting"));
+        assert_eq!(Some(&"hotdog".to_string()), hash.get("app"));
+
+        let msg = SyslogMessage {
+            msg: "bye".to_string(),
+            ..Default::default()
+        };
+        assert!(!evaluate(
+            &settings.rules[0].condition,
+            &msg,
+            &jmespaths,
+            &mut HashMap::new()
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_tagged() {
+        let settings = load("test/configs/rules-with-tags.yml");
+        let jmespaths = HashMap::new();
+        let mut msg = message();
+
+        assert!(!evaluate(
+            &settings.rules[1].condition,
+            &msg,
+            &jmespaths,
+            &mut HashMap::new()
+        ));
+        msg.tags.push("security".to_string());
+        assert!(evaluate(
+            &settings.rules[1].condition,
+            &msg,
+            &jmespaths,
+            &mut HashMap::new()
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_negated() {
+        let settings = load("test/configs/single-rule-with-negate.yml");
+        let jmespaths = HashMap::new();
+        let health = SyslogMessage {
+            msg: "GET /healthz 200".to_string(),
+            ..Default::default()
+        };
+
+        assert!(evaluate(
+            &settings.rules[0].condition,
+            &message(),
+            &jmespaths,
+            &mut HashMap::new()
+        ));
+        assert!(!evaluate(
+            &settings.rules[0].condition,
+            &health,
+            &jmespaths,
+            &mut HashMap::new()
+        ));
+        assert!(Prefilter::new(&settings.rules).is_none());
+    }
+
+    #[test]
+    fn test_numeric_value_facility() {
+        assert_eq!(Some(23.0), numeric_value(&Field::Facility, "local7"));
+        assert_eq!(Some(1.0), numeric_value(&Field::Facility, "1"));
+    }
+
+    #[test]
+    fn test_apply_rule_with_comparison() {
+        let settings = load("test/configs/single-rule-with-severity-comparison.yml");
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+
+        assert!(apply_condition(
+            &settings.rules[0].condition,
+            "err",
+            &jmespaths,
+            &mut hash
+        ));
+        assert!(apply_condition(
+            &settings.rules[0].condition,
+            "crit",
+            &jmespaths,
+            &mut hash
+        ));
+        assert!(!apply_condition(
+            &settings.rules[0].condition,
+            "info",
+            &jmespaths,
+            &mut hash
+        ));
+    }
+
+    #[test]
+    fn test_apply_rule_with_pointer() {
+        let settings = load("test/configs/single-rule-with-pointer.yml");
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+
+        assert!(apply_condition(
+            &settings.rules[0].condition,
+            r#"{"kubernetes":{"namespace":"prod"}}"#,
+            &jmespaths,
+            &mut hash
+        ));
+        assert_eq!(Some(&"prod".to_string()), hash.get("value"));
+        assert!(!apply_condition(
+            &settings.rules[0].condition,
+            r#"{"kubernetes":{"namespace":"dev"}}"#,
+            &jmespaths,
+            &mut hash
+        ));
+        assert!(!apply_condition(
+            &settings.rules[0].condition,
+            "prod",
+            &jmespaths,
+            &mut hash
+        ));
+    }
+
+    #[test]
+    fn test_apply_rule_with_jmespath_comparison() {
+        let settings = load("test/configs/single-rule-with-jmespath-comparison.yml");
+        let expression = settings.rules[0].condition.jmespath.as_ref().unwrap();
+        let mut jmespaths = HashMap::new();
+        jmespaths.insert(
+            expression.to_string(),
+            jmespath::compile(expression).unwrap(),
+        );
+        let mut hash = HashMap::new();
+
+        assert!(apply_condition(
+            &settings.rules[0].condition,
+            r#"{"kubernetes":{"namespace":"prod"}}"#,
+            &jmespaths,
+            &mut hash
+        ));
+        assert!(!apply_condition(
+            &settings.rules[0].condition,
+            r#"{"kubernetes":{"namespace":"dev"}}"#,
+            &jmespaths,
+            &mut hash
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_composite_condition() {
+        let settings = load("test/configs/single-rule-with-composite-condition.yml");
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+        let condition = &settings.rules[0].condition;
+
+        let matching = parse::parse_line(
+            r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut sshd - - - Accepted publickey"#,
+        )
+        .unwrap();
+        assert!(evaluate(condition, &matching, &jmespaths, &mut hash));
+
+        let wrong_host = parse::parse_line(
+            r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut-test sshd - - - Accepted publickey"#,
+        )
+        .unwrap();
+        assert!(!evaluate(condition, &wrong_host, &jmespaths, &mut hash));
+
+        let wrong_app = parse::parse_line(
+            r#"<13>1 2020-04-18T15:16:09.956153-07:00 coconut cron - - - Accepted publickey"#,
+        )
+        .unwrap();
+        assert!(!evaluate(condition, &wrong_app, &jmespaths, &mut hash));
+    }
+
+    #[test]
+    fn test_evaluate_empty_condition() {
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+        assert!(!evaluate(
+            &Condition::default(),
+            &message(),
+            &jmespaths,
+            &mut hash
+        ));
+    }
+
+    #[test]
+    fn test_apply_condition_with_literals() {
+        let jmespaths = HashMap::new();
+        let mut hash = HashMap::new();
+        let condition = Condition {
+            field: Some(Field::Msg),
+            starts_with: Some("Accepted".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_condition(
+            &condition,
+            "Accepted publickey",
+            &jmespaths,
+            &mut hash
+        ));
+        assert!(!apply_condition(
+            &condition,
+            "Failed publickey",
+            &jmespaths,
+            &mut hash
+        ));
+
+        let condition = Condition {
+            field: Some(Field::Msg),
+            contains: Some("publickey".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_condition(
+            &condition,
+            "Failed publickey for root",
+            &jmespaths,
+            &mut hash
+        ));
+
+        let condition = Condition {
+            field: Some(Field::Msg),
+            ends_with: Some("root".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_condition(
+            &condition,
+            "Failed publickey for root",
+            &jmespaths,
+            &mut hash
+        ));
+
+        let condition = Condition {
+            field: Some(Field::Appname),
+            equals: Some("sshd".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_condition(&condition, "sshd", &jmespaths, &mut hash));
+        assert!(!apply_condition(&condition, "sshd2", &jmespaths, &mut hash));
+    }
+
+    #[test]
+    fn test_prefilter() {
+        let settings = load("test/configs/single-rule-with-replace.yml");
+        let prefilter = Prefilter::new(&settings.rules).expect("Failed to build the prefilter");
+        let regex = settings.rules[0].condition.regex.as_ref().unwrap();
+
+        for line in &["hello world", "goodbye world", ""] {
+            assert_eq!(regex.is_match(line), prefilter.candidates(line)[0]);
+        }
+    }
+
+    #[test]
+    fn test_prefilter_ignores_other_fields() {
+        let settings = load("test/configs/single-rule-with-appname-field.yml");
+        assert!(Prefilter::new(&settings.rules).is_none());
+    }
+
+    #[test]
+    fn test_sample_probability() {
+        assert_eq!(0.01, sample_probability(Some(0.01), None));
+        assert_eq!(0.5, sample_probability(Some(0.5), Some(100)));
+        assert_eq!(0.25, sample_probability(None, Some(4)));
+        assert_eq!(0.0, sample_probability(None, Some(0)));
+        assert_eq!(1.0, sample_probability(None, None));
+    }
+
+    #[test]
+    fn test_sampled_extremes() {
+        for _ in 0..100 {
+            assert!(sampled(1.0));
+            assert!(!sampled(0.0));
+        }
+    }
+
+    #[test]
+    fn test_field_value_msg() {
+        assert_eq!(Some("hi"), field_value(&Field::Msg, &message()));
+    }
+
+    #[test]
+    fn test_apply_rule_on_hostname() {
+        let settings = load("test/configs/single-rule-with-hostname-field.yml");
+        let msg = message();
+        let mut hash = HashMap::new();
+        let jmespaths = HashMap::new();
+
+        assert!(evaluate(
+            &settings.rules[0].condition,
+            &msg,
+            &jmespaths,
+            &mut hash
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+/**
+ * The runtime module configures the async-std executor which hotdog runs on. The executor reads
+ * its configuration from the environment once it starts, so it must be configured before the
+ * first task is spawned
+ */
+use crate::settings::Runtime;
+use log::*;
+
+pub fn configure(runtime: &Runtime) {
+    /*
+     * The executor starts a worker for every CPU of the machine by default, rather than for every
+     * CPU hotdog has been pinned to
+     */
+    let workers = runtime.worker_threads.or_else(|| match runtime.cpus.len() {
+        0 => None,
+        cpus => Some(cpus),
+    });
+
+    if let Some(threads) = workers {
+        info!("Running hotdog's tasks on {} worker threads", threads);
+        std::env::set_var("ASYNC_STD_THREAD_COUNT", threads.to_string());
+    }
+
+    if let Some(threads) = runtime.blocking_threads {
+        info!("Running blocking work on at most {} threads", threads);
+        std::env::set_var("BLOCKING_MAX_THREADS", threads.to_string());
+    }
+}
+
+/**
+ * Pin hotdog to the configured CPUs, if any
+ */
+pub fn pin_cpus(runtime: &Runtime) {
+    if !runtime.cpus.is_empty() {
+        match pin(&runtime.cpus) {
+            Ok(()) => info!("Pinning hotdog to the CPUs {:?}", runtime.cpus),
+            Err(e) => error!("Failed to pin hotdog to the CPUs {:?}: {}", runtime.cpus, e),
+        }
+    }
+}
+
+/**
+ * Pin the current thread to the CPUs, which every thread it starts afterwards, such as those of
+ * the executor and of the Kafka producers, inherits
+ */
+#[cfg(target_os = "linux")]
+fn pin(cpus: &[usize]) -> std::io::Result<()> {
+    if let Some(cpu) = cpus.iter().find(|cpu| **cpu >= libc::CPU_SETSIZE as usize) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a valid CPU", cpu),
+        ));
+    }
+
+    let pinned = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus.iter() {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+
+    if pinned < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpus: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "pinning to CPUs is only supported on Linux",
+    ))
+}
@@ -0,0 +1,105 @@
+/**
+ * The sampled module rate-limits the logging of errors which may happen for every line, such as
+ * lines which cannot be parsed, so that an example of each kind of error is visible without
+ * flooding the logs. Every error is still counted in the metrics
+ */
+use log::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+ * The minimum number of seconds between two logged errors of the same kind
+ */
+const INTERVAL_SECS: u64 = 10;
+
+pub static DESTINATION_UNAVAILABLE: Sampled = Sampled::new("destination_unavailable");
+pub static INFLIGHT_LIMIT: Sampled = Sampled::new("inflight_limit");
+pub static LOG_PARSE: Sampled = Sampled::new("log_parse");
+pub static MERGE_INVALID_JSON: Sampled = Sampled::new("merge_of_invalid_json");
+pub static MERGE_TARGET_NOT_JSON: Sampled = Sampled::new("merge_target_not_json");
+pub static RATE_LIMIT_KEYS: Sampled = Sampled::new("rate_limit_keys");
+pub static SPOOL_FULL: Sampled = Sampled::new("spool_full");
+pub static TEMPLATE_RENDER: Sampled = Sampled::new("template_render_failed");
+
+/**
+ * Sampled logs one error of its kind per interval, along with the number of errors which were
+ * not logged since the last one
+ */
+pub struct Sampled {
+    /**
+     * The kind of error, which is logged as the `error` field and matches the name of its metric
+     */
+    kind: &'static str,
+    /**
+     * The time of the last logged error, in seconds since the epoch
+     */
+    last: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Sampled {
+    pub const fn new(kind: &'static str) -> Self {
+        Sampled {
+            kind,
+            last: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /**
+     * Log the error if none of its kind has been logged in the interval
+     */
+    pub fn error(&self, args: std::fmt::Arguments) {
+        match self.sample(now()) {
+            Some(0) => error!(error = self.kind; "{}", args),
+            Some(skipped) => error!(
+                error = self.kind;
+                "{} ({} more since the last time this was logged)",
+                args, skipped
+            ),
+            None => {}
+        }
+    }
+
+    /**
+     * Return the number of errors which were skipped since the last logged error if this one
+     * should be logged, otherwise count it as skipped
+     */
+    fn sample(&self, now: u64) -> Option<u64> {
+        let last = self.last.load(Ordering::Relaxed);
+
+        if now >= last + INTERVAL_SECS
+            && self
+                .last
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            return Some(self.skipped.swap(0, Ordering::Relaxed));
+        }
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let sampled = Sampled::new("test");
+
+        assert_eq!(Some(0), sampled.sample(100));
+        assert_eq!(None, sampled.sample(101));
+        assert_eq!(None, sampled.sample(105));
+        assert_eq!(Some(2), sampled.sample(110));
+        assert_eq!(None, sampled.sample(110));
+    }
+}
@@ -0,0 +1,137 @@
+/**
+ * The schema module validates the output of rules against JSON Schemas, so that documents which
+ * would break strict downstream consumers can be kept out of Kafka
+ */
+#[cfg(feature = "schema")]
+use async_std::sync::Arc;
+#[cfg(feature = "schema")]
+use dashmap::DashMap;
+use std::path::Path;
+
+/**
+ * Enum of the errors which can occur while validating output
+ */
+#[derive(Debug)]
+pub enum SchemaError {
+    /**
+     * The output to validate was not valid JSON
+     */
+    NotJson,
+    /**
+     * JSON Schema validation was not compiled into this build of hotdog
+     */
+    Unsupported(String),
+    /**
+     * The schema could not be read or compiled
+     */
+    Schema(String),
+}
+
+/**
+ * Schemas carries the cache of compiled JSON Schemas, and should be shared between all connections
+ */
+#[derive(Default)]
+pub struct Schemas {
+    #[cfg(feature = "schema")]
+    compiled: DashMap<std::path::PathBuf, Arc<jsonschema::JSONSchema>>,
+}
+
+impl Schemas {
+    pub fn new() -> Self {
+        Schemas::default()
+    }
+
+    /**
+     * Validate the output against the schema at the given path, returning the reasons the output
+     * is invalid, which are empty when it is valid
+     */
+    pub fn validate(&self, schema: &Path, output: &str) -> Result<Vec<String>, SchemaError> {
+        let document: serde_json::Value =
+            serde_json::from_str(output).map_err(|_| SchemaError::NotJson)?;
+        self.validate_document(schema, &document)
+    }
+
+    #[cfg(feature = "schema")]
+    fn validate_document(
+        &self,
+        schema: &Path,
+        document: &serde_json::Value,
+    ) -> Result<Vec<String>, SchemaError> {
+        let compiled = self.compiled(schema)?;
+
+        let reasons = match compiled.validate(document) {
+            Ok(_) => vec![],
+            Err(errors) => errors.map(|e| e.to_string()).collect(),
+        };
+        Ok(reasons)
+    }
+
+    #[cfg(not(feature = "schema"))]
+    fn validate_document(
+        &self,
+        _schema: &Path,
+        _document: &serde_json::Value,
+    ) -> Result<Vec<String>, SchemaError> {
+        Err(SchemaError::Unsupported(
+            "hotdog was not built with the `schema` feature".to_string(),
+        ))
+    }
+
+    /**
+     * Return the compiled schema, reading and compiling it on first use
+     */
+    #[cfg(feature = "schema")]
+    fn compiled(&self, schema: &Path) -> Result<Arc<jsonschema::JSONSchema>, SchemaError> {
+        if let Some(compiled) = self.compiled.get(schema) {
+            return Ok(compiled.value().clone());
+        }
+
+        let contents =
+            std::fs::read_to_string(schema).map_err(|e| SchemaError::Schema(e.to_string()))?;
+        let value: serde_json::Value =
+            serde_json::from_str(&contents).map_err(|e| SchemaError::Schema(e.to_string()))?;
+        let compiled = Arc::new(
+            jsonschema::JSONSchema::compile(&value)
+                .map_err(|e| SchemaError::Schema(e.to_string()))?,
+        );
+        self.compiled.insert(schema.to_path_buf(), compiled.clone());
+        Ok(compiled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_not_json() {
+        let schemas = Schemas::new();
+        let result = schemas.validate(Path::new("test/schemas/event.json"), "hello");
+        assert!(matches!(result, Err(SchemaError::NotJson)));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_validate() {
+        let schemas = Schemas::new();
+        let schema = Path::new("test/schemas/event.json");
+
+        let reasons = schemas
+            .validate(schema, r#"{"message": "hello", "severity": 3}"#)
+            .expect("Failed to validate");
+        assert!(reasons.is_empty());
+
+        let reasons = schemas
+            .validate(schema, r#"{"severity": "high"}"#)
+            .expect("Failed to validate");
+        assert_eq!(2, reasons.len());
+    }
+
+    #[cfg(not(feature = "schema"))]
+    #[test]
+    fn test_validate_unsupported() {
+        let schemas = Schemas::new();
+        let result = schemas.validate(Path::new("test/schemas/event.json"), "{}");
+        assert!(matches!(result, Err(SchemaError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,299 @@
+/**
+ * The script module runs the Lua snippets of script actions, for transformations which are too
+ * complex to express with regular expressions and templates
+ */
+#[cfg(feature = "lua")]
+use parking_lot::Mutex;
+use std::collections::HashMap;
+#[cfg(feature = "lua")]
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(feature = "lua")]
+use std::sync::Arc;
+
+/**
+ * The number of Lua instructions between checks of how long a script has been running
+ */
+#[cfg(feature = "lua")]
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+/**
+ * The number of checks a single run of a script may reach before it is aborted, so that a script
+ * which loops forever cannot stall the connection running it
+ */
+#[cfg(feature = "lua")]
+const MAX_HOOKS: u32 = 1_000;
+/**
+ * The most memory, in bytes, which the scripts of a connection may allocate
+ */
+#[cfg(feature = "lua")]
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/**
+ * Enum of the errors which can occur while running a script
+ */
+#[derive(Debug)]
+pub enum ScriptError {
+    /**
+     * Lua scripting was not compiled into this build of hotdog
+     */
+    Unsupported(String),
+    /**
+     * The script failed to compile, raised an error, or returned an unexpected value
+     */
+    Runtime(String),
+}
+
+/**
+ * The Outcome of running a script against a message
+ */
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /**
+     * The script returned nothing, so the output is left as it was
+     */
+    Unchanged,
+    /**
+     * The script returned a string which replaces the output
+     */
+    Replace(String),
+    /**
+     * The script returned false, so the message should be dropped
+     */
+    Drop,
+}
+
+#[cfg(feature = "lua")]
+struct Interpreter {
+    lua: mlua::Lua,
+    /**
+     * The number of instruction hooks reached by the script which is currently running
+     */
+    hooks: Arc<AtomicU32>,
+    /**
+     * The compiled functions for each script, keyed by the source of the script
+     */
+    functions: HashMap<String, mlua::RegistryKey>,
+}
+
+/**
+ * Engine carries the Lua interpreter for running scripts, and is meant to be created for each
+ * connection so that scripts are not contending for a single interpreter
+ */
+pub struct Engine {
+    #[cfg(feature = "lua")]
+    interpreter: Mutex<Interpreter>,
+}
+
+impl Engine {
+    #[cfg(feature = "lua")]
+    pub fn new() -> Self {
+        let lua = mlua::Lua::new();
+        let hooks = Arc::new(AtomicU32::new(0));
+
+        if let Err(e) = lua.set_memory_limit(MAX_MEMORY) {
+            log::error!("Failed to limit the memory of Lua scripts: {}", e);
+        }
+
+        let counted = hooks.clone();
+        let hooked = lua.set_hook(
+            mlua::HookTriggers {
+                every_nth_instruction: Some(HOOK_INSTRUCTIONS),
+                ..Default::default()
+            },
+            move |_lua, _debug| {
+                if counted.fetch_add(1, Ordering::Relaxed) >= MAX_HOOKS {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "The script ran for more than {} instructions",
+                        HOOK_INSTRUCTIONS as u64 * MAX_HOOKS as u64
+                    )));
+                }
+                Ok(())
+            },
+        );
+        if let Err(e) = hooked {
+            log::error!("Failed to limit the instructions of Lua scripts: {}", e);
+        }
+
+        Engine {
+            interpreter: Mutex::new(Interpreter {
+                lua,
+                hooks,
+                functions: HashMap::new(),
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "lua"))]
+    pub fn new() -> Self {
+        Engine {}
+    }
+
+    /**
+     * Run the script with the `msg` and `fields` globals set to the output and the variables of
+     * the rule. Any changes the script makes to `fields` are copied back into the variables.
+     */
+    #[cfg(feature = "lua")]
+    pub fn run(
+        &self,
+        script: &str,
+        msg: &str,
+        fields: &mut HashMap<String, String>,
+    ) -> Result<Outcome, ScriptError> {
+        let mut interpreter = self.interpreter.lock();
+        let Interpreter {
+            lua,
+            hooks,
+            functions,
+        } = &mut *interpreter;
+
+        hooks.store(0, Ordering::Relaxed);
+        run_lua(lua, functions, script, msg, fields)
+            .map_err(|e| ScriptError::Runtime(e.to_string()))?
+    }
+
+    #[cfg(not(feature = "lua"))]
+    pub fn run(
+        &self,
+        _script: &str,
+        _msg: &str,
+        _fields: &mut HashMap<String, String>,
+    ) -> Result<Outcome, ScriptError> {
+        Err(ScriptError::Unsupported(
+            "hotdog was not built with the `lua` feature".to_string(),
+        ))
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(feature = "lua")]
+fn run_lua(
+    lua: &mlua::Lua,
+    functions: &mut HashMap<String, mlua::RegistryKey>,
+    script: &str,
+    msg: &str,
+    fields: &mut HashMap<String, String>,
+) -> mlua::Result<Result<Outcome, ScriptError>> {
+    use mlua::Value;
+
+    if !functions.contains_key(script) {
+        let function = lua.load(script).into_function()?;
+        functions.insert(script.to_string(), lua.create_registry_value(function)?);
+    }
+    let function: mlua::Function = lua.registry_value(&functions[script])?;
+
+    let table = lua.create_table()?;
+    for (name, value) in fields.iter() {
+        table.set(name.as_str(), value.as_str())?;
+    }
+    lua.globals().set("msg", msg)?;
+    lua.globals().set("fields", table.clone())?;
+
+    let result: Value = function.call(())?;
+
+    for pair in table.pairs::<String, Value>() {
+        let (name, value) = pair?;
+        let value = match value {
+            Value::String(s) => s.to_str()?.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::Boolean(b) => b.to_string(),
+            _ => continue,
+        };
+        fields.insert(name, value);
+    }
+
+    Ok(match result {
+        Value::Nil | Value::Boolean(true) => Ok(Outcome::Unchanged),
+        Value::Boolean(false) => Ok(Outcome::Drop),
+        Value::String(s) => Ok(Outcome::Replace(s.to_str()?.to_string())),
+        other => Err(ScriptError::Runtime(format!(
+            "Scripts must return a string, false, or nothing, not a {}",
+            other.type_name()
+        ))),
+    })
+}
+
+#[cfg(all(test, feature = "lua"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_replace() {
+        let engine = Engine::new();
+        let mut fields = HashMap::new();
+        fields.insert("hostname".to_string(), "example".to_string());
+
+        let outcome = engine
+            .run(
+                "fields.length = #msg\nreturn fields.hostname .. ': ' .. msg:upper()",
+                "hello",
+                &mut fields,
+            )
+            .expect("Failed to run the script");
+        assert_eq!(Outcome::Replace("example: HELLO".to_string()), outcome);
+        assert_eq!(Some(&"5".to_string()), fields.get("length"));
+    }
+
+    #[test]
+    fn test_run_drop_and_unchanged() {
+        let engine = Engine::new();
+        let mut fields = HashMap::new();
+        let script = "if msg:find('healthcheck') then return false end";
+
+        assert_eq!(
+            Outcome::Drop,
+            engine.run(script, "GET /healthcheck", &mut fields).unwrap()
+        );
+        assert_eq!(
+            Outcome::Unchanged,
+            engine.run(script, "GET /", &mut fields).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_run_error() {
+        let engine = Engine::new();
+        let mut fields = HashMap::new();
+        assert!(engine.run("return {}", "hello", &mut fields).is_err());
+        assert!(engine.run("error('boom')", "hello", &mut fields).is_err());
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let engine = Engine::new();
+        let mut fields = HashMap::new();
+        assert!(engine
+            .run("while true do end", "hello", &mut fields)
+            .is_err());
+        assert!(engine
+            .run(
+                "return string.rep('x', 128 * 1024 * 1024)",
+                "hello",
+                &mut fields
+            )
+            .is_err());
+
+        assert_eq!(
+            Outcome::Replace("hello".to_string()),
+            engine.run("return msg", "hello", &mut fields).unwrap(),
+            "The engine should still run scripts after one was aborted"
+        );
+    }
+}
+
+#[cfg(all(test, not(feature = "lua")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_unsupported() {
+        let engine = Engine::new();
+        let mut fields = HashMap::new();
+        let result = engine.run("return msg", "hello", &mut fields);
+        assert!(matches!(result, Err(ScriptError::Unsupported(_))));
+    }
+}
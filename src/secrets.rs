@@ -0,0 +1,407 @@
+/**
+ * The secrets module resolves the credentials of the Kafka configurations from files or external
+ * secret stores when the settings are loaded, so that the secrets don't need to live in the
+ * configuration file
+ */
+use log::*;
+#[cfg(feature = "secrets")]
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/**
+ * Resolve every `<key>_file`, `<key>_vault`, and `<key>_aws` entry of the Kafka configuration into
+ * `<key>`, e.g. `sasl.password_file: /run/secrets/kafka` sets `sasl.password` to the contents of
+ * the file.
+ *
+ * Fetching the secrets from Vault or AWS blocks until they have been received, so this must not
+ * be called from a task on the async executor, see `settings::try_load_blocking`.
+ *
+ * Returns an error for each secret which could not be resolved
+ */
+pub fn resolve(conf: &mut HashMap<String, String>) -> Result<(), Vec<String>> {
+    let references: Vec<(String, String)> = conf
+        .iter()
+        .filter(|(key, _)| source_for(key).is_some())
+        .map(|(key, reference)| (key.clone(), reference.clone()))
+        .collect();
+    let mut errors = vec![];
+
+    for (key, reference) in references.into_iter() {
+        if let Some((name, source)) = source_for(&key) {
+            debug!("Resolving the secret for `{}` from {:?}", name, source);
+
+            match source.fetch(&reference) {
+                Ok(secret) => {
+                    conf.remove(&key);
+                    conf.insert(name.to_string(), secret);
+                }
+                Err(e) => errors.push(format!("`{}`: {}", key, e)),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(())
+}
+
+/**
+ * The places which secrets can be resolved from
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    /**
+     * A file, such as a Kubernetes or Docker secret, whose contents are the secret
+     */
+    File,
+    /**
+     * A HashiCorp Vault KV secret, referenced as `<path>#<field>`
+     */
+    Vault,
+    /**
+     * An AWS Secrets Manager secret, referenced as `<secret id>` or `<secret id>#<field>` for
+     * secrets which are JSON objects
+     */
+    Aws,
+}
+
+impl Source {
+    fn fetch(self, reference: &str) -> Result<String, String> {
+        match self {
+            Source::File => std::fs::read_to_string(reference)
+                .map(|secret| secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+                .map_err(|e| format!("Failed to read the secret from {}: {}", reference, e)),
+            #[cfg(feature = "secrets")]
+            Source::Vault => async_std::task::block_on(vault(reference)),
+            #[cfg(feature = "secrets")]
+            Source::Aws => async_std::task::block_on(aws(reference)),
+            #[cfg(not(feature = "secrets"))]
+            _ => Err(
+                "hotdog must be built with the `secrets` feature to fetch secrets from Vault or AWS"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/**
+ * Return the name of the setting which the key resolves a secret for, along with where the secret
+ * comes from
+ */
+fn source_for(key: &str) -> Option<(&str, Source)> {
+    let sources = [
+        ("_file", Source::File),
+        ("_vault", Source::Vault),
+        ("_aws", Source::Aws),
+    ];
+
+    sources.iter().find_map(|(suffix, source)| {
+        key.strip_suffix(suffix)
+            .filter(|name| !name.is_empty())
+            .map(|name| (name, *source))
+    })
+}
+
+/**
+ * Split the reference into the secret and the optional field within it
+ */
+#[cfg(feature = "secrets")]
+fn split_reference(reference: &str) -> (&str, Option<&str>) {
+    match reference.rsplit_once('#') {
+        Some((secret, field)) => (secret, Some(field)),
+        None => (reference, None),
+    }
+}
+
+/**
+ * Return the field of a secret which is a JSON object, or the secret itself without a field
+ */
+#[cfg(any(feature = "secrets", test))]
+fn field_of(secret: &str, field: Option<&str>) -> Result<String, String> {
+    match field {
+        None => Ok(secret.to_string()),
+        Some(field) => {
+            let value: serde_json::Value = serde_json::from_str(secret)
+                .map_err(|e| format!("The secret is not a JSON object: {}", e))?;
+            match value.get(field) {
+                Some(serde_json::Value::String(s)) => Ok(s.to_string()),
+                Some(other) => Ok(other.to_string()),
+                None => Err(format!("The secret has no field `{}`", field)),
+            }
+        }
+    }
+}
+
+/**
+ * Read the field of the KV secret from Vault, using the `VAULT_ADDR` and `VAULT_TOKEN` environment
+ * variables. Both version 1 and version 2 of the KV secrets engine are supported
+ */
+#[cfg(feature = "secrets")]
+async fn vault(reference: &str) -> Result<String, String> {
+    let (path, field) = split_reference(reference);
+    let field = field.ok_or_else(|| {
+        format!(
+            "The Vault secret `{}` must name a field, e.g. `{}#password`",
+            reference, reference
+        )
+    })?;
+    let addr = std::env::var("VAULT_ADDR")
+        .map_err(|_| "`VAULT_ADDR` must be set to fetch secrets from Vault".to_string())?;
+    let token = std::env::var("VAULT_TOKEN")
+        .map_err(|_| "`VAULT_TOKEN` must be set to fetch secrets from Vault".to_string())?;
+
+    let url = format!("{}/v1/{}", addr.trim_end_matches('/'), path);
+    let response: serde_json::Value = surf::get(&url)
+        .header("X-Vault-Token", token)
+        .recv_json()
+        .await
+        .map_err(|e| format!("Failed to fetch the secret from {}: {}", url, e))?;
+
+    let data = &response["data"];
+    let data = if data["data"].is_object() {
+        &data["data"]
+    } else {
+        data
+    };
+    field_of(&data.to_string(), Some(field))
+}
+
+/**
+ * Read the secret from AWS Secrets Manager, using the `AWS_REGION`, `AWS_ACCESS_KEY_ID`,
+ * `AWS_SECRET_ACCESS_KEY`, and optional `AWS_SESSION_TOKEN` environment variables
+ */
+#[cfg(feature = "secrets")]
+async fn aws(reference: &str) -> Result<String, String> {
+    let (secret_id, field) = split_reference(reference);
+    let env = |name: &str| {
+        std::env::var(name).map_err(|_| format!("`{}` must be set to fetch secrets from AWS", name))
+    };
+    let region = env("AWS_REGION")?;
+    let access_key = env("AWS_ACCESS_KEY_ID")?;
+    let secret_key = env("AWS_SECRET_ACCESS_KEY")?;
+    let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+
+    let host = format!("secretsmanager.{}.amazonaws.com", region);
+    let target = "secretsmanager.GetSecretValue";
+    let body = serde_json::json!({ "SecretId": secret_id }).to_string();
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", host.clone()),
+        ("x-amz-date", amz_date.clone()),
+        ("x-amz-target", target.to_string()),
+    ];
+    if let Some(token) = &session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    headers.sort();
+
+    let authorization = sign_v4(
+        &Credentials {
+            access_key: &access_key,
+            secret_key: &secret_key,
+            region: &region,
+            service: "secretsmanager",
+        },
+        &amz_date,
+        &headers,
+        &body,
+    );
+
+    let url = format!("https://{}/", host);
+    let mut request = surf::post(&url)
+        .body(body)
+        .header("Authorization", authorization);
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.header(*name, value.as_str());
+    }
+
+    let response: serde_json::Value = request
+        .recv_json()
+        .await
+        .map_err(|e| format!("Failed to fetch the secret `{}` from AWS: {}", secret_id, e))?;
+
+    match response["SecretString"].as_str() {
+        Some(secret) => field_of(secret, field),
+        None => Err(format!("The secret `{}` has no SecretString", secret_id)),
+    }
+}
+
+#[cfg(feature = "secrets")]
+struct Credentials<'a> {
+    access_key: &'a str,
+    secret_key: &'a str,
+    region: &'a str,
+    service: &'a str,
+}
+
+/**
+ * Return the Authorization header for a POST to `/` with the given headers and body, signed with
+ * AWS Signature Version 4. The headers must be sorted by their lowercase names
+ */
+#[cfg(feature = "secrets")]
+fn sign_v4(
+    credentials: &Credentials,
+    amz_date: &str,
+    headers: &[(&str, String)],
+    body: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{}\n{:x}",
+        canonical_headers,
+        signed_headers,
+        Sha256::digest(body.as_bytes())
+    );
+
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        date, credentials.region, credentials.service
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+        amz_date,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+
+    let key = format!("AWS4{}", credentials.secret_key);
+    let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, credentials.region.as_bytes());
+    let key = hmac_sha256(&key, credentials.service.as_bytes());
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature: String = hmac_sha256(&key, string_to_sign.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key, scope, signed_headers, signature
+    )
+}
+
+/**
+ * HMAC-SHA256 of the message with the key
+ */
+#[cfg(feature = "secrets")]
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    use hmac::{Mac, NewMac};
+
+    let mut mac = hmac::Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_for() {
+        assert_eq!(
+            Some(("sasl.password", Source::File)),
+            source_for("sasl.password_file")
+        );
+        assert_eq!(
+            Some(("sasl.password", Source::Vault)),
+            source_for("sasl.password_vault")
+        );
+        assert_eq!(
+            Some(("sasl.password", Source::Aws)),
+            source_for("sasl.password_aws")
+        );
+        assert_eq!(None, source_for("bootstrap.servers"));
+        assert_eq!(None, source_for("_file"));
+    }
+
+    #[test]
+    fn test_resolve_file() {
+        let mut conf = HashMap::new();
+        conf.insert(
+            "sasl.password_file".to_string(),
+            "test/secrets/kafka-password".to_string(),
+        );
+
+        assert!(resolve(&mut conf).is_ok());
+        assert_eq!(Some(&"hunter2".to_string()), conf.get("sasl.password"));
+        assert!(!conf.contains_key("sasl.password_file"));
+    }
+
+    #[test]
+    fn test_resolve_missing_file() {
+        let mut conf = HashMap::new();
+        conf.insert(
+            "sasl.password_file".to_string(),
+            "test/secrets/missing".to_string(),
+        );
+
+        let errors = resolve(&mut conf).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].contains("sasl.password_file"));
+    }
+
+    #[test]
+    fn test_field_of() {
+        let secret = r#"{"username":"hotdog","password":"hunter2"}"#;
+        assert_eq!(
+            Ok("hunter2".to_string()),
+            field_of(secret, Some("password"))
+        );
+        assert_eq!(Ok(secret.to_string()), field_of(secret, None));
+        assert!(field_of(secret, Some("missing")).is_err());
+        assert!(field_of("hunter2", Some("password")).is_err());
+    }
+
+    #[cfg(feature = "secrets")]
+    #[test]
+    fn test_hmac_sha256() {
+        /*
+         * Test case 2 of RFC 4231
+         */
+        let mac: String = hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            mac
+        );
+    }
+
+    #[cfg(feature = "secrets")]
+    #[test]
+    fn test_sign_v4() {
+        /*
+         * The `post-vanilla` case of the AWS Signature Version 4 test suite
+         */
+        let credentials = Credentials {
+            access_key: "AKIDEXAMPLE",
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            region: "us-east-1",
+            service: "service",
+        };
+        let headers = vec![
+            ("host", "example.amazonaws.com".to_string()),
+            ("x-amz-date", "20150830T123600Z".to_string()),
+        ];
+        let authorization = sign_v4(&credentials, "20150830T123600Z", &headers, "");
+
+        assert_eq!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b",
+            authorization
+        );
+    }
+}
@@ -0,0 +1,286 @@
+/**
+ * The serialize module is responsible for converting the rendered output of a rule into an
+ * alternative wire format, such as Avro, before it is sent along to Kafka
+ */
+use crate::settings::Serializer;
+#[cfg(any(feature = "avro", feature = "protobuf"))]
+use async_std::sync::Arc;
+#[cfg(any(feature = "avro", feature = "protobuf"))]
+use dashmap::DashMap;
+#[cfg(any(feature = "avro", feature = "protobuf"))]
+use log::*;
+use std::collections::HashMap;
+
+/**
+ * Enum of the errors which can occur while serializing output
+ */
+#[derive(Debug)]
+pub enum SerializeError {
+    /**
+     * The output to serialize was not valid JSON
+     */
+    NotJson,
+    /**
+     * The serializer was not compiled into this build of hotdog
+     */
+    Unsupported(String),
+    /**
+     * The schema could not be fetched or registered
+     */
+    Registry(String),
+    /**
+     * The output could not be encoded with the schema
+     */
+    Encoding(String),
+}
+
+/**
+ * A schema which has been registered with (or fetched from) the Schema Registry
+ */
+#[cfg(feature = "avro")]
+struct RegisteredSchema {
+    id: u32,
+    schema: avro_rs::Schema,
+}
+
+#[cfg(feature = "avro")]
+#[derive(Deserialize)]
+struct SchemaResponse {
+    id: u32,
+    #[serde(default)]
+    schema: Option<String>,
+}
+
+/**
+ * Serializers carries the state needed to serialize output, such as the cache of schemas fetched
+ * from a Schema Registry, and should be shared between all connections
+ */
+#[derive(Default)]
+pub struct Serializers {
+    #[cfg(feature = "avro")]
+    schemas: DashMap<String, Arc<RegisteredSchema>>,
+    #[cfg(feature = "protobuf")]
+    descriptors: DashMap<std::path::PathBuf, Arc<prost_reflect::DescriptorPool>>,
+}
+
+impl Serializers {
+    pub fn new() -> Self {
+        Serializers::default()
+    }
+
+    /**
+     * Serialize the rendered output with the given serializer, returning the bytes which should
+     * be sent to Kafka
+     */
+    pub async fn serialize(
+        &self,
+        serializer: &Serializer,
+        output: &str,
+        variables: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, SerializeError> {
+        match serializer {
+            #[cfg(feature = "avro")]
+            Serializer::Avro {
+                registry,
+                subject,
+                schema,
+            } => self.avro(registry, subject, schema, output).await,
+            #[cfg(not(feature = "avro"))]
+            Serializer::Avro { .. } => Err(SerializeError::Unsupported(
+                "hotdog was not built with the `avro` feature".to_string(),
+            )),
+            #[cfg(feature = "protobuf")]
+            Serializer::Protobuf {
+                descriptor,
+                message,
+            } => self.protobuf(descriptor, message, output, variables).await,
+            #[cfg(not(feature = "protobuf"))]
+            Serializer::Protobuf { .. } => {
+                let _ = (output, variables);
+                Err(SerializeError::Unsupported(
+                    "hotdog was not built with the `protobuf` feature".to_string(),
+                ))
+            }
+        }
+    }
+
+    /**
+     * Encode the output as the configured protobuf message
+     */
+    #[cfg(feature = "protobuf")]
+    async fn protobuf(
+        &self,
+        descriptor: &std::path::Path,
+        message: &str,
+        output: &str,
+        variables: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, SerializeError> {
+        use prost::Message;
+
+        let cached = self.descriptors.get(descriptor).map(|r| r.value().clone());
+
+        let pool = match cached {
+            Some(pool) => pool,
+            None => {
+                debug!(
+                    "Loading the protobuf descriptors from {}",
+                    descriptor.display()
+                );
+                let bytes = async_std::fs::read(descriptor)
+                    .await
+                    .map_err(|e| SerializeError::Encoding(e.to_string()))?;
+                let pool = Arc::new(
+                    prost_reflect::DescriptorPool::decode(bytes.as_slice())
+                        .map_err(|e| SerializeError::Encoding(e.to_string()))?,
+                );
+                self.descriptors
+                    .insert(descriptor.to_path_buf(), pool.clone());
+                pool
+            }
+        };
+
+        let desc = pool.get_message_by_name(message).ok_or_else(|| {
+            SerializeError::Encoding(format!("No protobuf message named {}", message))
+        })?;
+        let encoded = prost_reflect::DynamicMessage::deserialize(desc, json_for(output, variables))
+            .map_err(|e| SerializeError::Encoding(e.to_string()))?;
+
+        Ok(encoded.encode_to_vec())
+    }
+
+    /**
+     * Encode the output as an Avro datum in the Confluent wire format
+     */
+    #[cfg(feature = "avro")]
+    async fn avro(
+        &self,
+        registry: &str,
+        subject: &str,
+        schema_file: &Option<std::path::PathBuf>,
+        output: &str,
+    ) -> Result<Vec<u8>, SerializeError> {
+        let key = format!("{}/{}", registry, subject);
+        /*
+         * The cached value must be cloned out of the map before awaiting anything, otherwise the
+         * shard lock would be held across the await
+         */
+        let cached = self.schemas.get(&key).map(|r| r.value().clone());
+
+        let registered = match cached {
+            Some(registered) => registered,
+            None => {
+                let registered = Arc::new(fetch_schema(registry, subject, schema_file).await?);
+                self.schemas.insert(key, registered.clone());
+                registered
+            }
+        };
+
+        let json: serde_json::Value =
+            serde_json::from_str(output).map_err(|_| SerializeError::NotJson)?;
+        let value = avro_rs::types::Value::from(json)
+            .resolve(&registered.schema)
+            .map_err(|e| SerializeError::Encoding(e.to_string()))?;
+        let datum = avro_rs::to_avro_datum(&registered.schema, value)
+            .map_err(|e| SerializeError::Encoding(e.to_string()))?;
+
+        Ok(confluent_frame(registered.id, datum))
+    }
+}
+
+/**
+ * Fetch the latest schema for the subject, or register the schema from the given file if one has
+ * been configured
+ */
+#[cfg(feature = "avro")]
+async fn fetch_schema(
+    registry: &str,
+    subject: &str,
+    schema_file: &Option<std::path::PathBuf>,
+) -> Result<RegisteredSchema, SerializeError> {
+    let registry = registry.trim_end_matches('/');
+
+    let (id, schema) = if let Some(path) = schema_file {
+        let schema = async_std::fs::read_to_string(path)
+            .await
+            .map_err(|e| SerializeError::Registry(e.to_string()))?;
+        let url = format!("{}/subjects/{}/versions", registry, subject);
+        debug!("Registering the Avro schema for {} with {}", subject, url);
+
+        let body = surf::Body::from_json(&serde_json::json!({ "schema": schema }))
+            .map_err(|e| SerializeError::Registry(e.to_string()))?;
+        let response: SchemaResponse = surf::post(&url)
+            .content_type("application/vnd.schemaregistry.v1+json")
+            .body(body)
+            .recv_json()
+            .await
+            .map_err(|e| SerializeError::Registry(e.to_string()))?;
+        (response.id, schema)
+    } else {
+        let url = format!("{}/subjects/{}/versions/latest", registry, subject);
+        debug!("Fetching the Avro schema for {} from {}", subject, url);
+
+        let response: SchemaResponse = surf::get(&url)
+            .recv_json()
+            .await
+            .map_err(|e| SerializeError::Registry(e.to_string()))?;
+        let schema = response.schema.ok_or_else(|| {
+            SerializeError::Registry(format!("No schema was returned for {}", subject))
+        })?;
+        (response.id, schema)
+    };
+
+    let schema =
+        avro_rs::Schema::parse_str(&schema).map_err(|e| SerializeError::Registry(e.to_string()))?;
+    Ok(RegisteredSchema { id, schema })
+}
+
+/**
+ * Return the JSON value which should be serialized for the output, falling back to an object of
+ * the rule's variables when the output itself is not a JSON object
+ */
+#[cfg(any(feature = "protobuf", test))]
+fn json_for(output: &str, variables: &HashMap<String, String>) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(output) {
+        Ok(value) if value.is_object() => value,
+        _ => serde_json::to_value(variables).unwrap_or(serde_json::Value::Null),
+    }
+}
+
+/**
+ * Wrap the encoded datum in the Confluent wire format: a zero magic byte, followed by the
+ * big-endian schema identifier, followed by the datum itself
+ */
+#[cfg(any(feature = "avro", test))]
+fn confluent_frame(id: u32, datum: Vec<u8>) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(datum.len() + 5);
+    buffer.push(0);
+    buffer.extend_from_slice(&id.to_be_bytes());
+    buffer.extend(datum);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confluent_frame() {
+        let framed = confluent_frame(258, vec![42]);
+        assert_eq!(vec![0, 0, 0, 1, 2, 42], framed);
+    }
+
+    #[test]
+    fn test_json_for_object() {
+        let variables = HashMap::new();
+        let value = json_for(r#"{"hello":"world"}"#, &variables);
+        assert_eq!(Some("world"), value["hello"].as_str());
+    }
+
+    #[test]
+    fn test_json_for_plaintext() {
+        let mut variables = HashMap::new();
+        variables.insert("msg".to_string(), "hi".to_string());
+        let value = json_for("hi", &variables);
+        assert_eq!(Some("hi"), value["msg"].as_str());
+    }
+}
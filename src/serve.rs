@@ -1,25 +1,175 @@
+use crate::aggregate::{self, Aggregator};
 use crate::connection::*;
 use crate::errors;
-use crate::kafka::Kafka;
-use crate::settings::Settings;
+use crate::events;
+use crate::exec::Executor;
+use crate::geoip::GeoIp;
+#[cfg(unix)]
+use crate::handover;
+use crate::kafka::{self, DeliveryPolicy, Kafka, Produce, StatsContext};
+use crate::plugin::Plugins;
+use crate::ratelimit::RateLimiter;
+use crate::reload::Reloadable;
+use crate::schema::Schemas;
+use crate::serialize::Serializers;
+use crate::settings::{self, Destination, Settings, TlsType, TopicVerification};
+use crate::spool::{self, Spool};
 use crate::status;
+use crate::supervise;
+use crate::suppress::{self, Suppressor};
 /**
  * The serve module is responsible for general syslog over TCP serving functionality
  */
-use async_channel::Sender;
-use async_std::{io::BufReader, net::*, prelude::*, sync::Arc, task};
+use async_channel::{Receiver, Sender};
+use async_std::{
+    net::*,
+    sync::{Arc, Mutex},
+    task,
+};
 use async_trait::async_trait;
 use log::*;
+use rdkafka::producer::FutureProducer;
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+#[derive(Clone)]
 pub struct ServerState {
     /**
      * A reference to the global Settings object for all configuration information
      */
     pub settings: Arc<Settings>,
+    /**
+     * The reloadable settings, from which connections take the current rules
+     */
+    pub reloadable: Arc<Reloadable>,
     /**
      * A Sender for sending statistics to the status handler
      */
     pub stats: Sender<status::Statistic>,
+    /**
+     * The active connections, which every accepted connection is registered with
+     */
+    pub connections: Arc<status::Connections>,
+    /**
+     * The index of the listener to serve, where `global.listen` is the first listener followed by
+     * `global.listeners`
+     */
+    pub listener: usize,
+    /**
+     * Notified once the listener has been bound, or has failed to be bound, so that hotdog can
+     * fail to start rather than run without one of its listeners
+     */
+    pub bound: Sender<Result<(), String>>,
+    /*
+     * The state of the actions, which is shared by the connections of every listener
+     */
+    pub serializers: Arc<Serializers>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub geoip: Arc<GeoIp>,
+    pub plugins: Arc<Plugins>,
+    pub executor: Arc<Executor>,
+    pub suppressor: Arc<Suppressor>,
+    pub aggregator: Arc<Aggregator>,
+    pub schemas: Arc<Schemas>,
+}
+
+impl ServerState {
+    /**
+     * Create the state for serving the first listener, along with the state of the actions which
+     * every listener shares. The summaries of the dedupe and throttle actions and the rollups of
+     * the aggregate actions are sent for as long as hotdog runs
+     */
+    pub fn new(
+        settings: Arc<Settings>,
+        reloadable: Arc<Reloadable>,
+        stats: Sender<status::Statistic>,
+        connections: Arc<status::Connections>,
+        bound: Sender<Result<(), String>>,
+    ) -> Self {
+        let suppressor = Arc::new(Suppressor::new());
+        let aggregator = Arc::new(Aggregator::new());
+
+        task::spawn(suppress::summary_loop(
+            suppressor.clone(),
+            reloadable.clone(),
+            stats.clone(),
+        ));
+        task::spawn(aggregate::rollup_loop(
+            aggregator.clone(),
+            reloadable.clone(),
+            stats.clone(),
+        ));
+
+        ServerState {
+            settings,
+            reloadable,
+            stats,
+            connections,
+            listener: 0,
+            bound,
+            serializers: Arc::new(Serializers::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            geoip: Arc::new(GeoIp::new()),
+            plugins: Arc::new(Plugins::new()),
+            executor: Arc::new(Executor::new()),
+            suppressor,
+            aggregator,
+            schemas: Arc::new(Schemas::new()),
+        }
+    }
+
+    /**
+     * Return the state for serving another listener, which shares the state of the actions
+     */
+    pub fn for_listener(&self, listener: usize) -> Self {
+        ServerState {
+            listener,
+            ..self.clone()
+        }
+    }
+
+    /**
+     * Report whether the listener has been bound. Only the first bind of a listener is waited on,
+     * the result of binding a restarted listener is not reported to anyone
+     */
+    fn report_bound(&self, result: Result<(), String>) {
+        let _ = self.bound.try_send(result);
+    }
+
+    /**
+     * Return the settings of the listener to serve
+     */
+    pub fn listen(&self) -> &settings::Listen {
+        self.settings
+            .listeners()
+            .nth(self.listener)
+            .expect("The listener to serve is not in the settings")
+    }
+}
+
+/**
+ * Serve the listener of the state, over TLS if it has been configured with a certificate and key
+ */
+pub async fn serve(state: ServerState) -> Result<(), errors::HotdogError> {
+    match &state.listen().tls {
+        TlsType::CertAndKey {
+            cert: _,
+            key: _,
+            ca: _,
+        } => {
+            info!("Serving in TLS mode");
+            let mut server = crate::serve_tls::TlsServer::new(&state);
+            server.accept_loop(state).await
+        }
+        _ => {
+            info!("Serving in plaintext mode");
+            let mut server = crate::serve_plain::PlaintextServer {};
+            server.accept_loop(state).await
+        }
+    }
 }
 
 /**
@@ -55,71 +205,854 @@ pub trait Server {
         connection: Connection,
         stats: Sender<status::Statistic>,
     ) -> Result<(), std::io::Error> {
-        debug!("Accepting from: {}", stream.peer_addr()?);
-        let reader = BufReader::new(stream);
+        let peer = stream.peer_addr()?.to_string();
+        debug!("Accepting from: {}", peer);
+        let opened = Instant::now();
 
         task::spawn(async move {
-            if let Err(e) = connection.read_logs(reader).await {
-                error!("Failure occurred while read_logs executed: {:?}", e);
-            }
+            supervise::watch("connection", &peer, &stats, connection.read_logs(stream)).await;
 
             stats.send((status::Stats::ConnectionCount, -1)).await;
+            stats
+                .send((
+                    status::Stats::ConnectionLifetime,
+                    status::elapsed_us(opened),
+                ))
+                .await;
         });
 
         Ok(())
     }
 
     /**
-     * Accept connections on the addr
+     * Reconfigure can/should be overridden by implementations which need to perform some work when
+     * the listener settings have been reloaded. Returning an error keeps the current configuration
      */
-    async fn accept_loop(
-        &mut self,
-        addr: &str,
-        state: ServerState,
-    ) -> Result<(), errors::HotdogError> {
-        let mut addr = addr.to_socket_addrs().await?;
-        let addr = addr
-            .next()
-            .unwrap_or_else(|| panic!("Could not turn {:?} into a listenable interface", addr));
+    fn reconfigure(&mut self, listen: &settings::Listen) -> Result<(), errors::HotdogError> {
+        if listen.tls != TlsType::None {
+            warn!("Switching from plaintext to TLS requires hotdog to be restarted");
+        }
+        Ok(())
+    }
 
-        let mut kafka = Kafka::new(state.settings.global.kafka.buffer, state.stats.clone());
+    /**
+     * Accept connections on the address of the listener's settings, until hotdog is shut down.
+     *
+     * The listener is bound to the new address whenever the listen settings are reloaded, and the
+     * connections only evaluate the rules of the listener's ruleset
+     */
+    async fn accept_loop(&mut self, state: ServerState) -> Result<(), errors::HotdogError> {
+        let mut addr = addr_for(state.listen());
 
-        if !kafka.connect(
-            &state.settings.global.kafka.conf,
-            Some(state.settings.global.kafka.timeout_ms),
-        ) {
-            error!("Cannot start hotdog without a workable broker connection");
-            return Err(errors::HotdogError::KafkaConnectError);
+        if let Err(e) = self.bootstrap(&state) {
+            state.report_bound(Err(format!("Failed to set up {}: {:?}", addr, e)));
+            return Err(e);
         }
 
-        let sender = kafka.get_sender();
+        let mut ruleset = state.listen().ruleset.clone();
+        let mut listener = match bind(&addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                state.report_bound(Err(format!("Failed to listen on {}: {:?}", addr, e)));
+                state.reloadable.publish(events::Event::ListenerFailed {
+                    address: addr,
+                    error: format!("{:?}", e),
+                });
+                return Err(e);
+            }
+        };
+        info!("Listening on: {}", addr);
+        state.report_bound(Ok(()));
 
-        task::spawn(async move {
-            debug!("Starting Kafka sendloop");
-            kafka.sendloop().await;
-        });
+        let shutdown = crate::signals::shutdown();
+        let rebinds = state.reloadable.rebinds();
+
+        loop {
+            let event = smol::future::or(
+                async { Event::Accepted(listener.accept().await) },
+                smol::future::or(
+                    async {
+                        let _ = shutdown.recv().await;
+                        Event::Shutdown
+                    },
+                    async {
+                        let _ = rebinds.recv().await;
+                        Event::Rebind
+                    },
+                ),
+            )
+            .await;
 
-        self.bootstrap(&state)?;
+            let stream = match event {
+                Event::Accepted(Ok((stream, _))) => stream,
+                Event::Accepted(Err(e)) => {
+                    state.reloadable.publish(events::Event::ListenerFailed {
+                        address: addr,
+                        error: e.to_string(),
+                    });
+                    return Err(e.into());
+                }
+                Event::Shutdown => break,
+                Event::Rebind => {
+                    let settings = state.reloadable.current();
+                    /*
+                     * Removed listeners keep serving until hotdog is restarted
+                     */
+                    let listen = match settings.listeners().nth(state.listener) {
+                        Some(listen) => listen,
+                        None => continue,
+                    };
 
-        let listener = TcpListener::bind(addr).await?;
-        let mut incoming = listener.incoming();
+                    if let Err(e) = self.reconfigure(listen) {
+                        error!(
+                            "Failed to reconfigure the listener, keeping the current listener: {:?}",
+                            e
+                        );
+                        state.reloadable.publish(events::Event::ListenerFailed {
+                            address: addr.clone(),
+                            error: format!("{:?}", e),
+                        });
+                        continue;
+                    }
+                    ruleset = listen.ruleset.clone();
 
-        while let Some(stream) = incoming.next().await {
-            let stream = stream?;
+                    let reloaded = addr_for(listen);
+                    if reloaded != addr {
+                        match bind(&reloaded).await {
+                            Ok(rebound) => {
+                                info!("Listening on: {}", reloaded);
+                                #[cfg(unix)]
+                                handover::unregister(&addr);
+                                listener = rebound;
+                                addr = reloaded;
+                            }
+                            Err(e) => {
+                                error!(
+                                    "Failed to listen on {}, continuing to listen on {}: {:?}",
+                                    reloaded, addr, e
+                                );
+                                state.reloadable.publish(events::Event::ListenerFailed {
+                                    address: reloaded,
+                                    error: format!("{:?}", e),
+                                });
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
             debug!("Accepting from: {}", stream.peer_addr()?);
 
             state.stats.send((status::Stats::ConnectionCount, 1)).await;
 
-            let connection =
-                Connection::new(state.settings.clone(), sender.clone(), state.stats.clone());
+            let connection = Connection::new(
+                state.reloadable.clone(),
+                state.stats.clone(),
+                state.serializers.clone(),
+                state.rate_limiter.clone(),
+                state.geoip.clone(),
+                state.plugins.clone(),
+                state.executor.clone(),
+                state.suppressor.clone(),
+                state.aggregator.clone(),
+                state.schemas.clone(),
+                stream.peer_addr().ok(),
+                ruleset.clone(),
+                state.connections.open(stream.peer_addr().ok()),
+            );
 
             if let Err(e) = self.handle_connection(stream, connection, state.stats.clone()) {
                 error!("Failed to handle_connection properly: {:?}", e);
             }
         }
 
+        info!("No longer accepting connections");
+        drop(listener);
+        #[cfg(unix)]
+        handover::unregister(&addr);
         self.shutdown(&state)?;
+        let settings = state.reloadable.current();
+
+        /*
+         * Once a restarted hotdog accepts the new connections, the senders of the open ones are
+         * given the time to reconnect to it by themselves
+         */
+        #[cfg(unix)]
+        let handed_over = handover::handed_over();
+        #[cfg(not(unix))]
+        let handed_over = false;
+
+        if handed_over {
+            let handover = Duration::from_millis(settings.global.shutdown.handover_ms);
+            info!(
+                "Waiting up to {:?} for the connections to close after the handover",
+                handover
+            );
+            state.connections.wait(handover).await;
+        }
+
+        /*
+         * The connections finish the lines they have already read before the messages are flushed
+         * to Kafka, for as long as the grace period allows
+         */
+        let grace = Duration::from_millis(settings.global.shutdown.grace_ms);
+        let open = state.connections.drain(grace).await;
+        if open > 0 {
+            warn!(
+                "{} connection(s) did not finish within the shutdown grace period of {:?}",
+                open, grace
+            );
+        }
+        state.reloadable.sinks().flush().await;
 
         Ok(())
     }
 }
+
+/**
+ * The events which the accept loop waits on
+ */
+enum Event {
+    Accepted(std::io::Result<(TcpStream, SocketAddr)>),
+    Shutdown,
+    Rebind,
+}
+
+fn addr_for(listen: &settings::Listen) -> String {
+    format!("{}:{}", listen.address, listen.port)
+}
+
+/**
+ * Bind a listener to the address, which is resolved first in case it is a hostname
+ */
+async fn bind(addr: &str) -> Result<TcpListener, errors::HotdogError> {
+    #[cfg(unix)]
+    if let Some(inherited) = handover::inherited(addr) {
+        info!(
+            "Taking over the listener on {} from the previous hotdog",
+            addr
+        );
+        let listener = TcpListener::from(inherited);
+        handover::register(addr, listener.as_raw_fd());
+        return Ok(listener);
+    }
+
+    let resolved = addr.to_socket_addrs().await?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::AddrNotAvailable,
+            format!("Could not turn {} into a listenable interface", addr),
+        )
+    })?;
+    let listener = TcpListener::bind(resolved).await?;
+    #[cfg(unix)]
+    handover::register(addr, listener.as_raw_fd());
+    Ok(listener)
+}
+
+/**
+ * Sinks are the senders to the sendloops of the default Kafka producer and of every destination,
+ * which are replaced as a whole when the Kafka settings are reloaded
+ */
+pub struct Sinks {
+    /**
+     * The sender-side of the channel to the default Kafka producer
+     */
+    pub sender: Sender<Produce>,
+    /**
+     * The named destinations which Forward actions may send to instead, by their name
+     */
+    pub destinations: HashMap<String, Arc<DestinationSink>>,
+    /**
+     * The producers of the default Kafka, which are flushed when shutting down
+     */
+    producers: Vec<(FutureProducer<StatsContext>, Sender<Produce>, Duration)>,
+    /**
+     * Whether the messages end up in a receiver rather than being sent to Kafka
+     */
+    null: bool,
+}
+
+impl Sinks {
+    /**
+     * Connect to the Kafka brokers of the default producer, starting its sendloop. The destinations
+     * are only connected to once the first message is sent to them, unless they require Kafka at
+     * start
+     */
+    pub async fn start(
+        settings: &Arc<Settings>,
+        stats: Sender<status::Statistic>,
+    ) -> Result<Sinks, errors::HotdogError> {
+        let (sender, producer) = start_kafka(
+            "default",
+            &settings.global.kafka,
+            &settings.static_topics(),
+            stats.clone(),
+            None,
+        )
+        .await?;
+
+        let mut producers = vec![];
+        if let Some(producer) = producer {
+            producers.push((
+                producer,
+                sender.clone(),
+                Duration::from_millis(settings.global.kafka.flush_timeout_ms),
+            ));
+        }
+
+        let mut destinations = HashMap::new();
+        for (name, destination) in settings.global.destinations.iter() {
+            let sink = Arc::new(DestinationSink::new(name, settings.clone(), stats.clone()));
+
+            match destination {
+                Destination::Kafka(conf) => {
+                    if conf.require_kafka_at_start {
+                        sink.start().await?;
+                    }
+                }
+            }
+            destinations.insert(name.to_string(), sink);
+        }
+
+        Ok(Sinks {
+            sender,
+            destinations,
+            producers,
+            null: false,
+        })
+    }
+
+    /**
+     * Create sinks without any producers, whose messages for the default producer and every
+     * destination all end up in the returned receiver instead
+     */
+    pub fn null(settings: &Arc<Settings>) -> (Sinks, async_channel::Receiver<Produce>) {
+        let (sender, receiver) = async_channel::bounded(settings.global.kafka.buffer);
+        let destinations = settings
+            .global
+            .destinations
+            .keys()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    Arc::new(DestinationSink::started(
+                        name,
+                        settings.clone(),
+                        sender.clone(),
+                    )),
+                )
+            })
+            .collect();
+
+        (
+            Sinks {
+                sender,
+                destinations,
+                producers: vec![],
+                null: true,
+            },
+            receiver,
+        )
+    }
+
+    /**
+     * Create sinks without any producers, whose messages are never sent anywhere
+     */
+    #[cfg(test)]
+    pub fn unconnected() -> Sinks {
+        let (sender, _) = async_channel::bounded(1);
+        Sinks {
+            sender,
+            destinations: HashMap::new(),
+            producers: vec![],
+            null: false,
+        }
+    }
+
+    /**
+     * Return whether these sinks were created by `Sinks::null`, and never send to Kafka
+     */
+    pub fn is_null(&self) -> bool {
+        self.null
+    }
+
+    /**
+     * Flush the pending messages of every producer, including those of the destinations which
+     * have been started
+     */
+    pub async fn flush(&self) {
+        for (producer, sender, timeout) in self.producers.iter() {
+            kafka::flush(producer.clone(), sender, *timeout).await;
+        }
+
+        for sink in self.destinations.values() {
+            sink.flush().await;
+        }
+    }
+
+    /**
+     * Flush the pending messages of every producer and then stop their sendloops, once these sinks
+     * have been replaced by the reloaded ones
+     */
+    pub async fn retire(&self) {
+        self.flush().await;
+        self.sender.close();
+
+        for sink in self.destinations.values() {
+            sink.retire().await;
+        }
+    }
+}
+
+/**
+ * Receive the messages of null sinks while serving in a dry run, logging and counting what would
+ * have been sent to Kafka instead of sending it. Every message is only logged at the debug level,
+ * since a dry run against live traffic would otherwise flood the logs
+ */
+pub async fn dry_run(sunk: Receiver<Produce>, stats: Sender<status::Statistic>) {
+    while let Ok(produce) = sunk.recv().await {
+        let messages = match produce {
+            Produce::Message(kmsg) => vec![kmsg],
+            Produce::Transaction(kmsgs) => {
+                debug!(
+                    "Dry run, would send a transaction of {} messages",
+                    kmsgs.len()
+                );
+                kmsgs
+            }
+        };
+
+        for kmsg in messages.iter() {
+            debug!(
+                "Dry run, would send {} bytes to `{}` with the key {:?}",
+                kmsg.payload().len(),
+                kmsg.topic(),
+                kmsg.key()
+            );
+            trace!(
+                "Dry run, the payload for `{}`: {}",
+                kmsg.topic(),
+                String::from_utf8_lossy(kmsg.payload())
+            );
+
+            stats
+                .send((
+                    status::Stats::DryRunMessages {
+                        topic: kmsg.topic().to_string(),
+                    },
+                    1,
+                ))
+                .await;
+            stats
+                .send((
+                    status::Stats::DryRunBytes {
+                        topic: kmsg.topic().to_string(),
+                    },
+                    kmsg.payload().len() as i64,
+                ))
+                .await;
+        }
+    }
+}
+
+/**
+ * The sender to the sendloop of a destination which has been started, along with its producer
+ */
+type Started = (Sender<Produce>, Option<FutureProducer<StatsContext>>);
+
+/**
+ * A named destination, whose producer is connected and whose sendloop is started when the first
+ * message is sent to it, and then shared by every rule and connection which forwards to it
+ */
+pub struct DestinationSink {
+    name: String,
+    /**
+     * The settings which the destination was configured by
+     */
+    settings: Arc<Settings>,
+    stats: Sender<status::Statistic>,
+    /**
+     * The sender to the sendloop and the producer to flush, once the destination has been started
+     */
+    started: Mutex<Option<Started>>,
+    /**
+     * Whether the destination is being started in the background
+     */
+    starting: AtomicBool,
+    /**
+     * Whether the sinks of the destination have been replaced, after which it is never started
+     */
+    retired: AtomicBool,
+}
+
+impl DestinationSink {
+    fn new(name: &str, settings: Arc<Settings>, stats: Sender<status::Statistic>) -> Self {
+        DestinationSink {
+            name: name.to_string(),
+            settings,
+            stats,
+            started: Mutex::new(None),
+            starting: AtomicBool::new(false),
+            retired: AtomicBool::new(false),
+        }
+    }
+
+    /**
+     * Create a destination which sends its messages to the sender without a producer of its own
+     */
+    fn started(name: &str, settings: Arc<Settings>, sender: Sender<Produce>) -> Self {
+        let (stats, _) = async_channel::bounded(1);
+
+        DestinationSink {
+            started: Mutex::new(Some((sender, None))),
+            ..DestinationSink::new(name, settings, stats)
+        }
+    }
+
+    /**
+     * Return the sender to the sendloop of the destination. The first message sent to it starts
+     * the destination in the background, and the destination is unavailable until it has connected
+     * to its brokers, so that no connection waits on the brokers of a destination
+     */
+    pub async fn sender(self: &Arc<Self>) -> Result<Sender<Produce>, errors::HotdogError> {
+        if let Some((sender, _)) = &*self.started.lock().await {
+            return Ok(sender.clone());
+        }
+
+        if !self.settings.global.destinations.contains_key(&self.name) {
+            return Err(errors::HotdogError::DestinationMissing {
+                destination: self.name.clone(),
+            });
+        }
+
+        if !self.retired.load(Ordering::Relaxed) && !self.starting.swap(true, Ordering::AcqRel) {
+            let sink = self.clone();
+
+            task::spawn(async move {
+                if let Err(e) = sink.start().await {
+                    error!(
+                        "Failed to start the producer of the {} destination: {:?}",
+                        sink.name, e
+                    );
+                }
+                sink.starting.store(false, Ordering::Release);
+            });
+        }
+        Err(errors::HotdogError::DestinationUnavailable {
+            destination: self.name.clone(),
+        })
+    }
+
+    /**
+     * Connect to the brokers of the destination and start its sendloop, unless it has already been
+     * started, returning the sender to the sendloop
+     */
+    async fn start(&self) -> Result<Sender<Produce>, errors::HotdogError> {
+        let conf = match self.settings.global.destinations.get(&self.name) {
+            Some(Destination::Kafka(conf)) => conf,
+            None => {
+                return Err(errors::HotdogError::DestinationMissing {
+                    destination: self.name.clone(),
+                })
+            }
+        };
+        if self.retired.load(Ordering::Relaxed) {
+            return Err(errors::HotdogError::DestinationUnavailable {
+                destination: self.name.clone(),
+            });
+        }
+
+        info!("Starting the producer of the {} destination", self.name);
+        /*
+         * The lock is not held while connecting, the messages sent to the destination in the
+         * meantime are dropped rather than waiting on its brokers
+         */
+        let (sender, producer) = start_kafka(
+            &self.name,
+            conf,
+            &[],
+            self.stats.clone(),
+            Some(&self.retired),
+        )
+        .await?;
+        let mut started = self.started.lock().await;
+
+        /*
+         * Sinks which have been replaced while the destination was connecting stop its sendloop
+         * right away, as they would have done had it already been started
+         */
+        if self.retired.load(Ordering::Relaxed) {
+            sender.close();
+        }
+        *started = Some((sender.clone(), producer));
+        Ok(sender)
+    }
+
+    /**
+     * Flush the pending messages of the producer, if the destination has been started
+     */
+    async fn flush(&self) {
+        if let Some((sender, Some(producer))) = &*self.started.lock().await {
+            if let Some(Destination::Kafka(conf)) =
+                self.settings.global.destinations.get(&self.name)
+            {
+                kafka::flush(
+                    producer.clone(),
+                    sender,
+                    Duration::from_millis(conf.flush_timeout_ms),
+                )
+                .await;
+            }
+        }
+    }
+
+    /**
+     * Stop the sendloop of the destination, and keep it from being started any more. A destination
+     * which has not connected to its brokers stops trying to, and is no longer reported as
+     * unavailable
+     */
+    async fn retire(&self) {
+        self.retired.store(true, Ordering::Relaxed);
+
+        match &*self.started.lock().await {
+            Some((sender, _)) => {
+                sender.close();
+            }
+            None => {
+                self.stats.send(unavailable(&self.name, false)).await;
+            }
+        }
+    }
+}
+
+/**
+ * Return the statistic of whether the producer of the destination is unavailable, which the status
+ * server reports hotdog as not ready for
+ */
+fn unavailable(name: &str, unavailable: bool) -> status::Statistic {
+    (
+        status::Stats::KafkaUnavailable {
+            destination: name.to_string(),
+        },
+        unavailable as i64,
+    )
+}
+
+/**
+ * Connect to the Kafka brokers of the destination and start its sendloop, returning the Sender for
+ * the sendloop and the producer which should be flushed when shutting down.
+ *
+ * The given topics are verified to exist according to the destination's `verify_topics`. A
+ * destination which is retired while it is connecting stops trying to connect
+ */
+async fn start_kafka(
+    name: &str,
+    conf: &settings::Kafka,
+    topics: &[String],
+    stats: Sender<status::Statistic>,
+    retired: Option<&AtomicBool>,
+) -> Result<(Sender<Produce>, Option<FutureProducer<StatsContext>>), errors::HotdogError> {
+    let mut policy = DeliveryPolicy::from(conf);
+    if let Some(spool) = &conf.spool {
+        let dir = spool.path.join(name);
+        /*
+         * Opening a spool validates everything which was spooled before, which is left to a
+         * blocking thread
+         */
+        let opening = dir.clone();
+        let (max_bytes, dedupe) = (spool.max_bytes, spool.dedupe);
+        let opened = task::spawn_blocking(move || Spool::shared(&opening, max_bytes, dedupe))
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to open the spool of the {} destination in {:?}: {}",
+                    name, dir, e
+                );
+                e
+            })?;
+        let quarantined = opened.take_quarantined();
+        if quarantined > 0 {
+            stats
+                .send((status::Stats::KafkaSpoolQuarantined, quarantined as i64))
+                .await;
+        }
+        policy.spool = Some(opened);
+    }
+    let spool = policy.spool.clone();
+    let mut kafka = Kafka::new(conf.buffer, policy, stats.clone());
+
+    let mut backoff = Duration::from_secs(1);
+
+    while !kafka.connect(&conf.rdkafka_conf(), Some(conf.timeout_ms)) {
+        if conf.require_kafka_at_start {
+            error!(
+                "Cannot start hotdog without a workable broker connection for the {} destination",
+                name
+            );
+            return Err(errors::HotdogError::KafkaConnectError);
+        }
+
+        if retired.map_or(false, |retired| retired.load(Ordering::Relaxed)) {
+            stats.send(unavailable(name, false)).await;
+            return Err(errors::HotdogError::DestinationUnavailable {
+                destination: name.to_string(),
+            });
+        }
+
+        warn!(
+            "Unable to connect to the Kafka brokers of the {} destination, retrying in {:?}",
+            name, backoff
+        );
+        stats.send(unavailable(name, true)).await;
+        task::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, Duration::from_secs(60));
+    }
+    stats.send(unavailable(name, false)).await;
+
+    if conf.verify_topics != TopicVerification::Off {
+        let missing = kafka.missing_topics(topics);
+
+        if !missing.is_empty() {
+            if conf.verify_topics == TopicVerification::Fail {
+                error!("The configured topics do not exist in Kafka: {:?}", missing);
+                return Err(errors::HotdogError::MissingTopicsError { topics: missing });
+            }
+            warn!("The configured topics do not exist in Kafka: {:?}", missing);
+        }
+    }
+
+    let sender = kafka.get_sender();
+    let producer = kafka.get_producer();
+
+    if let (Some(spool), Some(producer), Some(spooling)) = (spool, &producer, &conf.spool) {
+        let producer = producer.clone();
+        let sender = sender.clone();
+        let replaying = stats.clone();
+        let interval = Duration::from_millis(spooling.replay_interval_ms);
+
+        task::spawn(supervise::supervise(
+            "spool",
+            stats.clone(),
+            crate::signals::shutdown(),
+            move || {
+                let replay = spool::replay_loop(
+                    spool.clone(),
+                    producer.clone(),
+                    sender.clone(),
+                    replaying.clone(),
+                    interval,
+                );
+                async move {
+                    replay.await;
+                    Ok::<(), ()>(())
+                }
+            },
+        ));
+    }
+
+    /*
+     * A sendloop which panicked is started again on the same channel
+     */
+    let kafka = Arc::new(kafka);
+    task::spawn(supervise::supervise(
+        "sendloop",
+        stats,
+        crate::signals::shutdown(),
+        move || {
+            let kafka = kafka.clone();
+            async move {
+                debug!("Starting Kafka sendloop");
+                kafka.sendloop().await;
+                Ok::<(), ()>(())
+            }
+        },
+    ));
+
+    Ok((sender, producer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_destination_started_lazily() {
+        let settings = Arc::new(settings::load(
+            "test/configs/single-rule-with-destination.yml",
+        ));
+        let (stats, reported) = async_channel::bounded(1);
+        let sink = Arc::new(DestinationSink::new("archive", settings.clone(), stats));
+
+        /*
+         * A retired destination which was never sent to never connects to its brokers, and is
+         * not reported as unavailable
+         */
+        sink.retire().await;
+        let (stat, unavailable) = reported
+            .try_recv()
+            .expect("Failed to report the destination");
+        assert!(matches!(
+            stat,
+            status::Stats::KafkaUnavailable { destination } if destination == "archive"
+        ));
+        assert_eq!(0, unavailable);
+        assert!(matches!(
+            sink.sender().await,
+            Err(errors::HotdogError::DestinationUnavailable { .. })
+        ));
+        assert!(!sink.starting.load(Ordering::Relaxed));
+
+        let missing = Arc::new(DestinationSink::new(
+            "missing",
+            settings.clone(),
+            async_channel::bounded(1).0,
+        ));
+        assert!(matches!(
+            missing.sender().await,
+            Err(errors::HotdogError::DestinationMissing { .. })
+        ));
+
+        let (sinks, sunk) = Sinks::null(&settings);
+        let sender = sinks.destinations["archive"]
+            .sender()
+            .await
+            .expect("Failed to start the destination");
+        /*
+         * The destinations of null sinks send to the same channel as their default producer
+         */
+        sender
+            .send(Produce::Message(kafka::KafkaMessage::new(
+                "archive".to_string(),
+                b"hi".to_vec(),
+            )))
+            .await
+            .expect("Failed to send to the destination");
+        assert!(sunk.try_recv().is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_dry_run() {
+        let settings = Arc::new(settings::load("test/configs/minimal.yml"));
+        let (sinks, sunk) = Sinks::null(&settings);
+        let (stats, counted) = async_channel::bounded(4);
+        assert!(sinks.is_null());
+
+        sinks
+            .sender
+            .send(Produce::Message(kafka::KafkaMessage::new(
+                "logs".to_string(),
+                "hello",
+            )))
+            .await
+            .unwrap();
+        drop(sinks);
+        dry_run(sunk, stats).await;
+
+        let (stat, count) = counted.recv().await.unwrap();
+        assert_eq!("dry_run.messages", stat.to_string());
+        assert_eq!(1, count);
+        let (stat, count) = counted.recv().await.unwrap();
+        assert_eq!("dry_run.bytes", stat.to_string());
+        assert_eq!(5, count);
+    }
+}
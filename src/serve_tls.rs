@@ -3,11 +3,12 @@ use crate::errors;
 use crate::serve::*;
 use crate::settings::*;
 use crate::status;
+use crate::supervise;
 /**
  * This module handles the necessary configuration to serve over TLS
  */
 use async_channel::Sender;
-use async_std::{io, io::BufReader, net::TcpStream, sync::Arc, task};
+use async_std::{io, net::TcpStream, sync::Arc, task};
 use async_tls::TlsAcceptor;
 use log::*;
 use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
@@ -16,6 +17,7 @@ use rustls::{
     ServerConfig,
 };
 use std::path::Path;
+use std::time::Instant;
 
 /**
  * TlsServer is a syslog-over-TLS implementation, which will allow for receiving logs over a TLS
@@ -29,8 +31,8 @@ pub struct TlsServer {
 
 impl TlsServer {
     pub fn new(state: &ServerState) -> Self {
-        let config =
-            load_tls_config(state).expect("Failed to generate the TLS ServerConfig properly");
+        let config = load_tls_config(state.listen())
+            .expect("Failed to generate the TLS ServerConfig properly");
         let acceptor = TlsAcceptor::from(Arc::new(config));
         TlsServer { acceptor }
     }
@@ -41,13 +43,29 @@ impl Server for TlsServer {
         Ok(())
     }
 
+    /**
+     * Load the certificates and keys again, so that rotated certificates are picked up by the new
+     * connections
+     */
+    fn reconfigure(&mut self, listen: &Listen) -> Result<(), errors::HotdogError> {
+        if listen.tls == TlsType::None {
+            warn!("Switching from TLS to plaintext requires hotdog to be restarted");
+            return Ok(());
+        }
+        let config = load_tls_config(listen)?;
+        self.acceptor = TlsAcceptor::from(Arc::new(config));
+        Ok(())
+    }
+
     fn handle_connection(
         &self,
         stream: TcpStream,
         connection: Connection,
         stats: Sender<status::Statistic>,
     ) -> Result<(), std::io::Error> {
-        debug!("Accepting from: {}", stream.peer_addr()?);
+        let peer = stream.peer_addr()?.to_string();
+        debug!("Accepting from: {}", peer);
+        let opened = Instant::now();
 
         // Calling `acceptor.accept` will start the TLS handshake
         let handshake = self.acceptor.accept(stream);
@@ -57,11 +75,13 @@ impl Server for TlsServer {
             // stream back.
             match handshake.await {
                 Ok(tls_stream) => {
-                    let reader = BufReader::new(tls_stream);
-
-                    if let Err(e) = connection.read_logs(reader).await {
-                        error!("Failure occurred while read_logs executed: {:?}", e);
-                    }
+                    supervise::watch(
+                        "connection",
+                        &peer,
+                        &stats,
+                        connection.read_logs(tls_stream),
+                    )
+                    .await;
                 }
                 Err(err) => {
                     error!("Unable to establish a TLS Stream for client! {:?}", err);
@@ -69,6 +89,12 @@ impl Server for TlsServer {
             };
 
             stats.send((status::Stats::ConnectionCount, -1)).await;
+            stats
+                .send((
+                    status::Stats::ConnectionLifetime,
+                    status::elapsed_us(opened),
+                ))
+                .await;
         });
         Ok(())
     }
@@ -77,14 +103,17 @@ impl Server for TlsServer {
 /**
  * Generate the default ServerConfig needed for rustls to work properly in server mode
  */
-fn load_tls_config(state: &ServerState) -> io::Result<ServerConfig> {
-    match &state.settings.global.listen.tls {
+fn load_tls_config(listen: &Listen) -> io::Result<ServerConfig> {
+    match &listen.tls {
         TlsType::CertAndKey { cert, key, ca } => {
             let certs = load_certs(cert.as_path())?;
             let mut keys = load_keys(key.as_path())?;
 
             if keys.is_empty() {
-                panic!("TLS key could not be properly loaded! This is fatal!");
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "TLS key could not be properly loaded! This is fatal!",
+                ));
             }
 
             let verifier = if ca.is_some() {
@@ -0,0 +1,166 @@
+/**
+ * The service module runs hotdog as a Windows service, which is started and stopped by the
+ * service control manager rather than from a terminal or by signals. Stopping the service shuts
+ * hotdog down as SIGTERM does elsewhere, and its `paramchange` control reloads it as SIGHUP does
+ */
+use crate::errors::HotdogError;
+use crate::signals;
+use log::*;
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/**
+ * The name hotdog is registered with, e.g. `sc.exe create hotdog ...`. A service which runs in a
+ * process of its own is started by whatever name it was registered with
+ */
+const NAME: &str = "hotdog";
+
+type Start = Box<dyn FnOnce() -> Result<(), HotdogError> + Send>;
+
+/**
+ * What the service runs once the service control manager has started it
+ */
+static START: Mutex<Option<Start>> = Mutex::new(None);
+
+/**
+ * The handle to report the status of the service with, while it is running as a service
+ */
+static STATUS: Mutex<Option<ServiceStatusHandle>> = Mutex::new(None);
+
+/**
+ * How long the service control manager waits for hotdog to bind its listeners
+ */
+const START_WAIT: Duration = Duration::from_secs(30);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/**
+ * Hand the main thread over to the service control manager, which runs `start` as the service
+ * on a thread of its own, returning once the service has stopped
+ */
+pub fn run<F>(start: F) -> Result<(), HotdogError>
+where
+    F: FnOnce() -> Result<(), HotdogError> + Send + 'static,
+{
+    if let Ok(mut starting) = START.lock() {
+        *starting = Some(Box::new(start));
+    }
+
+    service_dispatcher::start(NAME, ffi_service_main).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Failed to start as a Windows service: {}", e),
+        )
+    })?;
+    Ok(())
+}
+
+/**
+ * Report the service as running once hotdog serves, which does nothing unless it runs as a
+ * service
+ */
+pub fn ready() {
+    if let Some(status) = STATUS.lock().ok().and_then(|status| *status) {
+        report(
+            status,
+            ServiceState::Running,
+            ServiceControlAccept::STOP
+                | ServiceControlAccept::SHUTDOWN
+                | ServiceControlAccept::PARAM_CHANGE,
+            0,
+        );
+    }
+}
+
+/**
+ * Report the state of the service to the service control manager, which only waits for a pending
+ * state for as long as its wait hint
+ */
+fn report(
+    status: ServiceStatusHandle,
+    state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+    code: u32,
+) {
+    let reported = status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(code),
+        checkpoint: 0,
+        wait_hint: if state == ServiceState::StartPending {
+            START_WAIT
+        } else {
+            Duration::default()
+        },
+        process_id: None,
+    });
+
+    if let Err(e) = reported {
+        error!("Failed to report the service as {:?}: {}", state, e);
+    }
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let handler = |control: ServiceControl| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            signals::stop();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::ParamChange => {
+            signals::request_reload();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+
+    let status = match service_control_handler::register(NAME, handler) {
+        Ok(status) => status,
+        Err(e) => {
+            error!("Failed to register the service control handler: {}", e);
+            return;
+        }
+    };
+
+    /*
+     * The service is only running once hotdog has bound its listeners, which calls `ready`
+     */
+    report(
+        status,
+        ServiceState::StartPending,
+        ServiceControlAccept::empty(),
+        0,
+    );
+    if let Ok(mut current) = STATUS.lock() {
+        *current = Some(status);
+    }
+
+    let started = START.lock().ok().and_then(|mut start| start.take());
+    let code = match started.map(|start| start()) {
+        Some(Ok(())) => 0,
+        Some(Err(e)) => {
+            error!("hotdog stopped with an error: {:?}", e);
+            1
+        }
+        None => 1,
+    };
+
+    if let Ok(mut current) = STATUS.lock() {
+        *current = None;
+    }
+    report(
+        status,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        code,
+    );
+}
@@ -2,241 +2,4049 @@
  * The settings module contains the necessary structs and code to process the
  * hotdog.yml file format
  */
-use async_std::path::Path;
 use log::*;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::time::Duration;
 use uuid::Uuid;
 
+/**
+ * Load the settings from the file without any overrides, panicking if the configuration is
+ * invalid
+ */
+#[cfg(test)]
 pub fn load(file: &str) -> Settings {
-    let conf = load_configuration(file);
-    let mut settings: Settings = conf
-        .try_into()
-        .expect("Failed to parse the configuration file");
-    settings.populate_caches();
-    settings
+    try_load(file, &Overrides::default()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/**
+ * Load the settings from the file, returning an error rather than panicking if the configuration
+ * is invalid, which allows the rules to be reloaded while hotdog is running
+ */
+pub fn try_load(file: &str, overrides: &Overrides) -> Result<Settings, String> {
+    parse(file, overrides).map_err(|errors| {
+        format!(
+            "Found {} error(s) in the configuration file {}:\n  - {}",
+            errors.len(),
+            file,
+            errors.join("\n  - ")
+        )
+    })
 }
 
-fn load_configuration(file: &str) -> config::Config {
-    let file_path = Path::new(file);
+/**
+ * Load the settings from the file on a blocking thread, for the callers which are tasks on the
+ * async executor, since resolving the secrets of the settings may wait on Vault or AWS
+ */
+pub async fn try_load_blocking(file: &str, overrides: &Overrides) -> Result<Settings, String> {
+    let file = file.to_string();
+    let overrides = overrides.clone();
 
-    if file_path.extension().unwrap() != "yml" {
-        panic!("The configuration file must end with .yml");
-    }
+    async_std::task::spawn_blocking(move || try_load(&file, &overrides)).await
+}
+
+/**
+ * Read only the runtime settings from the file, without resolving any secrets, so that the
+ * executor can be configured before anything runs on it. The defaults are returned when the file
+ * cannot be read, since loading the settings reports the errors
+ */
+pub fn runtime(file: &str, overrides: &Overrides) -> Runtime {
+    let mut errors = vec![];
 
-    debug!("Loading configuration from {}", file);
+    load_configuration::<serde_json::Value>(file, overrides.profile.as_deref(), &mut errors)
+        .ok()
+        .and_then(|(conf, _)| conf.get::<Runtime>("global.runtime").ok())
+        .unwrap_or_default()
+}
+
+/**
+ * Parse the settings from the file, returning every error which was found rather than only the
+ * first, each with the key or rule it was found in
+ */
+pub fn parse(file: &str, overrides: &Overrides) -> Result<Settings, Vec<String>> {
+    let mut errors = vec![];
+    let (conf, rules) =
+        load_configuration(file, overrides.profile.as_deref(), &mut errors).map_err(|e| vec![e])?;
+    check_sections(&conf, &mut errors);
 
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut settings: Settings = conf
+        .try_into()
+        .map_err(|e| vec![format!("Failed to parse the configuration file: {}", e)])?;
     /*
-     * Load our settings in the priority order of:
-     *
-     *   - yaml file
-     *   - environment variables
-     *
-     * Each layer overriding properties from the last
+     * Merging the files would replace the rules of the included files with the configuration
+     * file's own rules, rather than combining them
      */
-    let mut conf = config::Config::default();
-    conf.merge(config::File::with_name(file))
-        .unwrap()
-        .merge(config::Environment::with_prefix("HOTDOG"))
-        .unwrap();
+    settings.rules = rules;
+    settings.hash = file_hash(file);
+    overrides.apply(&mut settings);
+
+    if let Err(mut secrets) = crate::secrets::resolve(&mut settings.global.kafka.conf) {
+        errors.append(&mut secrets);
+    }
+
+    for destination in settings.global.destinations.values_mut() {
+        match destination {
+            Destination::Kafka(conf) => {
+                if let Err(mut secrets) = crate::secrets::resolve(&mut conf.conf) {
+                    errors.append(&mut secrets);
+                }
+            }
+        }
+    }
+
+    if let Some(rules_d) = &settings.rules_d {
+        let mut rules =
+            load_rules_d(&rules_d_for(file, rules_d), &mut errors).map_err(|e| vec![e])?;
+        settings.rules.append(&mut rules);
+    }
 
-    let _port: u64 = conf
-        .get("global.listen.port")
-        .expect("Configuration had no `global.listen.port` setting");
-    conf
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    settings.populate_caches()?;
+    Ok(settings)
 }
 
 /**
- * Valid field to apply the rule upon
- *
- * They should be camel-cased in the yaml configuration
+ * Return the hex encoded SHA-256 hash of the file's contents, so that the configuration a running
+ * hotdog has loaded can be compared with the file on disk
  */
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub enum Field {
-    Severity,
-    Facility,
-    Hostname,
-    Appname,
-    Msg,
+fn file_hash(file: &str) -> Option<String> {
+    use sha2::Digest;
+    let contents = std::fs::read(file).ok()?;
+
+    Some(format!("{:x}", sha2::Sha256::digest(&contents)))
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase", tag = "type")]
-pub enum Action {
-    Forward {
-        topic: String,
-    },
-    Merge {
-        json: Value,
-        #[serde(default = "default_none")]
-        json_str: Option<String>,
-    },
-    Replace {
-        template: String,
-    },
-    Stop,
+/**
+ * Return the rules_d directory, which is relative to the configuration file rather than the working
+ * directory
+ */
+fn rules_d_for(file: &str, rules_d: &std::path::Path) -> std::path::PathBuf {
+    std::path::Path::new(file)
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""))
+        .join(rules_d)
 }
 
-impl Action {
-    fn populate_caches(&mut self) {
-        if let Action::Merge { json, json_str } = self {
-            *json_str =
-                Some(serde_json::to_string(json).expect("Failed to serialize Merge action"));
-        }
+/**
+ * Return the configuration as hotdog sees it once the included files, the profile, the
+ * environment variables, and the rules_d directory have been merged, with the secrets of the Kafka
+ * configurations masked.
+ *
+ * When `defaults` is set, the `global` settings are the settings which hotdog will run with,
+ * including the defaults of every setting which was left out, the overrides, and the resolved
+ * secrets
+ */
+pub fn resolved(
+    file: &str,
+    overrides: &Overrides,
+    defaults: bool,
+) -> Result<serde_json::Value, Vec<String>> {
+    let settings = parse(file, overrides)?;
+    let mut errors = vec![];
+    let (conf, mut rules) =
+        load_configuration::<serde_json::Value>(file, overrides.profile.as_deref(), &mut errors)
+            .map_err(|e| vec![e])?;
+
+    if let Some(rules_d) = &settings.rules_d {
+        let mut rules_d =
+            load_rules_d(&rules_d_for(file, rules_d), &mut errors).map_err(|e| vec![e])?;
+        rules.append(&mut rules_d);
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct Rule {
-    #[serde(skip_serializing, skip_deserializing, default = "default_uuid")]
-    pub uuid: Uuid,
-    pub field: Field,
-    pub actions: Vec<Action>,
-    #[serde(with = "serde_regex", default = "default_none")]
-    pub regex: Option<regex::Regex>,
-    #[serde(default = "default_none")]
-    pub jmespath: Option<String>,
-}
+    let mut resolved: serde_json::Value = conf
+        .try_into()
+        .map_err(|e| vec![format!("Failed to parse the configuration file: {}", e)])?;
+    resolved["rules"] = serde_json::Value::Array(rules);
 
-impl Rule {
-    fn populate_caches(&mut self) {
-        self.actions.iter_mut().for_each(|action| {
-            action.populate_caches();
-        });
+    if defaults {
+        resolved["global"] = serde_json::to_value(&settings.global)
+            .map_err(|e| vec![format!("Failed to serialize the global settings: {}", e)])?;
     }
+    mask_secrets(&mut resolved);
+    Ok(resolved)
 }
-impl std::fmt::Display for Rule {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        if let Some(regex) = &self.regex {
-            write!(f, "Regex: {}", regex)
-        } else {
-            write!(f, "JMESPath: {}", self.jmespath.as_ref().unwrap())
+
+/**
+ * Mask the values of the Kafka configurations which look like credentials, such as
+ * `sasl.password` or `ssl.key.pem`, or which embed them, such as `sasl.jaas.config`
+ */
+fn mask_secrets(value: &mut serde_json::Value) {
+    let sensitive = [
+        "password",
+        "secret",
+        "token",
+        "ssl.key",
+        "sasl.jaas.config",
+        "sasl.oauthbearer.config",
+    ];
+
+    if let Some(object) = value.as_object_mut() {
+        for (key, value) in object.iter_mut() {
+            if key == "conf" {
+                if let Some(conf) = value.as_object_mut() {
+                    for (name, setting) in conf.iter_mut() {
+                        if sensitive.iter().any(|s| name.contains(s)) {
+                            *setting = serde_json::Value::String("********".to_string());
+                        }
+                    }
+                    continue;
+                }
+            }
+            mask_secrets(value);
+        }
+    } else if let Some(array) = value.as_array_mut() {
+        for value in array.iter_mut() {
+            mask_secrets(value);
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-#[serde(untagged)]
-pub enum TlsType {
-    None,
-
+/**
+ * Overrides of the configuration's settings, which take precedence over both the configuration
+ * file and the environment variables, so that hotdog can be reconfigured with its arguments
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
     /**
-     * The CertsAndKey struct will contain PathBuf values if the tls section was
-     * included
+     * The address to listen on, overriding `global.listen.address`
      */
-    CertAndKey {
-        cert: std::path::PathBuf,
-        key: std::path::PathBuf,
-        ca: Option<std::path::PathBuf>,
-    },
+    pub listen: Option<String>,
+    /**
+     * The port to listen on, overriding `global.listen.port`
+     */
+    pub port: Option<u64>,
+    /**
+     * The Kafka brokers to connect to, overriding the `bootstrap.servers` of `global.kafka.conf`
+     */
+    pub brokers: Option<String>,
+    /**
+     * The name of the profile in `profiles` to overlay on the configuration
+     */
+    pub profile: Option<String>,
 }
 
-impl Default for TlsType {
-    fn default() -> TlsType {
-        TlsType::None
+impl Overrides {
+    fn apply(&self, settings: &mut Settings) {
+        if let Some(address) = &self.listen {
+            settings.global.listen.address = address.clone();
+        }
+
+        if let Some(port) = self.port {
+            settings.global.listen.port = port;
+        }
+
+        if let Some(brokers) = &self.brokers {
+            settings
+                .global
+                .kafka
+                .conf
+                .insert("bootstrap.servers".to_string(), brokers.clone());
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Listen {
-    pub address: String,
-    pub port: u64,
-    #[serde(default)]
-    pub tls: TlsType,
+/**
+ * Check each section of the configuration on its own, so that an invalid section is reported
+ * with its key, along with the values which it accepts, rather than failing the whole file
+ */
+fn check_sections(conf: &config::Config, errors: &mut Vec<String>) {
+    check_section::<Kafka>(conf, "global.kafka", true, errors);
+    check_section::<Listen>(conf, "global.listen", true, errors);
+    check_section::<Vec<Listen>>(conf, "global.listeners", false, errors);
+    check_section::<Metrics>(conf, "global.metrics", false, errors);
+    check_section::<Log>(conf, "global.log", false, errors);
+    check_section::<Status>(conf, "global.status", false, errors);
+    check_section::<Tracing>(conf, "global.tracing", false, errors);
+    check_section::<Heartbeat>(conf, "global.heartbeat", false, errors);
+    check_section::<Timestamps>(conf, "global.timestamps", false, errors);
+    check_section::<Runtime>(conf, "global.runtime", false, errors);
+    check_section::<Shutdown>(conf, "global.shutdown", false, errors);
+    check_section::<HashMap<String, String>>(conf, "global.vars", false, errors);
+    check_section::<ParseErrors>(conf, "global.parse_errors", false, errors);
+    check_section::<Limits>(conf, "global.limits", false, errors);
+    check_section::<HashMap<String, Destination>>(conf, "global.destinations", false, errors);
+    check_section::<RulesConfig>(conf, "global.rules", false, errors);
+    check_section::<Rule>(conf, "default", false, errors);
+    check_section::<std::path::PathBuf>(conf, "rules_d", false, errors);
+    check_section::<HashMap<String, String>>(conf, "patterns", false, errors);
+    check_section::<HashMap<String, CustomHelper>>(conf, "helpers", false, errors);
+    check_section::<HashMap<String, String>>(conf, "templates", false, errors);
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Kafka {
-    #[serde(default = "kafka_buffer_default")]
-    pub buffer: usize,
-    #[serde(default = "kafka_timeout_default")]
-    pub timeout_ms: Duration,
-    pub conf: HashMap<String, String>,
-    pub topic: String,
+fn check_section<T: serde::de::DeserializeOwned>(
+    conf: &config::Config,
+    key: &str,
+    required: bool,
+    errors: &mut Vec<String>,
+) {
+    match conf.get::<T>(key) {
+        Ok(_) => {}
+        Err(config::ConfigError::NotFound(_)) if !required => {}
+        Err(config::ConfigError::NotFound(_)) => {
+            errors.push(format!("`{}`: the section is required", key));
+        }
+        Err(e) => errors.push(format!("`{}`: {}", key, e)),
+    }
 }
 
+/**
+ * The files included by a configuration file
+ */
 #[derive(Debug, Deserialize)]
-pub struct Metrics {
-    pub statsd: String,
+struct IncludesFile {
+    #[serde(default)]
+    include: Vec<std::path::PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Status {
-    pub address: String,
-    pub port: u64,
+/**
+ * Load the rules from every YAML, TOML, or JSON file in the directory, in the order of their file
+ * names so that the rules are merged deterministically
+ */
+fn load_rules_d<T: serde::de::DeserializeOwned>(
+    dir: &std::path::Path,
+    errors: &mut Vec<String>,
+) -> Result<Vec<T>, String> {
+    let mut files: Vec<std::path::PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read the rules_d {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| format_for(path).is_some())
+        .collect();
+    files.sort();
+
+    let mut rules = vec![];
+
+    for path in files.iter() {
+        debug!("Loading rules from {}", path.display());
+        let contents = read_interpolated(path)?;
+        let format = format_for(path).unwrap_or(config::FileFormat::Yaml);
+        rules.append(&mut rules_from(&contents, format, path, errors));
+    }
+    Ok(rules)
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Global {
-    pub kafka: Kafka,
-    pub listen: Listen,
-    pub metrics: Metrics,
-    pub status: Option<Status>,
+/**
+ * Parse the rules out of the contents of a configuration or rules file.
+ *
+ * Each rule is parsed on its own so that every invalid rule is added to the errors with its
+ * index, while the valid rules are still returned
+ */
+fn rules_from<T: serde::de::DeserializeOwned>(
+    contents: &str,
+    format: config::FileFormat,
+    path: &std::path::Path,
+    errors: &mut Vec<String>,
+) -> Vec<T> {
+    let mut conf = config::Config::default();
+
+    if let Err(e) = conf.merge(config::File::from_str(contents, format)) {
+        errors.push(format!(
+            "Failed to load the rules {}: {}",
+            path.display(),
+            e
+        ));
+        return vec![];
+    }
+
+    let values: Vec<config::Value> = match conf.get("rules") {
+        Ok(values) => values,
+        Err(config::ConfigError::NotFound(_)) => vec![],
+        Err(e) => {
+            errors.push(format!("{}: `rules`: {}", path.display(), e));
+            return vec![];
+        }
+    };
+
+    let mut rules = vec![];
+
+    for (index, value) in values.into_iter().enumerate() {
+        match value.try_into() {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("{}: `rules[{}]`: {}", path.display(), index, e)),
+        }
+    }
+    rules
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Settings {
-    pub global: Global,
-    pub rules: Vec<Rule>,
+/**
+ * Return the files included by the configuration file, relative to its directory
+ */
+fn includes_from(
+    contents: &str,
+    format: config::FileFormat,
+    path: &std::path::Path,
+) -> Result<Vec<std::path::PathBuf>, String> {
+    let mut conf = config::Config::default();
+    conf.merge(config::File::from_str(contents, format))
+        .map_err(|e| {
+            format!(
+                "Failed to load the configuration file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+    let file: IncludesFile = conf
+        .try_into()
+        .map_err(|e| format!("Failed to parse the includes of {}: {}", path.display(), e))?;
+
+    let dir = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+    Ok(file
+        .include
+        .iter()
+        .map(|include| dir.join(include))
+        .collect())
 }
 
-impl Settings {
-    /**
-     * Populate any configuration caches which we want to us
+/**
+ * Return the format of the configuration file based on its extension
+ */
+pub fn format_for(path: &std::path::Path) -> Option<config::FileFormat> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yml") | Some("yaml") => Some(config::FileFormat::Yaml),
+        Some("toml") => Some(config::FileFormat::Toml),
+        Some("json") => Some(config::FileFormat::Json),
+        _ => None,
+    }
+}
+
+/**
+ * Load the configuration from the file and the files it includes, along with all of their rules,
+ * adding any invalid rules to the errors
+ */
+fn load_configuration<T: serde::de::DeserializeOwned>(
+    file: &str,
+    profile: Option<&str>,
+    errors: &mut Vec<String>,
+) -> Result<(config::Config, Vec<T>), String> {
+    let file_path = std::path::Path::new(file);
+
+    let (contents, format) = if !file_path.exists() && std::env::var(ENV_BROKERS).is_ok() {
+        info!(
+            "The configuration file {} does not exist, configuring hotdog from the environment",
+            file
+        );
+        (
+            environment_conf(|name| std::env::var(name).ok())?,
+            config::FileFormat::Json,
+        )
+    } else {
+        let format = format_for(file_path).ok_or_else(|| {
+            "The configuration file must end with .yml, .yaml, .toml, or .json".to_string()
+        })?;
+
+        debug!("Loading configuration from {}", file);
+        (read_interpolated(file_path)?, format)
+    };
+    let includes = includes_from(&contents, format, file_path)?;
+
+    /*
+     * Load our settings in the priority order of:
+     *
+     *   - included files, in the order they are listed
+     *   - configuration file
+     *   - the selected profile of the `profiles`
+     *   - environment variables
+     *
+     * Each layer overriding properties from the last, while the rules of every file are kept
      */
-    fn populate_caches(&mut self) {
-        self.rules.iter_mut().for_each(|rule| {
-            rule.populate_caches();
-        });
+    let mut conf = config::Config::default();
+    let mut rules = vec![];
+
+    for include in includes.iter() {
+        debug!("Including configuration from {}", include.display());
+        let format = format_for(include).ok_or_else(|| {
+            format!(
+                "The included file {} must end with .yml, .yaml, .toml, or .json",
+                include.display()
+            )
+        })?;
+        let included = read_interpolated(include)?;
+
+        conf.merge(config::File::from_str(&included, format))
+            .map_err(|e| {
+                format!(
+                    "Failed to load the included file {}: {}",
+                    include.display(),
+                    e
+                )
+            })?;
+        rules.append(&mut rules_from(&included, format, include, errors));
     }
+
+    conf.merge(config::File::from_str(&contents, format))
+        .map_err(|e| format!("Failed to load the configuration file {}: {}", file, e))?;
+    rules.append(&mut rules_from(&contents, format, file_path, errors));
+
+    if let Some(profile) = profile {
+        debug!("Applying the {} profile", profile);
+        let overlay: serde_json::Value = conf
+            .get(&format!("profiles.{}", profile.to_lowercase()))
+            .map_err(|_| format!("The profile `{}` is not defined in `profiles`", profile))?;
+        let overlay = overlay.to_string();
+
+        conf.merge(config::File::from_str(&overlay, config::FileFormat::Json))
+            .map_err(|e| format!("Failed to apply the profile `{}`: {}", profile, e))?;
+        let label = format!("profiles.{}", profile);
+        rules.append(&mut rules_from(
+            &overlay,
+            config::FileFormat::Json,
+            std::path::Path::new(&label),
+            errors,
+        ));
+    }
+
+    conf.merge(config::Environment::with_prefix("HOTDOG"))
+        .map_err(|e| format!("Failed to load the environment variables: {}", e))?;
+
+    /*
+     * A missing or invalid `global.listen.port` is reported along with every other error by
+     * check_sections
+     */
+    Ok((conf, rules))
 }
 
-/*
- * Default functions
+/**
+ * The environment variable with the Kafka brokers, which configures hotdog from the environment
+ * when the configuration file does not exist
  */
+const ENV_BROKERS: &str = "HOTDOG_KAFKA_BROKERS";
 
 /**
- * Return the default size used for the Kafka buffer
+ * Generate the configuration from the environment variables, for deployments without a
+ * configuration file.
+ *
+ * Only the listener, the Kafka brokers, the metrics, and the log settings can be configured, and
+ * every message is forwarded to the default topic by the default rule
  */
-fn kafka_buffer_default() -> usize {
-    1024
-}
+fn environment_conf<F: Fn(&str) -> Option<String>>(var: F) -> Result<String, String> {
+    let brokers = var(ENV_BROKERS).ok_or_else(|| format!("`{}` is not set", ENV_BROKERS))?;
 
-fn kafka_timeout_default() -> Duration {
-    Duration::from_secs(30)
-}
+    let port = match var("HOTDOG_LISTEN_PORT") {
+        Some(port) => port
+            .parse::<u64>()
+            .map_err(|_| format!("`HOTDOG_LISTEN_PORT`: `{}` is not a valid port", port))?,
+        None => 1514,
+    };
+    let topic = var("HOTDOG_KAFKA_TOPIC").unwrap_or_else(kafka_topic_default);
 
-fn default_none<T>() -> Option<T> {
-    None
+    let mut global = serde_json::json!({
+        "listen": {
+            "address": var("HOTDOG_LISTEN_ADDRESS").unwrap_or_else(|| "0.0.0.0".to_string()),
+            "port": port,
+        },
+        "kafka": {
+            "conf": {
+                "bootstrap.servers": brokers,
+            },
+            "topic": topic,
+        },
+    });
+
+    if let Some(statsd) = var("HOTDOG_METRICS_STATSD") {
+        global["metrics"] = serde_json::json!({ "statsd": statsd });
+    }
+
+    if let Some(level) = var("HOTDOG_LOG_LEVEL") {
+        global["log"] = serde_json::json!({ "level": level });
+    }
+
+    Ok(serde_json::json!({
+        "global": global,
+        "default": {
+            "actions": [
+                {
+                    "type": "forward",
+                    "topic": topic,
+                },
+            ],
+        },
+    })
+    .to_string())
 }
 
-fn default_uuid() -> Uuid {
-    Uuid::new_v4()
+/**
+ * Read the file, replacing every `${VAR}` or `${VAR:-default}` with the value of the environment
+ * variable
+ */
+fn read_interpolated(path: &std::path::Path) -> Result<String, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Failed to read the configuration file {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    interpolate(&contents).map_err(|e| format!("{} in {}", e, path.display()))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/**
+ * Replace every `${VAR}` in the contents with the value of the environment variable, or the
+ * default of a `${VAR:-default}` when the variable is not set. `$${` is a literal `${`
+ */
+fn interpolate(contents: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(contents.len());
+    let mut rest = contents;
 
-    #[test]
-    fn test_load_example_config() {
-        load("hotdog.yml");
-    }
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start]);
+            output.push('{');
+            rest = &rest[start + 2..];
+            continue;
+        }
+        output.push_str(&rest[..start]);
 
-    #[test]
-    fn test_load_example_and_populate_caches() {
-        let settings = load("test/configs/single-rule-with-merge.yml");
-        assert_eq!(settings.rules.len(), 1);
-        match &settings.rules[0].actions[0] {
-            Action::Merge { json: _, json_str } => {
-                assert!(json_str.is_some());
-            }
-            _ => {
-                assert!(false);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err("Unterminated `${` in the configuration".to_string()),
+        };
+        let expression = &rest[start + 2..end];
+
+        let (name, default) = match expression.find(":-") {
+            Some(index) => (&expression[..index], Some(&expression[index + 2..])),
+            None => (expression, None),
+        };
+
+        match (std::env::var(name), default) {
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => {
+                return Err(format!("The environment variable `{}` is not set", name));
             }
         }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/**
+ * Valid field to apply the rule upon
+ *
+ * They should be camel-cased in the yaml configuration, structured data parameters are referenced
+ * as `sd.<SD-ID>.<param>`, and variables such as named captures as `var.<name>`
+ */
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Field {
+    Severity,
+    Facility,
+    Hostname,
+    Appname,
+    Procid,
+    Msgid,
+    Msg,
+    StructuredData { id: String, param: String },
+    Variable(String),
+}
+
+impl std::convert::TryFrom<String> for Field {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "severity" => Ok(Field::Severity),
+            "facility" => Ok(Field::Facility),
+            "hostname" => Ok(Field::Hostname),
+            "appname" => Ok(Field::Appname),
+            "procid" => Ok(Field::Procid),
+            "msgid" => Ok(Field::Msgid),
+            "msg" => Ok(Field::Msg),
+            _ => {
+                /*
+                 * SD-IDs may themselves contain periods, e.g. `origin.example.com`, so the
+                 * parameter name is everything after the last period
+                 */
+                if let Some(name) = s.strip_prefix("var.") {
+                    if !name.is_empty() {
+                        return Ok(Field::Variable(name.to_string()));
+                    }
+                }
+                if let Some(sd) = s.strip_prefix("sd.") {
+                    if let Some((id, param)) = sd.rsplit_once('.') {
+                        if !id.is_empty() && !param.is_empty() {
+                            return Ok(Field::StructuredData {
+                                id: id.to_string(),
+                                param: param.to_string(),
+                            });
+                        }
+                    }
+                }
+                Err(format!("`{}` is not a valid field, expected one of: severity, facility, hostname, appname, procid, msgid, msg, sd.<SD-ID>.<param>, or var.<name>", s))
+            }
+        }
+    }
+}
+
+/**
+ * Serializers which can be applied to the output of a Forward action before it is sent to Kafka
+ */
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Serializer {
+    /**
+     * Encode the JSON output as an Avro record in the Confluent wire format, with the schema
+     * either fetched from, or registered with, the Schema Registry
+     */
+    Avro {
+        registry: String,
+        subject: String,
+        #[serde(default = "default_none")]
+        schema: Option<std::path::PathBuf>,
+    },
+    /**
+     * Encode the JSON output (or the rule's variables when the output is not JSON) as the named
+     * protobuf message, described by a compiled descriptor set file, e.g.
+     * `protoc --descriptor_set_out=logs.desc logs.proto`
+     */
+    Protobuf {
+        descriptor: std::path::PathBuf,
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Action {
+    Forward {
+        topic: String,
+        #[serde(default = "default_none")]
+        serializer: Option<Serializer>,
+        #[serde(default = "default_none")]
+        payload: Option<String>,
+        /**
+         * Name of the destination from `global.destinations` to forward to, instead of the
+         * default Kafka
+         */
+        #[serde(default = "default_none")]
+        to: Option<String>,
+    },
+    Merge {
+        json: Value,
+        #[serde(default = "default_none")]
+        json_str: Option<String>,
+    },
+    Replace {
+        template: String,
+    },
+    /**
+     * Add the templated fields to the JSON output, wrapping messages which are not JSON objects
+     * into an object with the original message under the `message_field`
+     */
+    #[serde(alias = "add_fields")]
+    AddFields {
+        fields: HashMap<String, String>,
+        #[serde(default = "add_fields_message_field_default")]
+        message_field: String,
+    },
+    /**
+     * Look up the IP address, either rendered from the `ip` template or found at the JSON
+     * `pointer`, in the MaxMind `database` and add its location to the JSON output under the
+     * `field`, and to the variables as `<field>_country`, `<field>_city`, etc
+     */
+    Geoip {
+        database: std::path::PathBuf,
+        #[serde(default = "default_none")]
+        ip: Option<String>,
+        #[serde(default = "default_none")]
+        pointer: Option<String>,
+        #[serde(default = "geoip_field_default")]
+        field: String,
+    },
+    /**
+     * Extract the `key=value` pairs from the message, or from the value rendered from the
+     * `source` template, into the variables and the JSON output. The pairs are nested under the
+     * `field` when it is set, and messages which are not JSON objects are wrapped into an object
+     * with the original message under the `message_field`
+     */
+    Kv {
+        #[serde(default = "default_none")]
+        source: Option<String>,
+        #[serde(default = "kv_separator_default")]
+        separator: String,
+        #[serde(default = "kv_delimiter_default")]
+        delimiter: String,
+        #[serde(default = "kv_quotes_default")]
+        quotes: String,
+        #[serde(default = "default_none")]
+        field: Option<String>,
+        #[serde(default = "add_fields_message_field_default")]
+        message_field: String,
+    },
+    /**
+     * Split the message, or the value rendered from the `source` template, on the `delimiter`
+     * and add the values of the named `columns` to the variables and the JSON output. Columns
+     * named `_` are skipped
+     */
+    Csv {
+        columns: Vec<String>,
+        #[serde(default = "default_none")]
+        source: Option<String>,
+        #[serde(default = "csv_delimiter_default")]
+        delimiter: String,
+        #[serde(default = "csv_quote_default")]
+        quote: Option<char>,
+        #[serde(default = "default_none")]
+        field: Option<String>,
+        #[serde(default = "add_fields_message_field_default")]
+        message_field: String,
+    },
+    /**
+     * Look up the value rendered from the `key` template in the `table`, a JSON or CSV file which
+     * is loaded with the settings, and add the fields found for it to the variables and the JSON
+     * output
+     */
+    Enrich {
+        table: std::path::PathBuf,
+        key: String,
+        #[serde(default = "default_none")]
+        field: Option<String>,
+        #[serde(default = "add_fields_message_field_default")]
+        message_field: String,
+        #[serde(skip)]
+        entries: crate::lookup::Table,
+    },
+    /**
+     * Decode the `variable`, or the string at the JSON `pointer` in the output, with the
+     * `encoding`. When neither is set the entire output is decoded
+     */
+    Decode {
+        encoding: Encoding,
+        #[serde(default = "default_none")]
+        variable: Option<String>,
+        #[serde(default = "default_none")]
+        pointer: Option<String>,
+    },
+    /**
+     * Validate the output against the JSON Schema, invalid documents are dropped or sent to the
+     * `invalid_topic` instead
+     */
+    Validate {
+        schema: std::path::PathBuf,
+        #[serde(default = "default_none")]
+        invalid_topic: Option<String>,
+    },
+    /**
+     * Normalize the timestamp of the syslog message, or the timestamp rendered from the `source`
+     * template with one of the `formats`, into the `output` format and add it to the JSON output
+     * and the variables as the `field`
+     */
+    Timestamp {
+        #[serde(default = "default_none")]
+        source: Option<String>,
+        #[serde(default)]
+        formats: Vec<String>,
+        #[serde(default)]
+        output: TimestampFormat,
+        #[serde(default = "timestamp_field_default")]
+        field: String,
+    },
+    /**
+     * Rewrite the severity of the message, for subsequent actions and rules, with the `map` of
+     * severities to their replacements. The severity to look up in the map is either the
+     * severity of the syslog message, or rendered from the `source` template
+     */
+    #[serde(alias = "remap_severity")]
+    RemapSeverity {
+        map: HashMap<String, String>,
+        #[serde(default = "default_none")]
+        source: Option<String>,
+    },
+    /**
+     * Run the Lua `script` with the output and the variables of the rule, which can replace the
+     * output or drop the message
+     */
+    Script {
+        script: String,
+    },
+    /**
+     * Emit a statsd metric with the rendered `name`, and the rendered `value` when one is given
+     */
+    Metric {
+        name: String,
+        #[serde(default)]
+        kind: MetricKind,
+        #[serde(default = "default_none")]
+        value: Option<String>,
+    },
+    /**
+     * Pipe the output to the stdin of the external `command` in the background, allowing at most
+     * `concurrency` of them to run at once for this action
+     */
+    Exec {
+        command: Vec<String>,
+        #[serde(default = "exec_concurrency_default")]
+        concurrency: usize,
+        #[serde(default = "exec_timeout_default")]
+        timeout_ms: u64,
+    },
+    /**
+     * Transform the output with the WebAssembly `module`, which can replace the output or drop
+     * the message
+     */
+    Plugin {
+        module: std::path::PathBuf,
+    },
+    /**
+     * Remove the fields at the JSON pointer `paths`, e.g. `/user/password`, from the JSON output
+     */
+    Remove {
+        paths: Vec<String>,
+    },
+    /**
+     * Replace the values of the fields at the JSON pointer `paths` in the JSON output with the
+     * `replacement`
+     */
+    Redact {
+        paths: Vec<String>,
+        #[serde(default = "redact_replacement_default")]
+        replacement: String,
+    },
+    /**
+     * Replace anything in the output which matches the `patterns` with the `replacement`, or with
+     * a salted SHA-256 hash of the match when `hash` is set
+     */
+    Mask {
+        #[serde(rename = "patterns")]
+        sources: Vec<String>,
+        /**
+         * The `patterns`, which are compiled when the settings are loaded
+         */
+        #[serde(skip)]
+        patterns: Vec<regex::Regex>,
+        #[serde(default = "mask_replacement_default")]
+        replacement: String,
+        #[serde(default)]
+        hash: bool,
+        #[serde(default)]
+        salt: String,
+    },
+    /**
+     * Set the Kafka record key, from the rendered template, for the subsequent Forward actions of
+     * the rule
+     */
+    #[serde(alias = "set_key")]
+    SetKey {
+        template: String,
+    },
+    Stop,
+    Drop,
+    /**
+     * Attach the tags to the message, which the rules evaluated afterwards can match on with
+     * `tagged`
+     */
+    Tag {
+        tags: Vec<String>,
+    },
+    /**
+     * Only continue processing a fraction of the messages, either with a `rate` between 0 and 1,
+     * or one in every `one_in` messages, the rest are dropped
+     */
+    Sample {
+        #[serde(default = "default_none")]
+        rate: Option<f64>,
+        #[serde(default = "default_none")]
+        one_in: Option<u64>,
+    },
+    /**
+     * Suppress messages with the same `key`, by default the output, for `window_ms` after the
+     * first one. Once the window ends, the `summary` of how many were suppressed is sent to the
+     * `topic`
+     */
+    Dedupe {
+        #[serde(default = "default_none")]
+        key: Option<String>,
+        #[serde(default = "dedupe_window_default")]
+        window_ms: u64,
+        topic: String,
+        #[serde(default = "dedupe_summary_default")]
+        summary: String,
+    },
+    /**
+     * Count the messages for each `key`, and sum their `value` when given, over tumbling windows
+     * of `window_ms`. Once a window ends, a rollup event is sent to the `topic`
+     */
+    Aggregate {
+        #[serde(default = "default_none")]
+        key: Option<String>,
+        #[serde(default = "default_none")]
+        value: Option<String>,
+        #[serde(default = "aggregate_window_default")]
+        window_ms: u64,
+        topic: String,
+    },
+    /**
+     * Forward at most `limit` messages for each `key` in every `interval_ms`, suppressing the
+     * rest. Once the interval ends, the `summary` of how many were suppressed is sent to the
+     * `topic`
+     */
+    Throttle {
+        #[serde(default = "default_none")]
+        key: Option<String>,
+        limit: u64,
+        #[serde(default = "throttle_interval_default")]
+        interval_ms: u64,
+        topic: String,
+        #[serde(default = "throttle_summary_default")]
+        summary: String,
+    },
+    /**
+     * Limit the messages for each `key`, a template such as `{{hostname}}`, to `per_second`
+     * messages per second with bursts of up to `burst` messages, the rest are dropped
+     */
+    #[serde(alias = "rate_limit")]
+    RateLimit {
+        #[serde(default = "default_none")]
+        key: Option<String>,
+        per_second: f64,
+        #[serde(default = "default_none")]
+        burst: Option<f64>,
+    },
+}
+
+impl Action {
+    fn populate_caches(&mut self, regex_bytes: usize) -> Result<(), String> {
+        match self {
+            Action::Mask {
+                sources, patterns, ..
+            } => {
+                *patterns = sources
+                    .iter()
+                    .map(|source| compile_regex(source, regex_bytes))
+                    .collect::<Result<_, _>>()?;
+            }
+            Action::Merge { json, json_str } => {
+                *json_str = Some(
+                    serde_json::to_string(json)
+                        .map_err(|e| format!("Failed to serialize Merge action: {}", e))?,
+                );
+            }
+            Action::Enrich { table, entries, .. } => {
+                *entries = crate::lookup::load(table)?;
+            }
+            Action::Decode {
+                variable: Some(_),
+                pointer: Some(_),
+                ..
+            } => {
+                return Err(
+                    "A decode action cannot have both a `variable` and a `pointer`".to_string(),
+                );
+            }
+            #[cfg(not(feature = "schema"))]
+            Action::Validate { .. } => {
+                return Err(
+                    "Validate actions require hotdog to be built with the `schema` feature"
+                        .to_string(),
+                );
+            }
+            Action::Dedupe { window_ms: 0, .. } => {
+                return Err("A dedupe action must have a `window_ms` greater than 0".to_string());
+            }
+            Action::Throttle { interval_ms: 0, .. } => {
+                return Err(
+                    "A throttle action must have an `interval_ms` greater than 0".to_string(),
+                );
+            }
+            Action::RemapSeverity { map, .. } => {
+                for severity in map.values() {
+                    if crate::rules::numeric_value(&Field::Severity, severity).is_none() {
+                        return Err(format!(
+                            "`{}` is not a valid severity to remap to, expected a syslog severity such as `warning`",
+                            severity
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Encodings which the decode action can decode
+ */
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Encoding {
+    Base64,
+    /**
+     * Percent encoding, as used in URLs and form data
+     */
+    Url,
+}
+
+/**
+ * Formats which timestamps can be normalized into
+ */
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TimestampFormat {
+    /**
+     * ISO 8601 in UTC with millisecond precision, e.g. `2020-04-18T22:16:09.956Z`
+     */
+    Iso8601,
+    /**
+     * Milliseconds since the Unix epoch
+     */
+    #[serde(alias = "epoch_millis")]
+    EpochMillis,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat::Iso8601
+    }
+}
+
+/**
+ * Kinds of metrics which can be emitted by the metric action
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MetricKind {
+    /**
+     * Increment a counter by the value, or by one
+     */
+    Counter,
+    /**
+     * Set a gauge to the value
+     */
+    Gauge,
+    /**
+     * Record the value as a timer, in milliseconds
+     */
+    Timer,
+}
+
+impl Default for MetricKind {
+    fn default() -> Self {
+        MetricKind::Counter
+    }
+}
+
+/**
+ * Comparison operators supported by the `compare` matcher
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Lt,
+    Lte,
+    Eq,
+    Ne,
+    Gte,
+    Gt,
+}
+
+/**
+ * A Comparison is deserialized from a string such as `<= warning` and compares the numeric value
+ * of a field against the given value
+ */
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Comparison {
+    pub op: Operator,
+    pub value: String,
+}
+
+impl Comparison {
+    /**
+     * Compare the left hand side with the right hand side of the comparison
+     */
+    pub fn compare(&self, lhs: f64, rhs: f64) -> bool {
+        match self.op {
+            Operator::Lt => lhs < rhs,
+            Operator::Lte => lhs <= rhs,
+            Operator::Eq => lhs == rhs,
+            Operator::Ne => lhs != rhs,
+            Operator::Gte => lhs >= rhs,
+            Operator::Gt => lhs > rhs,
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for Comparison {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let s = s.trim();
+        /*
+         * The two character operators must be checked first so that `<=` isn't mistaken for `<`
+         */
+        let operators = [
+            ("<=", Operator::Lte),
+            (">=", Operator::Gte),
+            ("==", Operator::Eq),
+            ("!=", Operator::Ne),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+            ("=", Operator::Eq),
+        ];
+
+        for (prefix, op) in operators.iter() {
+            if let Some(value) = s.strip_prefix(prefix) {
+                let value = value.trim();
+                if value.is_empty() {
+                    break;
+                }
+                return Ok(Comparison {
+                    op: *op,
+                    value: value.to_string(),
+                });
+            }
+        }
+        Err(format!(
+            "`{}` is not a valid comparison, expected something like `<= warning`",
+            s
+        ))
+    }
+}
+
+/**
+ * Compile the regular expression, rejecting it when its compiled program would be larger than
+ * the `regex_bytes`
+ */
+fn compile_regex(pattern: &str, regex_bytes: usize) -> Result<regex::Regex, String> {
+    regex::RegexBuilder::new(pattern)
+        .size_limit(regex_bytes)
+        .build()
+        .map_err(|e| format!("Failed to compile the regex `{}`: {}", pattern, e))
+}
+
+/**
+ * A Condition determines whether a rule matches a message, either by applying a matcher to a
+ * single field, or by combining other conditions with `all`, `any`, and `none`.
+ *
+ * When more than one of these are present, all of them must be satisfied
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct Condition {
+    #[serde(default = "default_none")]
+    pub field: Option<Field>,
+    /**
+     * Fields which the matcher is applied to in turn, matching when it matches any one of them.
+     * The captures from every field which matched are combined
+     */
+    #[serde(default)]
+    pub fields: Vec<Field>,
+    /**
+     * Invert the matcher, so that the condition matches when the matcher does not
+     */
+    #[serde(default)]
+    pub negate: bool,
+    /**
+     * Tag which must have been attached to the message by the `tag` action of a previous rule
+     */
+    #[serde(default = "default_none")]
+    pub tagged: Option<String>,
+    #[serde(rename = "regex", default = "default_none")]
+    pub pattern: Option<String>,
+    /**
+     * The compiled `regex`, or the expanded `grok` pattern, which is compiled when the settings
+     * are loaded
+     */
+    #[serde(skip)]
+    pub regex: Option<regex::Regex>,
+    /**
+     * Grok pattern, e.g. `%{IP:client} %{WORD:method}`, which is expanded into the `regex` when the
+     * settings are loaded
+     */
+    #[serde(default = "default_none")]
+    pub grok: Option<String>,
+    #[serde(default = "default_none")]
+    pub jmespath: Option<String>,
+    /**
+     * JSON pointer, e.g. `/kubernetes/namespace`, to look up in JSON messages
+     */
+    #[serde(default = "default_none")]
+    pub pointer: Option<String>,
+    /**
+     * Value which the result of the `jmespath` or `pointer` matcher must equal for the rule to
+     * match, otherwise the value which the field must equal
+     */
+    #[serde(default = "default_none")]
+    pub equals: Option<String>,
+    #[serde(default = "default_none")]
+    pub compare: Option<Comparison>,
+    #[serde(default = "default_none")]
+    pub contains: Option<String>,
+    #[serde(default = "default_none")]
+    pub starts_with: Option<String>,
+    #[serde(default = "default_none")]
+    pub ends_with: Option<String>,
+    /**
+     * Conditions which must all match
+     */
+    #[serde(default)]
+    pub all: Vec<Condition>,
+    /**
+     * Conditions of which at least one must match
+     */
+    #[serde(default)]
+    pub any: Vec<Condition>,
+    /**
+     * Conditions of which none may match
+     */
+    #[serde(default)]
+    pub none: Vec<Condition>,
+}
+
+impl Condition {
+    /**
+     * Return true if the condition has neither a field to match on nor any sub-conditions
+     */
+    pub fn is_empty(&self) -> bool {
+        self.field.is_none()
+            && self.fields.is_empty()
+            && self.tagged.is_none()
+            && self.all.is_empty()
+            && self.any.is_empty()
+            && self.none.is_empty()
+    }
+
+    /**
+     * Return all the JMESPath expressions used by this condition and its sub-conditions
+     */
+    pub fn jmespaths(&self) -> Vec<&String> {
+        let mut expressions = vec![];
+
+        if let Some(expression) = &self.jmespath {
+            expressions.push(expression);
+        }
+
+        for condition in self.all.iter().chain(&self.any).chain(&self.none) {
+            expressions.append(&mut condition.jmespaths());
+        }
+        expressions
+    }
+
+    /**
+     * Compile the regular expressions of this condition and its sub-conditions, expanding the grok
+     * patterns into regular expressions first
+     */
+    fn populate_caches(
+        &mut self,
+        patterns: &HashMap<String, String>,
+        regex_bytes: usize,
+    ) -> Result<(), String> {
+        if let Some(grok) = &self.grok {
+            if self.pattern.is_some() {
+                return Err(
+                    "A condition cannot have both a `grok` and a `regex` matcher".to_string(),
+                );
+            }
+            let expanded = crate::grok::expand(grok, patterns)
+                .map_err(|e| format!("Failed to expand the grok pattern: {}", e))?;
+            let regex = compile_regex(&expanded, regex_bytes)
+                .map_err(|e| format!("The grok pattern `{}`: {}", grok, e))?;
+            self.regex = Some(regex);
+        } else if let Some(pattern) = &self.pattern {
+            self.regex = Some(compile_regex(pattern, regex_bytes)?);
+        }
+
+        for condition in self
+            .all
+            .iter_mut()
+            .chain(self.any.iter_mut())
+            .chain(self.none.iter_mut())
+        {
+            condition.populate_caches(patterns, regex_bytes)?;
+        }
+        Ok(())
+    }
+
+    fn fmt_matcher(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if let Some(grok) = &self.grok {
+            write!(f, "Grok: {}", grok)
+        } else if let Some(pattern) = &self.pattern {
+            write!(f, "Regex: {}", pattern)
+        } else if let Some(jmespath) = &self.jmespath {
+            write!(f, "JMESPath: {}", jmespath)?;
+            if let Some(equals) = &self.equals {
+                write!(f, " == {}", equals)?;
+            }
+            Ok(())
+        } else if let Some(pointer) = &self.pointer {
+            write!(f, "Pointer: {}", pointer)?;
+            if let Some(equals) = &self.equals {
+                write!(f, " == {}", equals)?;
+            }
+            Ok(())
+        } else if let Some(compare) = &self.compare {
+            write!(f, "Compare: ")?;
+            self.fmt_fields(f)?;
+            write!(f, " {:?} {}", compare.op, compare.value)
+        } else if let Some(contains) = &self.contains {
+            write!(f, "Contains: {}", contains)
+        } else if let Some(starts_with) = &self.starts_with {
+            write!(f, "Starts with: {}", starts_with)
+        } else if let Some(ends_with) = &self.ends_with {
+            write!(f, "Ends with: {}", ends_with)
+        } else if let Some(equals) = &self.equals {
+            write!(f, "Equals: {}", equals)
+        } else {
+            write!(f, "Field: ")?;
+            self.fmt_fields(f)
+        }
+    }
+
+    fn fmt_fields(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if self.fields.is_empty() {
+            write!(f, "{:?}", self.field)
+        } else {
+            write!(f, "{:?}", self.fields)
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let mut first = true;
+
+        if self.field.is_some() || !self.fields.is_empty() {
+            if self.negate {
+                write!(f, "Not ")?;
+            }
+            self.fmt_matcher(f)?;
+            first = false;
+        }
+
+        if let Some(tag) = &self.tagged {
+            if !first {
+                write!(f, " and ")?;
+            }
+            write!(f, "Tagged: {}", tag)?;
+            first = false;
+        }
+
+        for (name, conditions) in
+            [("all", &self.all), ("any", &self.any), ("none", &self.none)].iter()
+        {
+            if conditions.is_empty() {
+                continue;
+            }
+            if !first {
+                write!(f, " and ")?;
+            }
+            first = false;
+
+            write!(f, "{}(", name)?;
+            for (index, condition) in conditions.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", condition)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * An action of a rule, which is only performed when its optional `when` condition matches
+ */
+#[derive(Debug, Deserialize)]
+pub struct RuleAction {
+    #[serde(flatten)]
+    pub action: Action,
+    #[serde(default = "default_none")]
+    pub when: Option<Condition>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    #[serde(skip_serializing, skip_deserializing, default = "default_uuid")]
+    pub uuid: Uuid,
+    /**
+     * Optional name of the rule, used for reporting metrics about the rule
+     */
+    #[serde(default = "default_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub condition: Condition,
+    pub actions: Vec<RuleAction>,
+    /**
+     * Rules with a higher priority are evaluated first, rules with the same priority are evaluated
+     * in the order they are defined
+     */
+    #[serde(default)]
+    pub priority: i64,
+    /**
+     * Log every `debug_sample`th message matched by the rule, along with what the rule did with
+     * it, at the info level
+     */
+    #[serde(default = "default_none")]
+    pub debug_sample: Option<u64>,
+    /**
+     * Optional name of the ruleset the rule belongs to, only the listeners serving that ruleset
+     * evaluate the rule. Rules without a ruleset are evaluated by the listeners without one
+     */
+    #[serde(default = "default_none")]
+    pub ruleset: Option<String>,
+    /**
+     * The number of messages the rule has matched, used for the debug samples
+     */
+    #[serde(skip)]
+    matches: std::sync::atomic::AtomicU64,
+}
+
+impl Rule {
+    /**
+     * Count a message matched by the rule, returning true if it should be logged as a debug
+     * sample
+     */
+    pub fn debug_sampled(&self) -> bool {
+        match self.debug_sample {
+            Some(every) if every > 0 => {
+                self.matches
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                    % every
+                    == 0
+            }
+            _ => false,
+        }
+    }
+
+    fn populate_caches(
+        &mut self,
+        patterns: &HashMap<String, String>,
+        regex_bytes: usize,
+    ) -> Result<(), String> {
+        self.condition.populate_caches(patterns, regex_bytes)?;
+
+        for (index, action) in self.actions.iter_mut().enumerate() {
+            action
+                .action
+                .populate_caches(regex_bytes)
+                .map_err(|e| format!("`actions[{}]`: {}", index, e))?;
+
+            if let Some(when) = &mut action.when {
+                when.populate_caches(patterns, regex_bytes)
+                    .map_err(|e| format!("`actions[{}].when`: {}", index, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if let Some(name) = &self.name {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "{}", self.condition)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum TlsType {
+    None,
+
+    /**
+     * The CertsAndKey struct will contain PathBuf values if the tls section was
+     * included
+     */
+    CertAndKey {
+        cert: std::path::PathBuf,
+        key: std::path::PathBuf,
+        ca: Option<std::path::PathBuf>,
+    },
+}
+
+impl Default for TlsType {
+    fn default() -> TlsType {
+        TlsType::None
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Listen {
+    pub address: String,
+    pub port: u64,
+    #[serde(default)]
+    pub tls: TlsType,
+    /**
+     * The ruleset whose rules are evaluated for the connections to this listener
+     */
+    #[serde(default = "default_none")]
+    pub ruleset: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Kafka {
+    #[serde(default = "kafka_buffer_default")]
+    pub buffer: usize,
+    #[serde(default = "kafka_timeout_default")]
+    pub timeout_ms: Duration,
+    pub conf: HashMap<String, String>,
+    #[serde(default = "kafka_topic_default")]
+    pub topic: String,
+    #[serde(default = "kafka_retries_default")]
+    pub retries: u32,
+    #[serde(default = "kafka_retry_backoff_default")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_none")]
+    pub dlq: Option<String>,
+    /**
+     * The topic which hotdog publishes its own lifecycle events to
+     */
+    #[serde(default = "default_none")]
+    pub events: Option<String>,
+    #[serde(default)]
+    pub transactional: bool,
+    /**
+     * Whether the messages of every connection are produced in the order their lines were read,
+     * by waiting for each of them to be delivered before sending the next
+     */
+    #[serde(default)]
+    pub preserve_order: bool,
+    #[serde(default)]
+    pub verify_topics: TopicVerification,
+    #[serde(default = "kafka_max_in_flight_default")]
+    pub max_in_flight: usize,
+    #[serde(default)]
+    pub batch: Batch,
+    #[serde(default = "default_true")]
+    pub require_kafka_at_start: bool,
+    #[serde(default = "kafka_flush_timeout_default")]
+    pub flush_timeout_ms: u64,
+    #[serde(default = "kafka_max_message_bytes_default")]
+    pub max_message_bytes: usize,
+    #[serde(default)]
+    pub oversize: OversizePolicy,
+    #[serde(default = "kafka_truncation_marker_default")]
+    pub truncation_marker: String,
+    /**
+     * How long a message may wait for Kafka to acknowledge it before the `send_timeout_policy`
+     * is applied, rather than leaving a stuck delivery in flight
+     */
+    #[serde(default = "default_none")]
+    pub send_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub send_timeout_policy: SendTimeoutPolicy,
+    #[serde(default = "kafka_topic_replacement_default")]
+    pub topic_replacement: String,
+    #[serde(default = "kafka_statistics_interval_default")]
+    pub statistics_interval_ms: u64,
+    #[serde(default)]
+    pub producer: Producer,
+    /**
+     * The spool on disk which the messages are written to while Kafka cannot be reached, and
+     * replayed from once it can
+     */
+    #[serde(default = "default_none")]
+    pub spool: Option<Spool>,
+    /**
+     * Map of logical topic names, which may be used by rules, to the actual Kafka topics
+     */
+    #[serde(default)]
+    pub topics: HashMap<String, String>,
+}
+
+/**
+ * What hotdog should do with messages which are larger than the maximum message size that the
+ * Kafka brokers will accept
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OversizePolicy {
+    /**
+     * Truncate the message and append a marker
+     */
+    Truncate,
+    /**
+     * Split the message into multiple messages
+     */
+    Split,
+    /**
+     * Send the message to the dead letter topic
+     */
+    Dlq,
+    /**
+     * Drop the message, counting it in the metrics
+     */
+    Drop,
+}
+
+impl Default for OversizePolicy {
+    fn default() -> OversizePolicy {
+        OversizePolicy::Drop
+    }
+}
+
+/**
+ * What hotdog should do with messages which have not been acknowledged by Kafka within the
+ * `send_timeout_ms`
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SendTimeoutPolicy {
+    /**
+     * Retry the message like any other retriable failure, spooling it or sending it to the dead
+     * letter topic once its retries are exhausted
+     */
+    Retry,
+    /**
+     * Write the message to the spool straight away, if there is one
+     */
+    Spool,
+    /**
+     * Drop the message, counting it in the metrics
+     */
+    Drop,
+}
+
+impl Default for SendTimeoutPolicy {
+    fn default() -> SendTimeoutPolicy {
+        SendTimeoutPolicy::Retry
+    }
+}
+
+/**
+ * What hotdog should do with lines which cannot be parsed as syslog messages
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ParseErrorPolicy {
+    /**
+     * Drop the line, counting it in the metrics
+     */
+    Drop,
+    /**
+     * Send the line as it was received to the dead letter topic
+     */
+    Dlq,
+    /**
+     * Evaluate the rules against the line as if the entire line was the `msg`
+     */
+    Raw,
+    /**
+     * Close the connection which sent the line
+     */
+    Disconnect,
+}
+
+impl Default for ParseErrorPolicy {
+    fn default() -> ParseErrorPolicy {
+        ParseErrorPolicy::Drop
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ParseErrors {
+    #[serde(default)]
+    pub policy: ParseErrorPolicy,
+    #[serde(default)]
+    pub invalid_utf8: InvalidUtf8Policy,
+}
+
+/**
+ * What hotdog should do with lines which are not valid UTF-8
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum InvalidUtf8Policy {
+    /**
+     * Handle the line with the parse error policy, since it cannot be parsed
+     */
+    Error,
+    /**
+     * Replace the invalid bytes with replacement characters and parse the line as usual
+     */
+    Lossy,
+    /**
+     * Send the line as it was received to the default topic, without evaluating any rules
+     */
+    Raw,
+}
+
+impl Default for InvalidUtf8Policy {
+    fn default() -> InvalidUtf8Policy {
+        InvalidUtf8Policy::Error
+    }
+}
+
+/**
+ * The limits which protect hotdog from pathological input
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Limits {
+    /**
+     * Lines longer than this are discarded, without buffering them any further
+     */
+    #[serde(default = "limits_max_line_bytes_default")]
+    pub max_line_bytes: usize,
+    /**
+     * The size of the read buffer of every connection
+     */
+    #[serde(default = "limits_buffer_bytes_default")]
+    pub buffer_bytes: usize,
+    /**
+     * The largest output the actions of a rule may build for a line
+     */
+    #[serde(default = "limits_max_message_bytes_default")]
+    pub max_message_bytes: usize,
+    /**
+     * The largest number of variables, such as the named captures, a line may have
+     */
+    #[serde(default = "limits_max_variables_default")]
+    pub max_variables: usize,
+    /**
+     * The number of lines of a connection whose messages may be waiting to be sent, before the
+     * connection stops reading any further lines
+     */
+    #[serde(default = "limits_dispatch_queue_default")]
+    pub dispatch_queue: usize,
+    /**
+     * The number of tasks of every connection which send its messages to Kafka
+     */
+    #[serde(default = "limits_dispatchers_default")]
+    pub dispatchers: usize,
+    /**
+     * How full the queue of the default Kafka producer, or the dispatch queue of a connection, may
+     * get before the connection stops reading from its socket until it has drained
+     */
+    #[serde(default = "limits_pause_ratio_default")]
+    pub pause_ratio: f64,
+    /**
+     * The largest compiled program of a regular expression in the rules, which rejects patterns
+     * whose repetitions would make them enormous
+     */
+    #[serde(default = "limits_max_regex_bytes_default")]
+    pub max_regex_bytes: usize,
+    /**
+     * The largest size of the messages a connection may have queued for its dispatchers, before
+     * the messages of its further lines are shed
+     */
+    #[serde(default = "limits_max_inflight_bytes_default")]
+    pub max_inflight_bytes: usize,
+    #[serde(default)]
+    pub inflight_policy: InflightPolicy,
+    #[serde(default)]
+    pub long_lines: LongLinePolicy,
+    /**
+     * The marker appended to lines which have been truncated by the `truncate` policy
+     */
+    #[serde(default = "limits_truncation_marker_default")]
+    pub truncation_marker: String,
+    #[serde(default)]
+    pub partial_lines: PartialLinePolicy,
+}
+
+/**
+ * What hotdog should do with lines which are longer than the `max_line_bytes`
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LongLinePolicy {
+    /**
+     * Drop the line, counting it in the metrics
+     */
+    Drop,
+    /**
+     * Truncate the line to `max_line_bytes` and append a marker, before parsing it as usual
+     */
+    Truncate,
+}
+
+impl Default for LongLinePolicy {
+    fn default() -> LongLinePolicy {
+        LongLinePolicy::Drop
+    }
+}
+
+/**
+ * What hotdog should do with the bytes of a line which the connection did not end, because it was
+ * closed or failed in the middle of the line
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PartialLinePolicy {
+    /**
+     * Parse the bytes as if the line had been ended
+     */
+    Parse,
+    /**
+     * Drop the bytes, counting them in the metrics
+     */
+    Drop,
+}
+
+impl Default for PartialLinePolicy {
+    fn default() -> PartialLinePolicy {
+        PartialLinePolicy::Parse
+    }
+}
+
+/**
+ * What hotdog should do with a connection whose messages exceed the `max_inflight_bytes`
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum InflightPolicy {
+    /**
+     * Drop the messages of the line, counting them in the metrics
+     */
+    Drop,
+    /**
+     * Close the connection which sent the line
+     */
+    Disconnect,
+}
+
+impl Default for InflightPolicy {
+    fn default() -> InflightPolicy {
+        InflightPolicy::Drop
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_line_bytes: limits_max_line_bytes_default(),
+            buffer_bytes: limits_buffer_bytes_default(),
+            max_message_bytes: limits_max_message_bytes_default(),
+            max_variables: limits_max_variables_default(),
+            dispatch_queue: limits_dispatch_queue_default(),
+            dispatchers: limits_dispatchers_default(),
+            pause_ratio: limits_pause_ratio_default(),
+            max_regex_bytes: limits_max_regex_bytes_default(),
+            max_inflight_bytes: limits_max_inflight_bytes_default(),
+            inflight_policy: InflightPolicy::default(),
+            long_lines: LongLinePolicy::default(),
+            truncation_marker: limits_truncation_marker_default(),
+            partial_lines: PartialLinePolicy::default(),
+        }
+    }
+}
+
+impl Limits {
+    /**
+     * Return a description of the limit which the output or the number of variables captured or
+     * set by the rule exceed, if any
+     */
+    pub fn exceeded(&self, output: &str, variables: usize) -> Option<String> {
+        if output.len() > self.max_message_bytes {
+            return Some(format!(
+                "the output of {} bytes exceeds `max_message_bytes`",
+                output.len()
+            ));
+        }
+
+        if variables > self.max_variables {
+            return Some(format!(
+                "the {} variables exceed `max_variables`",
+                variables
+            ));
+        }
+        None
+    }
+}
+
+impl Kafka {
+    /**
+     * Resolve a topic name referenced by a rule into the actual Kafka topic, using the `topics`
+     * aliases when the name is a logical topic
+     */
+    pub fn resolve_topic<'a>(&'a self, name: &'a str) -> &'a str {
+        self.topics.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    /**
+     * Generate the full librdkafka configuration, which is the `conf` map with the batching
+     * settings applied underneath it, so that explicit `conf` values always win
+     */
+    pub fn rdkafka_conf(&self) -> HashMap<String, String> {
+        let mut conf = HashMap::new();
+        conf.insert("linger.ms".to_string(), self.batch.linger_ms.to_string());
+        conf.insert(
+            "batch.num.messages".to_string(),
+            self.batch.num_messages.to_string(),
+        );
+        conf.insert(
+            "queue.buffering.max.messages".to_string(),
+            self.batch.max_messages.to_string(),
+        );
+        conf.insert(
+            "queue.buffering.max.kbytes".to_string(),
+            self.batch.max_kbytes.to_string(),
+        );
+        conf.insert(
+            "statistics.interval.ms".to_string(),
+            self.statistics_interval_ms.to_string(),
+        );
+
+        let overrides = vec![
+            ("reconnect.backoff.ms", self.producer.reconnect_backoff_ms),
+            (
+                "reconnect.backoff.max.ms",
+                self.producer.reconnect_backoff_max_ms,
+            ),
+            ("message.timeout.ms", self.producer.message_timeout_ms),
+            (
+                "message.send.max.retries",
+                self.producer.message_send_max_retries,
+            ),
+        ];
+
+        for (key, value) in overrides.into_iter() {
+            if let Some(value) = value {
+                conf.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        for (key, value) in self.conf.iter() {
+            conf.insert(key.to_string(), value.to_string());
+        }
+        conf
+    }
+}
+
+/**
+ * Settings governing how the Kafka producer behaves when the brokers are unavailable. Unless set,
+ * the librdkafka defaults will be used for everything but the queue_timeout_ms
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Producer {
+    #[serde(default = "default_none")]
+    pub reconnect_backoff_ms: Option<u64>,
+    #[serde(default = "default_none")]
+    pub reconnect_backoff_max_ms: Option<u64>,
+    #[serde(default = "default_none")]
+    pub message_timeout_ms: Option<u64>,
+    #[serde(default = "default_none")]
+    pub message_send_max_retries: Option<u64>,
+    /**
+     * How long hotdog will wait for room in the producer's queue before giving up on a message
+     */
+    #[serde(default = "producer_queue_timeout_default")]
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for Producer {
+    fn default() -> Producer {
+        Producer {
+            reconnect_backoff_ms: None,
+            reconnect_backoff_max_ms: None,
+            message_timeout_ms: None,
+            message_send_max_retries: None,
+            queue_timeout_ms: producer_queue_timeout_default(),
+        }
+    }
+}
+
+/**
+ * Batching settings for the Kafka producer, the defaults are tuned for forwarding a high volume
+ * of small log messages
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Batch {
+    #[serde(default = "batch_linger_ms_default")]
+    pub linger_ms: u64,
+    #[serde(default = "batch_num_messages_default")]
+    pub num_messages: u64,
+    #[serde(default = "batch_max_messages_default")]
+    pub max_messages: u64,
+    #[serde(default = "batch_max_kbytes_default")]
+    pub max_kbytes: u64,
+    /**
+     * How long the sendloop collects the messages of a topic before handing them to the producer
+     * together, zero hands every message over as soon as it arrives
+     */
+    #[serde(default)]
+    pub collect_ms: u64,
+    /**
+     * The number of messages of a topic which are handed to the producer together at most
+     */
+    #[serde(default = "batch_collect_messages_default")]
+    pub collect_messages: usize,
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch {
+            linger_ms: batch_linger_ms_default(),
+            num_messages: batch_num_messages_default(),
+            max_messages: batch_max_messages_default(),
+            max_kbytes: batch_max_kbytes_default(),
+            collect_ms: 0,
+            collect_messages: batch_collect_messages_default(),
+        }
+    }
+}
+
+/**
+ * The write-ahead spool of a Kafka producer, which keeps the messages that could not be delivered
+ * because the brokers were unreachable or the producer's queue stayed full
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Spool {
+    /**
+     * The directory which the spools are written to, every producer spools to a directory of its
+     * own inside of it
+     */
+    pub path: std::path::PathBuf,
+    /**
+     * The size of the spool on disk, messages which do not fit are dropped
+     */
+    #[serde(default = "spool_max_bytes_default")]
+    pub max_bytes: u64,
+    /**
+     * How often hotdog checks whether Kafka can be reached again while there are spooled messages
+     */
+    #[serde(default = "spool_replay_interval_ms_default")]
+    pub replay_interval_ms: u64,
+    /**
+     * Whether the messages which were replayed before hotdog stopped are skipped when the replay
+     * is resumed
+     */
+    #[serde(default = "default_true")]
+    pub dedupe: bool,
+}
+
+/**
+ * How hotdog should react at startup when topics referenced by the configuration do not exist on
+ * the Kafka cluster
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TopicVerification {
+    Off,
+    Warn,
+    Fail,
+}
+
+impl Default for TopicVerification {
+    fn default() -> TopicVerification {
+        TopicVerification::Off
+    }
+}
+
+/**
+ * Global settings for how the rules are evaluated
+ */
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RulesConfig {
+    #[serde(default)]
+    pub mode: RulesMode,
+}
+
+/**
+ * How many of the rules may match a single message
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RulesMode {
+    /**
+     * Every rule is evaluated, until one stops the processing with a stop, drop, or forward
+     */
+    All,
+    /**
+     * No further rules are evaluated once a rule has matched, as with rsyslog
+     */
+    #[serde(alias = "first_match")]
+    FirstMatch,
+}
+
+impl Default for RulesMode {
+    fn default() -> RulesMode {
+        RulesMode::All
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Metrics {
+    #[serde(default, rename = "type")]
+    pub output: MetricsOutput,
+    /**
+     * The prefix of the names of every metric, e.g. `hotdog.lines`
+     */
+    #[serde(default = "metrics_prefix_default")]
+    pub prefix: String,
+    #[serde(default = "metrics_statsd_default")]
+    pub statsd: String,
+    /**
+     * The host and port of the Graphite plaintext protocol, for the `graphite` type
+     */
+    #[serde(default = "metrics_graphite_default")]
+    pub graphite: String,
+    /**
+     * The format of the tags of the statsd metrics, such as the topic or the rule, which are part
+     * of the names of the metrics when the metrics are not tagged
+     */
+    #[serde(default)]
+    pub tag_format: TagFormat,
+    /**
+     * Tags which are added to every statsd metric when they are tagged, e.g. the host
+     */
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /**
+     * The number of topics which get metrics of their own, the metrics of any further topics are
+     * reported for the `_other` topic
+     */
+    #[serde(default = "metrics_max_topics_default")]
+    pub max_topics: usize,
+    /**
+     * The number of peers which get metrics of their own, the metrics of any further peers are
+     * reported for the `_other` peer
+     */
+    #[serde(default = "metrics_max_peers_default")]
+    pub max_peers: usize,
+    /**
+     * The number of names which the metric actions get metrics of their own for, the metrics of
+     * any further names are reported for the `_other` name
+     */
+    #[serde(default = "metrics_max_metrics_default")]
+    pub max_metrics: usize,
+    /**
+     * When set, the metrics are aggregated in memory and only sent every interval
+     */
+    #[serde(default = "default_none")]
+    pub flush_interval_ms: Option<u64>,
+    /**
+     * The fraction of the statsd metrics which are sent, statsd scales the sampled counters back
+     * up by it
+     */
+    #[serde(default = "metrics_sample_rate_default")]
+    pub sample_rate: f64,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics {
+            output: MetricsOutput::default(),
+            prefix: metrics_prefix_default(),
+            statsd: metrics_statsd_default(),
+            graphite: metrics_graphite_default(),
+            tag_format: TagFormat::default(),
+            tags: HashMap::new(),
+            max_topics: metrics_max_topics_default(),
+            max_peers: metrics_max_peers_default(),
+            max_metrics: metrics_max_metrics_default(),
+            flush_interval_ms: None,
+            sample_rate: metrics_sample_rate_default(),
+        }
+    }
+}
+
+impl Metrics {
+    /**
+     * Return where the metrics are sent, for the logs
+     */
+    pub fn destination(&self) -> &str {
+        match self.output {
+            MetricsOutput::Statsd => &self.statsd,
+            MetricsOutput::Graphite => &self.graphite,
+            MetricsOutput::Log => "the log",
+            MetricsOutput::Stdout => "stdout",
+            MetricsOutput::None => "nowhere",
+        }
+    }
+}
+
+/**
+ * Where hotdog's own metrics are sent
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum MetricsOutput {
+    Statsd,
+    /**
+     * The Graphite plaintext protocol
+     */
+    Graphite,
+    /**
+     * hotdog's own log, at the info level
+     */
+    Log,
+    Stdout,
+    /**
+     * No metrics are sent at all
+     */
+    None,
+}
+
+impl Default for MetricsOutput {
+    fn default() -> MetricsOutput {
+        MetricsOutput::Statsd
+    }
+}
+
+/**
+ * The statsd extensions for tagging metrics
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TagFormat {
+    None,
+    /**
+     * `name:1|c|#tag:value`
+     */
+    Dogstatsd,
+    /**
+     * `name,tag=value:1|c`
+     */
+    Influx,
+}
+
+impl Default for TagFormat {
+    fn default() -> TagFormat {
+        TagFormat::None
+    }
+}
+
+/**
+ * The settings for hotdog's own logging
+ */
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Log {
+    /**
+     * The level to log at, e.g. `info`, or filters in the format of `RUST_LOG` such as
+     * `hotdog=debug,rdkafka=warn`. When it is not set, only errors are logged
+     */
+    #[serde(default = "default_none")]
+    pub level: Option<String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    /**
+     * The file to append the logs to rather than writing them to stderr, e.g. for a Windows
+     * service whose output is discarded
+     */
+    #[serde(default = "default_none")]
+    pub file: Option<std::path::PathBuf>,
+}
+
+/**
+ * The formats which hotdog's own logs can be written in
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    /**
+     * Human readable lines
+     */
+    Text,
+    /**
+     * A JSON object on every line, for log collectors
+     */
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> LogFormat {
+        LogFormat::Text
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Status {
+    pub address: String,
+    pub port: u64,
+}
+
+/**
+ * The settings of the async executor which hotdog runs on, unless they are set the executor's own
+ * defaults are used
+ */
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Runtime {
+    /**
+     * The number of threads which run hotdog's tasks, which defaults to the number of CPUs
+     */
+    #[serde(default = "default_none")]
+    pub worker_threads: Option<usize>,
+    /**
+     * The largest number of threads for blocking work, such as reading files
+     */
+    #[serde(default = "default_none")]
+    pub blocking_threads: Option<usize>,
+    /**
+     * The CPUs which every thread of hotdog is pinned to, on Linux
+     */
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+}
+
+/**
+ * The settings of how hotdog shuts down once it has been asked to with SIGTERM or SIGINT
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Shutdown {
+    /**
+     * How long the open connections have to finish the lines they have already read, before
+     * hotdog flushes the messages to Kafka and exits regardless
+     */
+    #[serde(default = "shutdown_grace_ms_default")]
+    pub grace_ms: u64,
+    /**
+     * How long the connections have to close by themselves once the listeners have been handed
+     * over to a restarted hotdog, before they are drained
+     */
+    #[serde(default = "shutdown_handover_ms_default")]
+    pub handover_ms: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown {
+            grace_ms: shutdown_grace_ms_default(),
+            handover_ms: shutdown_handover_ms_default(),
+        }
+    }
+}
+
+/**
+ * The settings for exporting the spans of every line to an OpenTelemetry collector over OTLP
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Tracing {
+    /**
+     * The OTLP/HTTP endpoint of the collector which the spans are exported to
+     */
+    #[serde(default = "tracing_endpoint_default")]
+    pub endpoint: String,
+    #[serde(default = "tracing_service_name_default")]
+    pub service_name: String,
+    /**
+     * The ratio of the lines which are traced, between 0 and 1
+     */
+    #[serde(default = "tracing_ratio_default")]
+    pub ratio: f64,
+}
+
+/**
+ * The settings for the heartbeat messages which are sent to Kafka, so that consumers can tell a
+ * silent hotdog from a silent source of logs
+ */
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Heartbeat {
+    pub topic: String,
+    #[serde(default = "heartbeat_interval_ms_default")]
+    pub interval_ms: u64,
+    /**
+     * The identifier of this hotdog in the heartbeats, which defaults to the name of the host
+     */
+    #[serde(default = "default_none")]
+    pub instance: Option<String>,
+}
+
+/**
+ * What to do with the timestamp of a message which is too far in the future or the past of the
+ * time it was received at
+ */
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SkewedTimestampPolicy {
+    /**
+     * Keep the timestamp, and add the time the message was received at as `received_at`
+     */
+    Annotate,
+    /**
+     * Move the timestamp to the nearest edge of the allowed window
+     */
+    Clamp,
+    /**
+     * Replace the timestamp with the time the message was received at
+     */
+    Rewrite,
+}
+
+impl Default for SkewedTimestampPolicy {
+    fn default() -> Self {
+        SkewedTimestampPolicy::Annotate
+    }
+}
+
+/**
+ * The settings for detecting the timestamps of messages from sources whose clocks are obviously
+ * wrong
+ */
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Timestamps {
+    #[serde(default = "timestamps_max_future_secs_default")]
+    pub max_future_secs: u64,
+    #[serde(default = "timestamps_max_past_secs_default")]
+    pub max_past_secs: u64,
+    #[serde(default)]
+    pub skewed: SkewedTimestampPolicy,
+}
+
+/**
+ * A named destination which rules can forward messages to, in addition to the default Kafka
+ */
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Destination {
+    Kafka(Kafka),
+}
+
+/**
+ * A helper defined in the configuration, which can be used by the templates of every rule, e.g.
+ * `{{team appname}}`
+ */
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum CustomHelper {
+    /**
+     * Map the value to another with the `map`, values which are not in the map are rendered as
+     * the `default`, or unchanged if there is no default
+     */
+    Map {
+        map: HashMap<String, String>,
+        #[serde(default = "default_none")]
+        default: Option<String>,
+    },
+    /**
+     * Render the `template` with the value as the `value` variable
+     */
+    Template { template: String },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Global {
+    pub kafka: Kafka,
+    pub listen: Listen,
+    /**
+     * Additional listeners, which are served alongside `listen`
+     */
+    #[serde(default)]
+    pub listeners: Vec<Listen>,
+    /**
+     * No metrics are sent unless the section is configured
+     */
+    #[serde(default = "global_metrics_default")]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub log: Log,
+    #[serde(default)]
+    pub runtime: Runtime,
+    #[serde(default)]
+    pub shutdown: Shutdown,
+    pub status: Option<Status>,
+    #[serde(default = "default_none")]
+    pub tracing: Option<Tracing>,
+    #[serde(default = "default_none")]
+    pub heartbeat: Option<Heartbeat>,
+    #[serde(default = "default_none")]
+    pub timestamps: Option<Timestamps>,
+    /**
+     * Variables, such as the datacenter, which are available to the templates and conditions of
+     * every rule
+     */
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    #[serde(default)]
+    pub parse_errors: ParseErrors,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub destinations: HashMap<String, Destination>,
+    #[serde(default)]
+    pub rules: RulesConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    pub global: Global,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /**
+     * Rule without a matcher whose actions handle the messages which no other rule matched
+     */
+    #[serde(default = "default_none")]
+    pub default: Option<Rule>,
+    /**
+     * Directory of YAML files with additional rules, which are loaded after the rules of the
+     * configuration file in the order of their file names
+     */
+    #[serde(default = "default_none")]
+    pub rules_d: Option<std::path::PathBuf>,
+    /**
+     * Configuration files which are merged underneath this one in order, so that the global
+     * settings and the rules can live in separate files
+     */
+    #[serde(default)]
+    pub include: Vec<std::path::PathBuf>,
+    /**
+     * User-defined grok patterns which can be referenced by `grok` matchers, in addition to the
+     * standard pattern library
+     */
+    #[serde(default)]
+    pub patterns: HashMap<String, String>,
+    /**
+     * User-defined helpers which can be used in every template, in addition to the built-in
+     * helpers
+     */
+    #[serde(default)]
+    pub helpers: HashMap<String, CustomHelper>,
+    /**
+     * Named partials which can be included by the templates of every rule, e.g. `{{> envelope}}`
+     */
+    #[serde(default, rename = "templates")]
+    pub partials: HashMap<String, String>,
+    #[serde(skip)]
+    pub prefilter: Option<crate::rules::Prefilter>,
+    /**
+     * The templates of all the rules, which are registered once the settings are loaded
+     */
+    #[serde(skip)]
+    pub templates: crate::templates::Templates,
+    /**
+     * The hex encoded SHA-256 hash of the configuration file, which is reported by the status
+     * server, or None when hotdog was configured from the environment
+     */
+    #[serde(skip)]
+    pub hash: Option<String>,
+}
+
+impl Settings {
+    /**
+     * Return all the rules in the order they are evaluated, ending with the default rule
+     */
+    pub fn all_rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().chain(self.default.iter())
+    }
+
+    /**
+     * Return every listener, starting with `global.listen`
+     */
+    pub fn listeners(&self) -> impl Iterator<Item = &Listen> {
+        std::iter::once(&self.global.listen).chain(self.global.listeners.iter())
+    }
+
+    /**
+     * Return all the topics named in the configuration which are not templated, and can therefore
+     * be verified ahead of time
+     */
+    pub fn static_topics(&self) -> Vec<String> {
+        let mut topics = vec![];
+
+        if let Some(dlq) = &self.global.kafka.dlq {
+            topics.push(dlq.to_string());
+        }
+
+        if let Some(events) = &self.global.kafka.events {
+            topics.push(events.to_string());
+        }
+
+        if let Some(heartbeat) = &self.global.heartbeat {
+            topics.push(heartbeat.topic.to_string());
+        }
+
+        for rule in self.all_rules() {
+            for action in rule.actions.iter() {
+                /*
+                 * Only the topics of the default Kafka can be verified
+                 */
+                if let Action::Forward {
+                    topic, to: None, ..
+                } = &action.action
+                {
+                    let topic = self.global.kafka.resolve_topic(topic).to_string();
+                    if !topic.contains("{{") && !topics.contains(&topic) {
+                        topics.push(topic);
+                    }
+                }
+            }
+        }
+        topics
+    }
+
+    /**
+     * Populate any configuration caches which we want to us
+     */
+    fn populate_caches(&mut self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        let patterns = &self.patterns;
+        let regex_bytes = self.global.limits.max_regex_bytes;
+
+        for rule in self.rules.iter_mut() {
+            if let Err(e) = rule.populate_caches(patterns, regex_bytes) {
+                errors.push(format!("The rule `{}`: {}", rule, e));
+            }
+        }
+
+        if self.global.limits.max_line_bytes == 0 || self.global.limits.buffer_bytes == 0 {
+            errors.push(
+                "`global.limits`: `max_line_bytes` and `buffer_bytes` must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if self.global.limits.dispatch_queue == 0 || self.global.limits.dispatchers == 0 {
+            errors.push(
+                "`global.limits`: `dispatch_queue` and `dispatchers` must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if self.global.limits.max_inflight_bytes == 0 {
+            errors.push("`global.limits`: `max_inflight_bytes` must be greater than 0".to_string());
+        }
+
+        if !(self.global.limits.pause_ratio > 0.0 && self.global.limits.pause_ratio <= 1.0) {
+            errors.push(
+                "`global.limits`: `pause_ratio` must be greater than 0 and at most 1".to_string(),
+            );
+        }
+
+        if self.global.metrics.tag_format != TagFormat::None
+            && self.global.metrics.output != MetricsOutput::Statsd
+        {
+            errors.push(
+                "`global.metrics`: only metrics of the `statsd` type can be tagged".to_string(),
+            );
+        }
+
+        if self.global.metrics.tag_format != TagFormat::None
+            && self.global.metrics.flush_interval_ms.is_some()
+        {
+            errors.push(
+                "`global.metrics`: tagged metrics cannot be aggregated with `flush_interval_ms`"
+                    .to_string(),
+            );
+        }
+
+        if self.global.metrics.prefix.is_empty() {
+            errors.push("`global.metrics`: `prefix` cannot be empty".to_string());
+        }
+
+        if self.global.metrics.flush_interval_ms == Some(0) {
+            errors.push("`global.metrics`: `flush_interval_ms` must be greater than 0".to_string());
+        }
+
+        if !(self.global.metrics.sample_rate > 0.0 && self.global.metrics.sample_rate <= 1.0) {
+            errors.push(
+                "`global.metrics`: `sample_rate` must be greater than 0 and at most 1".to_string(),
+            );
+        }
+
+        if let Some(tracing) = &self.global.tracing {
+            if !(0.0..=1.0).contains(&tracing.ratio) {
+                errors.push("`global.tracing`: `ratio` must be between 0 and 1".to_string());
+            }
+        }
+
+        if self.global.runtime.worker_threads == Some(0)
+            || self.global.runtime.blocking_threads == Some(0)
+        {
+            errors.push(
+                "`global.runtime`: `worker_threads` and `blocking_threads` must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if let Some(heartbeat) = &self.global.heartbeat {
+            if heartbeat.interval_ms == 0 {
+                errors.push("`global.heartbeat`: `interval_ms` must be greater than 0".to_string());
+            }
+        }
+
+        let producers = std::iter::once(("global.kafka".to_string(), &self.global.kafka)).chain(
+            self.global
+                .destinations
+                .iter()
+                .map(|(name, destination)| match destination {
+                    Destination::Kafka(conf) => (format!("global.destinations.{}", name), conf),
+                }),
+        );
+        for (section, conf) in producers {
+            if let Some(spool) = &conf.spool {
+                if spool.replay_interval_ms == 0 {
+                    errors.push(format!(
+                        "`{}.spool`: `replay_interval_ms` must be greater than 0",
+                        section
+                    ));
+                }
+            }
+
+            if conf.send_timeout_ms == Some(0) {
+                errors.push(format!(
+                    "`{}`: `send_timeout_ms` must be greater than 0",
+                    section
+                ));
+            }
+
+            if conf.batch.collect_messages > conf.max_in_flight {
+                errors.push(format!(
+                    "`{}.batch`: `collect_messages` cannot be larger than `max_in_flight`",
+                    section
+                ));
+            }
+
+            if conf.send_timeout_policy == SendTimeoutPolicy::Spool && conf.spool.is_none() {
+                errors.push(format!(
+                    "`{}`: The `spool` send_timeout_policy requires `spool` to be set",
+                    section
+                ));
+            }
+        }
+
+        if self.global.parse_errors.policy == ParseErrorPolicy::Dlq
+            && self.global.kafka.dlq.is_none()
+        {
+            errors.push(
+                "`global.parse_errors`: The `dlq` policy requires `global.kafka.dlq` to be set"
+                    .to_string(),
+            );
+        }
+
+        if let Some(default) = &mut self.default {
+            if !default.condition.is_empty() {
+                errors.push(
+                    "`default`: The default rule cannot have a matcher or conditions".to_string(),
+                );
+            } else if let Err(e) = default.populate_caches(patterns, regex_bytes) {
+                errors.push(format!("`default`: {}", e));
+            }
+        }
+
+        for rule in self.all_rules() {
+            for action in rule.actions.iter() {
+                if let Action::Forward { to: Some(to), .. } = &action.action {
+                    if !self.global.destinations.contains_key(to) {
+                        errors.push(format!(
+                            "The rule `{}`: The destination `{}` is not defined in `global.destinations`",
+                            rule, to
+                        ));
+                    }
+                }
+            }
+
+            if let Some(ruleset) = &rule.ruleset {
+                if !self
+                    .listeners()
+                    .any(|listen| listen.ruleset.as_ref() == Some(ruleset))
+                {
+                    errors.push(format!(
+                        "The rule `{}`: The ruleset `{}` is not served by any listener",
+                        rule, ruleset
+                    ));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        /*
+         * The sort is stable, so rules with the same priority will remain in the order they were
+         * defined in
+         */
+        self.rules
+            .sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+
+        self.prefilter = crate::rules::Prefilter::new(&self.rules);
+        self.templates = crate::templates::Templates::new(self)?;
+        Ok(())
+    }
+}
+
+/*
+ * Default functions
+ */
+
+/**
+ * Return the default size used for the Kafka buffer
+ */
+fn kafka_buffer_default() -> usize {
+    1024
+}
+
+fn kafka_timeout_default() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn kafka_retries_default() -> u32 {
+    3
+}
+
+fn kafka_retry_backoff_default() -> u64 {
+    100
+}
+
+fn kafka_max_in_flight_default() -> usize {
+    10_000
+}
+
+fn batch_linger_ms_default() -> u64 {
+    10
+}
+
+fn batch_num_messages_default() -> u64 {
+    10_000
+}
+
+fn batch_max_messages_default() -> u64 {
+    100_000
+}
+
+fn batch_max_kbytes_default() -> u64 {
+    1_048_576
+}
+
+fn batch_collect_messages_default() -> usize {
+    500
+}
+
+fn spool_max_bytes_default() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn spool_replay_interval_ms_default() -> u64 {
+    10_000
+}
+
+fn kafka_flush_timeout_default() -> u64 {
+    10_000
+}
+
+fn kafka_max_message_bytes_default() -> usize {
+    1_000_000
+}
+
+fn kafka_truncation_marker_default() -> String {
+    String::from("...[truncated]")
+}
+
+fn metrics_max_topics_default() -> usize {
+    1000
+}
+
+fn metrics_max_peers_default() -> usize {
+    100
+}
+
+fn metrics_max_metrics_default() -> usize {
+    1000
+}
+
+fn metrics_prefix_default() -> String {
+    String::from("hotdog")
+}
+
+fn metrics_sample_rate_default() -> f64 {
+    1.0
+}
+
+fn limits_max_line_bytes_default() -> usize {
+    1_048_576
+}
+
+fn limits_buffer_bytes_default() -> usize {
+    8192
+}
+
+fn limits_max_message_bytes_default() -> usize {
+    4_194_304
+}
+
+fn limits_max_variables_default() -> usize {
+    1024
+}
+
+fn limits_dispatch_queue_default() -> usize {
+    1024
+}
+
+fn limits_dispatchers_default() -> usize {
+    1
+}
+
+fn limits_pause_ratio_default() -> f64 {
+    0.9
+}
+
+fn limits_max_regex_bytes_default() -> usize {
+    2 * 1024 * 1024
+}
+
+fn limits_max_inflight_bytes_default() -> usize {
+    64 * 1024 * 1024
+}
+
+fn shutdown_grace_ms_default() -> u64 {
+    10_000
+}
+
+fn shutdown_handover_ms_default() -> u64 {
+    300_000
+}
+
+fn limits_truncation_marker_default() -> String {
+    String::from("...[truncated]")
+}
+
+fn kafka_topic_default() -> String {
+    String::from("hotdog")
+}
+
+fn kafka_topic_replacement_default() -> String {
+    String::from("_")
+}
+
+fn kafka_statistics_interval_default() -> u64 {
+    60_000
+}
+
+fn producer_queue_timeout_default() -> u64 {
+    60_000
+}
+
+fn dedupe_window_default() -> u64 {
+    30_000
+}
+
+fn dedupe_summary_default() -> String {
+    "last message repeated {{count}} times".to_string()
+}
+
+fn aggregate_window_default() -> u64 {
+    60_000
+}
+
+fn throttle_interval_default() -> u64 {
+    60_000
+}
+
+fn throttle_summary_default() -> String {
+    "suppressed {{count}} messages".to_string()
+}
+
+fn exec_concurrency_default() -> usize {
+    1
+}
+
+fn exec_timeout_default() -> u64 {
+    5_000
+}
+
+fn timestamp_field_default() -> String {
+    "timestamp".to_string()
+}
+
+fn geoip_field_default() -> String {
+    "geo".to_string()
+}
+
+fn mask_replacement_default() -> String {
+    "[MASKED]".to_string()
+}
+
+fn redact_replacement_default() -> String {
+    "[REDACTED]".to_string()
+}
+
+fn add_fields_message_field_default() -> String {
+    "message".to_string()
+}
+
+fn csv_delimiter_default() -> String {
+    ",".to_string()
+}
+
+fn csv_quote_default() -> Option<char> {
+    Some('"')
+}
+
+fn kv_separator_default() -> String {
+    " ".to_string()
+}
+
+fn kv_delimiter_default() -> String {
+    "=".to_string()
+}
+
+fn kv_quotes_default() -> String {
+    "\"'".to_string()
+}
+
+fn global_metrics_default() -> Metrics {
+    Metrics {
+        output: MetricsOutput::None,
+        ..Metrics::default()
+    }
+}
+
+fn metrics_statsd_default() -> String {
+    String::from("localhost:8125")
+}
+
+fn metrics_graphite_default() -> String {
+    String::from("localhost:2003")
+}
+
+fn heartbeat_interval_ms_default() -> u64 {
+    10_000
+}
+
+fn timestamps_max_future_secs_default() -> u64 {
+    3_600
+}
+
+fn timestamps_max_past_secs_default() -> u64 {
+    7 * 24 * 3_600
+}
+
+fn tracing_endpoint_default() -> String {
+    String::from("http://localhost:4318/v1/traces")
+}
+
+fn tracing_service_name_default() -> String {
+    String::from("hotdog")
+}
+
+fn tracing_ratio_default() -> f64 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_none<T>() -> Option<T> {
+    None
+}
+
+fn default_uuid() -> Uuid {
+    Uuid::new_v4()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_example_config() {
+        load("hotdog.yml");
+    }
+
+    #[test]
+    fn test_file_hash() {
+        let settings = load("hotdog.yml");
+        let hash = settings
+            .hash
+            .expect("Failed to hash the configuration file");
+
+        assert_eq!(64, hash.len());
+        assert_eq!(Some(hash), file_hash("hotdog.yml"));
+        assert_eq!(None, file_hash("test/configs/does-not-exist.yml"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        std::env::set_var("HOTDOG_TEST_BROKERS", "kafka:9092");
+
+        assert_eq!(
+            Ok("servers: 'kafka:9092'".to_string()),
+            interpolate("servers: '${HOTDOG_TEST_BROKERS}'")
+        );
+        assert_eq!(
+            Ok("topic: logs".to_string()),
+            interpolate("topic: ${HOTDOG_TEST_UNSET:-logs}")
+        );
+        assert_eq!(
+            Ok("literal: ${HOTDOG_TEST_BROKERS}".to_string()),
+            interpolate("literal: $${HOTDOG_TEST_BROKERS}")
+        );
+        assert!(interpolate("topic: ${HOTDOG_TEST_UNSET}").is_err());
+        assert!(interpolate("topic: ${HOTDOG_TEST_BROKERS").is_err());
+    }
+
+    #[test]
+    fn test_environment_conf() {
+        let mut env = HashMap::new();
+        env.insert(ENV_BROKERS, "kafka:9092");
+        env.insert("HOTDOG_LISTEN_PORT", "6514");
+        env.insert("HOTDOG_KAFKA_TOPIC", "logs");
+
+        let conf = environment_conf(|name| env.get(name).map(|value| value.to_string()))
+            .expect("Failed to generate the configuration");
+        let settings: Settings = serde_json::from_str(&conf).expect("Failed to parse the settings");
+
+        assert_eq!("0.0.0.0", settings.global.listen.address);
+        assert_eq!(6514, settings.global.listen.port);
+        assert_eq!(
+            Some(&"kafka:9092".to_string()),
+            settings.global.kafka.conf.get("bootstrap.servers")
+        );
+        assert!(settings.rules.is_empty());
+        assert!(settings.default.is_some());
+    }
+
+    #[test]
+    fn test_environment_conf_invalid() {
+        let mut env: HashMap<&str, &str> = HashMap::new();
+        assert!(environment_conf(|name| env.get(name).map(|value| value.to_string())).is_err());
+
+        env.insert(ENV_BROKERS, "kafka:9092");
+        env.insert("HOTDOG_LISTEN_PORT", "syslog");
+        assert!(environment_conf(|name| env.get(name).map(|value| value.to_string())).is_err());
+    }
+
+    #[test]
+    fn test_load_includes() {
+        let settings = load("test/configs/rules-with-includes.yml");
+
+        assert_eq!("included", settings.global.kafka.topic);
+        assert_eq!(
+            Some(&"127.0.0.1:9092".to_string()),
+            settings.global.kafka.conf.get("bootstrap.servers")
+        );
+        assert_eq!(1515, settings.global.listen.port);
+
+        let names: Vec<Option<&str>> = settings.rules.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(
+            vec![Some("nginx"), Some("haproxy"), Some("catch-all")],
+            names
+        );
+    }
+
+    #[test]
+    fn test_load_toml() {
+        let settings = load("test/configs/single-rule.toml");
+        assert_eq!("test", settings.global.kafka.topic);
+        assert_eq!(1, settings.rules.len());
+        assert_eq!(
+            Some(&"127.0.0.1:9092".to_string()),
+            settings.global.kafka.conf.get("bootstrap.servers")
+        );
+    }
+
+    #[test]
+    fn test_load_json() {
+        let settings = load("test/configs/single-rule.json");
+        assert_eq!("test", settings.global.kafka.topic);
+        assert_eq!(1, settings.rules.len());
+    }
+
+    #[test]
+    fn test_load_unknown_format() {
+        assert!(try_load("test/configs/single-rule.ini", &Overrides::default()).is_err());
+    }
+
+    #[test]
+    fn test_load_interpolated() {
+        std::env::set_var("HOTDOG_TEST_TOPIC", "interpolated");
+        let settings = load("test/configs/single-rule-with-interpolation.yml");
+
+        assert_eq!("interpolated", settings.global.kafka.topic);
+        assert_eq!(1514, settings.global.listen.port);
+    }
+
+    #[test]
+    fn test_load_example_and_populate_caches() {
+        let settings = load("test/configs/single-rule-with-merge.yml");
+        assert_eq!(settings.rules.len(), 1);
+        match &settings.rules[0].actions[0].action {
+            Action::Merge { json: _, json_str } => {
+                assert!(json_str.is_some());
+            }
+            _ => {
+                assert!(false);
+            }
+        }
+    }
+
+    #[test]
+    fn test_static_topics() {
+        let settings = load("hotdog.yml");
+        let topics = settings.static_topics();
+        assert!(topics.contains(&"test".to_string()));
+        assert!(topics.contains(&"logs-unknown".to_string()));
+        assert!(!topics.contains(&"{{value}}".to_string()));
+    }
+
+    #[test]
+    fn test_rdkafka_conf_defaults() {
+        let settings = load("hotdog.yml");
+        let conf = settings.global.kafka.rdkafka_conf();
+        assert_eq!(Some(&"10".to_string()), conf.get("linger.ms"));
+        assert_eq!(
+            Some(&"127.0.0.1:9092".to_string()),
+            conf.get("bootstrap.servers")
+        );
+    }
+
+    #[test]
+    fn test_resolve_topic() {
+        let mut settings = load("hotdog.yml");
+        settings
+            .global
+            .kafka
+            .topics
+            .insert("logical".to_string(), "physical".to_string());
+        assert_eq!("physical", settings.global.kafka.resolve_topic("logical"));
+        assert_eq!("other", settings.global.kafka.resolve_topic("other"));
+    }
+
+    #[test]
+    fn test_load_grok() {
+        let settings = load("test/configs/single-rule-with-grok.yml");
+        let regex = settings.rules[0]
+            .condition
+            .regex
+            .as_ref()
+            .expect("The grok pattern was not expanded");
+        let captures = regex
+            .captures("GET /index.html from 10.0.0.1")
+            .expect("Failed to match");
+        assert_eq!("GET", &captures["method"]);
+        assert_eq!("10.0.0.1", &captures["client"]);
+    }
+
+    #[test]
+    fn test_rule_priorities() {
+        let settings = load("test/configs/rules-with-priorities.yml");
+        let topics: Vec<String> = settings
+            .rules
+            .iter()
+            .map(|rule| match &rule.actions[0].action {
+                Action::Forward { topic, .. } => topic.to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(vec!["urgent", "first", "second", "last"], topics);
+    }
+
+    #[test]
+    fn test_load_remap_severity() {
+        let settings = load("test/configs/single-rule-with-remap-severity.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::RemapSeverity { map, source } => {
+                assert_eq!(Some(&"warning".to_string()), map.get("err"));
+                assert_eq!(&Some("{{level}}".to_string()), source);
+            }
+            _ => assert!(false, "Expected a RemapSeverity action"),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_load_remap_severity_invalid() {
+        load("test/configs/single-rule-with-invalid-remap-severity.yml");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_load_dedupe_invalid_window() {
+        load("test/configs/single-rule-with-invalid-dedupe.yml");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_load_throttle_invalid_interval() {
+        load("test/configs/single-rule-with-invalid-throttle.yml");
+    }
+
+    #[test]
+    fn test_load_kv() {
+        let settings = load("test/configs/single-rule-with-kv.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::Kv {
+                source,
+                separator,
+                delimiter,
+                quotes,
+                field,
+                message_field,
+            } => {
+                assert!(source.is_none());
+                assert_eq!(" ", separator);
+                assert_eq!("=", delimiter);
+                assert_eq!("\"'", quotes);
+                assert_eq!(Some("fields".to_string()), *field);
+                assert_eq!("message", message_field);
+            }
+            _ => panic!("Expected a kv action"),
+        }
+    }
+
+    #[test]
+    fn test_load_csv() {
+        let settings = load("test/configs/single-rule-with-csv.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::Csv {
+                columns,
+                delimiter,
+                quote,
+                ..
+            } => {
+                assert_eq!(vec!["client", "_", "method", "status"], *columns);
+                assert_eq!("|", delimiter);
+                assert_eq!(Some('"'), *quote);
+            }
+            _ => panic!("Expected a csv action"),
+        }
+    }
+
+    #[test]
+    fn test_load_enrich() {
+        let settings = load("test/configs/single-rule-with-enrich.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::Enrich { key, entries, .. } => {
+                assert_eq!("{{hostname}}", key);
+                assert_eq!(2, entries.len());
+            }
+            _ => panic!("Expected an enrich action"),
+        }
+    }
+
+    #[test]
+    fn test_load_decode() {
+        let settings = load("test/configs/single-rule-with-decode.yml");
+        let actions = &settings.rules[0].actions;
+        assert!(matches!(
+            &actions[0].action,
+            Action::Decode {
+                encoding: Encoding::Base64,
+                variable: None,
+                pointer: Some(_),
+            }
+        ));
+        assert!(matches!(
+            &actions[1].action,
+            Action::Decode {
+                encoding: Encoding::Url,
+                variable: Some(_),
+                pointer: None,
+            }
+        ));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_load_validate() {
+        let settings = load("test/configs/single-rule-with-validate.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::Validate {
+                schema,
+                invalid_topic,
+            } => {
+                assert_eq!(
+                    std::path::Path::new("test/schemas/event.json"),
+                    schema.as_path()
+                );
+                assert_eq!(Some("invalid".to_string()), *invalid_topic);
+            }
+            _ => panic!("Expected a validate action"),
+        }
+    }
+
+    #[cfg(not(feature = "schema"))]
+    #[test]
+    #[should_panic]
+    fn test_load_validate_unsupported() {
+        load("test/configs/single-rule-with-validate.yml");
+    }
+
+    #[test]
+    fn test_debug_sampled() {
+        let settings = load("test/configs/single-rule-with-debug-sample.yml");
+        let rule = &settings.rules[0];
+        assert_eq!(Some(3), rule.debug_sample);
+
+        let sampled: Vec<bool> = (0..4).map(|_| rule.debug_sampled()).collect();
+        assert_eq!(vec![true, false, false, true], sampled);
+
+        let settings = load("test/configs/single-rule-with-merge.yml");
+        assert!(!settings.rules[0].debug_sampled());
+    }
+
+    #[test]
+    fn test_load_rules_mode() {
+        let settings = load("test/configs/rules-with-first-match.yml");
+        assert_eq!(RulesMode::FirstMatch, settings.global.rules.mode);
+
+        let settings = load("test/configs/rules-with-priorities.yml");
+        assert_eq!(RulesMode::All, settings.global.rules.mode);
+    }
+
+    #[test]
+    fn test_load_tags() {
+        let settings = load("test/configs/rules-with-tags.yml");
+        match &settings.rules[0].actions[0].action {
+            Action::Tag { tags } => assert_eq!(vec!["security".to_string()], *tags),
+            _ => panic!("Expected a tag action"),
+        }
+        assert_eq!(
+            Some("security".to_string()),
+            settings.rules[1].condition.tagged
+        );
+    }
+
+    #[test]
+    fn test_load_default_rule() {
+        let settings = load("test/configs/single-rule-with-default.yml");
+        assert_eq!(1, settings.rules.len());
+        assert!(settings.default.is_some());
+        assert_eq!(2, settings.all_rules().count());
+        assert!(settings.static_topics().contains(&"unmatched".to_string()));
+    }
+
+    #[test]
+    fn test_load_default_rule_with_matcher() {
+        assert!(try_load(
+            "test/configs/single-rule-with-invalid-default.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_destinations() {
+        let settings = load("test/configs/single-rule-with-destination.yml");
+        assert!(matches!(
+            settings.global.destinations.get("archive"),
+            Some(Destination::Kafka(_))
+        ));
+        match &settings.rules[0].actions[0].action {
+            Action::Forward { to, .. } => assert_eq!(Some("archive".to_string()), *to),
+            _ => panic!("Expected a forward action"),
+        }
+        assert!(!settings.static_topics().contains(&"archived".to_string()));
+    }
+
+    #[test]
+    fn test_load_undefined_destination() {
+        assert!(try_load(
+            "test/configs/single-rule-with-undefined-destination.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_minimal() {
+        let settings = load("test/configs/minimal.yml");
+
+        assert_eq!(TlsType::None, settings.global.listen.tls);
+        assert_eq!("hotdog", settings.global.kafka.topic);
+        assert_eq!(MetricsOutput::None, settings.global.metrics.output);
+        assert_eq!(None, settings.global.status);
+        assert_eq!(1, settings.rules.len());
+    }
+
+    #[test]
+    fn test_load_profile() {
+        let overrides = Overrides {
+            profile: Some("prod".to_string()),
+            ..Overrides::default()
+        };
+        let settings = try_load("test/configs/rules-with-profiles.yml", &overrides)
+            .expect("Failed to load the settings");
+
+        assert_eq!("0.0.0.0", settings.global.listen.address);
+        assert_eq!(514, settings.global.listen.port);
+        assert_eq!("test", settings.global.kafka.topic);
+        assert_eq!(
+            Some(&"kafka.prod:9092".to_string()),
+            settings.global.kafka.conf.get("bootstrap.servers")
+        );
+        assert_eq!(2, settings.rules.len());
+    }
+
+    #[test]
+    fn test_load_without_profile() {
+        let settings = load("test/configs/rules-with-profiles.yml");
+
+        assert_eq!("127.0.0.1", settings.global.listen.address);
+        assert_eq!(1, settings.rules.len());
+    }
+
+    #[test]
+    fn test_load_undefined_profile() {
+        let overrides = Overrides {
+            profile: Some("staging".to_string()),
+            ..Overrides::default()
+        };
+        assert!(try_load("test/configs/rules-with-profiles.yml", &overrides).is_err());
+    }
+
+    #[test]
+    fn test_load_graphite() {
+        let settings = load("test/configs/single-rule-with-graphite.yml");
+
+        assert_eq!(MetricsOutput::Graphite, settings.global.metrics.output);
+        assert_eq!("graphite:2003", settings.global.metrics.destination());
+    }
+
+    #[test]
+    fn test_load_sampled_metrics() {
+        let settings = load("test/configs/single-rule-with-sampled-metrics.yml");
+
+        assert_eq!("hotdog-edge", settings.global.metrics.prefix);
+        assert_eq!(Some(10000), settings.global.metrics.flush_interval_ms);
+        assert_eq!(0.25, settings.global.metrics.sample_rate);
+    }
+
+    #[test]
+    fn test_metrics_defaults() {
+        let settings = load("test/configs/single-rule-with-graphite.yml");
+
+        assert_eq!("hotdog", settings.global.metrics.prefix);
+        assert_eq!(100, settings.global.metrics.max_peers);
+        assert_eq!(1000, settings.global.metrics.max_metrics);
+        assert_eq!(None, settings.global.metrics.flush_interval_ms);
+        assert_eq!(1.0, settings.global.metrics.sample_rate);
+    }
+
+    #[test]
+    fn test_load_events() {
+        let settings = load("test/configs/single-rule-with-events.yml");
+
+        assert_eq!(
+            Some("hotdog-events".to_string()),
+            settings.global.kafka.events
+        );
+        assert!(settings
+            .static_topics()
+            .contains(&"hotdog-events".to_string()));
+    }
+
+    #[test]
+    fn test_load_metrics_tags() {
+        let settings = load("test/configs/single-rule-with-tags.yml");
+
+        assert_eq!(TagFormat::Dogstatsd, settings.global.metrics.tag_format);
+        assert_eq!(
+            Some(&"web-1".to_string()),
+            settings.global.metrics.tags.get("host")
+        );
+    }
+
+    #[test]
+    fn test_load_tracing() {
+        let settings = load("test/configs/single-rule-with-tracing.yml");
+        let tracing = settings
+            .global
+            .tracing
+            .expect("Failed to load the tracing settings");
+
+        assert_eq!("http://otel-collector:4318/v1/traces", tracing.endpoint);
+        assert_eq!("hotdog", tracing.service_name);
+        assert_eq!(0.1, tracing.ratio);
+    }
+
+    #[test]
+    fn test_load_shutdown() {
+        assert_eq!(
+            shutdown_grace_ms_default(),
+            load("test/configs/minimal.yml").global.shutdown.grace_ms
+        );
+        assert_eq!(
+            shutdown_handover_ms_default(),
+            load("test/configs/minimal.yml").global.shutdown.handover_ms
+        );
+        assert_eq!(
+            30_000,
+            load("test/configs/single-rule-with-shutdown.yml")
+                .global
+                .shutdown
+                .grace_ms
+        );
+    }
+
+    #[test]
+    fn test_load_preserve_order() {
+        assert!(!load("test/configs/minimal.yml").global.kafka.preserve_order);
+        assert!(
+            load("test/configs/single-rule-with-preserve-order.yml")
+                .global
+                .kafka
+                .preserve_order
+        );
+    }
+
+    #[test]
+    fn test_load_spool() {
+        assert_eq!(None, load("test/configs/minimal.yml").global.kafka.spool);
+
+        let settings = load("test/configs/single-rule-with-spool.yml");
+        let spool = settings
+            .global
+            .kafka
+            .spool
+            .expect("Failed to load the spool");
+        assert_eq!(std::path::PathBuf::from("/var/spool/hotdog"), spool.path);
+        assert_eq!(1_048_576, spool.max_bytes);
+        assert_eq!(spool_replay_interval_ms_default(), spool.replay_interval_ms);
+        assert!(spool.dedupe);
+    }
+
+    #[test]
+    fn test_load_send_timeout() {
+        let minimal = load("test/configs/minimal.yml");
+        assert_eq!(None, minimal.global.kafka.send_timeout_ms);
+        assert_eq!(
+            SendTimeoutPolicy::Retry,
+            minimal.global.kafka.send_timeout_policy
+        );
+
+        let settings = load("test/configs/single-rule-with-spool.yml");
+        assert_eq!(Some(5000), settings.global.kafka.send_timeout_ms);
+        assert_eq!(
+            SendTimeoutPolicy::Spool,
+            settings.global.kafka.send_timeout_policy
+        );
+
+        assert!(try_load(
+            "test/configs/single-rule-with-invalid-send-timeout.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_batch_invalid() {
+        assert!(try_load(
+            "test/configs/single-rule-with-invalid-batch.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_runtime() {
+        let settings = load("test/configs/single-rule-with-runtime.yml");
+        assert_eq!(
+            settings.global.runtime,
+            runtime(
+                "test/configs/single-rule-with-runtime.yml",
+                &Overrides::default()
+            )
+        );
+        assert_eq!(
+            Runtime::default(),
+            runtime("test/configs/minimal.yml", &Overrides::default())
+        );
+    }
+
+    #[test]
+    fn test_load_runtime() {
+        let settings = load("test/configs/single-rule-with-runtime.yml");
+
+        assert_eq!(Some(2), settings.global.runtime.worker_threads);
+        assert_eq!(None, settings.global.runtime.blocking_threads);
+        assert_eq!(vec![2, 3], settings.global.runtime.cpus);
+    }
+
+    #[test]
+    fn test_load_heartbeat() {
+        let settings = load("test/configs/single-rule-with-heartbeat.yml");
+        let heartbeat = settings
+            .global
+            .heartbeat
+            .as_ref()
+            .expect("Failed to load the heartbeat settings");
+
+        assert_eq!("hotdog-heartbeats", heartbeat.topic);
+        assert_eq!(10_000, heartbeat.interval_ms);
+        assert_eq!(Some("edge-1".to_string()), heartbeat.instance);
+        assert!(settings
+            .static_topics()
+            .contains(&"hotdog-heartbeats".to_string()));
+    }
+
+    #[test]
+    fn test_load_timestamps() {
+        let settings = load("test/configs/single-rule-with-timestamps.yml");
+        let timestamps = settings
+            .global
+            .timestamps
+            .as_ref()
+            .expect("Failed to load the timestamps settings");
+
+        assert_eq!(300, timestamps.max_future_secs);
+        assert_eq!(604_800, timestamps.max_past_secs);
+        assert_eq!(SkewedTimestampPolicy::Clamp, timestamps.skewed);
+    }
+
+    #[test]
+    fn test_load_limits() {
+        let settings = load("test/configs/single-rule-with-limits.yml");
+
+        assert_eq!(64, settings.global.limits.max_line_bytes);
+        assert_eq!(
+            limits_buffer_bytes_default(),
+            settings.global.limits.buffer_bytes
+        );
+        assert_eq!(4, settings.global.limits.dispatchers);
+        assert_eq!(0.9, settings.global.limits.pause_ratio);
+        assert_eq!(1048576, settings.global.limits.max_inflight_bytes);
+        assert_eq!(
+            InflightPolicy::Disconnect,
+            settings.global.limits.inflight_policy
+        );
+        assert_eq!(LongLinePolicy::Truncate, settings.global.limits.long_lines);
+        assert_eq!(
+            PartialLinePolicy::Drop,
+            settings.global.limits.partial_lines
+        );
+        assert_eq!(
+            limits_truncation_marker_default(),
+            settings.global.limits.truncation_marker
+        );
+        assert_eq!(
+            limits_dispatch_queue_default(),
+            settings.global.limits.dispatch_queue
+        );
+    }
+
+    #[test]
+    fn test_limits_exceeded() {
+        let limits = Limits {
+            max_message_bytes: 4,
+            max_variables: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(None, limits.exceeded("four", 1));
+        assert!(limits.exceeded("fives", 1).is_some());
+        assert!(limits.exceeded("four", 2).is_some());
+    }
+
+    #[test]
+    fn test_load_parse_errors() {
+        let settings = load("test/configs/single-rule-with-parse-errors.yml");
+        assert_eq!(ParseErrorPolicy::Dlq, settings.global.parse_errors.policy);
+        assert_eq!(
+            InvalidUtf8Policy::Lossy,
+            settings.global.parse_errors.invalid_utf8
+        );
+    }
+
+    #[test]
+    fn test_load_parse_errors_default() {
+        let settings = load("test/configs/minimal.yml");
+        assert_eq!(ParseErrorPolicy::Drop, settings.global.parse_errors.policy);
+        assert_eq!(
+            InvalidUtf8Policy::Error,
+            settings.global.parse_errors.invalid_utf8
+        );
+    }
+
+    #[test]
+    fn test_load_vars() {
+        let settings = load("test/configs/single-rule-with-vars.yml");
+
+        assert_eq!(
+            Some(&"us-west-2".to_string()),
+            settings.global.vars.get("datacenter")
+        );
+    }
+
+    #[test]
+    fn test_load_rulesets() {
+        let settings = load("test/configs/rules-with-rulesets.yml");
+        let rulesets: Vec<Option<&str>> = settings
+            .listeners()
+            .map(|listen| listen.ruleset.as_deref())
+            .collect();
+
+        assert_eq!(vec![Some("appliances"), None], rulesets);
+        assert_eq!(Some("appliances"), settings.rules[0].ruleset.as_deref());
+        assert_eq!(None, settings.rules[1].ruleset);
+    }
+
+    #[test]
+    fn test_load_undefined_ruleset() {
+        assert!(try_load(
+            "test/configs/rules-with-undefined-ruleset.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_log() {
+        let settings = load("test/configs/single-rule-with-log.yml");
+
+        assert_eq!(Some("hotdog=debug"), settings.global.log.level.as_deref());
+        assert_eq!(LogFormat::Json, settings.global.log.format);
+        assert_eq!(
+            Some(std::path::Path::new("/var/log/hotdog.log")),
+            settings.global.log.file.as_deref()
+        );
+    }
+
+    #[test]
+    fn test_load_log_default() {
+        let settings = load("test/configs/minimal.yml");
+        assert_eq!(Log::default(), settings.global.log);
+    }
+
+    #[test]
+    fn test_load_secrets() {
+        let settings = load("test/configs/single-rule-with-secrets.yml");
+        let conf = &settings.global.kafka.conf;
+
+        assert_eq!(Some(&"hunter2".to_string()), conf.get("sasl.password"));
+        assert_eq!(None, conf.get("sasl.password_file"));
+    }
+
+    #[test]
+    fn test_resolved() {
+        let resolved = resolved(
+            "test/configs/single-rule-with-secrets.yml",
+            &Overrides::default(),
+            true,
+        )
+        .expect("Failed to resolve the configuration");
+        let kafka = &resolved["global"]["kafka"];
+
+        assert_eq!("********", kafka["conf"]["sasl.password"]);
+        assert_eq!("********", kafka["conf"]["sasl.jaas.config"]);
+        assert_eq!("hotdog", kafka["conf"]["sasl.username"]);
+        assert_eq!(1024, kafka["buffer"]);
+        assert_eq!(1, resolved["rules"].as_array().map(Vec::len).unwrap_or(0));
+    }
+
+    #[test]
+    fn test_resolved_without_defaults() {
+        let resolved = resolved(
+            "test/configs/single-rule-with-secrets.yml",
+            &Overrides::default(),
+            false,
+        )
+        .expect("Failed to resolve the configuration");
+        let kafka = &resolved["global"]["kafka"];
+
+        assert_eq!("********", kafka["conf"]["sasl.password_file"]);
+        assert!(kafka.get("buffer").is_none());
+    }
+
+    #[test]
+    fn test_load_overrides() {
+        let overrides = Overrides {
+            listen: Some("0.0.0.0".to_string()),
+            port: Some(1514),
+            brokers: Some("kafka:9092".to_string()),
+            profile: None,
+        };
+        let settings = try_load("test/configs/single-rule-with-merge.yml", &overrides)
+            .expect("Failed to load the settings");
+
+        assert_eq!("0.0.0.0", settings.global.listen.address);
+        assert_eq!(1514, settings.global.listen.port);
+        assert_eq!(
+            Some(&"kafka:9092".to_string()),
+            settings.global.kafka.conf.get("bootstrap.servers")
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_errors() {
+        let errors = parse("test/configs/multiple-errors.yml", &Overrides::default()).unwrap_err();
+
+        assert_eq!(3, errors.len());
+        assert!(errors[0].contains("`rules[1]`"));
+        assert!(errors[0].contains("teleport"));
+        assert!(errors[1].contains("`rules[2]`"));
+        assert!(errors[2].contains("`global.listen`"));
+    }
+
+    #[test]
+    fn test_parse_invalid_regexes() {
+        let errors = parse(
+            "test/configs/rules-with-invalid-regexes.yml",
+            &Overrides::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(3, errors.len());
+        assert!(errors[0].contains("Failed to compile the regex `^hello"));
+        assert!(errors[1].contains("exceeds size limit"));
+        assert!(errors[2].contains("`actions[0]`"));
+        assert!(errors[2].contains("[unclosed"));
+    }
+
+    #[test]
+    fn test_load_mask_patterns() {
+        let settings = load("test/configs/single-rule-with-mask.yml");
+
+        match &settings.rules[0].actions[0].action {
+            Action::Mask { patterns, .. } => assert!(patterns[0].is_match("card 1234-5678")),
+            _ => panic!("Expected a mask action"),
+        }
+    }
+
+    #[test]
+    fn test_load_conditional_actions() {
+        let settings = load("test/configs/single-rule-with-conditional-actions.yml");
+        let actions = &settings.rules[0].actions;
+        assert_eq!(2, actions.len());
+
+        let when = actions[0]
+            .when
+            .as_ref()
+            .expect("Expected a `when` condition");
+        assert_eq!(Some(Field::Variable("status".to_string())), when.field);
+        assert!(matches!(actions[0].action, Action::Forward { .. }));
+        assert!(actions[1].when.is_none());
+    }
+
+    #[test]
+    fn test_load_rules_d() {
+        let settings = load("test/configs/rules-d.yml");
+        let names: Vec<Option<&str>> = settings
+            .rules
+            .iter()
+            .map(|rule| rule.name.as_deref())
+            .collect();
+        assert_eq!(
+            vec![Some("inline"), Some("first"), Some("second"), Some("third")],
+            names
+        );
+    }
+
+    #[test]
+    fn test_field_parse() {
+        use std::convert::TryFrom;
+        assert_eq!(Ok(Field::Msg), Field::try_from("msg".to_string()));
+        assert_eq!(
+            Ok(Field::StructuredData {
+                id: "exampleSDID@32473".to_string(),
+                param: "eventSource".to_string()
+            }),
+            Field::try_from("sd.exampleSDID@32473.eventSource".to_string())
+        );
+        assert_eq!(
+            Ok(Field::Variable("status".to_string())),
+            Field::try_from("var.status".to_string())
+        );
+        assert!(Field::try_from("var.".to_string()).is_err());
+        assert!(Field::try_from("sd.missing".to_string()).is_err());
+        assert!(Field::try_from("bogus".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_comparison_parse() {
+        use std::convert::TryFrom;
+        let comparison = Comparison::try_from("<= warning".to_string()).unwrap();
+        assert_eq!(Operator::Lte, comparison.op);
+        assert_eq!("warning", comparison.value);
+
+        let comparison = Comparison::try_from(">err".to_string()).unwrap();
+        assert_eq!(Operator::Gt, comparison.op);
+        assert_eq!("err", comparison.value);
+    }
+
+    #[test]
+    fn test_comparison_parse_invalid() {
+        use std::convert::TryFrom;
+        assert!(Comparison::try_from("warning".to_string()).is_err());
+        assert!(Comparison::try_from("<=".to_string()).is_err());
     }
 
     #[test]
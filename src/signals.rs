@@ -0,0 +1,238 @@
+/**
+ * The signals module is responsible for turning POSIX signals into something the rest of hotdog
+ * can await on. Windows has no such signals, so there Ctrl-C and the controls of the Windows
+ * service take their place, and what cannot be asked for without signals is never notified
+ */
+#[cfg(windows)]
+use async_channel::Sender;
+#[cfg(windows)]
+use async_channel::TrySendError;
+use async_channel::{bounded, Receiver};
+#[cfg(unix)]
+use async_std::{prelude::*, task};
+use log::*;
+#[cfg(unix)]
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+#[cfg(unix)]
+use signal_hook_async_std::Signals;
+use std::sync::Mutex;
+#[cfg(windows)]
+use std::sync::Once;
+
+/**
+ * The Receiver returned by `shutdown`, so that the signal handlers are only registered once
+ */
+#[cfg(unix)]
+static SHUTDOWN: Mutex<Option<Receiver<()>>> = Mutex::new(None);
+
+/**
+ * The senders of the Receivers returned by `shutdown`, which are closed once hotdog has been
+ * asked to stop, after which they are None
+ */
+#[cfg(windows)]
+static SHUTDOWNS: Mutex<Option<Vec<Sender<()>>>> = Mutex::new(Some(Vec::new()));
+/**
+ * The senders of the Receivers returned by `reload`
+ */
+#[cfg(windows)]
+static RELOADS: Mutex<Vec<Sender<()>>> = Mutex::new(Vec::new());
+#[cfg(windows)]
+static CTRL_C: Once = Once::new();
+
+/**
+ * Return a Receiver which will be closed once hotdog has been asked to shut down with either
+ * SIGTERM or SIGINT.
+ *
+ * Since closing a channel wakes up every receiver, the returned Receiver can be cloned and awaited
+ * by as many tasks as need to know about the shutdown. Every call returns the same Receiver, the
+ * signal handlers are only registered by the first.
+ */
+#[cfg(unix)]
+pub fn shutdown() -> Receiver<()> {
+    let mut shutdown = SHUTDOWN
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Some(rx) = shutdown.as_ref() {
+        return rx.clone();
+    }
+
+    let (tx, rx) = bounded(1);
+    *shutdown = Some(rx.clone());
+
+    match Signals::new(&[SIGTERM, SIGINT]) {
+        Ok(mut signals) => {
+            task::spawn(async move {
+                if let Some(signal) = signals.next().await {
+                    info!("Received signal {}, shutting down", signal);
+                    tx.close();
+                }
+            });
+        }
+        Err(e) => {
+            error!("Failed to register the shutdown signal handlers: {:?}", e);
+            /*
+             * Dropping the sender would close the channel, which means shutting down, so it is
+             * parked in a task which never finishes instead
+             */
+            task::spawn(async move {
+                let _tx = tx;
+                futures::future::pending::<()>().await;
+            });
+        }
+    }
+    rx
+}
+
+/**
+ * Return a Receiver which will be notified every time hotdog has been asked to reload its rules
+ * with SIGHUP.
+ *
+ * Multiple signals received before the notification has been handled only result in a single
+ * notification
+ */
+#[cfg(unix)]
+pub fn reload() -> Receiver<()> {
+    let (tx, rx) = bounded(1);
+
+    match Signals::new(&[SIGHUP]) {
+        Ok(mut signals) => {
+            task::spawn(async move {
+                while let Some(signal) = signals.next().await {
+                    info!("Received signal {}, reloading", signal);
+                    let _ = tx.try_send(());
+                }
+            });
+        }
+        Err(e) => {
+            error!("Failed to register the reload signal handler: {:?}", e);
+        }
+    }
+    rx
+}
+
+/**
+ * Return a Receiver which will be notified every time hotdog has been asked to dump its runtime
+ * statistics with SIGUSR1
+ */
+#[cfg(unix)]
+pub fn dump() -> Receiver<()> {
+    let (tx, rx) = bounded(1);
+
+    match Signals::new(&[SIGUSR1]) {
+        Ok(mut signals) => {
+            task::spawn(async move {
+                while let Some(signal) = signals.next().await {
+                    info!("Received signal {}, dumping the runtime statistics", signal);
+                    let _ = tx.try_send(());
+                }
+            });
+        }
+        Err(e) => {
+            error!("Failed to register the dump signal handler: {:?}", e);
+        }
+    }
+    rx
+}
+
+/**
+ * Return a Receiver which will be notified every time hotdog has been asked to restart itself
+ * without closing its listeners with SIGUSR2
+ */
+#[cfg(unix)]
+pub fn restart() -> Receiver<()> {
+    let (tx, rx) = bounded(1);
+
+    match Signals::new(&[SIGUSR2]) {
+        Ok(mut signals) => {
+            task::spawn(async move {
+                while let Some(signal) = signals.next().await {
+                    info!("Received signal {}, restarting", signal);
+                    let _ = tx.try_send(());
+                }
+            });
+        }
+        Err(e) => {
+            error!("Failed to register the restart signal handler: {:?}", e);
+        }
+    }
+    rx
+}
+
+/**
+ * Return a Receiver which will be closed once hotdog has been asked to shut down with Ctrl-C or
+ * by stopping its Windows service
+ */
+#[cfg(windows)]
+pub fn shutdown() -> Receiver<()> {
+    let (tx, rx) = bounded(1);
+
+    CTRL_C.call_once(|| {
+        if let Err(e) = ctrlc::set_handler(stop) {
+            error!("Failed to register the Ctrl-C handler: {:?}", e);
+        }
+    });
+
+    if let Ok(mut shutdowns) = SHUTDOWNS.lock() {
+        match shutdowns.as_mut() {
+            Some(shutdowns) => shutdowns.push(tx),
+            None => {
+                tx.close();
+            }
+        }
+    }
+    rx
+}
+
+/**
+ * Ask hotdog to shut down, closing every Receiver returned by `shutdown`
+ */
+#[cfg(windows)]
+pub fn stop() {
+    if let Ok(mut shutdowns) = SHUTDOWNS.lock() {
+        if let Some(shutdowns) = shutdowns.take() {
+            info!("Asked to stop, shutting down");
+            for tx in shutdowns.iter() {
+                tx.close();
+            }
+        }
+    }
+}
+
+/**
+ * Return a Receiver which will be notified every time hotdog has been asked to reload its rules
+ * with the `paramchange` control of its Windows service
+ */
+#[cfg(windows)]
+pub fn reload() -> Receiver<()> {
+    let (tx, rx) = bounded(1);
+
+    if let Ok(mut reloads) = RELOADS.lock() {
+        /*
+         * The Receivers of reloaded listeners are dropped along with them
+         */
+        reloads.retain(|tx| !tx.is_closed());
+        reloads.push(tx);
+    }
+    rx
+}
+
+/**
+ * Ask hotdog to reload, notifying every Receiver returned by `reload`
+ */
+#[cfg(windows)]
+pub fn request_reload() {
+    info!("Asked to reload");
+    if let Ok(mut reloads) = RELOADS.lock() {
+        reloads.retain(|tx| !matches!(tx.try_send(()), Err(TrySendError::Closed(_))));
+    }
+}
+
+/**
+ * Return a Receiver which is closed right away, since Windows has no signal to dump the runtime
+ * statistics with, which the status server reports as well
+ */
+#[cfg(windows)]
+pub fn dump() -> Receiver<()> {
+    let (_, rx) = bounded(1);
+    rx
+}
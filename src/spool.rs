@@ -0,0 +1,858 @@
+/**
+ * The spool module keeps the messages which could not be delivered while Kafka was unreachable in
+ * a size-capped write-ahead spool on disk, and replays them once Kafka can be reached again, so
+ * that a maintenance window of the brokers does not lose any logs
+ */
+use crate::kafka::{KafkaMessage, Produce, StatsContext};
+use crate::status::{Statistic, Stats};
+use async_channel::{bounded, unbounded, Receiver, Sender};
+use async_std::task;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use log::*;
+use parking_lot::Mutex;
+use rdkafka::producer::{FutureProducer, Producer};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/**
+ * The file which the messages are appended to
+ */
+const SPOOLED: &str = "spooled";
+/**
+ * The file which the messages are replayed from, a spool which was being replayed when hotdog
+ * stopped is replayed again once it starts
+ */
+const REPLAYING: &str = "replaying";
+/**
+ * The file which records the ids of the messages of the replayed file which have settled, so that
+ * a replay which was interrupted by hotdog stopping does not send them again
+ */
+const REPLAYED: &str = "replayed";
+/**
+ * The prefix of the files which the corrupt records of a spool are moved to when it is opened
+ */
+const QUARANTINED: &str = "quarantined";
+
+/**
+ * The spools which are open, by their directory, so that the producers started for reloaded Kafka
+ * settings share the spool of the producers they replace rather than opening it a second time
+ */
+static SPOOLS: std::sync::Mutex<Vec<(PathBuf, Weak<Spool>)>> = std::sync::Mutex::new(Vec::new());
+
+/**
+ * Spool appends messages to a file in its directory, every record being the topic, the key and
+ * the payload of a message, each prefixed by its length. The offset of a record in the file is its
+ * stable id while the file is being replayed
+ */
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: AtomicU64,
+    /**
+     * Whether the ids of the replayed messages which have settled are recorded, and skipped when
+     * the replay is resumed
+     */
+    dedupe: AtomicBool,
+    /**
+     * The size of the files of the spool, including the one which is being replayed
+     */
+    bytes: AtomicU64,
+    /**
+     * The file which is being appended to, which is only opened once there is a message to spool
+     */
+    file: Mutex<Option<BufWriter<File>>>,
+    /**
+     * The bytes which were moved out of the spool when it was opened, because they were not
+     * valid records, until they have been reported
+     */
+    quarantined: AtomicU64,
+}
+
+impl std::fmt::Debug for Spool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Spool")
+            .field("dir", &self.dir)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+impl Spool {
+    /**
+     * Return the spool in the directory, which is only opened if no producer has it open already.
+     * The limits of a spool which is already open are replaced by the given ones
+     */
+    pub fn shared(dir: &Path, max_bytes: u64, dedupe: bool) -> std::io::Result<Arc<Spool>> {
+        let mut spools = SPOOLS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        spools.retain(|(_, spool)| spool.strong_count() > 0);
+
+        if let Some(spool) = spools
+            .iter()
+            .find(|(opened, _)| opened == dir)
+            .and_then(|(_, spool)| spool.upgrade())
+        {
+            spool.max_bytes.store(max_bytes, Ordering::Relaxed);
+            spool.dedupe.store(dedupe, Ordering::Relaxed);
+            return Ok(spool);
+        }
+
+        let spool = Arc::new(Spool::open(dir, max_bytes, dedupe)?);
+        spools.push((dir.to_path_buf(), Arc::downgrade(&spool)));
+        Ok(spool)
+    }
+
+    /**
+     * Open the spool in the directory, creating the directory if needed, along with whatever
+     * was spooled there before hotdog last stopped.
+     *
+     * The records which were spooled before are validated, since hotdog may have stopped in the
+     * middle of writing one, and anything after the last valid record of a file is quarantined
+     */
+    pub fn open(dir: &Path, max_bytes: u64, dedupe: bool) -> std::io::Result<Spool> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut bytes = 0;
+        let mut quarantined = 0;
+        for name in [SPOOLED, REPLAYING].iter() {
+            let (valid, corrupt) = recover(&dir.join(name))?;
+            bytes += valid;
+            quarantined += corrupt;
+        }
+
+        if bytes > 0 {
+            info!(
+                "Recovered {} bytes of messages from the spool in {:?}, which are replayed once Kafka can be reached",
+                bytes, dir
+            );
+        }
+
+        Ok(Spool {
+            dir: dir.to_path_buf(),
+            max_bytes: AtomicU64::new(max_bytes),
+            dedupe: AtomicBool::new(dedupe),
+            bytes: AtomicU64::new(bytes),
+            file: Mutex::new(None),
+            quarantined: AtomicU64::new(quarantined),
+        })
+    }
+
+    /**
+     * Return the number of bytes which were quarantined when the spool was opened, only the first
+     * time, so that a shared spool is only reported once
+     */
+    pub fn take_quarantined(&self) -> u64 {
+        self.quarantined.swap(0, Ordering::Relaxed)
+    }
+
+    /**
+     * Return whether there are messages in the spool
+     */
+    pub fn is_empty(&self) -> bool {
+        self.bytes.load(Ordering::Relaxed) == 0
+    }
+
+    /**
+     * Append the message to the spool, returning false if the spool has no room for it. The
+     * message is written on a blocking thread, so that a slow disk does not hold up the executor
+     */
+    pub async fn append(self: &Arc<Self>, kmsg: &KafkaMessage) -> std::io::Result<bool> {
+        let record = encode(kmsg);
+        let spool = self.clone();
+
+        task::spawn_blocking(move || spool.write(&record)).await
+    }
+
+    /**
+     * Write the encoded record of a message to the spool, returning false if the spool has no room
+     * for it
+     */
+    fn write(&self, record: &[u8]) -> std::io::Result<bool> {
+        let mut file = self.file.lock();
+
+        if self.bytes.load(Ordering::Relaxed) + record.len() as u64
+            > self.max_bytes.load(Ordering::Relaxed)
+        {
+            return Ok(false);
+        }
+
+        if file.is_none() {
+            *file = Some(BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(SPOOLED))?,
+            ));
+        }
+
+        if let Some(file) = file.as_mut() {
+            file.write_all(record)?;
+            file.flush()?;
+        }
+        self.bytes.fetch_add(record.len() as u64, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /**
+     * Take the messages of the spool for replaying, the spool is appended to afresh from now on
+     */
+    fn take(&self) -> std::io::Result<Option<Replay>> {
+        let replaying = self.dir.join(REPLAYING);
+
+        /*
+         * A spool which was only partially replayed is finished before the spool is taken again
+         */
+        if !replaying.exists() {
+            let mut file = self.file.lock();
+            *file = None;
+
+            match std::fs::rename(self.dir.join(SPOOLED), &replaying) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            /*
+             * The ids which were recorded for a previous replayed file do not belong to this one
+             */
+            remove_if_exists(&self.dir.join(REPLAYED))?;
+        }
+
+        let settled = if self.dedupe.load(Ordering::Relaxed) {
+            self.settled()?
+        } else {
+            HashSet::new()
+        };
+        let file = File::open(&replaying)?;
+        let bytes = file.metadata()?.len();
+        Ok(Some(Replay {
+            path: replaying,
+            bytes,
+            reader: BufReader::new(file),
+            offset: 0,
+            settled,
+        }))
+    }
+
+    /**
+     * Return the ids of the messages of the replayed file which have already settled
+     */
+    fn settled(&self) -> std::io::Result<HashSet<u64>> {
+        let mut ids = vec![];
+
+        match File::open(self.dir.join(REPLAYED)) {
+            Ok(mut file) => file.read_to_end(&mut ids)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        /*
+         * An id which was only partially written when hotdog stopped is ignored
+         */
+        Ok(ids
+            .chunks_exact(8)
+            .map(|id| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(id);
+                u64::from_be_bytes(bytes)
+            })
+            .collect())
+    }
+
+    /**
+     * Open the file which the ids of the replayed messages which have been delivered, failed or
+     * spooled again are appended to, so that they are not replayed again
+     */
+    fn recorder(&self) -> std::io::Result<BufWriter<File>> {
+        Ok(BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(REPLAYED))?,
+        ))
+    }
+
+    /**
+     * Forget the replayed file, once every message in it has been sent again
+     */
+    fn replayed(&self, replay: Replay) -> std::io::Result<()> {
+        std::fs::remove_file(&replay.path)?;
+        remove_if_exists(&self.dir.join(REPLAYED))?;
+        self.bytes.fetch_sub(
+            replay.bytes.min(self.bytes.load(Ordering::Relaxed)),
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+}
+
+/**
+ * The messages of a spool which are being replayed
+ */
+struct Replay {
+    path: PathBuf,
+    bytes: u64,
+    reader: BufReader<File>,
+    /**
+     * The offset of the next record, which is its id
+     */
+    offset: u64,
+    /**
+     * The ids of the messages which settled before the replay was interrupted
+     */
+    settled: HashSet<u64>,
+}
+
+impl Iterator for Replay {
+    type Item = (u64, KafkaMessage);
+
+    /**
+     * Read the next message which has not settled yet along with its id, or None once every
+     * message has been read. A record which was only partially written when hotdog stopped ends
+     * the replay
+     */
+    fn next(&mut self) -> Option<(u64, KafkaMessage)> {
+        loop {
+            let id = self.offset;
+            let topic = read_field(&mut self.reader)?;
+            let key = read_field(&mut self.reader)?;
+            let msg = read_field(&mut self.reader)?;
+            self.offset += (12 + topic.len() + key.len() + msg.len()) as u64;
+
+            if self.settled.contains(&id) {
+                continue;
+            }
+
+            let topic = String::from_utf8(topic).ok()?;
+            let key = if key.is_empty() {
+                None
+            } else {
+                Some(String::from_utf8(key).ok()?)
+            };
+            return Some((id, KafkaMessage::new(topic, msg).with_key(key)));
+        }
+    }
+}
+
+/**
+ * Replay the spool to the sendloop every interval, once the brokers of the producer can be reached
+ * again
+ */
+pub async fn replay_loop(
+    spool: Arc<Spool>,
+    producer: FutureProducer<StatsContext>,
+    sender: Sender<Produce>,
+    stats: Sender<Statistic>,
+    interval: Duration,
+) {
+    while !sender.is_closed() {
+        task::sleep(interval).await;
+
+        if spool.is_empty() || !reachable(&producer, interval).await {
+            continue;
+        }
+
+        let mut replay = match spool.take() {
+            Ok(Some(replay)) => replay,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Failed to replay the spool in {:?}: {}", spool.dir, e);
+                continue;
+            }
+        };
+        info!(
+            "Kafka can be reached again, replaying the spool in {:?}",
+            spool.dir
+        );
+
+        /*
+         * The replayed file is only forgotten once every message in it has settled, which closes
+         * the channel, and once the recorder has written the ids of all of them
+         */
+        let (all_settled, waiting) = bounded::<()>(1);
+        let (settling, recording) = unbounded::<(u64, Receiver<()>)>();
+        let recorder = if spool.dedupe.load(Ordering::Relaxed) {
+            Some(task::spawn(record_settled(
+                spool.clone(),
+                sender.clone(),
+                recording,
+            )))
+        } else {
+            None
+        };
+
+        let mut replayed = 0;
+        for (id, kmsg) in replay.by_ref() {
+            let kmsg = if recorder.is_some() {
+                let (settled, waiting) = bounded::<()>(1);
+                let _ = settling.send((id, waiting)).await;
+                kmsg.with_settled(settled)
+            } else {
+                kmsg.with_settled(all_settled.clone())
+            };
+
+            if sender.send(Produce::Message(kmsg)).await.is_err() {
+                /*
+                 * The sendloop has stopped, the rest of the spool is replayed by the next one
+                 */
+                return;
+            }
+            replayed += 1;
+        }
+        drop(all_settled);
+        drop(settling);
+        let _ = waiting.recv().await;
+        if let Some(recorder) = recorder {
+            recorder.await;
+        }
+        stats.send((Stats::KafkaMsgReplayed, replayed)).await;
+        info!("Replayed {} messages from the spool", replayed);
+
+        if let Err(e) = spool.replayed(replay) {
+            error!(
+                "Failed to remove the replayed spool in {:?}: {}",
+                spool.dir, e
+            );
+        }
+    }
+}
+
+/**
+ * Record the ids of the replayed messages as they settle, which is signalled by the channel of
+ * each of them closing. A single task waits on every message and appends their ids through a
+ * buffer, so a few of the ids may be lost if hotdog stops abruptly, which only means that those
+ * messages are replayed again
+ */
+async fn record_settled(
+    spool: Arc<Spool>,
+    sender: Sender<Produce>,
+    settling: Receiver<(u64, Receiver<()>)>,
+) {
+    let mut pending = FuturesUnordered::new();
+    let mut recorder = match spool.recorder() {
+        Ok(recorder) => Some(recorder),
+        Err(e) => {
+            error!(
+                "Failed to record the replayed messages in the spool in {:?}: {}",
+                spool.dir, e
+            );
+            None
+        }
+    };
+    let mut receiving = true;
+
+    loop {
+        let id = if receiving {
+            futures::select! {
+                received = settling.recv().fuse() => {
+                    match received {
+                        Ok((id, waiting)) => pending.push(async move {
+                            let _ = waiting.recv().await;
+                            id
+                        }),
+                        Err(_) => receiving = false,
+                    }
+                    continue;
+                }
+                id = pending.select_next_some() => id,
+            }
+        } else {
+            match pending.next().await {
+                Some(id) => id,
+                None => break,
+            }
+        };
+
+        /*
+         * A message which is dropped because the sendloop has stopped has not been delivered,
+         * and is left to be replayed again
+         */
+        if sender.is_closed() {
+            continue;
+        }
+        if let Some(writer) = recorder.as_mut() {
+            if let Err(e) = writer.write_all(&id.to_be_bytes()) {
+                error!(
+                    "Failed to record a replayed message in the spool in {:?}: {}",
+                    spool.dir, e
+                );
+                recorder = None;
+            }
+        }
+    }
+
+    if let Some(mut writer) = recorder {
+        if let Err(e) = writer.flush() {
+            error!(
+                "Failed to record the replayed messages in the spool in {:?}: {}",
+                spool.dir, e
+            );
+        }
+    }
+}
+
+/**
+ * Validate the records of the spooled file, moving the corrupt segments of it into a quarantined
+ * file of their own so that the valid records before and after them are still replayed, and any
+ * further records are appended after them. Returns the size of the valid records and of the
+ * quarantined bytes
+ */
+fn recover(path: &Path) -> std::io::Result<(u64, u64)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut offset = 0;
+    let mut corrupt = vec![];
+
+    while offset < len {
+        match valid_record(&mut reader, len - offset) {
+            Some(size) => offset += size,
+            None => {
+                let end = resync(&mut reader, offset + 1, len)?;
+                corrupt.push((offset, end));
+                offset = end;
+                reader.seek(SeekFrom::Start(offset))?;
+            }
+        }
+    }
+
+    if corrupt.is_empty() {
+        return Ok((len, 0));
+    }
+
+    let quarantined: u64 = corrupt.iter().map(|(start, end)| end - start).sum();
+    let quarantine = path.with_file_name(format!("{}-{}", QUARANTINED, uuid::Uuid::new_v4()));
+    warn!(
+        "The spooled file {:?} has {} corrupt bytes in {} segments, quarantining them in {:?}",
+        path,
+        quarantined,
+        corrupt.len(),
+        quarantine
+    );
+
+    /*
+     * The valid records are copied to a file of their own which then replaces the spooled file,
+     * so that a crash while recovering leaves the spooled file as it was
+     */
+    let recovering = path.with_extension("recovering");
+    let mut source = File::open(path)?;
+    let mut valid = BufWriter::new(File::create(&recovering)?);
+    let mut quarantined_file = BufWriter::new(File::create(&quarantine)?);
+    let mut copied = 0;
+    for (start, end) in corrupt.iter() {
+        copy_range(&mut source, copied, *start, &mut valid)?;
+        copy_range(&mut source, *start, *end, &mut quarantined_file)?;
+        copied = *end;
+    }
+    copy_range(&mut source, copied, len, &mut valid)?;
+    quarantined_file.flush()?;
+    valid.into_inner()?.sync_all()?;
+    std::fs::rename(&recovering, path)?;
+
+    Ok((len - quarantined, quarantined))
+}
+
+/**
+ * Find the offset after a corrupt record at which the valid records continue, which is the first
+ * offset of a valid record followed either by another valid record or by the end of the file. A
+ * file without one is corrupt up to its end
+ */
+fn resync<R: Read + Seek>(reader: &mut R, from: u64, len: u64) -> std::io::Result<u64> {
+    for candidate in from..len {
+        reader.seek(SeekFrom::Start(candidate))?;
+
+        if let Some(size) = valid_record(reader, len - candidate) {
+            let next = candidate + size;
+            if next == len || valid_record(reader, len - next).is_some() {
+                return Ok(candidate);
+            }
+        }
+    }
+    Ok(len)
+}
+
+/**
+ * Copy the bytes of the file between the offsets to the writer
+ */
+fn copy_range<W: Write>(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    std::io::copy(&mut file.take(end - start), writer)?;
+    Ok(())
+}
+
+/**
+ * Read a record, returning its size if it is complete and could be replayed
+ */
+fn valid_record<R: Read>(reader: &mut R, remaining: u64) -> Option<u64> {
+    let mut size = 0;
+
+    for field in 0..3 {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len).ok()?;
+        let len = u64::from(u32::from_be_bytes(len));
+        size += 4 + len;
+
+        /*
+         * The length of a field which was not written completely may be anything
+         */
+        if size > remaining {
+            return None;
+        }
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes).ok()?;
+
+        /*
+         * The topic and the key must be valid UTF-8, and every message has a topic
+         */
+        if (field < 2 && std::str::from_utf8(&bytes).is_err()) || (field == 0 && bytes.is_empty()) {
+            return None;
+        }
+    }
+    Some(size)
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/**
+ * Return whether the brokers of the producer can be reached, by fetching the metadata of the
+ * cluster
+ */
+async fn reachable(producer: &FutureProducer<StatsContext>, timeout: Duration) -> bool {
+    let producer = producer.clone();
+
+    task::spawn_blocking(move || producer.client().fetch_metadata(None, timeout).is_ok()).await
+}
+
+/**
+ * Encode the topic, the key and the payload of the message as a record of the spool
+ */
+fn encode(kmsg: &KafkaMessage) -> Vec<u8> {
+    let key = kmsg.key().unwrap_or_default().as_bytes();
+    let fields: [&[u8]; 3] = [kmsg.topic().as_bytes(), key, kmsg.payload()];
+    let mut record = Vec::with_capacity(fields.iter().map(|field| 4 + field.len()).sum());
+
+    for field in fields.iter() {
+        record.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        record.extend_from_slice(field);
+    }
+    record
+}
+
+fn read_field<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).ok()?;
+
+    let mut field = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut field).ok()?;
+    Some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_spool_round_trip() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to open the spool"));
+        assert!(spool.is_empty());
+
+        let keyed = KafkaMessage::new("logs".to_string(), b"hello".to_vec())
+            .with_key(Some("host".to_string()));
+        assert!(spool.append(&keyed).await.expect("Failed to spool"));
+        assert!(spool
+            .append(&KafkaMessage::new("audit".to_string(), b"world".to_vec()))
+            .await
+            .expect("Failed to spool"));
+
+        /*
+         * Reopening the spool finds the messages which were spooled before
+         */
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to reopen the spool"));
+        assert!(!spool.is_empty());
+
+        let mut replay = spool.take().expect("Failed to take the spool").unwrap();
+        let (id, first) = replay.next().unwrap();
+        assert_eq!(0, id);
+        assert_eq!("logs", first.topic());
+        assert_eq!(Some("host"), first.key());
+        assert_eq!(b"hello", first.payload());
+        let (_, second) = replay.next().unwrap();
+        assert_eq!("audit", second.topic());
+        assert_eq!(None, second.key());
+        assert!(replay.next().is_none());
+
+        spool.replayed(replay).expect("Failed to forget the replay");
+        assert!(spool.is_empty());
+        assert!(spool.take().expect("Failed to take the spool").is_none());
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+
+    #[test]
+    fn test_spool_shared() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Spool::shared(&dir, 1024, true).expect("Failed to open the spool");
+        let shared = Spool::shared(&dir, 32, false).expect("Failed to share the spool");
+
+        assert!(Arc::ptr_eq(&spool, &shared));
+        assert_eq!(32, spool.max_bytes.load(Ordering::Relaxed));
+        drop(spool);
+        drop(shared);
+
+        let spool = Spool::shared(&dir, 1024, true).expect("Failed to reopen the spool");
+        assert_eq!(1024, spool.max_bytes.load(Ordering::Relaxed));
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+
+    #[async_std::test]
+    async fn test_spool_full() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, 32, true).expect("Failed to open the spool"));
+        let kmsg = KafkaMessage::new("logs".to_string(), b"hello".to_vec());
+
+        /* The record of the message takes 4 + 4 + 4 + 4 + 5 bytes */
+        assert!(spool.append(&kmsg).await.expect("Failed to spool"));
+        assert!(!spool.append(&kmsg).await.expect("Failed to spool"));
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+
+    #[async_std::test]
+    async fn test_spool_resumed_replay_skips_settled() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to open the spool"));
+        for payload in ["first", "second", "third"].iter() {
+            let kmsg = KafkaMessage::new("logs".to_string(), payload.as_bytes().to_vec());
+            assert!(spool.append(&kmsg).await.expect("Failed to spool"));
+        }
+
+        let mut replay = spool.take().expect("Failed to take the spool").unwrap();
+        let (first, _) = replay.next().unwrap();
+        let (second, _) = replay.next().unwrap();
+        assert_ne!(first, second);
+
+        let (sender, _sunk) = bounded::<Produce>(1);
+        let (settling, recording) = unbounded();
+        let recorder = task::spawn(record_settled(spool.clone(), sender, recording));
+        let (settled, waiting) = bounded::<()>(1);
+        settling
+            .send((first, waiting))
+            .await
+            .expect("Failed to wait for the message to settle");
+        drop(settled);
+        drop(settling);
+        recorder.await;
+
+        /*
+         * Only the messages which had not settled when hotdog stopped are replayed again
+         */
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to reopen the spool"));
+        let mut replay = spool.take().expect("Failed to take the spool").unwrap();
+        let (id, kmsg) = replay.next().unwrap();
+        assert_eq!(second, id);
+        assert_eq!(b"second", kmsg.payload());
+        assert_eq!(b"third", replay.next().unwrap().1.payload());
+        assert!(replay.next().is_none());
+
+        /*
+         * Without dedupe every message is replayed again
+         */
+        let spool = Arc::new(Spool::open(&dir, 1024, false).expect("Failed to reopen the spool"));
+        let replay = spool.take().expect("Failed to take the spool").unwrap();
+        assert_eq!(3, replay.count());
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+
+    #[async_std::test]
+    async fn test_spool_quarantines_corrupt_tail() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to open the spool"));
+        let kmsg = KafkaMessage::new("logs".to_string(), b"hello".to_vec());
+        assert!(spool.append(&kmsg).await.expect("Failed to spool"));
+
+        /*
+         * A record which hotdog stopped in the middle of writing
+         */
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(SPOOLED))
+            .expect("Failed to open the spooled file");
+        file.write_all(&[0, 0, 0, 4, b'l', b'o'])
+            .expect("Failed to write the partial record");
+
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to reopen the spool"));
+        assert_eq!(6, spool.take_quarantined());
+        assert_eq!(0, spool.take_quarantined());
+        let quarantined = std::fs::read_dir(&dir)
+            .expect("Failed to list the spool")
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(QUARANTINED))
+            .count();
+        assert_eq!(1, quarantined);
+
+        /*
+         * The records appended after the recovery follow the valid records
+         */
+        assert!(spool
+            .append(&KafkaMessage::new("audit".to_string(), b"world".to_vec()))
+            .await
+            .expect("Failed to spool"));
+        let replay = spool.take().expect("Failed to take the spool").unwrap();
+        let topics: Vec<String> = replay.map(|(_, kmsg)| kmsg.topic().to_string()).collect();
+        assert_eq!(vec!["logs", "audit"], topics);
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+
+    #[async_std::test]
+    async fn test_spool_quarantines_corrupt_segment() {
+        let dir = std::env::temp_dir().join(format!("hotdog-spool-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to open the spool"));
+        assert!(spool
+            .append(&KafkaMessage::new("logs".to_string(), b"hello".to_vec()))
+            .await
+            .expect("Failed to spool"));
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join(SPOOLED))
+            .expect("Failed to open the spooled file")
+            .write_all(&[0xff, 0xff, 0xff, 0xff])
+            .expect("Failed to write the corrupt bytes");
+        assert!(spool
+            .append(&KafkaMessage::new("audit".to_string(), b"world".to_vec()))
+            .await
+            .expect("Failed to spool"));
+
+        /*
+         * Only the corrupt bytes are quarantined, the records after them are still replayed
+         */
+        let spool = Arc::new(Spool::open(&dir, 1024, true).expect("Failed to reopen the spool"));
+        assert_eq!(4, spool.take_quarantined());
+        let replay = spool.take().expect("Failed to take the spool").unwrap();
+        let topics: Vec<String> = replay.map(|(_, kmsg)| kmsg.topic().to_string()).collect();
+        assert_eq!(vec!["logs", "audit"], topics);
+
+        std::fs::remove_dir_all(&dir).expect("Failed to remove the spool");
+    }
+}
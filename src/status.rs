@@ -4,14 +4,26 @@
  *
  * The status module is also responsible for dispatching _all_ statsd metrics.
  */
+use crate::prometheus::{self, Kind, Label, Registry};
+use crate::reload::Reloadable;
+use crate::settings::MetricsOutput;
+use crate::tagged::Tagged;
+use crate::version;
 use async_channel::{bounded, Receiver, Sender};
 use async_std::sync::Arc;
 use dashmap::DashMap;
-use dipstick::{InputScope, StatsdScope};
+use dipstick::{
+    AtomicBucket, Cancel, CancelHandle, Flush, Graphite, Input, InputScope, Prefixed, Sampled,
+    Sampling, ScheduleFlush, Statsd, Stream,
+};
 use log::*;
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tide::{Body, Request, Response, StatusCode};
 
 /**
@@ -24,6 +36,281 @@ struct HealthResponse {
     stats: HashMap<String, i64>,
 }
 
+/**
+ * StatusResponse is the summary of the running hotdog which the /status endpoint responds with
+ */
+#[derive(Deserialize, Serialize)]
+struct StatusResponse {
+    version: String,
+    /**
+     * The commit hotdog was built from
+     */
+    commit: String,
+    uptime_secs: u64,
+    connections: i64,
+    /**
+     * The number of messages waiting in hotdog's internal queue for the Kafka producers
+     */
+    queue_depth: i64,
+    /**
+     * The number of messages waiting in librdkafka's queue to be delivered
+     */
+    producer_queue_depth: i64,
+    /**
+     * The number of messages each named rule has matched
+     */
+    rules: HashMap<String, i64>,
+    config_hash: Option<String>,
+    /**
+     * The destinations whose Kafka producer could not be created or connected yet
+     */
+    unavailable: Vec<String>,
+}
+
+/**
+ * The interval between the summaries of the throughput which are logged
+ */
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/**
+ * Log a summary of the throughput since the last summary every interval, so that it is visible in
+ * the logs even without a metrics backend
+ */
+pub async fn summary_loop(stats: Arc<StatsHandler>) {
+    let mut previous = stats.totals();
+    let mut since = Instant::now();
+
+    loop {
+        async_std::task::sleep(SUMMARY_INTERVAL).await;
+
+        let totals = stats.totals();
+        info!("{}", totals.summary(&previous, since.elapsed()));
+        previous = totals;
+        since = Instant::now();
+    }
+}
+
+/**
+ * The interval between the reports of the counters to the metrics scope
+ */
+const COUNTER_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * Report the counts of the counters to the metrics scope every interval, so that counting a line
+ * is only an atomic add rather than a trip through the metrics output
+ */
+pub async fn counter_loop(stats: Arc<StatsHandler>) {
+    loop {
+        async_std::task::sleep(COUNTER_INTERVAL).await;
+        stats.report_counters().await;
+    }
+}
+
+/**
+ * The totals of the counters which the logged summaries report the change of
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub lines: i64,
+    pub matches: i64,
+    pub forwarded: i64,
+    pub errors: i64,
+}
+
+impl Totals {
+    /**
+     * Describe the change of the totals since the previous totals, which were taken the elapsed
+     * time ago
+     */
+    fn summary(&self, previous: &Totals, elapsed: Duration) -> String {
+        let lines = self.lines - previous.lines;
+        let secs = elapsed.as_secs_f64().max(1.0);
+
+        format!(
+            "In the last {}s: {} lines ({:.1}/s), {} matches, {} forwarded, {} errors",
+            elapsed.as_secs(),
+            lines,
+            lines as f64 / secs,
+            self.matches - previous.matches,
+            self.forwarded - previous.forwarded,
+            self.errors - previous.errors
+        )
+    }
+}
+
+/**
+ * Log the runtime statistics report every time the receiver is notified, until it is closed
+ */
+pub async fn dump_loop(
+    stats: Arc<StatsHandler>,
+    reloadable: Arc<Reloadable>,
+    notifications: Receiver<()>,
+) {
+    while notifications.recv().await.is_ok() {
+        info!("{}", stats.report(reloadable.sinks().sender.len()));
+    }
+}
+
+/**
+ * How often the active connections are counted while they are draining
+ */
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/**
+ * The active connections, which are registered for as long as they are open so that the runtime
+ * statistics report can list them
+ */
+pub struct Connections {
+    next: AtomicU64,
+    active: DashMap<u64, Arc<Peer>>,
+    /**
+     * The lines and the bytes read by every connection which have not been counted yet, which are
+     * added to directly rather than sending a statistic for every line
+     */
+    lines: AtomicI64,
+    bytes: AtomicI64,
+    /**
+     * Closed once hotdog is shutting down, which wakes up every connection waiting on `closing`
+     */
+    draining: Sender<()>,
+    closing: Receiver<()>,
+}
+
+impl Default for Connections {
+    fn default() -> Connections {
+        let (draining, closing) = bounded(1);
+
+        Connections {
+            next: AtomicU64::new(0),
+            active: DashMap::new(),
+            lines: AtomicI64::new(0),
+            bytes: AtomicI64::new(0),
+            draining,
+            closing,
+        }
+    }
+}
+
+impl Connections {
+    /**
+     * Register a connection from the peer, which is active until the returned Tracked is dropped
+     */
+    pub fn open(self: &Arc<Self>, addr: Option<SocketAddr>) -> Tracked {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let peer = Arc::new(Peer {
+            addr,
+            opened: Instant::now(),
+            lines: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        });
+        self.active.insert(id, peer.clone());
+
+        Tracked {
+            id,
+            connections: self.clone(),
+            peer,
+        }
+    }
+
+    /**
+     * Take the lines and the bytes which have been read since they were last taken
+     */
+    fn take_received(&self) -> (i64, i64) {
+        (
+            self.lines.swap(0, Ordering::Relaxed),
+            self.bytes.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    /**
+     * Return the number of active connections
+     */
+    pub fn count(&self) -> usize {
+        self.active.len()
+    }
+
+    /**
+     * Ask every active connection to stop reading once it has handled its current line, and wait
+     * for up to the grace period for them to close, returning the number which are still open
+     */
+    pub async fn drain(&self, grace: Duration) -> usize {
+        self.draining.close();
+        self.wait(grace).await
+    }
+
+    /**
+     * Wait for up to the timeout for every active connection to close, returning the number which
+     * are still open
+     */
+    pub async fn wait(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+
+        while self.count() > 0 && Instant::now() < deadline {
+            async_std::task::sleep(DRAIN_INTERVAL).await;
+        }
+        self.count()
+    }
+}
+
+/**
+ * The statistics of a single active connection
+ */
+pub struct Peer {
+    addr: Option<SocketAddr>,
+    opened: Instant,
+    lines: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/**
+ * Tracked records the statistics of a connection, and removes it from the active connections once
+ * it is dropped along with the connection
+ */
+pub struct Tracked {
+    id: u64,
+    connections: Arc<Connections>,
+    peer: Arc<Peer>,
+}
+
+impl Tracked {
+    /**
+     * Count a line which was read from the connection
+     */
+    pub fn line(&self) {
+        self.peer.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Count a line which was parsed, for the `lines` counter
+     */
+    pub fn line_received(&self) {
+        self.connections.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Count the bytes which were read from the connection
+     */
+    pub fn received(&self, bytes: usize) {
+        self.peer.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.connections
+            .bytes
+            .fetch_add(bytes as i64, Ordering::Relaxed);
+    }
+
+    /**
+     * Wait until the connections are being drained for the shutdown
+     */
+    pub async fn closing(&self) {
+        let _ = self.connections.closing.recv().await;
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.connections.active.remove(&self.id);
+    }
+}
+
 /**
  * Launch the status server
  */
@@ -46,36 +333,519 @@ pub async fn status_server(
             Ok(res)
         });
 
+    app.at("/status")
+        .get(|req: Request<Arc<StatsHandler>>| async move {
+            let status = req.state().status();
+
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(Body::from_json(&status)?);
+            Ok(res)
+        });
+
+    app.at("/ready")
+        .get(|req: Request<Arc<StatsHandler>>| async move {
+            let unavailable = req.state().unavailable();
+
+            if unavailable.is_empty() {
+                return Ok(Response::new(StatusCode::Ok));
+            }
+            Ok(Response::builder(StatusCode::ServiceUnavailable)
+                .body(format!(
+                    "Kafka is unavailable for: {}",
+                    unavailable.join(", ")
+                ))
+                .content_type(tide::http::mime::PLAIN)
+                .build())
+        });
+
+    app.at("/metrics")
+        .get(|req: Request<Arc<StatsHandler>>| async move {
+            Ok(Response::builder(StatusCode::Ok)
+                .body(req.state().prometheus.render())
+                .content_type(tide::http::mime::PLAIN)
+                .build())
+        });
+
     app.listen(listen_to).await?;
     Ok(())
 }
 
+/**
+ * The topic which the metrics of the topics beyond `max_topics` are reported for
+ */
+const OTHER_TOPIC: &str = "_other";
+
+/**
+ * The peer which the metrics of the peers beyond `max_peers` are reported for
+ */
+const OTHER_PEER: &str = "_other";
+
+/**
+ * The name which the metrics of the metric actions beyond `max_metrics` names are reported for
+ */
+const OTHER_METRIC: &str = "_other";
+
 /**
  * Simple type for tracking our statistics as time goes on
  */
 type ThreadsafeStats = Arc<DashMap<String, i64>>;
 pub type Statistic = (Stats, i64);
+/**
+ * The dipstick scope of the configured metrics output
+ */
+type MetricsScope = Arc<dyn InputScope + Send + Sync>;
+
+/**
+ * The outputs which the metrics are sent to
+ */
+#[derive(Clone)]
+pub struct Metrics {
+    scope: MetricsScope,
+    /**
+     * Whether only a sample of the metrics is sent, which the counters are not summed up for
+     */
+    sampled: bool,
+    /**
+     * Sends the statsd metrics with tags rather than the scope, when they are tagged
+     */
+    tagged: Option<Arc<Tagged>>,
+    /**
+     * Flushes the aggregated metrics every interval, when `flush_interval_ms` is set
+     */
+    flusher: Option<Arc<Flusher>>,
+}
+
+impl Metrics {
+    /**
+     * Metrics which are recorded but never sent anywhere, for when there is no metrics backend or
+     * it cannot be reached
+     */
+    pub fn none() -> Metrics {
+        Metrics {
+            scope: Arc::new(AtomicBucket::new()),
+            sampled: false,
+            tagged: None,
+            flusher: None,
+        }
+    }
+}
+
+/**
+ * Create the outputs of the configured type which all of the metrics are sent to
+ */
+pub fn metrics_for(settings: &crate::settings::Metrics) -> std::io::Result<Metrics> {
+    let prefix = settings.prefix.as_str();
+    let (scope, flusher) = match settings.output {
+        MetricsOutput::Statsd => {
+            let sampling = if settings.sample_rate < 1.0 {
+                Sampling::Random(settings.sample_rate)
+            } else {
+                Sampling::Full
+            };
+            scope_for(
+                Statsd::send_to(&settings.statsd)?
+                    .sampled(sampling)
+                    .named(prefix),
+                settings.flush_interval_ms,
+            )
+        }
+        MetricsOutput::Graphite => scope_for(
+            Graphite::send_to(&settings.graphite)?.named(prefix),
+            settings.flush_interval_ms,
+        ),
+        MetricsOutput::Log => scope_for(
+            dipstick::Log::to_log().named(prefix),
+            settings.flush_interval_ms,
+        ),
+        MetricsOutput::Stdout => scope_for(
+            Stream::write_to_stdout().named(prefix),
+            settings.flush_interval_ms,
+        ),
+        MetricsOutput::None => return Ok(Metrics::none()),
+    };
+    let tagged = Tagged::connect(settings)?.map(Arc::new);
+
+    Ok(Metrics {
+        scope,
+        sampled: settings.output == MetricsOutput::Statsd && settings.sample_rate < 1.0,
+        tagged,
+        flusher,
+    })
+}
+
+/**
+ * Return the scope of the output, which aggregates the metrics in a bucket that is flushed to
+ * the output every interval when there is one
+ */
+fn scope_for<I: Input + Send + Sync + 'static>(
+    output: I,
+    flush_interval_ms: Option<u64>,
+) -> (MetricsScope, Option<Arc<Flusher>>)
+where
+    I::SCOPE: Send + Sync + 'static,
+{
+    match flush_interval_ms {
+        Some(interval) => {
+            let bucket = AtomicBucket::new();
+            bucket.drain(output);
+            let handle = bucket.flush_every(std::time::Duration::from_millis(interval));
+
+            (
+                Arc::new(bucket.clone()),
+                Some(Arc::new(Flusher { bucket, handle })),
+            )
+        }
+        None => (Arc::new(output.metrics()), None),
+    }
+}
+
+/**
+ * Flusher stops flushing the bucket once the metrics settings have been replaced, flushing what
+ * is left in it one last time
+ */
+struct Flusher {
+    bucket: AtomicBucket,
+    handle: CancelHandle,
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.handle.cancel();
+
+        if let Err(e) = self.bucket.flush() {
+            error!("Failed to flush the aggregated metrics: {:?}", e);
+        }
+    }
+}
 
 pub struct StatsHandler {
     values: ThreadsafeStats,
-    metrics: Arc<StatsdScope>,
+    /**
+     * The metrics scope, which is swapped out when the metrics settings are reloaded
+     */
+    metrics: RwLock<Metrics>,
+    /**
+     * The counts of the untagged statsd counters which have not been reported yet, each counter
+     * is bound once and then only ever added to until `counter_loop` reports it
+     */
+    counters: DashMap<String, Arc<AtomicI64>>,
+    /**
+     * The metrics which are exposed to Prometheus on the status server
+     */
+    prometheus: Registry,
+    /**
+     * The topics which have metrics of their own, so that templated topics cannot explode the
+     * cardinality of the metrics
+     */
+    topics: DashMap<String, ()>,
+    max_topics: AtomicUsize,
+    /**
+     * Whether more than `max_topics` topics have been seen, which is only warned about once
+     */
+    topics_overflowed: AtomicBool,
+    /**
+     * The peers which have metrics of their own, so that many senders cannot explode the
+     * cardinality of the metrics
+     */
+    peers: DashMap<String, ()>,
+    max_peers: AtomicUsize,
+    /**
+     * Whether more than `max_peers` peers have been seen, which is only warned about once
+     */
+    peers_overflowed: AtomicBool,
+    /**
+     * The names which the metric actions have metrics of their own for, so that templated names
+     * cannot explode the cardinality of the metrics
+     */
+    log_metrics: DashMap<String, ()>,
+    max_metrics: AtomicUsize,
+    /**
+     * Whether more than `max_metrics` names have been seen, which is only warned about once
+     */
+    metrics_overflowed: AtomicBool,
+    /**
+     * The number of messages each named rule has matched, for the /status endpoint
+     */
+    rules: DashMap<String, i64>,
+    /**
+     * The hash of the configuration which was loaded most recently
+     */
+    config_hash: RwLock<Option<String>>,
+    /**
+     * The active connections, for the runtime statistics report
+     */
+    connections: Arc<Connections>,
+    started: Instant,
     rx: Receiver<Statistic>,
     pub tx: Sender<Statistic>,
 }
 
 impl StatsHandler {
-    pub fn new(metrics: Arc<StatsdScope>) -> Self {
+    pub fn new(metrics: Metrics, max_topics: usize, max_peers: usize, max_metrics: usize) -> Self {
         let (tx, rx) = bounded(1_000_000);
         let values = Arc::new(DashMap::default());
 
         StatsHandler {
             values,
-            metrics,
+            metrics: RwLock::new(metrics),
+            counters: DashMap::default(),
+            prometheus: Registry::new(),
+            topics: DashMap::default(),
+            max_topics: AtomicUsize::new(max_topics),
+            topics_overflowed: AtomicBool::new(false),
+            peers: DashMap::default(),
+            max_peers: AtomicUsize::new(max_peers),
+            peers_overflowed: AtomicBool::new(false),
+            log_metrics: DashMap::default(),
+            max_metrics: AtomicUsize::new(max_metrics),
+            metrics_overflowed: AtomicBool::new(false),
+            rules: DashMap::default(),
+            config_hash: RwLock::new(None),
+            connections: Arc::new(Connections::default()),
+            started: Instant::now(),
             rx,
             tx,
         }
     }
 
+    /**
+     * Send all of the metrics recorded from now on to the new metrics scope
+     */
+    pub fn set_metrics(&self, metrics: Metrics) {
+        /* The counts so far belong to the metrics scope which is being replaced */
+        self.flush_counters();
+        *self.metrics.write() = metrics;
+    }
+
+    /**
+     * Count the lines and the bytes which the connections have read, and report the counts which
+     * have been added to the counters since they were last reported
+     */
+    pub async fn report_counters(&self) {
+        let (lines, bytes) = self.connections.take_received();
+
+        if lines > 0 {
+            self.handle_counter(Stats::LineReceived, lines).await;
+        }
+        if bytes > 0 {
+            self.handle_counter(Stats::BytesReceived, bytes).await;
+        }
+        self.flush_counters();
+    }
+
+    /**
+     * Report the counts which have been added to the counters since they were last reported
+     */
+    fn flush_counters(&self) {
+        let metrics = self.metrics();
+
+        for entry in self.counters.iter() {
+            let count = entry.value().swap(0, Ordering::Relaxed);
+
+            if let Ok(sized_count) = count.try_into() {
+                if sized_count > 0 {
+                    metrics.counter(entry.key()).count(sized_count);
+                }
+            }
+        }
+    }
+
+    /**
+     * Add to the counter of the key, binding it the first time the key is counted. Sampled
+     * metrics are counted right away instead, since sampling the counts of a whole interval would
+     * drop every count of the intervals which are not sampled
+     */
+    fn count(&self, key: &str, count: i64) {
+        if self.metrics.read().sampled {
+            if let Ok(sized_count) = count.try_into() {
+                self.metrics().counter(key).count(sized_count);
+            }
+            return;
+        }
+
+        if let Some(counter) = self.counters.get(key) {
+            counter.fetch_add(count, Ordering::Relaxed);
+            return;
+        }
+        self.counters
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(AtomicI64::new(0)))
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn metrics(&self) -> MetricsScope {
+        self.metrics.read().scope.clone()
+    }
+
+    /**
+     * Return the tagged statsd metrics, when the metrics are tagged
+     */
+    fn tagged(&self) -> Option<Arc<Tagged>> {
+        self.metrics.read().tagged.clone()
+    }
+
+    /**
+     * Change the number of topics which get metrics of their own, the topics which already have
+     * their own metrics keep them
+     */
+    pub fn set_max_topics(&self, max_topics: usize) {
+        self.max_topics.store(max_topics, Ordering::Relaxed);
+    }
+
+    /**
+     * Change the number of peers which get metrics of their own, the peers which already have
+     * their own metrics keep them
+     */
+    pub fn set_max_peers(&self, max_peers: usize) {
+        self.max_peers.store(max_peers, Ordering::Relaxed);
+    }
+
+    /**
+     * Change the number of names which the metric actions get metrics of their own for, the
+     * names which already have their own metrics keep them
+     */
+    pub fn set_max_metrics(&self, max_metrics: usize) {
+        self.max_metrics.store(max_metrics, Ordering::Relaxed);
+    }
+
+    /**
+     * Record the hash of the configuration which has been loaded, for the /status endpoint
+     */
+    pub fn set_config_hash(&self, hash: Option<String>) {
+        *self.config_hash.write() = hash;
+    }
+
+    /**
+     * Return the topic which the metrics of the given topic are reported for, which is
+     * `OTHER_TOPIC` once `max_topics` other topics have metrics of their own
+     */
+    fn tracked_topic(&self, topic: String) -> String {
+        if self.topics.contains_key(&topic) {
+            return topic;
+        }
+
+        if self.topics.len() < self.max_topics.load(Ordering::Relaxed) {
+            self.topics.insert(topic.clone(), ());
+
+            let tracked = self.topics.len() as i64;
+            let key = Stats::KafkaTopics.to_string();
+            match self.tagged() {
+                Some(tagged) => tagged.gauge(&key, &None, tracked),
+                None => self.metrics().gauge(&key).value(tracked),
+            }
+            self.values.insert(key, tracked);
+            self.prometheus
+                .set(Stats::KafkaTopics.series().0, None, tracked);
+            return topic;
+        }
+
+        if !self.topics_overflowed.swap(true, Ordering::Relaxed) {
+            warn!(
+                "More than {} topics have been forwarded to, the metrics of `{}` and any further topics are reported for `{}`",
+                self.max_topics.load(Ordering::Relaxed),
+                topic,
+                OTHER_TOPIC
+            );
+        }
+        OTHER_TOPIC.to_string()
+    }
+
+    /**
+     * Return the peer which the metrics of the given peer are reported for, which is
+     * `OTHER_PEER` once `max_peers` other peers have metrics of their own
+     */
+    fn tracked_peer(&self, peer: String) -> String {
+        if self.peers.contains_key(&peer) {
+            return peer;
+        }
+
+        if self.peers.len() < self.max_peers.load(Ordering::Relaxed) {
+            self.peers.insert(peer.clone(), ());
+            return peer;
+        }
+
+        if !self.peers_overflowed.swap(true, Ordering::Relaxed) {
+            warn!(
+                "More than {} peers have sent lines, the metrics of `{}` and any further peers are reported for `{}`",
+                self.max_peers.load(Ordering::Relaxed),
+                peer,
+                OTHER_PEER
+            );
+        }
+        OTHER_PEER.to_string()
+    }
+
+    /**
+     * Return the name which the metrics of the given metric action name are reported for, which
+     * is `OTHER_METRIC` once `max_metrics` other names have metrics of their own
+     */
+    fn tracked_metric(&self, name: String) -> String {
+        if self.log_metrics.contains_key(&name) {
+            return name;
+        }
+
+        if self.log_metrics.len() < self.max_metrics.load(Ordering::Relaxed) {
+            self.log_metrics.insert(name.clone(), ());
+            return name;
+        }
+
+        if !self.metrics_overflowed.swap(true, Ordering::Relaxed) {
+            warn!(
+                "More than {} names have been emitted by metric actions, the metrics of `{}` and any further names are reported for `{}`",
+                self.max_metrics.load(Ordering::Relaxed),
+                name,
+                OTHER_METRIC
+            );
+        }
+        OTHER_METRIC.to_string()
+    }
+
+    /**
+     * Replace the topic, the peer or the metric name of the statistic when it is beyond the
+     * topics, the peers or the metric names which have metrics of their own
+     */
+    fn limit_cardinality(&self, stat: Stats) -> Stats {
+        match stat {
+            Stats::KafkaMsgSubmitted { topic } => Stats::KafkaMsgSubmitted {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::KafkaMsgProduced { topic } => Stats::KafkaMsgProduced {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::KafkaMsgDelivered { topic } => Stats::KafkaMsgDelivered {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::KafkaMsgFailed { topic } => Stats::KafkaMsgFailed {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::KafkaMsgSent { topic } => Stats::KafkaMsgSent {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::DryRunMessages { topic } => Stats::DryRunMessages {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::DryRunBytes { topic } => Stats::DryRunBytes {
+                topic: self.tracked_topic(topic),
+            },
+            Stats::PeerLines { peer } => Stats::PeerLines {
+                peer: self.tracked_peer(peer),
+            },
+            Stats::PeerBytes { peer } => Stats::PeerBytes {
+                peer: self.tracked_peer(peer),
+            },
+            Stats::LogCounter { name } => Stats::LogCounter {
+                name: self.tracked_metric(name),
+            },
+            Stats::LogGauge { name } => Stats::LogGauge {
+                name: self.tracked_metric(name),
+            },
+            Stats::LogTimer { name } => Stats::LogTimer {
+                name: self.tracked_metric(name),
+            },
+            stat => stat,
+        }
+    }
+
     /**
      * The runloop will simply read from the channel and record statistics as
      * they come in
@@ -84,14 +854,28 @@ impl StatsHandler {
         loop {
             if let Ok((stat, count)) = self.rx.recv().await {
                 trace!("Received stat to record: {} - {}", stat, count);
+                let stat = self.limit_cardinality(stat);
 
                 match stat {
-                    Stats::ConnectionCount => {
+                    Stats::ConnectionCount | Stats::KafkaQueueDepth | Stats::Backpressured => {
                         self.handle_gauge(stat, count).await;
                     }
-                    Stats::KafkaMsgSent => {
+                    Stats::KafkaClientQueueDepth
+                    | Stats::KafkaClientTxBytes
+                    | Stats::KafkaBrokerRtt { .. }
+                    | Stats::KafkaUnavailable { .. } => {
+                        self.handle_level(stat, count).await;
+                    }
+                    Stats::KafkaMsgSent { .. }
+                    | Stats::LineLatency
+                    | Stats::LineParsed
+                    | Stats::TemplateRendered
+                    | Stats::ConnectionLifetime => {
                         self.handle_timer(stat, count).await;
                     }
+                    Stats::LogCounter { .. } | Stats::LogGauge { .. } | Stats::LogTimer { .. } => {
+                        self.handle_log_metric(stat, count).await;
+                    }
                     _ => {
                         self.handle_counter(stat, count).await;
                     }
@@ -111,8 +895,34 @@ impl StatsHandler {
             new_count = *gauge.value();
         }
         new_count += count;
-        self.metrics.gauge(key).value(new_count);
         self.values.insert(key.to_string(), new_count);
+
+        let (name, label) = stat.series();
+        match self.tagged() {
+            Some(tagged) => tagged.gauge(key, &label, new_count),
+            None => self.metrics().gauge(key).value(new_count),
+        }
+        self.prometheus.add(Kind::Gauge, name, label, count);
+    }
+
+    /**
+     * Update the internal map with an absolute value for a gauge, unlike handle_gauge which
+     * treats the value as a change to the current value
+     */
+    async fn handle_level(&self, stat: Stats, value: i64) {
+        let mut key = stat.to_string();
+
+        if let Some(specific) = stat.specific_key() {
+            key = specific;
+        }
+
+        let (name, label) = stat.series();
+        match self.tagged() {
+            Some(tagged) => tagged.gauge(&stat.to_string(), &label, value),
+            None => self.metrics().gauge(&key).value(value),
+        }
+        self.values.insert(key, value);
+        self.prometheus.set(name, label, value);
     }
 
     /**
@@ -120,47 +930,123 @@ impl StatsHandler {
      */
     async fn handle_counter(&self, stat: Stats, count: i64) {
         let key = &stat.to_string();
-        let mut new_count = 0;
+        let (name, label) = stat.series();
+        let tagged = self.tagged();
+
+        self.increment(key, count, tagged.is_none());
 
-        if let Some(counter) = self.values.get(key) {
-            new_count = *counter.value();
+        /* Handle special case enums which have more data associated */
+        if let Some(specific) = stat.specific_key() {
+            self.increment(&specific, count, tagged.is_none());
         }
-        new_count += count;
 
-        let sized_count: usize = count.try_into().expect("Could not convert to usize!");
+        if let Some(tagged) = tagged {
+            tagged.count(key, &label, count);
+        }
 
-        self.metrics.counter(key).count(sized_count);
+        if let Stats::RuleMatched { rule: Some(rule) } = &stat {
+            *self.rules.entry(rule.clone()).or_insert(0) += count;
+        }
 
-        /* Handle special case enums which have more data associated */
-        match &stat {
-            Stats::KafkaMsgSubmitted { topic } => {
-                let subkey = &*format!("{}.{}", key, topic);
-                self.metrics.counter(subkey).count(sized_count);
-                self.values.insert(subkey.to_string(), new_count);
-            }
-            Stats::KafkaMsgErrored { errcode } => {
-                let subkey = &*format!("{}.{}", key, errcode);
-                self.metrics.counter(subkey).count(sized_count);
-                self.values.insert(subkey.to_string(), new_count);
-            }
-            _ => {}
-        };
+        self.prometheus
+            .add(Kind::Counter, format!("{}_total", name), label, count);
+    }
 
-        self.values.insert(key.to_string(), new_count);
+    /**
+     * Increment the internal map, and the untagged statsd counter if it should be reported, for
+     * the given key without allocating once the key has been counted before
+     */
+    fn increment(&self, key: &str, count: i64, report: bool) {
+        if report {
+            self.count(key, count);
+        }
+
+        match self.values.get_mut(key) {
+            Some(mut value) => *value += count,
+            None => {
+                self.values.insert(key.to_string(), count);
+            }
+        }
     }
 
     /**
      * Update the internal map with the latest timero
      */
     async fn handle_timer(&self, stat: Stats, duration_us: i64) {
-        let key = &stat.to_string();
+        let key = stat.to_string();
+        let mut keys = vec![key.clone()];
+        let (name, label) = stat.series();
+        let tagged = self.tagged();
+
+        if let Some(specific) = stat.specific_key() {
+            keys.push(specific);
+        }
+
+        if let Some(tagged) = &tagged {
+            tagged.timer(&key, &label, duration_us);
+        }
+
+        for key in keys.iter() {
+            if tagged.is_some() {
+                /* The tagged timer has been reported under the general key */
+            } else if let Ok(duration) = duration_us.try_into() {
+                self.metrics().timer(key).interval_us(duration);
+            } else {
+                error!("Failed to report timer to statsd with an i64 that couldn't fit into u64");
+            }
+            self.values.insert(key.to_string(), duration_us);
+        }
+
+        self.prometheus
+            .observe(format!("{}_us", name), label, duration_us, stat.buckets());
+    }
+
+    /**
+     * Record a metric emitted by a metric action, which is only reported under its own name
+     */
+    async fn handle_log_metric(&self, stat: Stats, value: i64) {
+        let key = match stat.specific_key() {
+            Some(key) => key,
+            None => return,
+        };
+        let (name, label) = stat.series();
+
+        match stat {
+            Stats::LogCounter { .. } => {
+                if value >= 0 {
+                    let tagged = self.tagged();
 
-        if let Ok(duration) = duration_us.try_into() {
-            self.metrics.timer(key).interval_us(duration);
-        } else {
-            error!("Failed to report timer to statsd with an i64 that couldn't fit into u64");
+                    self.increment(&key, value, tagged.is_none());
+                    if let Some(tagged) = tagged {
+                        tagged.count(&key, &None, value);
+                    }
+                    self.prometheus
+                        .add(Kind::Counter, format!("{}_total", name), label, value);
+                }
+            }
+            Stats::LogGauge { .. } => {
+                match self.tagged() {
+                    Some(tagged) => tagged.gauge(&key, &None, value),
+                    None => self.metrics().gauge(&key).value(value),
+                }
+                self.values.insert(key, value);
+                self.prometheus.set(name, label, value);
+            }
+            Stats::LogTimer { .. } => {
+                if let Ok(duration) = value.try_into() {
+                    match self.tagged() {
+                        Some(tagged) => tagged.timer(&key, &None, value),
+                        None => {
+                            self.metrics().timer(&key).interval_us(duration);
+                        }
+                    }
+                    self.values.insert(key, value);
+                    self.prometheus
+                        .observe(format!("{}_us", name), label, value, stat.buckets());
+                }
+            }
+            _ => {}
         }
-        self.values.insert(key.to_string(), duration_us);
     }
 
     /**
@@ -179,6 +1065,163 @@ impl StatsHandler {
             stats,
         }
     }
+
+    /**
+     * Return the value which was recorded for the statistic, or zero if nothing has been recorded
+     */
+    fn value(&self, stat: Stats) -> i64 {
+        self.values
+            .get(&stat.to_string())
+            .map(|value| *value.value())
+            .unwrap_or(0)
+    }
+
+    /**
+     * Return the totals of the counters which are summarized in the logs, the errors are every
+     * `error.*` counter along with the messages which could not be delivered to Kafka
+     */
+    pub fn totals(&self) -> Totals {
+        let errors: i64 = self
+            .values
+            .iter()
+            .filter(|entry| entry.key().starts_with("error."))
+            .map(|entry| *entry.value())
+            .sum();
+
+        Totals {
+            lines: self.value(Stats::LineReceived),
+            matches: self.value(Stats::RuleMatched { rule: None }),
+            forwarded: self.value(Stats::KafkaMsgDelivered {
+                topic: String::new(),
+            }),
+            errors: errors
+                + self.value(Stats::KafkaMsgFailed {
+                    topic: String::new(),
+                }),
+        }
+    }
+
+    /**
+     * Return the number of lines or messages which have been dropped, for any reason
+     */
+    pub fn dropped(&self) -> i64 {
+        self.value(Stats::Dropped { reason: "" })
+    }
+
+    /**
+     * Return how long hotdog has been running
+     */
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /**
+     * Return the active connections, which every accepted connection should be registered with
+     */
+    pub fn connections(&self) -> Arc<Connections> {
+        self.connections.clone()
+    }
+
+    /**
+     * Generate the human-readable report of the runtime statistics, with the number of messages
+     * which are queued for the default Kafka producer
+     */
+    fn report(&self, queued: usize) -> String {
+        let totals = self.totals();
+        let mut report = format!(
+            "Runtime statistics of hotdog {}, up for {}s\n  lines: {} received, {} matched, {} forwarded, {} dropped, {} errors",
+            env!("CARGO_PKG_VERSION"),
+            self.uptime().as_secs(),
+            totals.lines,
+            totals.matches,
+            totals.forwarded,
+            self.dropped(),
+            totals.errors
+        );
+
+        let mut rules: Vec<(String, i64)> = self
+            .rules
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        rules.sort();
+        report.push_str(&format!("\n  rules ({}):", rules.len()));
+        for (rule, matches) in rules.iter() {
+            report.push_str(&format!("\n    {}: {} matches", rule, matches));
+        }
+
+        let mut peers: Vec<Arc<Peer>> = self
+            .connections
+            .active
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        peers.sort_by_key(|peer| peer.opened);
+        report.push_str(&format!("\n  connections ({}):", peers.len()));
+        for peer in peers.iter() {
+            report.push_str(&format!(
+                "\n    {}: open for {}s, {} lines, {} bytes",
+                peer.addr
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_else(|| "unknown".to_string()),
+                peer.opened.elapsed().as_secs(),
+                peer.lines.load(Ordering::Relaxed),
+                peer.bytes.load(Ordering::Relaxed)
+            ));
+        }
+
+        report.push_str(&format!(
+            "\n  kafka: {} messages queued for the producer, {} in flight, {} in the librdkafka queue",
+            queued,
+            self.value(Stats::KafkaQueueDepth),
+            self.value(Stats::KafkaClientQueueDepth)
+        ));
+        report
+    }
+
+    /**
+     * Generate the StatusResponse struct for the /status url to respond with
+     */
+    fn status(&self) -> StatusResponse {
+        let rules = self
+            .rules
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+
+        StatusResponse {
+            version: version::VERSION.to_string(),
+            commit: version::COMMIT.to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            connections: self.value(Stats::ConnectionCount),
+            queue_depth: self.value(Stats::KafkaQueueDepth),
+            producer_queue_depth: self.value(Stats::KafkaClientQueueDepth),
+            rules,
+            config_hash: self.config_hash.read().clone(),
+            unavailable: self.unavailable(),
+        }
+    }
+
+    /**
+     * Return the destinations whose Kafka producer is unavailable, hotdog is not ready while there
+     * are any
+     */
+    fn unavailable(&self) -> Vec<String> {
+        let prefix = format!(
+            "{}.",
+            Stats::KafkaUnavailable {
+                destination: String::new()
+            }
+        );
+        let mut unavailable: Vec<String> = self
+            .values
+            .iter()
+            .filter(|entry| *entry.value() > 0)
+            .filter_map(|entry| entry.key().strip_prefix(&prefix).map(String::from))
+            .collect();
+        unavailable.sort();
+        unavailable
+    }
 }
 
 #[derive(Debug, Display, Hash, PartialEq, Eq)]
@@ -186,39 +1229,617 @@ pub enum Stats {
     /* Gauges */
     #[strum(serialize = "connections")]
     ConnectionCount,
+    #[strum(serialize = "kafka.queue_depth")]
+    KafkaQueueDepth,
+    #[strum(serialize = "kafka.client.msg_cnt")]
+    KafkaClientQueueDepth,
+    #[strum(serialize = "kafka.client.tx_bytes")]
+    KafkaClientTxBytes,
+    #[strum(serialize = "kafka.client.broker.rtt")]
+    KafkaBrokerRtt { broker: String },
+    #[strum(serialize = "kafka.topics")]
+    KafkaTopics,
+    #[strum(serialize = "kafka.unavailable")]
+    KafkaUnavailable { destination: String },
+    #[strum(serialize = "backpressure.active")]
+    Backpressured,
 
     /* Counters */
+    #[strum(serialize = "connections.bytes_received")]
+    BytesReceived,
     #[strum(serialize = "lines")]
     LineReceived,
+    #[strum(serialize = "peers.lines")]
+    PeerLines { peer: String },
+    #[strum(serialize = "peers.bytes_received")]
+    PeerBytes { peer: String },
+    #[strum(serialize = "lines.dropped")]
+    LineDropped,
+    #[strum(serialize = "lines.sampled_out")]
+    LineSampledOut,
+    #[strum(serialize = "lines.rate_limited")]
+    LineRateLimited,
+    #[strum(serialize = "lines.deduplicated")]
+    LineDeduplicated,
+    #[strum(serialize = "lines.throttled")]
+    LineThrottled,
+    #[strum(serialize = "lines.unmatched")]
+    LineUnmatched,
+    #[strum(serialize = "lines.invalid")]
+    LineInvalid,
+    #[strum(serialize = "lines.invalid_utf8")]
+    LineInvalidUtf8,
+    #[strum(serialize = "lines.timestamp_skewed")]
+    LineTimestampSkewed,
+    #[strum(serialize = "lines.not_enriched")]
+    LineNotEnriched,
+    #[strum(serialize = "lines.too_long")]
+    LineTooLong,
+    #[strum(serialize = "lines.truncated")]
+    LineTruncated,
+    #[strum(serialize = "lines.partial")]
+    LinePartial,
+    #[strum(serialize = "lines.over_limit")]
+    LineOverLimit,
+    #[strum(serialize = "lines.shed")]
+    LineShed,
+    #[strum(serialize = "dropped")]
+    Dropped { reason: &'static str },
+    #[strum(serialize = "tasks.failed")]
+    TaskFailed { task: String },
+    #[strum(serialize = "summaries.sent")]
+    SummarySent,
+    #[strum(serialize = "rollups.sent")]
+    RollupSent,
+    #[strum(serialize = "config.reloaded")]
+    ConfigReloaded,
+    #[strum(serialize = "exec.started")]
+    ExecStarted,
+    #[strum(serialize = "exec.skipped")]
+    ExecSkipped,
+    #[strum(serialize = "rules.matches")]
+    RuleMatched { rule: Option<String> },
     #[strum(serialize = "kafka.submitted")]
     KafkaMsgSubmitted { topic: String },
+    #[strum(serialize = "kafka.produced")]
+    KafkaMsgProduced { topic: String },
+    #[strum(serialize = "kafka.delivered")]
+    KafkaMsgDelivered { topic: String },
+    #[strum(serialize = "kafka.failed")]
+    KafkaMsgFailed { topic: String },
+    #[strum(serialize = "dry_run.messages")]
+    DryRunMessages { topic: String },
+    #[strum(serialize = "dry_run.bytes")]
+    DryRunBytes { topic: String },
     #[strum(serialize = "kafka.producer.error")]
     KafkaMsgErrored { errcode: String },
+    #[strum(serialize = "kafka.producer.retried")]
+    KafkaMsgRetried,
+    #[strum(serialize = "kafka.producer.dead_lettered")]
+    KafkaMsgDeadLettered,
+    #[strum(serialize = "kafka.producer.dropped")]
+    KafkaMsgDropped,
+    #[strum(serialize = "kafka.producer.timed_out")]
+    KafkaMsgTimedOut,
+    #[strum(serialize = "kafka.producer.spooled")]
+    KafkaMsgSpooled,
+    #[strum(serialize = "kafka.producer.replayed")]
+    KafkaMsgReplayed,
+    #[strum(serialize = "kafka.producer.quarantined")]
+    KafkaSpoolQuarantined,
+    #[strum(serialize = "kafka.producer.oversize")]
+    KafkaMsgOversize,
+    #[strum(serialize = "kafka.transaction.committed")]
+    KafkaTransactionCommitted,
+    #[strum(serialize = "kafka.transaction.aborted")]
+    KafkaTransactionAborted,
     #[strum(serialize = "error.log_parse")]
     LogParseError,
+    #[strum(serialize = "error.destination_missing")]
+    DestinationMissing,
     #[strum(serialize = "error.full_internal_queue")]
     FullInternalQueueError,
     #[strum(serialize = "error.topic_parse_failed")]
     TopicParseFailed,
+    #[strum(serialize = "topic.sanitized")]
+    TopicSanitized,
+    #[strum(serialize = "topic.fallback")]
+    TopicFallback,
     #[strum(serialize = "error.internal_push_failed")]
     InternalPushError,
     #[strum(serialize = "error.merge_of_invalid_json")]
     MergeInvalidJsonError,
     #[strum(serialize = "error.merge_target_not_json")]
     MergeTargetNotJsonError,
+    #[strum(serialize = "error.serialization_failed")]
+    SerializationFailed,
+    #[strum(serialize = "error.template_render_failed")]
+    TemplateRenderFailed,
+    #[strum(serialize = "error.geoip_lookup_failed")]
+    GeoIpLookupFailed,
+    #[strum(serialize = "error.schema")]
+    SchemaFailed,
+    #[strum(serialize = "error.decode")]
+    DecodeFailed,
+    #[strum(serialize = "error.timestamp_parse_failed")]
+    TimestampParseFailed,
+    #[strum(serialize = "error.script_failed")]
+    ScriptFailed,
+    #[strum(serialize = "error.config_reload_failed")]
+    ConfigReloadFailed,
+    #[strum(serialize = "error.aggregate_value_invalid")]
+    AggregateValueInvalid,
+    #[strum(serialize = "error.metric_value_invalid")]
+    MetricValueInvalid,
+    #[strum(serialize = "error.exec_failed")]
+    ExecFailed,
+    #[strum(serialize = "error.plugin_failed")]
+    PluginFailed,
+
+    /* Metrics emitted by metric actions, reported as `logs.<name>` */
+    #[strum(serialize = "logs")]
+    LogCounter { name: String },
+    #[strum(serialize = "logs")]
+    LogGauge { name: String },
+    #[strum(serialize = "logs")]
+    LogTimer { name: String },
 
     /* Timers */
     #[strum(serialize = "kafka.producer.sent")]
-    KafkaMsgSent,
+    KafkaMsgSent { topic: String },
+    #[strum(serialize = "lines.latency")]
+    LineLatency,
+    #[strum(serialize = "lines.parse")]
+    LineParsed,
+    #[strum(serialize = "templates.render")]
+    TemplateRendered,
+    #[strum(serialize = "connections.lifetime")]
+    ConnectionLifetime,
+}
+
+impl Stats {
+    /**
+     * Some statistics carry additional data which should be reported as a more specific metric
+     * in addition to the general one, e.g. `kafka.submitted.<topic>`
+     */
+    fn suffix(&self) -> Option<&str> {
+        match self {
+            Stats::KafkaMsgSubmitted { topic }
+            | Stats::KafkaMsgProduced { topic }
+            | Stats::KafkaMsgDelivered { topic }
+            | Stats::KafkaMsgFailed { topic }
+            | Stats::KafkaMsgSent { topic }
+            | Stats::DryRunMessages { topic }
+            | Stats::DryRunBytes { topic } => Some(topic),
+            Stats::KafkaMsgErrored { errcode } => Some(errcode),
+            Stats::KafkaBrokerRtt { broker } => Some(broker),
+            Stats::KafkaUnavailable { destination } => Some(destination),
+            Stats::Dropped { reason } => Some(reason),
+            Stats::TaskFailed { task } => Some(task),
+            _ => None,
+        }
+    }
+
+    /**
+     * Return the more specific metric name for statistics which carry additional data, e.g.
+     * `kafka.submitted.<topic>` or `rules.<name>.matches`
+     */
+    fn specific_key(&self) -> Option<String> {
+        match self {
+            Stats::RuleMatched { rule: Some(rule) } => {
+                Some(format!("rules.{}.matches", metric_safe(rule)))
+            }
+            Stats::LogCounter { name } | Stats::LogGauge { name } | Stats::LogTimer { name } => {
+                let segments: Vec<String> = name.split('.').map(metric_safe).collect();
+                Some(format!("{}.{}", self, segments.join(".")))
+            }
+            Stats::PeerLines { peer } | Stats::PeerBytes { peer } => {
+                Some(format!("{}.{}", self, metric_safe(peer)))
+            }
+            _ => self.suffix().map(|suffix| format!("{}.{}", self, suffix)),
+        }
+    }
+}
+
+impl Stats {
+    /**
+     * Return the name and the label of the Prometheus series for the statistic, the additional
+     * data which is part of the specific statsd metric name becomes a label instead
+     */
+    fn series(&self) -> (String, Label) {
+        let label = match self {
+            Stats::KafkaMsgSubmitted { topic }
+            | Stats::KafkaMsgProduced { topic }
+            | Stats::KafkaMsgDelivered { topic }
+            | Stats::KafkaMsgFailed { topic }
+            | Stats::KafkaMsgSent { topic }
+            | Stats::DryRunMessages { topic }
+            | Stats::DryRunBytes { topic } => Some(("topic", topic.clone())),
+            Stats::KafkaMsgErrored { errcode } => Some(("errcode", errcode.clone())),
+            Stats::KafkaBrokerRtt { broker } => Some(("broker", broker.clone())),
+            Stats::KafkaUnavailable { destination } => Some(("destination", destination.clone())),
+            Stats::Dropped { reason } => Some(("reason", reason.to_string())),
+            Stats::TaskFailed { task } => Some(("task", task.clone())),
+            Stats::PeerLines { peer } | Stats::PeerBytes { peer } => Some(("peer", peer.clone())),
+            Stats::RuleMatched { rule: Some(rule) } => Some(("rule", rule.clone())),
+            Stats::LogCounter { name } | Stats::LogGauge { name } | Stats::LogTimer { name } => {
+                return (
+                    format!("hotdog_logs_{}", prometheus::metric_name(name)),
+                    None,
+                );
+            }
+            _ => None,
+        };
+        (
+            format!("hotdog_{}", prometheus::metric_name(&self.to_string())),
+            label,
+        )
+    }
+
+    /**
+     * Return the bounds of the buckets of the Prometheus histogram for a timer, so that the
+     * steps of the pipeline which take microseconds and the connections which last for hours
+     * aren't squashed into the first or the last bucket of the latencies
+     */
+    fn buckets(&self) -> &'static [i64] {
+        match self {
+            Stats::LineParsed | Stats::TemplateRendered => prometheus::FAST_BUCKETS,
+            Stats::ConnectionLifetime => prometheus::LONG_BUCKETS,
+            _ => prometheus::BUCKETS,
+        }
+    }
+}
+
+/**
+ * Return the statistic of a single message which was lost for the reason, which is counted as
+ * `dropped.<reason>` so that it is clear where lost messages went
+ */
+pub fn dropped(reason: &'static str) -> Statistic {
+    (Stats::Dropped { reason }, 1)
+}
+
+/**
+ * Return the microseconds since the start, for the timers
+ */
+pub fn elapsed_us(start: Instant) -> i64 {
+    start.elapsed().as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+/**
+ * Replace characters which have special meaning in statsd metric names
+ */
+fn metric_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[async_std::test]
+    async fn test_drain_connections() {
+        let connections = Arc::new(Connections::default());
+        let tracked = connections.open(None);
+        let lingering = connections.open(None);
+
+        async_std::task::spawn(async move {
+            tracked.closing().await;
+        });
+
+        assert_eq!(1, connections.drain(Duration::from_millis(200)).await);
+        drop(lingering);
+        assert_eq!(0, connections.count());
+    }
+
     #[test]
     fn test_sanity_check_strum_serialize() {
         let s = Stats::ConnectionCount.to_string();
         assert_eq!("connections", s);
     }
+
+    #[test]
+    fn test_suffix_for_topic() {
+        let stat = Stats::KafkaMsgDelivered {
+            topic: "logs".to_string(),
+        };
+        assert_eq!(Some("logs"), stat.suffix());
+    }
+
+    #[test]
+    fn test_specific_key_for_rule() {
+        let stat = Stats::RuleMatched {
+            rule: Some("sshd logins".to_string()),
+        };
+        assert_eq!(
+            Some("rules.sshd_logins.matches".to_string()),
+            stat.specific_key()
+        );
+        assert_eq!(None, Stats::RuleMatched { rule: None }.specific_key());
+    }
+
+    #[test]
+    fn test_specific_key_for_topic() {
+        let stat = Stats::KafkaMsgDelivered {
+            topic: "logs".to_string(),
+        };
+        assert_eq!(
+            Some("kafka.delivered.logs".to_string()),
+            stat.specific_key()
+        );
+    }
+
+    #[test]
+    fn test_specific_key_for_dropped() {
+        let (stat, count) = dropped("parse_error");
+
+        assert_eq!(1, count);
+        assert_eq!(Some("dropped.parse_error".to_string()), stat.specific_key());
+    }
+
+    #[test]
+    fn test_specific_key_for_log_metric() {
+        let stat = Stats::LogTimer {
+            name: "http.GET /api.latency".to_string(),
+        };
+        assert_eq!(
+            Some("logs.http.GET__api.latency".to_string()),
+            stat.specific_key()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_metrics_none() {
+        let mut settings = crate::settings::Metrics::default();
+        settings.output = MetricsOutput::None;
+        settings.statsd = "statsd.invalid:8125".to_string();
+
+        let metrics = metrics_for(&settings).expect("Failed to create the metrics scope");
+        assert!(metrics.tagged.is_none());
+
+        let stats = StatsHandler::new(metrics, 2, 2, 2);
+        stats.handle_counter(Stats::LineReceived, 1).await;
+        assert_eq!(1, stats.value(Stats::LineReceived));
+    }
+
+    #[async_std::test]
+    async fn test_report_counters() {
+        let stats = StatsHandler::new(Metrics::none(), 2, 2, 2);
+        stats.handle_counter(Stats::LineReceived, 2).await;
+        stats.handle_counter(Stats::LineReceived, 3).await;
+
+        let key = Stats::LineReceived.to_string();
+        let pending =
+            |stats: &StatsHandler| stats.counters.get(&key).map(|c| c.load(Ordering::Relaxed));
+        assert_eq!(Some(5), pending(&stats));
+
+        stats.report_counters().await;
+        assert_eq!(Some(0), pending(&stats));
+        assert_eq!(5, stats.value(Stats::LineReceived));
+    }
+
+    #[async_std::test]
+    async fn test_report_counters_received() {
+        let stats = StatsHandler::new(Metrics::none(), 2, 2, 2);
+        let tracked = stats.connections().open(None);
+        tracked.line_received();
+        tracked.line_received();
+        tracked.received(42);
+
+        stats.report_counters().await;
+        assert_eq!(2, stats.value(Stats::LineReceived));
+        assert_eq!(42, stats.value(Stats::BytesReceived));
+
+        stats.report_counters().await;
+        assert_eq!(2, stats.value(Stats::LineReceived));
+    }
+
+    #[test]
+    fn test_tracked_topic() {
+        let metrics = metrics_for(&crate::settings::Metrics::default())
+            .expect("Failed to create the metrics scope");
+        let stats = StatsHandler::new(metrics, 2, 2, 2);
+
+        assert_eq!("a", stats.tracked_topic("a".to_string()));
+        assert_eq!("b", stats.tracked_topic("b".to_string()));
+        assert_eq!(OTHER_TOPIC, stats.tracked_topic("c".to_string()));
+        assert_eq!("a", stats.tracked_topic("a".to_string()));
+
+        stats.set_max_topics(3);
+        assert_eq!("c", stats.tracked_topic("c".to_string()));
+    }
+
+    #[test]
+    fn test_tracked_peer() {
+        let metrics = metrics_for(&crate::settings::Metrics::default())
+            .expect("Failed to create the metrics scope");
+        let stats = StatsHandler::new(metrics, 2, 1, 2);
+        let stat = stats.limit_cardinality(Stats::PeerLines {
+            peer: "10.0.0.1".to_string(),
+        });
+
+        assert_eq!(
+            Some("peers.lines.10_0_0_1".to_string()),
+            stat.specific_key()
+        );
+        assert_eq!("10.0.0.1", stats.tracked_peer("10.0.0.1".to_string()));
+        assert_eq!(OTHER_PEER, stats.tracked_peer("10.0.0.2".to_string()));
+    }
+
+    #[test]
+    fn test_tracked_metric() {
+        let stats = StatsHandler::new(Metrics::none(), 2, 2, 1);
+        let stat = stats.limit_cardinality(Stats::LogCounter {
+            name: "http.status.200".to_string(),
+        });
+
+        assert_eq!(
+            Some("logs.http.status.200".to_string()),
+            stat.specific_key()
+        );
+        assert_eq!(
+            OTHER_METRIC,
+            stats.tracked_metric("http.status.404".to_string())
+        );
+
+        stats.set_max_metrics(2);
+        assert_eq!(
+            "http.status.404",
+            stats.tracked_metric("http.status.404".to_string())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_status() {
+        let metrics = metrics_for(&crate::settings::Metrics::default())
+            .expect("Failed to create the metrics scope");
+        let stats = StatsHandler::new(metrics, 2, 2, 2);
+        let rule = Stats::RuleMatched {
+            rule: Some("sshd".to_string()),
+        };
+
+        stats.handle_gauge(Stats::ConnectionCount, 2).await;
+        stats.handle_gauge(Stats::ConnectionCount, -1).await;
+        stats.handle_counter(rule, 3).await;
+        stats
+            .handle_counter(Stats::RuleMatched { rule: None }, 1)
+            .await;
+        stats.set_config_hash(Some("abc".to_string()));
+
+        let status = stats.status();
+        assert_eq!(1, status.connections);
+        assert_eq!(0, status.queue_depth);
+        assert_eq!(Some(&3), status.rules.get("sshd"));
+        assert_eq!(1, status.rules.len());
+        assert_eq!(Some("abc".to_string()), status.config_hash);
+        assert!(status.unavailable.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_unavailable() {
+        let stats = StatsHandler::new(Metrics::none(), 2, 2, 2);
+        let unavailable = |destination: &str| Stats::KafkaUnavailable {
+            destination: destination.to_string(),
+        };
+
+        stats.handle_level(unavailable("default"), 1).await;
+        stats.handle_level(unavailable("archive"), 1).await;
+        assert_eq!(vec!["archive", "default"], stats.unavailable());
+
+        stats.handle_level(unavailable("default"), 0).await;
+        assert_eq!(vec!["archive"], stats.unavailable());
+    }
+
+    #[async_std::test]
+    async fn test_report() {
+        let metrics = metrics_for(&crate::settings::Metrics::default())
+            .expect("Failed to create the metrics scope");
+        let stats = StatsHandler::new(metrics, 2, 2, 2);
+        stats
+            .handle_counter(
+                Stats::RuleMatched {
+                    rule: Some("sshd".to_string()),
+                },
+                3,
+            )
+            .await;
+
+        let tracked = stats
+            .connections()
+            .open(Some("127.0.0.1:5140".parse().unwrap()));
+        tracked.line();
+        tracked.received(42);
+
+        let report = stats.report(7);
+        assert!(report.contains("sshd: 3 matches"));
+        assert!(report.contains("127.0.0.1:5140: open for 0s, 1 lines, 42 bytes"));
+        assert!(report.contains("7 messages queued for the producer"));
+
+        drop(tracked);
+        assert!(stats.report(0).contains("connections (0):"));
+    }
+
+    #[async_std::test]
+    async fn test_totals() {
+        let metrics = metrics_for(&crate::settings::Metrics::default())
+            .expect("Failed to create the metrics scope");
+        let stats = StatsHandler::new(metrics, 2, 2, 2);
+        let previous = stats.totals();
+
+        stats.handle_counter(Stats::LineReceived, 120).await;
+        stats
+            .handle_counter(
+                Stats::RuleMatched {
+                    rule: Some("sshd".to_string()),
+                },
+                100,
+            )
+            .await;
+        stats
+            .handle_counter(
+                Stats::KafkaMsgDelivered {
+                    topic: "logs".to_string(),
+                },
+                99,
+            )
+            .await;
+        stats
+            .handle_counter(
+                Stats::KafkaMsgFailed {
+                    topic: "logs".to_string(),
+                },
+                1,
+            )
+            .await;
+        stats.handle_counter(Stats::LogParseError, 2).await;
+
+        assert_eq!(
+            "In the last 60s: 120 lines (2.0/s), 100 matches, 99 forwarded, 3 errors",
+            stats.totals().summary(&previous, Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_series() {
+        let stat = Stats::KafkaMsgDelivered {
+            topic: "logs".to_string(),
+        };
+        assert_eq!(
+            (
+                "hotdog_kafka_delivered".to_string(),
+                Some(("topic", "logs".to_string()))
+            ),
+            stat.series()
+        );
+
+        let stat = Stats::LogCounter {
+            name: "http.requests".to_string(),
+        };
+        assert_eq!(
+            ("hotdog_logs_http_requests".to_string(), None),
+            stat.series()
+        );
+    }
+
+    #[test]
+    fn test_buckets() {
+        assert_eq!(prometheus::FAST_BUCKETS, Stats::TemplateRendered.buckets());
+        assert_eq!(
+            prometheus::LONG_BUCKETS,
+            Stats::ConnectionLifetime.buckets()
+        );
+        assert_eq!(prometheus::BUCKETS, Stats::LineLatency.buckets());
+    }
+
+    #[test]
+    fn test_suffix_none() {
+        assert_eq!(None, Stats::LineReceived.suffix());
+    }
 }
@@ -0,0 +1,175 @@
+/**
+ * The supervise module watches the tasks which hotdog spawns, logging and counting the failures
+ * and panics which would otherwise vanish along with the dropped JoinHandle of the task, and
+ * restarting the tasks which can be restarted, such as the listeners and the sendloops
+ */
+use crate::status::{Statistic, Stats};
+use async_channel::{Receiver, Sender};
+use async_std::task;
+use futures::FutureExt;
+use log::*;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+/**
+ * How long a failed task waits before it is restarted the first time, the wait doubles with
+ * every further failure
+ */
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/**
+ * The number of times in a row a task may fail before it is no longer restarted, a task which
+ * ran for longer than the MAX_BACKOFF before it failed starts counting again
+ */
+const MAX_RESTARTS: u32 = 5;
+
+/**
+ * Run the task, logging its failure or panic along with the context and counting it under the
+ * name of the task. Returns the output of the task, or None if it failed
+ */
+pub async fn watch<T, E, F>(
+    name: &'static str,
+    context: &str,
+    stats: &Sender<Statistic>,
+    future: F,
+) -> Option<T>
+where
+    E: std::fmt::Debug,
+    F: Future<Output = Result<T, E>>,
+{
+    let failure = match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(Ok(output)) => return Some(output),
+        Ok(Err(e)) => format!("failed: {:?}", e),
+        Err(panic) => format!("panicked: {}", panic_message(&*panic)),
+    };
+
+    error!("The {} task ({}) {}", name, context, failure);
+    stats
+        .send((
+            Stats::TaskFailed {
+                task: name.to_string(),
+            },
+            1,
+        ))
+        .await;
+    None
+}
+
+/**
+ * Run the task started by the closure until it finishes successfully, starting it again with a
+ * backoff whenever it fails or panics, unless the shutdown Receiver has been closed or the task
+ * keeps failing. Returns the output of the task, or None if it was given up on
+ */
+pub async fn supervise<T, E, F, Fut>(
+    name: &'static str,
+    stats: Sender<Statistic>,
+    shutdown: Receiver<()>,
+    mut start: F,
+) -> Option<T>
+where
+    E: std::fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut failures = 0;
+
+    loop {
+        let started = Instant::now();
+
+        if let Some(output) = watch(name, "supervised", &stats, start()).await {
+            return Some(output);
+        }
+        if shutdown.is_closed() {
+            return None;
+        }
+
+        if started.elapsed() >= MAX_BACKOFF {
+            failures = 0;
+            backoff = INITIAL_BACKOFF;
+        }
+        failures += 1;
+
+        if failures > MAX_RESTARTS {
+            error!(
+                "The {} task failed {} times in a row, no longer restarting it",
+                name, failures
+            );
+            return None;
+        }
+        warn!("Restarting the {} task in {:?}", name, backoff);
+        task::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/**
+ * Return the message which the task panicked with, which is almost always a string
+ */
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_channel::bounded;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn broken() -> Result<(), ()> {
+        panic!("the task broke");
+    }
+
+    #[async_std::test]
+    async fn test_watch_panic() {
+        let (stats, counted) = bounded(1);
+        let watched = watch("test", "panicking", &stats, broken()).await;
+
+        assert!(watched.is_none());
+        let (stat, count) = counted.recv().await.unwrap();
+        assert_eq!("tasks.failed", stat.to_string());
+        assert_eq!(1, count);
+    }
+
+    #[async_std::test]
+    async fn test_supervise_restarts() {
+        let (stats, _counted) = bounded(4);
+        let (_stop, shutdown) = bounded(1);
+        let started = AtomicUsize::new(0);
+
+        supervise("test", stats, shutdown, || {
+            let attempt = started.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if attempt == 0 {
+                    return Err("the first attempt fails");
+                }
+                Ok(())
+            }
+        })
+        .await;
+
+        assert_eq!(2, started.load(Ordering::SeqCst));
+    }
+
+    #[async_std::test]
+    async fn test_supervise_shutdown() {
+        let (stats, _counted) = bounded(4);
+        let (stop, shutdown) = bounded::<()>(1);
+        stop.close();
+
+        let supervised = supervise("test", stats, shutdown, || async {
+            Err::<(), _>("every attempt fails")
+        })
+        .await;
+
+        assert!(supervised.is_none());
+    }
+}
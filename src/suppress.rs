@@ -0,0 +1,239 @@
+/**
+ * The suppress module tracks how often messages are seen within a window of time, allowing the
+ * repeats to be suppressed with a summary of how many were suppressed sent once the window ends
+ */
+use crate::kafka::{KafkaMessage, Produce};
+use crate::reload::Reloadable;
+use crate::status::{Statistic, Stats};
+use async_channel::Sender;
+use async_std::{sync::Arc, task};
+use dashmap::DashMap;
+use handlebars::Handlebars;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/**
+ * The Summary sent once a window in which messages were suppressed has ended
+ */
+#[derive(Debug)]
+pub struct Summary {
+    pub topic: String,
+    /**
+     * The template for the summary, which has the `count` of suppressed messages in addition to
+     * the variables of the first message in the window
+     */
+    pub template: String,
+    pub variables: HashMap<String, String>,
+}
+
+impl Summary {
+    /**
+     * Render the summary message for the number of suppressed messages
+     */
+    fn render(mut self, count: u64) -> Option<KafkaMessage> {
+        self.variables
+            .insert("count".to_string(), count.to_string());
+
+        match Handlebars::new().render_template(&self.template, &self.variables) {
+            Ok(rendered) => Some(KafkaMessage::new(self.topic, rendered.into_bytes())),
+            Err(e) => {
+                error!("Failed to render the summary template: {}", e);
+                None
+            }
+        }
+    }
+}
+
+struct Window {
+    expires: Instant,
+    seen: u64,
+    limit: u64,
+    summary: Summary,
+}
+
+impl Window {
+    /**
+     * Return the number of messages which were suppressed in this window
+     */
+    fn suppressed(&self) -> u64 {
+        self.seen.saturating_sub(self.limit)
+    }
+}
+
+/**
+ * Suppressor carries the windows for every key, and should be shared between all connections
+ */
+pub struct Suppressor {
+    windows: DashMap<String, Window>,
+    /**
+     * Windows which ended while their key was seen again, before they could be summarized
+     */
+    finished: Mutex<Vec<(Summary, u64)>>,
+}
+
+impl Suppressor {
+    pub fn new() -> Self {
+        Suppressor {
+            windows: DashMap::default(),
+            finished: Mutex::new(vec![]),
+        }
+    }
+
+    /**
+     * Count an occurrence of the key, returning false if it should be suppressed because the key
+     * has already been seen `limit` times in the current window.
+     *
+     * A window starts with the first occurrence of a key, which is when its summary is created
+     */
+    pub fn admit<F>(&self, key: &str, limit: u64, window: Duration, summary: F) -> bool
+    where
+        F: FnOnce() -> Summary,
+    {
+        self.admit_at(key, limit, window, summary, Instant::now())
+    }
+
+    fn admit_at<F>(&self, key: &str, limit: u64, window: Duration, summary: F, now: Instant) -> bool
+    where
+        F: FnOnce() -> Summary,
+    {
+        let mut summary = Some(summary);
+        let mut new_window = || Window {
+            expires: now + window,
+            seen: 0,
+            limit,
+            summary: (summary
+                .take()
+                .expect("The summary can only be created once"))(),
+        };
+
+        let mut entry = self
+            .windows
+            .entry(key.to_string())
+            .or_insert_with(&mut new_window);
+
+        if entry.expires <= now {
+            let ended = std::mem::replace(&mut *entry, new_window());
+
+            if ended.suppressed() > 0 {
+                let suppressed = ended.suppressed();
+                self.finished.lock().push((ended.summary, suppressed));
+            }
+        }
+
+        entry.seen += 1;
+        entry.seen <= entry.limit
+    }
+
+    /**
+     * Remove the windows which have ended, returning the summaries of those which suppressed
+     * messages along with the number of messages they suppressed
+     */
+    fn ended(&self, now: Instant) -> Vec<(Summary, u64)> {
+        let mut ended: Vec<(Summary, u64)> = self.finished.lock().drain(..).collect();
+
+        let expired: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|window| window.expires <= now)
+            .map(|window| window.key().to_string())
+            .collect();
+
+        for key in expired.iter() {
+            if let Some((_, window)) = self.windows.remove_if(key, |_, w| w.expires <= now) {
+                let suppressed = window.suppressed();
+
+                if suppressed > 0 {
+                    ended.push((window.summary, suppressed));
+                }
+            }
+        }
+        ended
+    }
+}
+
+impl Default for Suppressor {
+    fn default() -> Self {
+        Suppressor::new()
+    }
+}
+
+/**
+ * Periodically send the summaries of the windows which have ended along to Kafka
+ */
+pub async fn summary_loop(
+    suppressor: Arc<Suppressor>,
+    reloadable: Arc<Reloadable>,
+    stats: Sender<Statistic>,
+) {
+    loop {
+        task::sleep(Duration::from_secs(1)).await;
+        let sender = reloadable.sinks().sender.clone();
+
+        for (summary, suppressed) in suppressor.ended(Instant::now()) {
+            if let Some(kmsg) = summary.render(suppressed) {
+                sender.send(Produce::Message(kmsg)).await;
+                stats.send((Stats::SummarySent, 1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> Summary {
+        let mut variables = HashMap::new();
+        variables.insert("msg".to_string(), "hello".to_string());
+
+        Summary {
+            topic: "logs".to_string(),
+            template: "{{msg}} repeated {{count}} times".to_string(),
+            variables,
+        }
+    }
+
+    #[test]
+    fn test_admit_within_window() {
+        let suppressor = Suppressor::new();
+        let window = Duration::from_secs(10);
+        let now = Instant::now();
+
+        assert!(suppressor.admit_at("hello", 1, window, summary, now));
+        assert!(!suppressor.admit_at("hello", 1, window, summary, now));
+        assert!(!suppressor.admit_at("hello", 1, window, summary, now));
+        assert!(suppressor.admit_at("other", 1, window, summary, now));
+
+        assert!(suppressor.ended(now).is_empty());
+
+        let ended = suppressor.ended(now + window);
+        assert_eq!(1, ended.len());
+        assert_eq!(2, ended[0].1);
+    }
+
+    #[test]
+    fn test_admit_after_window() {
+        let suppressor = Suppressor::new();
+        let window = Duration::from_secs(10);
+        let now = Instant::now();
+
+        assert!(suppressor.admit_at("hello", 1, window, summary, now));
+        assert!(!suppressor.admit_at("hello", 1, window, summary, now));
+        assert!(suppressor.admit_at("hello", 1, window, summary, now + window));
+
+        let ended = suppressor.ended(now + window);
+        assert_eq!(1, ended.len());
+        assert_eq!(1, ended[0].1);
+    }
+
+    #[test]
+    fn test_summary_render() {
+        let kmsg = summary().render(3).expect("Failed to render the summary");
+        assert_eq!(
+            r#"KafkaMessage { topic: "logs", key: None, msg: "hello repeated 3 times" }"#,
+            format!("{:?}", kmsg)
+        );
+    }
+}
@@ -0,0 +1,201 @@
+/**
+ * The tagged module sends statsd metrics with tags, such as the topic or the rule, in the
+ * DogStatsD or the InfluxDB format, which dipstick does not support
+ */
+use crate::prometheus::Label;
+use crate::settings::{Metrics, TagFormat};
+use log::*;
+use std::net::UdpSocket;
+
+pub struct Tagged {
+    socket: UdpSocket,
+    format: TagFormat,
+    prefix: String,
+    /**
+     * The fraction of the counters and timers which are sent
+     */
+    sample_rate: f64,
+    /**
+     * The tags from the settings which are added to every metric, e.g. the host
+     */
+    tags: Vec<(String, String)>,
+}
+
+impl Tagged {
+    /**
+     * Connect to the statsd server of the settings, returning None when the metrics are not tagged
+     */
+    pub fn connect(settings: &Metrics) -> std::io::Result<Option<Self>> {
+        if settings.tag_format == TagFormat::None {
+            return Ok(None);
+        }
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&settings.statsd)?;
+        socket.set_nonblocking(true)?;
+
+        let mut tags: Vec<(String, String)> = settings
+            .tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        tags.sort();
+
+        Ok(Some(Tagged {
+            socket,
+            format: settings.tag_format,
+            prefix: settings.prefix.clone(),
+            sample_rate: settings.sample_rate,
+            tags,
+        }))
+    }
+
+    pub fn count(&self, name: &str, label: &Label, count: i64) {
+        if self.sample() {
+            self.send(name, label, count, "c");
+        }
+    }
+
+    pub fn gauge(&self, name: &str, label: &Label, value: i64) {
+        self.send(name, label, value, "g");
+    }
+
+    /**
+     * Send a timer, which statsd expects in milliseconds
+     */
+    pub fn timer(&self, name: &str, label: &Label, duration_us: i64) {
+        if self.sample() {
+            self.send(name, label, duration_us / 1000, "ms");
+        }
+    }
+
+    /**
+     * Decide whether a counter or a timer is sent, according to the sample rate
+     */
+    fn sample(&self) -> bool {
+        self.sample_rate >= 1.0 || fastrand::f64() < self.sample_rate
+    }
+
+    fn send(&self, name: &str, label: &Label, value: i64, kind: &str) {
+        let line = self.line(name, label, value, kind);
+
+        /*
+         * Metrics are best effort, a full socket buffer or an unreachable server only loses them
+         */
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            trace!("Failed to send the metric {}: {}", line, e);
+        }
+    }
+
+    /**
+     * Return the statsd line of the metric with the label and the tags of the settings
+     */
+    fn line(&self, name: &str, label: &Label, value: i64, kind: &str) -> String {
+        /*
+         * statsd scales the sampled counters and timers back up by their rate
+         */
+        let kind = if self.sample_rate < 1.0 && kind != "g" {
+            format!("{}|@{}", kind, self.sample_rate)
+        } else {
+            kind.to_string()
+        };
+
+        let tags: Vec<(&str, String)> = label
+            .iter()
+            .map(|(k, v)| (*k, tag_safe(v)))
+            .chain(self.tags.iter().map(|(k, v)| (k.as_str(), tag_safe(v))))
+            .collect();
+
+        match self.format {
+            TagFormat::Dogstatsd if !tags.is_empty() => {
+                let tags: Vec<String> = tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+                format!(
+                    "{}.{}:{}|{}|#{}",
+                    self.prefix,
+                    name,
+                    value,
+                    kind,
+                    tags.join(",")
+                )
+            }
+            TagFormat::Influx => {
+                let tags: String = tags.iter().map(|(k, v)| format!(",{}={}", k, v)).collect();
+                format!("{}.{}{}:{}|{}", self.prefix, name, tags, value, kind)
+            }
+            _ => format!("{}.{}:{}|{}", self.prefix, name, value, kind),
+        }
+    }
+}
+
+/**
+ * Replace the characters which separate the tags or the parts of a statsd line
+ */
+fn tag_safe(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | '=' | ':' | ' ' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(format: TagFormat) -> Tagged {
+        let mut settings = Metrics::default();
+        settings.tag_format = format;
+        settings
+            .tags
+            .insert("host".to_string(), "web-1".to_string());
+
+        Tagged::connect(&settings)
+            .expect("Failed to connect")
+            .expect("Failed to create the tagged metrics")
+    }
+
+    #[test]
+    fn test_untagged() {
+        assert!(Tagged::connect(&Metrics::default())
+            .expect("Failed to connect")
+            .is_none());
+    }
+
+    #[test]
+    fn test_dogstatsd_line() {
+        let label = Some(("topic", "logs".to_string()));
+
+        assert_eq!(
+            "hotdog.kafka.delivered:1|c|#topic:logs,host:web-1",
+            tagged(TagFormat::Dogstatsd).line("kafka.delivered", &label, 1, "c")
+        );
+    }
+
+    #[test]
+    fn test_influx_line() {
+        let label = Some(("rule", "sshd logins".to_string()));
+
+        assert_eq!(
+            "hotdog.rules.matches,rule=sshd_logins,host=web-1:2|c",
+            tagged(TagFormat::Influx).line("rules.matches", &label, 2, "c")
+        );
+    }
+
+    #[test]
+    fn test_sampled_line() {
+        let mut tagged = tagged(TagFormat::Dogstatsd);
+        tagged.prefix = "edge".to_string();
+        tagged.sample_rate = 0.1;
+
+        assert_eq!(
+            "edge.lines:1|c|@0.1|#host:web-1",
+            tagged.line("lines", &None, 1, "c")
+        );
+        assert_eq!(
+            "edge.connections:3|g|#host:web-1",
+            tagged.line("connections", &None, 3, "g")
+        );
+    }
+}
@@ -0,0 +1,861 @@
+/**
+ * The templates module registers the Handlebars templates of all the rules once the settings have
+ * been loaded, so that every template is only parsed once rather than for every line
+ */
+use crate::settings::{Action, CustomHelper, Rule, Settings};
+use chrono::DateTime;
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext,
+    RenderError,
+};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/**
+ * Templates is the registry of all the templates of the rules, which are rendered by their
+ * identifiers
+ */
+pub struct Templates {
+    registry: Handlebars<'static>,
+}
+
+impl Templates {
+    /**
+     * Register the templates for all the actions of the settings' rules, returning an error for
+     * each of them which is not a valid template
+     */
+    pub fn new(settings: &Settings) -> Result<Templates, Vec<String>> {
+        let mut templates = Templates::default();
+        let mut errors = vec![];
+
+        for (name, helper) in settings.helpers.iter() {
+            if let Err(e) = templates.register_helper(name, helper) {
+                errors.push(format!("`helpers.{}`: {}", name, e));
+            }
+        }
+
+        for (name, partial) in settings.partials.iter() {
+            if let Err(e) = templates.registry.register_partial(name, partial) {
+                errors.push(format!(
+                    "`templates.{}`: Failed to register the template: {}",
+                    name, e
+                ));
+            }
+        }
+
+        for rule in settings.all_rules() {
+            for index in 0..rule.actions.len() {
+                if let Err(e) = templates.register_action(rule, index) {
+                    errors.push(format!("The rule `{}`: `actions[{}]`: {}", rule, index, e));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(templates)
+    }
+
+    fn register_action(&mut self, rule: &Rule, index: usize) -> Result<(), String> {
+        let template_id = id_for(rule, index);
+
+        match &rule.actions[index].action {
+            Action::Merge { json: _, json_str } => match json_str {
+                Some(template) => self.register(&template_id, template)?,
+                None => {
+                    return Err("Could not look up the json_str for a Merge action".to_string());
+                }
+            },
+            Action::Forward { topic, payload, .. } => {
+                self.register(&part_id_for(&template_id, "topic"), topic)?;
+
+                if let Some(template) = payload {
+                    self.register(&template_id, template)?;
+                }
+            }
+            Action::AddFields { fields, .. } => {
+                for (field, template) in fields.iter() {
+                    self.register(&part_id_for(&template_id, field), template)?;
+                }
+            }
+            Action::Metric { name, value, .. } => {
+                self.register(&part_id_for(&template_id, "name"), name)?;
+
+                if let Some(template) = value {
+                    self.register(&part_id_for(&template_id, "value"), template)?;
+                }
+            }
+            Action::Aggregate {
+                key, value, topic, ..
+            } => {
+                self.register(&part_id_for(&template_id, "topic"), topic)?;
+
+                if let Some(template) = key {
+                    self.register(&template_id, template)?;
+                }
+                if let Some(template) = value {
+                    self.register(&part_id_for(&template_id, "value"), template)?;
+                }
+            }
+            Action::Dedupe { key, topic, .. } | Action::Throttle { key, topic, .. } => {
+                self.register(&part_id_for(&template_id, "topic"), topic)?;
+
+                if let Some(template) = key {
+                    self.register(&template_id, template)?;
+                }
+            }
+            Action::RateLimit {
+                key: Some(template),
+                ..
+            }
+            | Action::Geoip {
+                ip: Some(template), ..
+            }
+            | Action::Timestamp {
+                source: Some(template),
+                ..
+            }
+            | Action::RemapSeverity {
+                source: Some(template),
+                ..
+            }
+            | Action::Kv {
+                source: Some(template),
+                ..
+            }
+            | Action::Enrich { key: template, .. }
+            | Action::Csv {
+                source: Some(template),
+                ..
+            }
+            | Action::Replace { template }
+            | Action::SetKey { template } => self.register(&template_id, template)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn register_helper(&mut self, name: &str, helper: &CustomHelper) -> Result<(), String> {
+        if self.registry.get_helper(name).is_some() {
+            return Err(format!("The helper `{}` is already defined", name));
+        }
+
+        match helper {
+            CustomHelper::Map { map, default } => {
+                let helper = MapHelper {
+                    map: map.clone(),
+                    default: default.clone(),
+                };
+                self.registry.register_helper(name, Box::new(helper));
+            }
+            CustomHelper::Template { template } => {
+                let template_id = format!("helper-{}", name);
+                self.register(&template_id, template)?;
+                self.registry
+                    .register_helper(name, Box::new(TemplateHelper { template_id }));
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&mut self, template_id: &str, template: &str) -> Result<(), String> {
+        self.registry
+            .register_template_string(template_id, template)
+            .map_err(|e| format!("Failed to register the template `{}`: {}", template, e))
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        let mut registry = Handlebars::new();
+        registry.register_helper("date_format", Box::new(date_format));
+        registry.register_helper("uuid", Box::new(uuid));
+        registry.register_helper("random_token", Box::new(random_token));
+        registry.register_helper("sha256", Box::new(sha256));
+        registry.register_helper("md5", Box::new(md5));
+        registry.register_helper("env", Box::new(env));
+        registry.register_helper("upper", Box::new(upper));
+        registry.register_helper("lower", Box::new(lower));
+        registry.register_helper("trim", Box::new(trim));
+        registry.register_helper("replace", Box::new(replace));
+        registry.register_helper("truncate", Box::new(truncate));
+        registry.register_helper("json", Box::new(json));
+        registry.register_helper("default", Box::new(default));
+        registry.register_helper("eq", Box::new(eq));
+        registry.register_helper("ne", Box::new(ne));
+        registry.register_helper("gt", Box::new(gt));
+        registry.register_helper("gte", Box::new(gte));
+        registry.register_helper("lt", Box::new(lt));
+        registry.register_helper("lte", Box::new(lte));
+
+        Templates { registry }
+    }
+}
+
+impl std::fmt::Debug for Templates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Templates").finish_non_exhaustive()
+    }
+}
+
+impl std::ops::Deref for Templates {
+    type Target = Handlebars<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+/**
+ * The `date_format` helper formats an RFC 3339 timestamp, such as the `timestamp` variable, with
+ * a strftime-style format, e.g. `{{date_format timestamp "%Y.%m.%d"}}`
+ */
+fn date_format(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let timestamp = h
+        .param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("date_format requires a timestamp"))?;
+    let format = h
+        .param(1)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("date_format requires a format"))?;
+
+    let parsed = DateTime::parse_from_rfc3339(timestamp).map_err(|e| {
+        RenderError::new(format!(
+            "date_format could not parse `{}`: {}",
+            timestamp, e
+        ))
+    })?;
+
+    /*
+     * Invalid format strings are only detected when the timestamp is formatted
+     */
+    let mut formatted = String::new();
+    write!(formatted, "{}", parsed.format(format))
+        .map_err(|_| RenderError::new(format!("date_format has an invalid format `{}`", format)))?;
+
+    out.write(&formatted)?;
+    Ok(())
+}
+
+/**
+ * The `uuid` helper generates a new random UUID for every render, e.g. `{{uuid}}`
+ */
+fn uuid(
+    _: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(&uuid::Uuid::new_v4().to_string())?;
+    Ok(())
+}
+
+/**
+ * The `random_token` helper generates a new random alphanumeric token for every render, which is
+ * 16 characters long unless another length is given, e.g. `{{random_token 32}}`
+ */
+fn random_token(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let length = match h.param(0) {
+        Some(param) => param
+            .value()
+            .as_u64()
+            .ok_or_else(|| RenderError::new("random_token requires a numeric length"))?,
+        None => 16,
+    };
+    let token: String = (0..length).map(|_| fastrand::alphanumeric()).collect();
+
+    out.write(&token)?;
+    Ok(())
+}
+
+/**
+ * The `sha256` helper renders the hex encoded SHA-256 hash of its value, e.g. `{{sha256 user}}`
+ */
+fn sha256(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(string_param(h, 0, "sha256")?);
+
+    out.write(&format!("{:x}", hasher.finalize()))?;
+    Ok(())
+}
+
+/**
+ * The `md5` helper renders the hex encoded MD5 hash of its value, e.g. `{{md5 user}}`
+ */
+fn md5(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    use md5::Digest;
+    let mut hasher = md5::Md5::new();
+    hasher.update(string_param(h, 0, "md5")?);
+
+    out.write(&format!("{:x}", hasher.finalize()))?;
+    Ok(())
+}
+
+/**
+ * Return the helper's parameter at the index as a string, values which are not strings are
+ * converted to their JSON representation
+ */
+fn string_param(h: &Helper, index: usize, helper: &str) -> Result<String, RenderError> {
+    match h.param(index).map(|param| param.value()) {
+        Some(serde_json::Value::String(value)) => Ok(value.to_string()),
+        Some(serde_json::Value::Null) | None => {
+            Err(RenderError::new(format!("{} requires a value", helper)))
+        }
+        Some(value) => Ok(value.to_string()),
+    }
+}
+
+/**
+ * The `upper` helper renders its value in upper case, e.g. `{{upper appname}}`
+ */
+fn upper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(&string_param(h, 0, "upper")?.to_uppercase())?;
+    Ok(())
+}
+
+/**
+ * The `lower` helper renders its value in lower case, e.g. `{{lower appname}}`
+ */
+fn lower(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(&string_param(h, 0, "lower")?.to_lowercase())?;
+    Ok(())
+}
+
+/**
+ * The `trim` helper renders its value without leading and trailing whitespace, e.g.
+ * `{{trim name}}`
+ */
+fn trim(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(string_param(h, 0, "trim")?.trim())?;
+    Ok(())
+}
+
+/**
+ * The `replace` helper renders its value with every occurrence of a string replaced, e.g.
+ * `{{replace appname "." "-"}}`
+ */
+fn replace(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = string_param(h, 0, "replace")?;
+    let from = string_param(h, 1, "replace")?;
+    let to = string_param(h, 2, "replace")?;
+
+    out.write(&value.replace(&from, &to))?;
+    Ok(())
+}
+
+/**
+ * The `truncate` helper renders at most the given number of characters of its value, e.g.
+ * `{{truncate msg 64}}`
+ */
+fn truncate(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = string_param(h, 0, "truncate")?;
+    let length = h
+        .param(1)
+        .and_then(|param| param.value().as_u64())
+        .ok_or_else(|| RenderError::new("truncate requires a numeric length"))?;
+
+    out.write(&value.chars().take(length as usize).collect::<String>())?;
+    Ok(())
+}
+
+/**
+ * The `env` helper renders the value of an environment variable, or the default when it is not
+ * set, e.g. `{{env "REGION" "us-west-2"}}`
+ */
+fn env(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let name = h
+        .param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("env requires the name of a variable"))?;
+    let default = h.param(1).and_then(|param| param.value().as_str());
+
+    match (std::env::var(name), default) {
+        (Ok(value), _) => out.write(&value)?,
+        (Err(_), Some(default)) => out.write(default)?,
+        (Err(_), None) => {
+            return Err(RenderError::new(format!(
+                "The environment variable `{}` is not set",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * The `json` helper renders its value as JSON, so that a string is quoted and escaped and can be
+ * safely placed in a JSON payload, e.g. `{ "message": {{json msg}} }`.
+ *
+ * Missing values are rendered as `null`
+ */
+fn json(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = h
+        .param(0)
+        .map(|param| param.value().clone())
+        .unwrap_or(serde_json::Value::Null);
+
+    out.write(&value.to_string())?;
+    Ok(())
+}
+
+/**
+ * The `default` helper renders its value, or the default when the value is missing or empty, e.g.
+ * `{{default status "unknown"}}` for an optional capture which did not participate in the match
+ */
+fn default(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let default = string_param(h, 1, "default")?;
+
+    match h.param(0).map(|param| param.value()) {
+        Some(serde_json::Value::String(value)) if !value.is_empty() => out.write(value)?,
+        Some(serde_json::Value::String(_)) | Some(serde_json::Value::Null) | None => {
+            out.write(&default)?
+        }
+        Some(value) => out.write(&value.to_string())?,
+    }
+    Ok(())
+}
+
+/**
+ * MapHelper renders the value of a `map` helper from the configuration
+ */
+struct MapHelper {
+    map: HashMap<String, String>,
+    default: Option<String>,
+}
+
+impl HelperDef for MapHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let value = string_param(h, 0, h.name())?;
+
+        /*
+         * The keys of the map are lowercased when the configuration is loaded
+         */
+        match (self.map.get(&value.to_lowercase()), &self.default) {
+            (Some(mapped), _) => out.write(mapped)?,
+            (None, Some(default)) => out.write(default)?,
+            (None, None) => out.write(&value)?,
+        }
+        Ok(())
+    }
+}
+
+/**
+ * TemplateHelper renders the template of a `template` helper from the configuration with the
+ * helper's parameter as the `value` variable
+ */
+struct TemplateHelper {
+    template_id: String,
+}
+
+impl HelperDef for TemplateHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let mut variables = HashMap::new();
+        variables.insert("value", string_param(h, 0, h.name())?);
+
+        out.write(&r.render(&self.template_id, &variables)?)?;
+        Ok(())
+    }
+}
+
+/*
+ * The comparison helpers are meant for the conditions of `#if` and `#unless` blocks, e.g.
+ * `{{#if (eq status "500")}}`. Since the variables are all strings, `eq` and `ne` compare the
+ * values as strings, while the ordering helpers compare numeric values as numbers
+ */
+handlebars_helper!(eq: |x: Json, y: Json| as_string(x) == as_string(y));
+handlebars_helper!(ne: |x: Json, y: Json| as_string(x) != as_string(y));
+handlebars_helper!(gt: |x: Json, y: Json| compare(x, y).map(Ordering::is_gt) == Some(true));
+handlebars_helper!(gte: |x: Json, y: Json| compare(x, y).map(Ordering::is_ge) == Some(true));
+handlebars_helper!(lt: |x: Json, y: Json| compare(x, y).map(Ordering::is_lt) == Some(true));
+handlebars_helper!(lte: |x: Json, y: Json| compare(x, y).map(Ordering::is_le) == Some(true));
+
+/**
+ * Return the value as a string for comparisons, missing values are empty
+ */
+fn as_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.to_string(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/**
+ * Compare the values as numbers when they both are numeric, and as strings otherwise
+ */
+fn compare(x: &serde_json::Value, y: &serde_json::Value) -> Option<Ordering> {
+    let (x, y) = (as_string(x), as_string(y));
+
+    match (x.trim().parse::<f64>(), y.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.partial_cmp(&y),
+        _ => Some(x.cmp(&y)),
+    }
+}
+
+/**
+ * Generate the unique identifier for the primary template of the rule's action
+ */
+pub fn id_for(rule: &Rule, index: usize) -> String {
+    format!("{}-{}", rule.uuid, index)
+}
+
+/**
+ * Generate the identifier for one of the other templates of an action, such as the topic of a
+ * Forward action or the fields of an AddFields action
+ */
+pub fn part_id_for(template_id: &str, part: &str) -> String {
+    format!("{}-{}", template_id, part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::*;
+
+    #[test]
+    fn test_templates_merge() {
+        let settings = load("test/configs/single-rule-with-merge.yml");
+        let template_id = format!("{}-{}", settings.rules[0].uuid, 0);
+
+        assert!(settings.templates.has_template(&template_id));
+    }
+
+    #[test]
+    fn test_templates_replace() {
+        let settings = load("test/configs/single-rule-with-replace.yml");
+        let template_id = format!("{}-{}", settings.rules[0].uuid, 0);
+
+        assert!(settings.templates.has_template(&template_id));
+    }
+
+    #[test]
+    fn test_templates_forward() {
+        let settings = load("test/configs/single-rule-with-forward-payload.yml");
+        let template_id = id_for(&settings.rules[0], 0);
+
+        assert!(settings.templates.has_template(&template_id));
+        assert!(settings
+            .templates
+            .has_template(&part_id_for(&template_id, "topic")));
+    }
+
+    #[test]
+    fn test_date_format() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("timestamp", "2021-03-04T05:06:07+00:00");
+
+        assert_eq!(
+            "logs-2021.03.04",
+            templates
+                .render_template(r#"logs-{{date_format timestamp "%Y.%m.%d"}}"#, &hash)
+                .unwrap()
+        );
+        assert!(templates
+            .render_template(r#"{{date_format missing "%Y"}}"#, &hash)
+            .is_err());
+        assert!(templates
+            .render_template(r#"{{date_format timestamp "%Q"}}"#, &hash)
+            .is_err());
+    }
+
+    #[test]
+    fn test_uuid() {
+        let templates = Templates::default();
+        let rendered = templates.render_template("{{uuid}}", &()).unwrap();
+
+        assert!(uuid::Uuid::parse_str(&rendered).is_ok());
+        assert_ne!(
+            rendered,
+            templates.render_template("{{uuid}}", &()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_random_token() {
+        let templates = Templates::default();
+        let rendered = templates.render_template("{{random_token}}", &()).unwrap();
+        assert_eq!(16, rendered.len());
+        assert!(rendered.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let rendered = templates
+            .render_template("{{random_token 4}}", &())
+            .unwrap();
+        assert_eq!(4, rendered.len());
+        assert!(templates
+            .render_template(r#"{{random_token "long"}}"#, &())
+            .is_err());
+    }
+
+    #[test]
+    fn test_hashing() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("user", "tyler");
+
+        assert_eq!(
+            templates.render_template("{{sha256 user}}", &hash).unwrap(),
+            templates
+                .render_template(r#"{{sha256 "tyler"}}"#, &hash)
+                .unwrap()
+        );
+        assert_eq!(
+            "900150983cd24fb0d6963f7d28e17f72",
+            templates
+                .render_template(r#"{{md5 "abc"}}"#, &hash)
+                .unwrap()
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            templates
+                .render_template(r#"{{sha256 "abc"}}"#, &hash)
+                .unwrap()
+        );
+        assert!(templates.render_template("{{md5 missing}}", &hash).is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        let templates = Templates::default();
+        std::env::set_var("HOTDOG_TEST_REGION", "us-west-2");
+
+        assert_eq!(
+            "us-west-2",
+            templates
+                .render_template(r#"{{env "HOTDOG_TEST_REGION" "unknown"}}"#, &())
+                .unwrap()
+        );
+        assert_eq!(
+            "unknown",
+            templates
+                .render_template(r#"{{env "HOTDOG_TEST_UNSET" "unknown"}}"#, &())
+                .unwrap()
+        );
+        assert!(templates
+            .render_template(r#"{{env "HOTDOG_TEST_UNSET"}}"#, &())
+            .is_err());
+    }
+
+    #[test]
+    fn test_string_helpers() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("appname", " Web.Frontend ");
+
+        let render = |template: &str| templates.render_template(template, &hash).unwrap();
+
+        assert_eq!(" WEB.FRONTEND ", render("{{upper appname}}"));
+        assert_eq!("logs- web.frontend ", render("logs-{{lower appname}}"));
+        assert_eq!("Web.Frontend", render("{{trim appname}}"));
+        assert_eq!(" Web-Frontend ", render(r#"{{replace appname "." "-"}}"#));
+        assert_eq!(" Web", render("{{truncate appname 4}}"));
+        assert_eq!(
+            "logs-web-frontend",
+            render(r#"logs-{{lower (replace (trim appname) "." "-")}}"#)
+        );
+        assert!(templates
+            .render_template(r#"{{truncate appname "four"}}"#, &hash)
+            .is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("msg", "she said \"hi\"\n<then left>");
+
+        let rendered = templates
+            .render_template(
+                r#"{ "message": {{json msg}}, "missing": {{json missing}} }"#,
+                &hash,
+            )
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+
+        assert_eq!("she said \"hi\"\n<then left>", value["message"]);
+        assert!(value["missing"].is_null());
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("status", "500");
+        hash.insert("user", "tyler");
+
+        let render = |template: &str| templates.render_template(template, &hash).unwrap();
+
+        assert_eq!("error", render(r#"{{#if (eq status "500")}}error{{/if}}"#));
+        assert_eq!("error", render(r#"{{#if (eq status 500)}}error{{/if}}"#));
+        assert_eq!(
+            "ok",
+            render(r#"{{#if (ne status "500")}}error{{else}}ok{{/if}}"#)
+        );
+        assert_eq!("error", render(r#"{{#if (gte status 500)}}error{{/if}}"#));
+        assert_eq!("", render(r#"{{#if (lt status 90)}}fast{{/if}}"#));
+        assert_eq!("tyler", render("{{#if user}}{{user}}{{/if}}"));
+        assert_eq!(
+            "anonymous",
+            render("{{#unless missing}}anonymous{{/unless}}")
+        );
+    }
+
+    #[test]
+    fn test_custom_helpers() {
+        let settings = load("test/configs/rules-with-helpers.yml");
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("appname", "Web");
+        hash.insert("service", "Web.Frontend");
+        hash.insert("hostname", "coconut");
+
+        let render = |template: &str| settings.templates.render_template(template, &hash).unwrap();
+
+        assert_eq!("frontend", render("{{team appname}}"));
+        assert_eq!("unknown", render("{{team hostname}}"));
+        assert_eq!("coconut", render("{{datacenter hostname}}"));
+        assert_eq!("logs-web-frontend", render("{{topic_for service}}"));
+    }
+
+    #[test]
+    fn test_partials() {
+        let settings = load("test/configs/rules-with-partials.yml");
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("hostname", "coconut");
+        hash.insert("msg", "hello");
+
+        let rendered = settings
+            .templates
+            .render(&id_for(&settings.rules[0], 0), &hash)
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+
+        assert_eq!("coconut", value["host"]);
+        assert_eq!("hello", value["message"]);
+    }
+
+    #[test]
+    fn test_custom_helper_shadowing_builtin() {
+        assert!(try_load(
+            "test/configs/rules-with-invalid-helper.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_default() {
+        let templates = Templates::default();
+        let mut hash = std::collections::HashMap::new();
+        hash.insert("status", "200");
+        hash.insert("empty", "");
+
+        let render = |template: &str| templates.render_template(template, &hash).unwrap();
+
+        assert_eq!("200", render(r#"{{default status "unknown"}}"#));
+        assert_eq!("unknown", render(r#"{{default empty "unknown"}}"#));
+        assert_eq!("unknown", render(r#"{{default missing "unknown"}}"#));
+        assert!(templates
+            .render_template("{{default missing}}", &hash)
+            .is_err());
+    }
+
+    #[test]
+    fn test_templates_invalid() {
+        assert!(try_load(
+            "test/configs/single-rule-with-invalid-template.yml",
+            &Overrides::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,169 @@
+/**
+ * The timestamp module parses timestamps in a variety of formats so they can be normalized before
+ * being sent along to Kafka
+ */
+use crate::settings::{SkewedTimestampPolicy, TimestampFormat, Timestamps};
+use chrono::prelude::*;
+
+/**
+ * Parse the value with the first of the formats which matches it.
+ *
+ * Formats are either the names `rfc3339`, `rfc2822`, `epoch`, and `epoch_millis`, or
+ * strftime-style formats such as `%d/%b/%Y:%H:%M:%S %z`. Formats without a timezone are assumed
+ * to be in UTC. When no formats are given, RFC 3339 and RFC 2822 are attempted.
+ */
+pub fn parse(value: &str, formats: &[String]) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+
+    if formats.is_empty() {
+        return parse_with(value, "rfc3339").or_else(|| parse_with(value, "rfc2822"));
+    }
+    formats.iter().find_map(|format| parse_with(value, format))
+}
+
+fn parse_with(value: &str, format: &str) -> Option<DateTime<Utc>> {
+    match format {
+        "rfc3339" => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        "rfc2822" => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        "epoch" => value
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single()),
+        "epoch_millis" => value
+            .parse::<i64>()
+            .ok()
+            .and_then(|millis| Utc.timestamp_millis_opt(millis).single()),
+        format => DateTime::parse_from_str(value, format)
+            .map(|t| t.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                NaiveDateTime::parse_from_str(value, format)
+                    .ok()
+                    .map(|t| DateTime::<Utc>::from_utc(t, Utc))
+            }),
+    }
+}
+
+/**
+ * Format the timestamp as a JSON value in the given format
+ */
+pub fn format(timestamp: &DateTime<Utc>, format: &TimestampFormat) -> serde_json::Value {
+    match format {
+        TimestampFormat::Iso8601 => {
+            serde_json::Value::from(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+        }
+        TimestampFormat::EpochMillis => serde_json::Value::from(timestamp.timestamp_millis()),
+    }
+}
+
+/**
+ * Check the timestamp of a message against the window around the time it was received at.
+ *
+ * Returns None when the timestamp is within the window, otherwise the timestamp which the
+ * message should carry according to the policy for skewed timestamps.
+ */
+pub fn sanitize(
+    timestamp: DateTime<Utc>,
+    received: DateTime<Utc>,
+    settings: &Timestamps,
+) -> Option<DateTime<Utc>> {
+    let offset = |secs| chrono::Duration::from_std(std::time::Duration::from_secs(secs)).ok();
+    let earliest =
+        offset(settings.max_past_secs).and_then(|past| received.checked_sub_signed(past));
+    let latest =
+        offset(settings.max_future_secs).and_then(|future| received.checked_add_signed(future));
+
+    let bound = match (earliest, latest) {
+        (Some(earliest), _) if timestamp < earliest => earliest,
+        (_, Some(latest)) if timestamp > latest => latest,
+        _ => return None,
+    };
+
+    Some(match settings.skewed {
+        SkewedTimestampPolicy::Annotate => timestamp,
+        SkewedTimestampPolicy::Clamp => bound,
+        SkewedTimestampPolicy::Rewrite => received,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_defaults() {
+        let expected = Utc.ymd(2020, 4, 18).and_hms(22, 16, 9);
+        assert_eq!(Some(expected), parse("2020-04-18T15:16:09-07:00", &[]));
+        assert_eq!(
+            Some(expected),
+            parse("Sat, 18 Apr 2020 15:16:09 -0700", &[])
+        );
+        assert_eq!(None, parse("yesterday", &[]));
+    }
+
+    #[test]
+    fn test_parse_custom_formats() {
+        let formats = vec![
+            "epoch_millis".to_string(),
+            "%d/%b/%Y:%H:%M:%S %z".to_string(),
+            "%Y-%m-%d %H:%M:%S".to_string(),
+        ];
+        let expected = Utc.ymd(2000, 10, 10).and_hms(20, 55, 36);
+
+        assert_eq!(
+            Some(expected),
+            parse("10/Oct/2000:13:55:36 -0700", &formats)
+        );
+        assert_eq!(Some(expected), parse("2000-10-10 20:55:36", &formats));
+        assert_eq!(Some(expected), parse("971211336000", &formats));
+    }
+
+    #[test]
+    fn test_format() {
+        let timestamp = Utc.ymd(2000, 10, 10).and_hms(20, 55, 36);
+        assert_eq!(
+            serde_json::Value::from("2000-10-10T20:55:36.000Z"),
+            format(&timestamp, &TimestampFormat::Iso8601)
+        );
+        assert_eq!(
+            serde_json::Value::from(971211336000i64),
+            format(&timestamp, &TimestampFormat::EpochMillis)
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let received = Utc.ymd(2020, 4, 18).and_hms(12, 0, 0);
+        let mut settings = Timestamps {
+            max_future_secs: 3_600,
+            max_past_secs: 86_400,
+            skewed: SkewedTimestampPolicy::Annotate,
+        };
+        let future = Utc.ymd(2020, 4, 18).and_hms(14, 0, 0);
+        let past = Utc.ymd(2020, 4, 1).and_hms(0, 0, 0);
+
+        assert_eq!(None, sanitize(received, received, &settings));
+        assert_eq!(
+            None,
+            sanitize(Utc.ymd(2020, 4, 17).and_hms(13, 0, 0), received, &settings)
+        );
+        assert_eq!(Some(future), sanitize(future, received, &settings));
+
+        settings.skewed = SkewedTimestampPolicy::Clamp;
+        assert_eq!(
+            Some(Utc.ymd(2020, 4, 18).and_hms(13, 0, 0)),
+            sanitize(future, received, &settings)
+        );
+        assert_eq!(
+            Some(Utc.ymd(2020, 4, 17).and_hms(12, 0, 0)),
+            sanitize(past, received, &settings)
+        );
+
+        settings.skewed = SkewedTimestampPolicy::Rewrite;
+        assert_eq!(Some(received), sanitize(past, received, &settings));
+    }
+}
@@ -0,0 +1,217 @@
+/**
+ * The trace module records the stages which every line passes through, from the connection which
+ * received it to its delivery to Kafka, as OpenTelemetry spans which are exported over OTLP when
+ * `global.tracing` is configured
+ */
+use crate::settings::Tracing;
+use log::*;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{TraceContextExt, Tracer};
+
+/**
+ * Install the OTLP exporter which the spans are sent to, until it is installed the spans are not
+ * recorded at all
+ */
+#[cfg(feature = "otel")]
+pub fn init(tracing: &Tracing) {
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(&tracing.endpoint);
+    /*
+     * Only the lines are sampled, the spans of their stages follow the sampling of their line
+     */
+    let config = trace::config()
+        .with_sampler(trace::Sampler::ParentBased(Box::new(
+            trace::Sampler::TraceIdRatioBased(tracing.ratio),
+        )))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            tracing.service_name.clone(),
+        )]));
+
+    match opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(opentelemetry::runtime::AsyncStd)
+    {
+        Ok(_) => info!("Exporting spans to {}", tracing.endpoint),
+        Err(e) => error!(
+            "Failed to install the OTLP exporter for {}: {}",
+            tracing.endpoint, e
+        ),
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(_tracing: &Tracing) {
+    warn!("hotdog was not built with the `otel` feature, `global.tracing` is ignored");
+}
+
+/**
+ * Export the spans which are still buffered, before hotdog exits
+ */
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/**
+ * The trace which a span belongs to, which is carried along with a message so that the spans of
+ * its later stages, such as the delivery to Kafka, are part of the same trace
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    #[cfg(feature = "otel")]
+    context: opentelemetry::Context,
+}
+
+/**
+ * A Span records a single stage, and ends once it is dropped
+ */
+pub struct Span {
+    #[cfg(feature = "otel")]
+    context: opentelemetry::Context,
+}
+
+impl Span {
+    /**
+     * Start a span which begins a trace of its own
+     */
+    pub fn root(name: &'static str) -> Span {
+        Span::start(name, &Trace::default())
+    }
+
+    /**
+     * Start a span within the given trace
+     */
+    #[cfg(feature = "otel")]
+    pub fn start(name: &'static str, trace: &Trace) -> Span {
+        let span = opentelemetry::global::tracer("hotdog").start_with_context(name, &trace.context);
+
+        Span {
+            context: trace.context.with_span(span),
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn start(_name: &'static str, _trace: &Trace) -> Span {
+        Span {}
+    }
+
+    /**
+     * Return the trace of this span, for starting the spans of the stages within it
+     */
+    pub fn trace(&self) -> Trace {
+        Trace {
+            #[cfg(feature = "otel")]
+            context: self.context.clone(),
+        }
+    }
+
+    /**
+     * Set an attribute of the span, such as the peer or the topic
+     */
+    #[cfg(feature = "otel")]
+    pub fn set(&self, key: &'static str, value: String) {
+        self.context
+            .span()
+            .set_attribute(opentelemetry::KeyValue::new(key, value));
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn set(&self, _key: &'static str, _value: String) {}
+
+    /**
+     * Mark the stage as failed, with a description of the error
+     */
+    #[cfg(feature = "otel")]
+    pub fn fail(&self, error: String) {
+        self.context
+            .span()
+            .set_status(opentelemetry::trace::StatusCode::Error, error);
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn fail(&self, _error: String) {}
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        self.context.span().end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_without_exporter() {
+        let line = Span::root("line");
+        line.set("peer", "127.0.0.1:514".to_string());
+
+        let parse = Span::start("parse", &line.trace());
+        parse.fail("UnknownFormat".to_string());
+    }
+
+    #[cfg(feature = "otel")]
+    #[derive(Clone, Debug, Default)]
+    struct Recorder {
+        spans: std::sync::Arc<std::sync::Mutex<Vec<opentelemetry::sdk::export::trace::SpanData>>>,
+    }
+
+    #[cfg(feature = "otel")]
+    #[async_trait::async_trait]
+    impl opentelemetry::sdk::export::trace::SpanExporter for Recorder {
+        async fn export(
+            &mut self,
+            batch: Vec<opentelemetry::sdk::export::trace::SpanData>,
+        ) -> opentelemetry::sdk::export::trace::ExportResult {
+            self.spans.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[test]
+    fn test_spans_exported() {
+        use opentelemetry::trace::TraceContextExt;
+
+        let recorder = Recorder::default();
+        opentelemetry::global::set_tracer_provider(
+            opentelemetry::sdk::trace::TracerProvider::builder()
+                .with_simple_exporter(recorder.clone())
+                .build(),
+        );
+
+        let line = Span::root("line");
+        let trace_id = line.context.span().span_context().trace_id();
+        line.set("peer", "127.0.0.1:514".to_string());
+        let parse = Span::start("parse", &line.trace());
+        parse.fail("UnknownFormat".to_string());
+        drop(parse);
+        drop(line);
+
+        /*
+         * Shutting down the provider waits until the ended spans have been exported
+         */
+        opentelemetry::global::shutdown_tracer_provider();
+
+        let spans = recorder.spans.lock().unwrap();
+        let traced: Vec<_> = spans
+            .iter()
+            .filter(|span| span.span_context.trace_id() == trace_id)
+            .collect();
+        assert_eq!(2, traced.len(), "Both spans of the line should be exported");
+
+        let line = traced.iter().find(|span| span.name == "line").unwrap();
+        let parse = traced.iter().find(|span| span.name == "parse").unwrap();
+        assert_eq!(line.span_context.span_id(), parse.parent_span_id);
+    }
+}
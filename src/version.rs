@@ -0,0 +1,101 @@
+/**
+ * The version module describes exactly which hotdog is running, the commit and the date it was
+ * built from, the optional features it was compiled with and the librdkafka it is linked against,
+ * so that bug reports and audits of a fleet can tell the builds of the same version apart
+ */
+
+/**
+ * The version of hotdog
+ */
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/**
+ * The short hash of the commit hotdog was built from, or `unknown` outside of a git checkout
+ */
+pub const COMMIT: &str = env!("HOTDOG_COMMIT");
+/**
+ * The date hotdog was built, in UTC
+ */
+pub const BUILT: &str = env!("HOTDOG_BUILT");
+
+/**
+ * Return the optional features which hotdog was compiled with
+ */
+pub fn features() -> Vec<&'static str> {
+    let features = [
+        ("avro", cfg!(feature = "avro")),
+        ("geoip", cfg!(feature = "geoip")),
+        ("lua", cfg!(feature = "lua")),
+        ("otel", cfg!(feature = "otel")),
+        ("protobuf", cfg!(feature = "protobuf")),
+        ("schema", cfg!(feature = "schema")),
+        ("secrets", cfg!(feature = "secrets")),
+        ("simd", cfg!(feature = "simd")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect()
+}
+
+/**
+ * Return the security which hotdog's librdkafka was built with, on Windows it is built without
+ * libsasl2 to link against
+ */
+pub fn kafka_security() -> &'static str {
+    if cfg!(unix) {
+        "ssl and sasl"
+    } else {
+        "ssl"
+    }
+}
+
+/**
+ * Return the version of the librdkafka which hotdog is linked against
+ */
+pub fn librdkafka() -> String {
+    rdkafka::util::get_rdkafka_version().1
+}
+
+/**
+ * Describe the build of hotdog, for `hotdog version`
+ */
+pub fn describe() -> String {
+    let features = features();
+
+    format!(
+        "hotdog {}\n  commit:     {}\n  built:      {}\n  features:   {}\n  tls:        rustls (listeners), {} (Kafka)\n  librdkafka: {}\n  platform:   {}",
+        VERSION,
+        COMMIT,
+        BUILT,
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        },
+        kafka_security(),
+        librdkafka(),
+        std::env::consts::OS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let described = describe();
+
+        assert!(described.starts_with(&format!("hotdog {}\n", VERSION)));
+        assert!(described.contains(&format!("commit:     {}", COMMIT)));
+        assert!(described.contains(&format!("rustls (listeners), {} (Kafka)", kafka_security())));
+        assert_eq!(
+            cfg!(feature = "geoip"),
+            features().contains(&"geoip"),
+            "The features should be those hotdog was compiled with"
+        );
+    }
+}